@@ -0,0 +1,4 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS ap_deliveries;
+DROP TABLE IF EXISTS ap_followers;
+DROP TABLE IF EXISTS ap_actor_keys;
@@ -0,0 +1,32 @@
+-- Add up migration script here
+CREATE TABLE ap_actor_keys
+(
+    user_id         INTEGER NOT NULL PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    private_key_pem TEXT    NOT NULL,
+    public_key_pem  TEXT    NOT NULL,
+    created_at      INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+CREATE TABLE ap_followers
+(
+    id               INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id          INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    actor_url        TEXT    NOT NULL,
+    inbox_url        TEXT    NOT NULL,
+    shared_inbox_url TEXT    NULL,
+    created_at       INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    UNIQUE (user_id, actor_url)
+);
+
+CREATE TABLE ap_deliveries
+(
+    id              INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id         INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, -- signing actor
+    inbox_url       TEXT    NOT NULL,
+    payload         TEXT    NOT NULL,
+    attempts        INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    last_error      TEXT    NULL,
+    created_at      INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+CREATE INDEX idx_ap_deliveries_next_attempt_at ON ap_deliveries (next_attempt_at);
@@ -0,0 +1,10 @@
+-- Add up migration script here
+CREATE TABLE echo_imports
+(
+    source      TEXT    NOT NULL, -- e.g. `ech0`, `memos`
+    source_id   TEXT    NOT NULL, -- id of the item in the source export
+    echo_id     INTEGER NOT NULL REFERENCES echos (id) ON DELETE CASCADE,
+    imported_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    PRIMARY KEY (source, source_id)
+);
+CREATE INDEX idx_echo_imports_echo_id ON echo_imports (echo_id);
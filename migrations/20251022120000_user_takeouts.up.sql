@@ -0,0 +1,15 @@
+-- Add up migration script here
+CREATE TABLE user_takeouts
+(
+    id            INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id       INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    status        INTEGER NOT NULL DEFAULT 1, -- see `TakeoutStatus`
+    file_uuid     BLOB    NOT NULL UNIQUE,    -- archive file under `<local_storage_path>/takeouts`
+    file_size     INTEGER NULL,
+    error_message TEXT    NULL,
+    created_at    INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    finished_at   INTEGER NULL,
+    expires_at    INTEGER NULL
+);
+CREATE INDEX idx_user_takeouts_user_id_id ON user_takeouts (user_id, id);
+CREATE INDEX idx_user_takeouts_status_expires_at ON user_takeouts (status, expires_at);
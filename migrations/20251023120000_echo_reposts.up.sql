@@ -0,0 +1,8 @@
+-- Add up migration script here
+CREATE TABLE echo_reposts
+(
+    echo_id          INTEGER NOT NULL PRIMARY KEY REFERENCES echos (id) ON DELETE CASCADE, -- the repost itself
+    original_echo_id INTEGER NULL REFERENCES echos (id) ON DELETE SET NULL,                -- NULL once the original is gone
+    created_at       INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+CREATE INDEX idx_echo_reposts_original_echo_id ON echo_reposts (original_echo_id);
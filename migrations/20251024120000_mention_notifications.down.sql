@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS notifications;
+DROP TABLE IF EXISTS echo_mentions;
@@ -0,0 +1,22 @@
+-- Add up migration script here
+CREATE TABLE echo_mentions
+(
+    echo_id    INTEGER NOT NULL REFERENCES echos (id) ON DELETE CASCADE,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, -- only users who can see the mention
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    PRIMARY KEY (echo_id, user_id)
+);
+CREATE INDEX idx_echo_mentions_user_id ON echo_mentions (user_id);
+
+CREATE TABLE notifications
+(
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, -- the recipient
+    kind       INTEGER NOT NULL,
+    actor_id   INTEGER NULL REFERENCES users (id) ON DELETE SET NULL,
+    echo_id    INTEGER NULL REFERENCES echos (id) ON DELETE CASCADE,
+    is_read    INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+CREATE INDEX idx_notifications_user_id ON notifications (user_id, id);
+CREATE INDEX idx_notifications_unread ON notifications (user_id) WHERE is_read = 0;
@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE echo_share_links
+(
+    id             INTEGER PRIMARY KEY AUTOINCREMENT,
+    echo_id        INTEGER NOT NULL REFERENCES echos (id) ON DELETE CASCADE,
+    owner_id       INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    permission_ids TEXT    NOT NULL DEFAULT '[]', -- json array, the permissions the echo is rendered with
+    one_time       INTEGER NOT NULL DEFAULT 0,
+    used_at        INTEGER NULL,                  -- first time the link was opened
+    expires_at     INTEGER NOT NULL,
+    created_at     INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+CREATE INDEX idx_echo_share_links_owner_id ON echo_share_links (owner_id, id);
+CREATE INDEX idx_echo_share_links_echo_id ON echo_share_links (echo_id);
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- Only used when the database itself is encrypted, otherwise the key ring lives in a key file
+CREATE TABLE auth_key_ring
+(
+    id         INTEGER PRIMARY KEY CHECK (id = 1),
+    ring       TEXT    NOT NULL, -- json, see `AuthKeyRing`
+    updated_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
@@ -0,0 +1,14 @@
+-- Add up migration script here
+CREATE TABLE user_sessions
+(
+    id           INTEGER PRIMARY KEY AUTOINCREMENT,
+    session_uuid BLOB    NOT NULL UNIQUE, -- carried by the basic auth cookie
+    user_id      INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    device_name  TEXT    NULL,            -- label supplied by the client on login
+    ip_address   TEXT    NULL,
+    user_agent   TEXT    NULL,
+    created_at   INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    last_seen_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    revoked_at   INTEGER NULL
+);
+CREATE INDEX idx_user_sessions_user_id ON user_sessions (user_id, id);
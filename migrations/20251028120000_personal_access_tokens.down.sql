@@ -0,0 +1,4 @@
+-- Add down migration script here
+ALTER TABLE auth_tokens DROP COLUMN scopes;
+ALTER TABLE auth_tokens DROP COLUMN name;
+ALTER TABLE auth_tokens RENAME COLUMN token_hash TO token;
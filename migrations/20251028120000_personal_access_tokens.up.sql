@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- nothing ever issued a token, and from now on only hashes are stored
+DELETE FROM auth_tokens;
+ALTER TABLE auth_tokens RENAME COLUMN token TO token_hash;
+ALTER TABLE auth_tokens ADD COLUMN name TEXT NOT NULL DEFAULT '';
+ALTER TABLE auth_tokens ADD COLUMN scopes TEXT NOT NULL DEFAULT '[]'; -- json array of token scopes
@@ -0,0 +1,3 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_oidc_identities_user_id;
+DROP TABLE IF EXISTS oidc_identities;
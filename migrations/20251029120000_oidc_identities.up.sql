@@ -0,0 +1,11 @@
+-- Add up migration script here
+CREATE TABLE oidc_identities
+(
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    provider   TEXT    NOT NULL, -- name of the provider in the config
+    subject    TEXT    NOT NULL, -- `sub` claim issued by the provider
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    UNIQUE (provider, subject)
+);
+CREATE INDEX idx_oidc_identities_user_id ON oidc_identities (user_id);
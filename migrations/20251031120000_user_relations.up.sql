@@ -0,0 +1,12 @@
+-- Add up migration script here
+CREATE TABLE user_relations
+(
+    id         INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    target_id  INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    kind       INTEGER NOT NULL, -- 1: block, 2: mute
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    UNIQUE (user_id, kind, target_id),
+    CHECK (user_id <> target_id)
+);
+CREATE INDEX idx_user_relations_target_id ON user_relations (target_id, kind);
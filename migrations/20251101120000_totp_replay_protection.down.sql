@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE totp_credentials DROP COLUMN last_time_step;
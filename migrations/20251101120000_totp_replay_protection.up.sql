@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- codes from this time step or an earlier one are rejected as replays
+ALTER TABLE totp_credentials ADD COLUMN last_time_step INTEGER NULL;
@@ -0,0 +1,10 @@
+-- Add up migration script here
+CREATE TABLE mfa_recovery_codes
+(
+    id         INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id    INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash  TEXT    NOT NULL, -- hex encoded sha256 of the normalized code
+    created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    used_at    INTEGER NULL
+);
+CREATE INDEX idx_mfa_recovery_codes_user_id ON mfa_recovery_codes (user_id, code_hash);
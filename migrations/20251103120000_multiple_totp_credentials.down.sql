@@ -0,0 +1,20 @@
+-- Add down migration script here
+-- only the oldest credential of each user is kept
+CREATE TABLE totp_credentials_old
+(
+    id                   INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id              INTEGER NOT NULL UNIQUE REFERENCES users (id) ON DELETE CASCADE,
+    totp_credential_data BLOB    NOT NULL,
+    created_at           INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    updated_at           INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    last_used_at         INTEGER NULL,
+    last_time_step       INTEGER NULL
+);
+INSERT INTO totp_credentials_old
+(id, user_id, totp_credential_data, created_at, updated_at, last_used_at, last_time_step)
+SELECT id, user_id, totp_credential_data, created_at, updated_at, last_used_at, last_time_step
+FROM totp_credentials
+WHERE id IN (SELECT MIN(id) FROM totp_credentials GROUP BY user_id);
+DROP INDEX IF EXISTS idx_totp_credentials_user_id;
+DROP TABLE totp_credentials;
+ALTER TABLE totp_credentials_old RENAME TO totp_credentials;
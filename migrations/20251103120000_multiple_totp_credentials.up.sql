@@ -0,0 +1,20 @@
+-- Add up migration script here
+-- sqlite cannot drop the unique constraint on user_id, so the table is rebuilt
+CREATE TABLE totp_credentials_new
+(
+    id                   INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    user_id              INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    label                TEXT    NOT NULL DEFAULT '',
+    totp_credential_data BLOB    NOT NULL,
+    created_at           INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    updated_at           INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+    last_used_at         INTEGER NULL,
+    last_time_step       INTEGER NULL
+);
+INSERT INTO totp_credentials_new
+(id, user_id, label, totp_credential_data, created_at, updated_at, last_used_at, last_time_step)
+SELECT id, user_id, 'Authenticator', totp_credential_data, created_at, updated_at, last_used_at, last_time_step
+FROM totp_credentials;
+DROP TABLE totp_credentials;
+ALTER TABLE totp_credentials_new RENAME TO totp_credentials;
+CREATE INDEX idx_totp_credentials_user_id ON totp_credentials (user_id, created_at DESC);
@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE mfa_infos DROP COLUMN passkey_login;
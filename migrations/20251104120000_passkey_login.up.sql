@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- whether the passkeys of the user may also log in without a password
+ALTER TABLE mfa_infos ADD COLUMN passkey_login INTEGER NOT NULL DEFAULT 0;
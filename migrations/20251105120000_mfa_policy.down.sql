@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE mfa_infos DROP COLUMN mfa_required_since;
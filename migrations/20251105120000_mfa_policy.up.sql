@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- when a site-wide policy first required MFA from a user who has not set it up, starts the grace period
+ALTER TABLE mfa_infos ADD COLUMN mfa_required_since INTEGER NULL;
@@ -0,0 +1,20 @@
+-- Add up migration script here
+-- no foreign keys, entries have to outlive the users they mention
+CREATE TABLE audit_logs
+(
+    id             INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    actor_id       INTEGER NULL,     -- unknown for failed logins with a wrong username
+    action         INTEGER NOT NULL,
+    target_user_id INTEGER NULL,
+    target         TEXT    NULL,     -- what was acted on when it is not a user, e.g. a setting key
+    before_summary TEXT    NULL,     -- json
+    after_summary  TEXT    NULL,     -- json
+    ip_address     TEXT    NULL,
+    user_agent     TEXT    NULL,
+    request_id     TEXT    NULL,
+    time           INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+CREATE INDEX idx_audit_logs_actor_id ON audit_logs (actor_id, id);
+CREATE INDEX idx_audit_logs_target_user_id ON audit_logs (target_user_id, id);
+CREATE INDEX idx_audit_logs_action ON audit_logs (action, id);
+CREATE INDEX idx_audit_logs_time ON audit_logs (time);
@@ -0,0 +1,2 @@
+-- Add down migration script here
+-- unix timestamps are what the column was meant to hold, nothing to undo
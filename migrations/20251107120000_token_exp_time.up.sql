@@ -0,0 +1,5 @@
+-- Add up migration script here
+-- tokens were stored with an RFC 3339 exp_time, which sorts after every unix timestamp and never expired
+UPDATE auth_tokens
+SET exp_time = CAST(strftime('%s', exp_time) AS INTEGER)
+WHERE typeof(exp_time) = 'text';
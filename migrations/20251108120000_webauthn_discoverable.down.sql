@@ -0,0 +1,2 @@
+-- Add down migration script here
+ALTER TABLE webauthn_credentials DROP COLUMN discoverable;
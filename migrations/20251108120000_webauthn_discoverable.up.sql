@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- credProps reported by the browser at registration, NULL when it did not say
+ALTER TABLE webauthn_credentials ADD COLUMN discoverable INTEGER NULL;
@@ -75,8 +75,9 @@ pub mod prelude {
     pub use super::ext_plugins::ALL_EXT_IDS;
     pub use super::pipeline::GladiatorTransformer;
     pub use super::pipeline::cons::{
-        IncomingCheckConsError, IncomingEchoCheckCons, IncomingEchoResExtractorCons,
-        OutGoingEchoFilterCons, OutGoingEchoSSRCons,
+        IncomingCheckConsError, IncomingEchoCheckCons, IncomingEchoMention,
+        IncomingEchoMentionExtractorCons, IncomingEchoResExtractorCons, OutGoingEchoFilterCons,
+        OutGoingEchoSSRCons, OutGoingEchoSSRConsCtx,
     };
     pub use super::pipeline::ends::{GladiatorCollectEnd, GladiatorNoopEnd};
     pub use ahash::HashSet;
@@ -367,4 +368,36 @@ mod test {
         let res_ids = res_collector.res_ids_ref();
         assert_eq!(res_ids, None);
     }
+
+    #[test]
+    fn mention_extractor() {
+        let input =
+            // language=html
+            r#"
+                <p>hi @alice, mail me at bob@example.com. cc @carol.</p>
+                <span echo-pm="2">psst @dave and @alice</span>
+                <p>@alice again</p>
+            "#;
+        let permission_ids = into_set(&[2]);
+        let ext_ids = into_set::<i32>(&[]);
+        let ts = GladiatorTransformer::new(&permission_ids, &ext_ids);
+        let mut checker = IncomingEchoCheckCons::new();
+        let mut mentions = IncomingEchoMentionExtractorCons::new();
+        let mut chain = hlist![&mut checker, &mut mentions, GladiatorNoopEnd];
+        ts.transform(input, &mut chain).unwrap();
+        assert!(checker.check_passed());
+        let mention = |username: &str, permission_id| IncomingEchoMention {
+            username: username.to_string(),
+            permission_id,
+        };
+        assert_eq!(
+            mentions.mentions_take(),
+            vec![
+                mention("alice", None),
+                mention("carol", None),
+                mention("dave", Some(2)),
+                mention("alice", Some(2)),
+            ]
+        );
+    }
 }
@@ -8,7 +8,6 @@ use markup5ever::Attribute;
 use serde::Serialize;
 use std::cell::Ref;
 use std::sync::Weak as WeakArc;
-use time::Duration;
 
 // TODO: zero-copy error key display
 #[derive(Debug, thiserror::Error, EchoBusinessError)]
@@ -154,9 +153,12 @@ impl<'a> EchoExtHandler<'a> for EchoResourceExt<'a> {
             .map_err(|_| EchoExtError::CustomValidation(res_id.to_string(), "not a valid id"))?;
         let state = state.upgrade().ok_or(EchoExtError::ArcUpgrade)?;
         let res_manager = ResManagerService::new(state);
-        let res_url = res_manager
-            .sign(ctx.user_id, Duration::minutes(10), res_id_int)?
-            .to_url(Some("/api/v1/resource"))?;
+        let signed = if ctx.res_sign_public {
+            res_manager.sign_public(ctx.user_id, ctx.res_sign_exp, res_id_int)?
+        } else {
+            res_manager.sign(ctx.user_id, ctx.res_sign_exp, res_id_int)?
+        };
+        let res_url = signed.to_url(Some(&ctx.res_base_url))?;
         Ok(Self { res_id, res_url })
     }
 }
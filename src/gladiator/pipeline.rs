@@ -17,6 +17,10 @@ pub trait GladiatorPipelineCons: Sized {
     /// In fact, the [`Handle`] inside [`GladiatorElement`] uses [`std::cell::RefCell`], so it is partially mutable.
     /// Be sure to **pay attention to the stacking order** when using it
     fn process(&mut self, elem: &GladiatorElement, depth: usize);
+
+    /// Called for every text node, `echo_pm` is the permission of the enclosing element (if any)
+    #[inline]
+    fn process_text(&mut self, _text: &str, _echo_pm: Option<&str>) {}
 }
 
 pub trait GladiatorPipelineEnd {
@@ -33,6 +37,11 @@ where
     fn process(&mut self, elem: &GladiatorElement<'_>, depth: usize) {
         (**self).process(elem, depth);
     }
+
+    #[inline]
+    fn process_text(&mut self, text: &str, echo_pm: Option<&str>) {
+        (**self).process_text(text, echo_pm);
+    }
 }
 
 impl<E> GladiatorPipelineEnd for &mut E
@@ -50,6 +59,7 @@ where
 pub trait PipelineChain {
     type Output;
     fn process_one(&mut self, elem: &GladiatorElement, depth: usize);
+    fn process_text_one(&mut self, text: &str, echo_pm: Option<&str>);
     fn postprocess_end(&self, dom: &RcDom) -> GladiatorPipelineResult<Self::Output>;
 }
 
@@ -64,6 +74,11 @@ where
         // no-op
     }
 
+    #[inline]
+    fn process_text_one(&mut self, _: &str, _: Option<&str>) {
+        // no-op
+    }
+
     #[inline]
     fn postprocess_end(&self, dom: &RcDom) -> GladiatorPipelineResult<Self::Output> {
         self.head.postprocess(dom)
@@ -83,6 +98,12 @@ where
         self.tail.process_one(elem, depth);
     }
 
+    #[inline]
+    fn process_text_one(&mut self, text: &str, echo_pm: Option<&str>) {
+        self.head.process_text(text, echo_pm);
+        self.tail.process_text_one(text, echo_pm);
+    }
+
     #[inline]
     fn postprocess_end(&self, dom: &RcDom) -> GladiatorPipelineResult<Self::Output> {
         self.tail.postprocess_end(dom)
@@ -122,26 +143,33 @@ impl<'a> GladiatorTransformer<'a> {
         let dom = parse_fragment_for_element(sink, ParseOpts::default(), ctx_elem, false, None)
             .from_utf8()
             .one(input.as_bytes());
-        self.process_node(&dom.document, pipelines, 1);
+        self.process_node(&dom.document, pipelines, 1, None);
         pipelines.postprocess_end(&dom)
     }
 
-    fn process_node<L>(&self, node: &Handle, pipelines: &mut L, depth: usize)
+    fn process_node<L>(&self, node: &Handle, pipelines: &mut L, depth: usize, echo_pm: Option<&str>)
     where
         L: PipelineChain,
     {
+        if let NodeData::Text { contents } = &node.data {
+            pipelines.process_text_one(&contents.borrow(), echo_pm);
+            return;
+        }
         // Meeting the definition means satisfying a valid [`GladiatorElement`]
         let mut is_valid_gladiator_element = false;
+        let mut elem_pm = None;
         if let NodeData::Element { name, attrs, .. } = &node.data
             && name.ns == ns!(html)
-            && let Some(has_permission) = {
+            && let Some(pm) = {
                 attrs
                     .borrow()
                     .iter()
                     .find(|a| *a.name.local == *"echo-pm")
-                    .map(|a| self.permissions.get(a.value.as_ref()).is_some())
+                    .map(|a| a.value.to_string())
             }
         {
+            let has_permission = self.permissions.get(pm.as_str()).is_some();
+            elem_pm = Some(pm);
             is_valid_gladiator_element = true;
             match name.local {
                 local_name!("span") => pipelines.process_one(
@@ -189,15 +217,11 @@ impl<'a> GladiatorTransformer<'a> {
         // but due to constraints in trait design, it appears we have no choice but to continue
         // recursing indefinitely...
         for child in node.children.borrow().iter() {
-            self.process_node(
-                child,
-                pipelines,
-                if is_valid_gladiator_element {
-                    depth + 1
-                } else {
-                    depth
-                },
-            )
+            let (depth, echo_pm) = match is_valid_gladiator_element {
+                true => (depth + 1, elem_pm.as_deref()),
+                false => (depth, echo_pm),
+            };
+            self.process_node(child, pipelines, depth, echo_pm)
         }
     }
 }
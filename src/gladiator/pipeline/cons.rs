@@ -11,8 +11,10 @@ use markup5ever::tendril::TendrilSink;
 use markup5ever::{Attribute, LocalName, QualName};
 use markup5ever_rcdom::{Handle, NodeData, RcDom};
 use smallvec::SmallVec;
+use std::borrow::Cow;
 use std::rc::Rc;
 use std::sync::Weak as WeakArc;
+use time::Duration;
 use unicode_segmentation::UnicodeSegmentation;
 
 #[derive(Debug, thiserror::Error, EchoBusinessError)]
@@ -155,6 +157,84 @@ impl GladiatorPipelineCons for IncomingEchoResExtractorCons {
     }
 }
 
+/// A mention of `@username` found in the text of an incoming echo
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct IncomingEchoMention {
+    pub username: String,
+    /// Permission of the element the mention sits in, `None` if it is outside of any element,
+    /// i.e. everyone who can see the echo can see the mention
+    pub permission_id: Option<i64>,
+}
+
+/// Collects `@username` mentions from text nodes together with the permission guarding them
+/// ## Interior mutability (Safety)
+/// I'm just an extractor
+pub struct IncomingEchoMentionExtractorCons {
+    mentions: Vec<IncomingEchoMention>,
+}
+
+impl IncomingEchoMentionExtractorCons {
+    pub fn new() -> Self {
+        Self {
+            mentions: Vec::new(),
+        }
+    }
+
+    pub fn mentions_ref(&self) -> &[IncomingEchoMention] {
+        &self.mentions
+    }
+
+    pub fn mentions_take(&mut self) -> Vec<IncomingEchoMention> {
+        std::mem::take(&mut self.mentions)
+    }
+
+    #[inline]
+    fn is_mention_char(c: char) -> bool {
+        c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+    }
+
+    /// Usernames following an `@` which does not sit inside a word (e.g. an email address)
+    fn mention_names(text: &str) -> impl Iterator<Item = &str> {
+        text.match_indices('@').filter_map(move |(at, _)| {
+            let at_boundary = text[..at]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !Self::is_mention_char(c));
+            let rest = &text[at + 1..];
+            let end = rest
+                .find(|c| !Self::is_mention_char(c))
+                .unwrap_or(rest.len());
+            // a trailing dot ends the sentence rather than the name
+            let name = rest[..end].trim_end_matches('.');
+            (at_boundary && !name.is_empty()).then_some(name)
+        })
+    }
+}
+
+impl GladiatorPipelineCons for IncomingEchoMentionExtractorCons {
+    fn process(&mut self, _: &GladiatorElement<'_>, _: usize) {
+        // mentions only live in text nodes
+    }
+
+    fn process_text(&mut self, text: &str, echo_pm: Option<&str>) {
+        let permission_id = match echo_pm.map(str::parse::<i64>) {
+            None => None,
+            Some(Ok(id)) => Some(id),
+            // the checker refuses such an echo anyway
+            Some(Err(_)) => return,
+        };
+        for username in Self::mention_names(text) {
+            let mention = IncomingEchoMention {
+                username: username.to_string(),
+                permission_id,
+            };
+            if !self.mentions.contains(&mention) {
+                self.mentions.push(mention);
+            }
+        }
+    }
+}
+
 /// Used to filter out DOM trees that don't meet requirements
 /// ## Behavior and Processing:
 /// - Base elements must satisfy permission requirements; those failing will have their inner layers
@@ -217,6 +297,48 @@ impl GladiatorPipelineCons for OutGoingEchoFilterCons {
 
 pub struct OutGoingEchoSSRConsCtx {
     pub user_id: i64,
+    /// Base url of the signed resource links
+    pub res_base_url: Cow<'static, str>,
+    /// How long a signed resource link stays valid
+    pub res_sign_exp: Duration,
+    /// Whether the links are signed for the public route rather than for the viewer's session
+    pub res_sign_public: bool,
+}
+
+impl OutGoingEchoSSRConsCtx {
+    pub fn new(user_id: i64) -> Self {
+        Self {
+            user_id,
+            res_base_url: Cow::Borrowed("/api/v1/resource"),
+            res_sign_exp: Duration::minutes(10),
+            res_sign_public: false,
+        }
+    }
+
+    /// Context for echos opened through a share link: the viewer has no session,
+    /// so the links go to the public signed resource route of this site.
+    pub fn shared(user_id: i64) -> Self {
+        Self {
+            user_id,
+            res_base_url: Cow::Borrowed("/api/v1/resource/signed"),
+            res_sign_exp: Duration::minutes(10),
+            res_sign_public: true,
+        }
+    }
+
+    /// Context for echos leaving the site (feeds, federation): absolute links to the public
+    /// signed resource route, signed long enough for remote clients which fetch lazily.
+    pub fn public(user_id: i64, site_url: &str) -> Self {
+        Self {
+            user_id,
+            res_base_url: Cow::Owned(format!(
+                "{}/api/v1/resource/signed",
+                site_url.trim_end_matches('/')
+            )),
+            res_sign_exp: Duration::days(30),
+            res_sign_public: true,
+        }
+    }
 }
 
 /// Perform SSR rendering on the `ext` portion of the output echo.
@@ -230,9 +352,13 @@ pub struct OutGoingEchoSSRCons {
 
 impl OutGoingEchoSSRCons {
     pub fn new(state: WeakArc<EchoState>, user_id: i64) -> Self {
+        Self::new_with_ctx(state, OutGoingEchoSSRConsCtx::new(user_id))
+    }
+
+    pub fn new_with_ctx(state: WeakArc<EchoState>, ctx: OutGoingEchoSSRConsCtx) -> Self {
         Self {
             state,
-            ctx: OutGoingEchoSSRConsCtx { user_id },
+            ctx,
             error: None,
         }
     }
@@ -241,7 +367,7 @@ impl OutGoingEchoSSRCons {
     pub fn new_with_dummy_state(user_id: i64) -> Self {
         Self {
             state: WeakArc::new(),
-            ctx: OutGoingEchoSSRConsCtx { user_id },
+            ctx: OutGoingEchoSSRConsCtx::new(user_id),
             error: None,
         }
     }
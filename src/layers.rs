@@ -22,7 +22,7 @@ macro_rules! echo_layer_builder {
                 ))
         }
     };
-    ($state:expr,b,m $(,)?) => {
+    ($state:expr,b,m, $policy:expr $(,)?) => {
         || {
             tower::ServiceBuilder::new()
                 .layer($crate::layers::session::SessionLayer::new($state.clone()))
@@ -30,9 +30,21 @@ macro_rules! echo_layer_builder {
                 .layer(axum::middleware::from_fn(
                     $crate::layers::auth::basic_auth_checker,
                 ))
-                .layer(axum::middleware::from_fn(
+                .layer(axum::middleware::from_fn_with_state(
+                    $policy.clone(),
                     $crate::layers::auth::mfa_auth_checker,
                 ))
         }
     };
+    ($state:expr,b,m, $policy:expr, $scope:expr, $tokens:expr $(,)?) => {
+        || {
+            tower::ServiceBuilder::new()
+                .layer($crate::layers::session::SessionLayer::new($state.clone()))
+                .layer($crate::layers::client_info::ClientInfoLayer::new())
+                .layer(axum::middleware::from_fn_with_state(
+                    ($policy.clone(), $tokens.clone(), $scope),
+                    $crate::layers::auth::scoped_auth_checker,
+                ))
+        }
+    };
 }
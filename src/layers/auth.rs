@@ -1,10 +1,17 @@
 use crate::layers::session::{SessionError, SessionHelper};
 use crate::models::api::prelude::*;
 use crate::models::session::BasicAuthData;
-use axum::extract::{FromRequestParts, Request as AxumExtractRequest};
+use crate::models::token::TokenScope;
+use crate::services::access_token::AccessTokenService;
+use crate::services::mfa_policy::{MfaCompliance, MfaPolicyService};
+use axum::extract::{FromRequestParts, Request as AxumExtractRequest, State};
+use axum::http::HeaderMap;
+use axum::http::header::AUTHORIZATION;
 use axum::http::request::Parts;
 use axum::middleware::Next;
 use axum::response::IntoResponse;
+use std::sync::Arc;
+use uuid::Uuid;
 
 impl From<SessionError> for ApiError {
     fn from(e: SessionError) -> Self {
@@ -12,7 +19,11 @@ impl From<SessionError> for ApiError {
             SessionError::MissingCookie(inner) => unauthorized!(err = inner),
             SessionError::MissingSessionId
             | SessionError::SessionExpired
-            | SessionError::InvalidSessionId(_) => unauthorized!(err = e),
+            | SessionError::InvalidSessionId(_)
+            | SessionError::SessionRevoked => unauthorized!(err = e),
+            SessionError::MfaEnrollmentRequired | SessionError::StepUpRequired => {
+                forbidden!(err = e)
+            }
             _ => internal!(err = e),
         }
     }
@@ -43,16 +54,96 @@ pub async fn basic_auth_checker(
     if let (_, Err(e)) = (request.method(), session.extract_csrf_auth()) {
         return Err(e.into());
     }
-    let auth = session.extract_basic_auth()?;
+    let auth = session.extract_basic_auth().await?;
     request.extensions_mut().insert(auth.inner);
     Ok(next.run(request).await)
 }
 
 pub async fn mfa_auth_checker(
+    State(policy): State<Arc<MfaPolicyService>>,
     session: SessionHelper,
     request: AxumExtractRequest,
     next: Next,
 ) -> ApiResult<impl IntoResponse> {
-    session.extract_mfa_auth()?;
+    let user_id = request
+        .extensions()
+        .get::<BasicAuthData>()
+        .map(|auth| auth.user_id)
+        .ok_or_else(|| {
+            internal!("Cannot extract authed user info. Is `basic_auth_checker` enabled?")
+        })?;
+    check_mfa_or_policy(&policy, &session, user_id).await?;
+    Ok(next.run(request).await)
+}
+
+/// Users the MFA policy does not hold to, or who are still within its grace period,
+/// pass without the MFA cookie
+async fn check_mfa_or_policy(
+    policy: &MfaPolicyService,
+    session: &SessionHelper,
+    user_id: i64,
+) -> ApiResult<()> {
+    let Err(e) = session.extract_mfa_auth() else {
+        return Ok(());
+    };
+    match check_policy(policy, user_id).await? {
+        MfaCompliance::NotRequired | MfaCompliance::Grace(_) => Ok(()),
+        MfaCompliance::Enrolled => Err(e.into()),
+        MfaCompliance::Overdue => Err(SessionError::MfaEnrollmentRequired.into()),
+    }
+}
+
+async fn check_policy(policy: &MfaPolicyService, user_id: i64) -> ApiResult<MfaCompliance> {
+    policy
+        .check(user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to check MFA policy"))
+}
+
+/// Personal access token of the `Authorization` header, if any
+pub(crate) fn bearer_token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_owned())
+}
+
+/// Full MFA through cookies, or a personal access token holding `scope`. Token requests
+/// need neither CSRF nor MFA, since only fully authenticated sessions can create tokens,
+/// but their owner is still held to the MFA policy.
+pub async fn scoped_auth_checker(
+    State((policy, tokens, scope)): State<(
+        Arc<MfaPolicyService>,
+        Arc<AccessTokenService>,
+        TokenScope,
+    )>,
+    session: SessionHelper,
+    mut request: AxumExtractRequest,
+    next: Next,
+) -> ApiResult<impl IntoResponse> {
+    let bearer = bearer_token(request.headers());
+    let Some(bearer) = bearer else {
+        session.extract_csrf_auth()?;
+        let auth = session.extract_basic_auth().await?;
+        check_mfa_or_policy(&policy, &session, auth.inner.user_id).await?;
+        request.extensions_mut().insert(auth.inner);
+        return Ok(next.run(request).await);
+    };
+    let token = tokens
+        .authenticate(&bearer)
+        .await
+        .map_err(|e| internal!(e, "Failed to check access token"))?
+        .ok_or_else(|| unauthorized!("Invalid or expired access token"))?;
+    if !token.scopes.contains(&scope) {
+        return Err(forbidden!("The access token lacks the required scope"));
+    }
+    if check_policy(&policy, token.user_id).await? == MfaCompliance::Overdue {
+        return Err(SessionError::MfaEnrollmentRequired.into());
+    }
+    // tokens are not sessions, they can never be listed or revoked as one
+    request
+        .extensions_mut()
+        .insert(BasicAuthData::new(token.user_id, Uuid::nil()));
     Ok(next.run(request).await)
 }
@@ -9,6 +9,8 @@ use tower::{Layer, Service};
 pub struct ClientInfo {
     pub user_agent: Option<String>,
     pub ip_address: Option<String>,
+    /// Set by `SetRequestIdLayer` before this layer runs
+    pub request_id: Option<String>,
 }
 
 impl ClientInfo {
@@ -22,9 +24,14 @@ impl ClientInfo {
             .or_else(|| headers.get("x-forwarded-for"))
             .and_then(|v| v.to_str().ok())
             .map(|s| s.split(',').next().unwrap_or(s).trim().to_string());
+        let request_id = headers
+            .get("x-hananokioku")
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_string());
         Self {
             user_agent,
             ip_address,
+            request_id,
         }
     }
 }
@@ -1,9 +1,14 @@
 use crate::models::api::prelude::*;
+use crate::models::const_val::ECHO_USER_SESSION_CACHE_TTL;
 use crate::models::session::{
     BaseSession, BasicAuthData, BasicAuthSessionData, CsrfAuthData, CsrfAuthSessionData,
-    MfaAuthData, MfaAuthSessionData, PreMfaAuthData, PreMfaAuthSessionData,
+    MfaAuthData, MfaAuthSessionData, OidcFlowData, OidcFlowSessionData, PreMfaAuthData,
+    PreMfaAuthSessionData,
 };
 use crate::services::states::EchoState;
+use crate::services::states::auth::{AuthKeySet, AuthKeys};
+use crate::services::states::cache::MokaExpiration;
+use crate::services::states::db::{DataBaseError, EchoDatabaseExecutor};
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
 use axum::http::{Request, Response};
@@ -17,9 +22,40 @@ use tower::{Layer, Service};
 use tower_cookies::{CookieManager, CookieManagerLayer, Cookies, PrivateCookies};
 use uuid::Uuid;
 
+/// Whether `session_uuid` is a live entry of the session registry owned by `user_id`
+pub(crate) async fn check_user_session(
+    state: &EchoState,
+    user_id: i64,
+    session_uuid: Uuid,
+) -> SessionResult<()> {
+    let cache = &state.cache;
+    if let Some((_, owner)) = cache.get_user_session(session_uuid).await
+        && owner == user_id
+    {
+        return Ok(());
+    }
+    let now = time::OffsetDateTime::now_utc();
+    let owner = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.user_session().touch_session(session_uuid, now).await
+        })
+        .await?;
+    match owner {
+        Some(owner) if owner == user_id => {
+            let exp = MokaExpiration::new(ECHO_USER_SESSION_CACHE_TTL);
+            cache.set_user_session(session_uuid, (exp, owner)).await;
+            Ok(())
+        }
+        _ => Err(SessionError::SessionRevoked),
+    }
+}
+
 #[derive(Clone)]
 pub struct SessionHelper {
     state: Arc<EchoState>,
+    /// Snapshot taken per request, so a rotation never splits one request across keys
+    keys: Arc<AuthKeys>,
     cookies: Cookies,
 }
 
@@ -40,6 +76,16 @@ pub enum SessionError {
     #[error("Invalid Session ID: {0}, please log in again")]
     #[code(13000)]
     InvalidSessionId(Uuid),
+    #[error("Your session has been revoked, please log in again")]
+    #[code(13100)]
+    SessionRevoked,
+    // Policy
+    #[error("You must set up MFA to continue")]
+    #[code(14000)]
+    MfaEnrollmentRequired,
+    #[error("Please verify MFA again to continue")]
+    #[code(14100)]
+    StepUpRequired,
     // misc
     #[error(transparent)]
     Base64Decode(#[from] base64::DecodeError),
@@ -49,37 +95,48 @@ pub enum SessionError {
     MessagePackEncode(#[from] rmp_serde::encode::Error),
     #[error(transparent)]
     MessagePackDecode(#[from] rmp_serde::decode::Error),
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
 }
 
 pub type SessionResult<T> = Result<T, SessionError>;
 
 impl SessionHelper {
     pub fn new(state: Arc<EchoState>, cookies: Cookies) -> Self {
-        Self { state, cookies }
+        Self {
+            keys: state.auth.keys(),
+            state,
+            cookies,
+        }
     }
 
     fn session_id(&self) -> &Uuid {
-        self.state.auth.get_session_id()
+        self.keys.get_session_id()
     }
 
-    fn basic_auth_jar(&self) -> PrivateCookies<'_> {
-        self.cookies.private(self.state.auth.get_basic_auth_key())
+    fn basic_auth_jar<'a>(&'a self, keys: &'a AuthKeySet) -> PrivateCookies<'a> {
+        self.cookies.private(keys.get_basic_auth_key())
     }
 
-    fn csrf_auth_jar(&self) -> PrivateCookies<'_> {
-        self.cookies.private(self.state.auth.get_csrf_auth_key())
+    fn csrf_auth_jar<'a>(&'a self, keys: &'a AuthKeySet) -> PrivateCookies<'a> {
+        self.cookies.private(keys.get_csrf_auth_key())
     }
 
-    fn pre_mfa_auth_jar(&self) -> PrivateCookies<'_> {
-        self.cookies.private(self.state.auth.get_pre_mfa_auth_key())
+    fn pre_mfa_auth_jar<'a>(&'a self, keys: &'a AuthKeySet) -> PrivateCookies<'a> {
+        self.cookies.private(keys.get_pre_mfa_auth_key())
     }
 
-    fn mfa_auth_jar(&self) -> PrivateCookies<'_> {
-        self.cookies.private(self.state.auth.get_mfa_auth_key())
+    fn mfa_auth_jar<'a>(&'a self, keys: &'a AuthKeySet) -> PrivateCookies<'a> {
+        self.cookies.private(keys.get_mfa_auth_key())
     }
 
-    pub fn sign_basic_and_csrf_auth(&self, user_id: i64) -> SessionResult<()> {
-        self.sign_basic_auth(BasicAuthData::new(user_id))?;
+    fn oidc_flow_jar<'a>(&'a self, keys: &'a AuthKeySet) -> PrivateCookies<'a> {
+        self.cookies.private(keys.get_oidc_flow_key())
+    }
+
+    /// `session_uuid` must have been added to the session registry already
+    pub fn sign_basic_and_csrf_auth(&self, user_id: i64, session_uuid: Uuid) -> SessionResult<()> {
+        self.sign_basic_auth(BasicAuthData::new(user_id, session_uuid))?;
         self.sign_csrf_auth(())?;
         Ok(())
     }
@@ -91,6 +148,63 @@ impl SessionHelper {
     pub fn sign_mfa(&self) -> SessionResult<()> {
         self.sign_mfa_auth(())
     }
+
+    pub fn start_oidc_flow(&self, flow: OidcFlowData) -> SessionResult<()> {
+        self.sign_oidc_flow(flow)
+    }
+
+    /// The pending OpenID Connect login, it can only be finished once
+    pub fn take_oidc_flow(&self) -> SessionResult<OidcFlowSessionData> {
+        use crate::models::const_val::ECHO_OIDC_FLOW;
+        let sess = self.open_oidc_flow();
+        self.oidc_flow_jar(self.keys.current())
+            .remove(Cookie::build(ECHO_OIDC_FLOW).path("/").build());
+        sess
+    }
+
+    /// Unlike the other cookies, a basic auth cookie is only as good as its entry in the
+    /// session registry, so that a single device can be logged out
+    pub(crate) async fn extract_basic_auth(&self) -> SessionResult<BasicAuthSessionData> {
+        let sess = self.open_basic_auth()?;
+        check_user_session(&self.state, sess.inner.user_id, sess.inner.session_uuid).await?;
+        Ok(sess)
+    }
+
+    pub(crate) fn extract_csrf_auth(&self) -> SessionResult<CsrfAuthSessionData> {
+        self.open_csrf_auth()
+    }
+
+    pub(crate) fn extract_pre_mfa_auth(&self) -> SessionResult<PreMfaAuthSessionData> {
+        self.open_pre_mfa_auth()
+    }
+
+    pub(crate) fn extract_mfa_auth(&self) -> SessionResult<MfaAuthSessionData> {
+        self.open_mfa_auth()
+    }
+
+    /// For sensitive operations, which need an MFA verification within the last
+    /// `mfa.step_up_minutes` rather than within the lifetime of the MFA cookie. Users
+    /// without MFA, which the policy let through, have nothing to step up with.
+    pub(crate) async fn require_fresh_mfa(&self, user_id: i64) -> SessionResult<()> {
+        let window = time::Duration::minutes(self.state.config.mfa.step_up_minutes as i64);
+        if let Ok(sess) = self.open_mfa_auth() {
+            if sess.create_at.add(window) <= time::OffsetDateTime::now_utc() {
+                return Err(SessionError::StepUpRequired);
+            }
+            return Ok(());
+        }
+        let mfa_enabled = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.mfa().mfa_enabled(user_id).await
+            })
+            .await?;
+        if mfa_enabled {
+            return Err(SessionError::StepUpRequired);
+        }
+        Ok(())
+    }
 }
 
 macro_rules! auth_session {
@@ -117,7 +231,7 @@ macro_rules! auth_session {
                         let sess: [<$base_name:camel SessionData>] = BaseSession::new(*self.session_id(), inner);
                         let bytes = rmp_serde::to_vec(&sess)?;
                         let enc = b64_general_engine::URL_SAFE.encode(bytes);
-                        self.[<$base_name _jar>]().add(
+                        self.[<$base_name _jar>](self.keys.current()).add(
                             Cookie::build(([<ECHO_ $base_name:snake:upper>], enc))
                                 .path("/")
                                 .secure(cfg!(feature = "secure-cookie"))
@@ -128,12 +242,14 @@ macro_rules! auth_session {
                         );
                         Ok(())
                     }
-                    pub(crate) fn [<extract_ $base_name>](&self) -> SessionResult<[<$base_name:camel SessionData>]> {
+                    fn [<open_ $base_name>](&self) -> SessionResult<[<$base_name:camel SessionData>]> {
                         use crate::models::const_val::[<ECHO_ $base_name:snake:upper>];
                         use crate::models::const_val::[<ECHO_ $base_name:snake:upper _EXPIRE>];
+                        // cookies signed by a retired key stay valid during its grace period
                         let cookie = self
-                            .[<$base_name _jar>]()
-                            .get([<ECHO_ $base_name:snake:upper>])
+                            .keys
+                            .accepted()
+                            .find_map(|keys| self.[<$base_name _jar>](keys).get([<ECHO_ $base_name:snake:upper>]))
                             .ok_or(MissingCookieError::[<$base_name:camel>])?;
                         let raw = b64_general_engine::URL_SAFE
                             .decode(cookie.value())
@@ -158,6 +274,8 @@ auth_session!(
     csrf_auth => (data = CsrfAuthData, same_site = SameSite::Strict, biz_code = 11200),
     pre_mfa_auth => (data = PreMfaAuthData, same_site = SameSite::Lax, biz_code = 11300),
     mfa_auth => (data = MfaAuthData, same_site = SameSite::Lax, biz_code = 11400),
+    // sent along with the top level redirect back from the provider
+    oidc_flow => (data = OidcFlowData, same_site = SameSite::Lax, biz_code = 11500),
 );
 
 impl<S> FromRequestParts<S> for SessionHelper
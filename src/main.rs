@@ -12,7 +12,10 @@ mod services;
 mod utils;
 
 use crate::errors::EchoError;
+use crate::models::echo_import::{ImportOptions, ImportSource};
 use crate::routers::router;
+use crate::services::echo_baker::EchoBaker;
+use crate::services::echo_import::EchoImportService;
 use crate::services::states::db::EchoDatabaseExecutor;
 use clap::Parser;
 use services::states::EchoState;
@@ -21,9 +24,11 @@ use services::states::cache::CacheState;
 use services::states::config::AppConfig;
 use services::states::db::DataBaseState;
 use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
+use tokio_util::sync::CancellationToken;
 
 #[cfg(all(target_os = "windows", feature = "alternative-allocator"))]
 #[global_allocator]
@@ -71,6 +76,85 @@ pub mod shadow {
 pub struct Cli {
     #[clap(short, long, help = "Path to config file", default_value = "echo.toml")]
     config: String,
+    #[clap(subcommand)]
+    command: Option<CliCommand>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum CliCommand {
+    /// Import echos from an Ech0 or Memos export, print the report as JSON and exit
+    Import(ImportArgs),
+    /// Manage the keys behind sessions and signed links, running servers pick changes up on restart
+    #[clap(subcommand)]
+    Keys(KeysCommand),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum KeysCommand {
+    /// Print the keys as JSON
+    List,
+    /// Sign with a fresh key, the previous one stays accepted for the grace period
+    Rotate,
+    /// Invalidate every session by bumping the session epoch
+    LogoutAll,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+    #[clap(value_enum)]
+    source: ImportSource,
+    #[clap(help = "JSON export or SQLite database")]
+    file: PathBuf,
+    #[clap(long = "user", value_parser = parse_user_mapping, help = "Map a source author to a local user id, e.g. `alice=2`")]
+    user_map: Vec<(String, i64)>,
+    #[clap(long, help = "Owner of the items whose author is not mapped")]
+    default_user_id: Option<i64>,
+    #[clap(
+        long,
+        help = "Permission gating imported images, images are skipped without it"
+    )]
+    image_permission_id: Option<i64>,
+    #[clap(
+        long,
+        help = "Permission for Memos `PROTECTED` memos, they become private without it"
+    )]
+    protected_permission_id: Option<i64>,
+    #[clap(
+        long,
+        help = "Base url of the source instance to download missing images from"
+    )]
+    source_base_url: Option<String>,
+    #[clap(
+        long,
+        help = "Data directory of the source instance holding local images"
+    )]
+    assets_dir: Option<PathBuf>,
+    #[clap(long, help = "Only report what would be imported")]
+    dry_run: bool,
+}
+
+fn parse_user_mapping(raw: &str) -> Result<(String, i64), String> {
+    let (author, user_id) = raw
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected `author=user_id`, got `{raw}`"))?;
+    let user_id = user_id
+        .parse()
+        .map_err(|e| format!("invalid user id: {e}"))?;
+    Ok((author.to_string(), user_id))
+}
+
+impl From<ImportArgs> for ImportOptions {
+    fn from(args: ImportArgs) -> Self {
+        Self {
+            user_map: args.user_map.into_iter().collect(),
+            default_user_id: args.default_user_id,
+            image_permission_id: args.image_permission_id,
+            protected_permission_id: args.protected_permission_id,
+            source_base_url: args.source_base_url,
+            assets_dir: args.assets_dir,
+            dry_run: args.dry_run,
+        }
+    }
 }
 
 #[tokio::main]
@@ -145,22 +229,52 @@ async fn main() -> anyhow::Result<()> {
     })
     .await?;
     let cache = CacheState::new();
-    let auth = AuthState::new();
+    let auth = AuthState::load(&config, &db).await?;
     let addr = format!("{}:{}", config.common.host, config.common.port);
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
-    tracing::info!(
-        "Starting server at {}:{}",
-        config.common.host,
-        config.common.port
-    );
     let echo_state = Arc::new(EchoState {
         db,
         cache,
         auth,
         config,
+        shutdown: CancellationToken::new(),
     });
+    match cli.command {
+        Some(CliCommand::Import(args)) => {
+            let baker = Arc::new(EchoBaker::new(echo_state.config.perf.echo_cache_capacity));
+            let importer = EchoImportService::new(echo_state.clone(), baker)?;
+            let (source, file) = (args.source, args.file.clone());
+            let report = importer.import_file(source, &file, &args.into()).await?;
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            echo_state.db.close_conn().await;
+            return Ok(());
+        }
+        Some(CliCommand::Keys(cmd)) => {
+            let (auth, db) = (&echo_state.auth, &echo_state.db);
+            match cmd {
+                KeysCommand::List => {}
+                KeysCommand::Rotate => {
+                    auth.rotate(db).await?;
+                }
+                KeysCommand::LogoutAll => auth.logout_everyone(db).await?,
+            }
+            println!("{}", serde_json::to_string_pretty(&auth.key_infos().await)?);
+            if !matches!(cmd, KeysCommand::List) {
+                tracing::warn!("Restart running servers to apply the change");
+            }
+            echo_state.db.close_conn().await;
+            return Ok(());
+        }
+        None => {}
+    }
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    tracing::info!(
+        "Starting server at {}:{}",
+        echo_state.config.common.host,
+        echo_state.config.common.port
+    );
+    let shutdown = echo_state.shutdown.clone();
     axum::serve(listener, router(echo_state.clone()).await)
-        .with_graceful_shutdown(async {
+        .with_graceful_shutdown(async move {
             #[cfg(unix)]
             {
                 use tokio::signal::unix::{SignalKind, signal};
@@ -183,6 +297,7 @@ async fn main() -> anyhow::Result<()> {
                 futures::future::pending::<()>().await;
             }
             tracing::warn!("Received shutdown signal, shutting down gracefully...");
+            shutdown.cancel();
         })
         .await?;
     tracing::info!("Trying to close database connections...");
@@ -1,16 +1,27 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+pub mod activity_pub;
 pub mod api;
+pub mod audit;
+pub mod auth_key;
 mod build_info;
 pub mod const_val;
 pub mod dyn_setting;
 pub mod echo;
+pub mod echo_import;
+pub mod echo_share;
+pub mod follow;
 pub mod invite_code;
 pub mod mfa;
+pub mod notification;
+pub mod oidc;
 pub mod permission;
+pub mod relation;
 pub mod resource;
 pub mod session;
+pub mod takeout;
+pub mod timeline;
 pub mod token;
 pub mod users;
 
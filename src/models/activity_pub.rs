@@ -0,0 +1,111 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::FromRow;
+use time::OffsetDateTime;
+
+pub const AP_CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+pub const AP_SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+pub const AP_PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+pub const AP_CONTENT_TYPE: &str = "application/activity+json";
+
+#[derive(Debug, FromRow)]
+pub struct ApActorKeyRow {
+    pub user_id: i64,
+    pub private_key_pem: String,
+    pub public_key_pem: String,
+}
+
+#[derive(Debug, FromRow)]
+pub struct ApDeliveryRow {
+    pub id: i64,
+    pub user_id: i64,
+    pub inbox_url: String,
+    pub payload: String,
+    pub attempts: i64,
+    pub next_attempt_at: OffsetDateTime,
+}
+
+#[derive(Debug)]
+pub struct NewApFollower<'a> {
+    pub user_id: i64,
+    pub actor_url: &'a str,
+    pub inbox_url: &'a str,
+    pub shared_inbox_url: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApEchoActivity {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebFingerQuery {
+    pub resource: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebFingerLink {
+    pub rel: &'static str,
+    #[serde(rename = "type")]
+    pub typ: &'static str,
+    pub href: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WebFingerRes {
+    pub subject: String,
+    pub aliases: Vec<String>,
+    pub links: Vec<WebFingerLink>,
+}
+
+/// The subset of a remote actor document we need
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteActor {
+    pub id: String,
+    pub inbox: String,
+    #[serde(default)]
+    pub endpoints: Option<RemoteActorEndpoints>,
+    pub public_key: RemoteActorPublicKey,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteActorEndpoints {
+    pub shared_inbox: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemoteActorPublicKey {
+    pub id: String,
+    pub owner: String,
+    pub public_key_pem: String,
+}
+
+/// Incoming activity, only the fields the inbox dispatches on are typed
+#[derive(Debug, Deserialize)]
+pub struct IncomingActivity {
+    pub id: Option<String>,
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub actor: String,
+    pub object: Value,
+}
+
+impl IncomingActivity {
+    /// `object` may either be embedded or referenced by its id
+    pub fn object_id(&self) -> Option<&str> {
+        match &self.object {
+            Value::String(s) => Some(s),
+            Value::Object(o) => o.get("id").and_then(Value::as_str),
+            _ => None,
+        }
+    }
+
+    pub fn object_type(&self) -> Option<&str> {
+        self.object.get("type").and_then(Value::as_str)
+    }
+}
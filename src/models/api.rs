@@ -129,7 +129,14 @@ macro_rules! define_api_error {
 
 define_api_error!(bad_request, StatusCode::BAD_REQUEST, "Bad Request");
 define_api_error!(unauthorized, StatusCode::UNAUTHORIZED, "Unauthorized");
+define_api_error!(forbidden, StatusCode::FORBIDDEN, "Forbidden");
 define_api_error!(conflict, StatusCode::CONFLICT, "Conflict");
+define_api_error!(not_found, StatusCode::NOT_FOUND, "Not Found");
+define_api_error!(
+    too_many_requests,
+    StatusCode::TOO_MANY_REQUESTS,
+    "Too Many Requests"
+);
 define_api_error!(
     internal,
     StatusCode::INTERNAL_SERVER_ERROR,
@@ -158,5 +165,7 @@ impl IntoResponse for ApiError {
 pub mod prelude {
     pub use super::{ApiError, ApiResult, GeneralResponse};
     pub(crate) use crate::models::api::general_json_res;
-    pub(crate) use crate::models::api::{bad_request, conflict, internal, unauthorized};
+    pub(crate) use crate::models::api::{
+        bad_request, conflict, forbidden, internal, not_found, too_many_requests, unauthorized,
+    };
 }
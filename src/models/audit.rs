@@ -0,0 +1,108 @@
+use crate::layers::client_info::ClientInfo;
+use crate::services::states::db::PageQueryCursor;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::Json;
+use time::OffsetDateTime;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[repr(u8)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login = 1,
+    LoginFailed = 2,
+    UserDelete = 3,
+    RoleChange = 4,
+    PermissionGrant = 5,
+    PermissionRevoke = 6,
+    InviteCodeCreate = 7,
+    InviteCodeRevoke = 8,
+    DynSettingChange = 9,
+    ResourceDelete = 10,
+    AuthKeyRotate = 11,
+    LogoutEveryone = 12,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct AuditLog {
+    pub id: i64,
+    pub actor_id: Option<i64>,
+    pub action: AuditAction,
+    pub target_user_id: Option<i64>,
+    pub target: Option<String>,
+    pub before_summary: Option<Json<serde_json::Value>>,
+    pub after_summary: Option<Json<serde_json::Value>>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// The `x-hananokioku` header of the request, to find it in the server logs
+    pub request_id: Option<String>,
+    #[serde(with = "time::serde::timestamp")]
+    pub time: OffsetDateTime,
+}
+
+impl PageQueryCursor for AuditLog {
+    fn cursor_field(&self) -> i64 {
+        self.id
+    }
+}
+
+#[derive(Debug)]
+pub struct NewAuditLog {
+    pub actor_id: Option<i64>,
+    pub action: AuditAction,
+    pub target_user_id: Option<i64>,
+    pub target: Option<String>,
+    pub before_summary: Option<serde_json::Value>,
+    pub after_summary: Option<serde_json::Value>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: Option<String>,
+}
+
+impl NewAuditLog {
+    pub fn new(action: AuditAction, actor_id: Option<i64>, client_info: &ClientInfo) -> Self {
+        Self {
+            actor_id,
+            action,
+            target_user_id: None,
+            target: None,
+            before_summary: None,
+            after_summary: None,
+            ip_address: client_info.ip_address.clone(),
+            user_agent: client_info.user_agent.clone(),
+            request_id: client_info.request_id.clone(),
+        }
+    }
+
+    pub fn target_user(mut self, user_id: i64) -> Self {
+        self.target_user_id = Some(user_id);
+        self
+    }
+
+    pub fn target(mut self, target: impl Into<String>) -> Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    pub fn before(mut self, summary: serde_json::Value) -> Self {
+        self.before_summary = Some(summary);
+        self
+    }
+
+    pub fn after(mut self, summary: serde_json::Value) -> Self {
+        self.after_summary = Some(summary);
+        self
+    }
+}
+
+/// Every given condition has to hold
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditLogFilter {
+    pub actor_id: Option<i64>,
+    pub target_user_id: Option<i64>,
+    pub action: Option<AuditAction>,
+    #[serde(default, with = "time::serde::timestamp::option")]
+    pub since: Option<OffsetDateTime>,
+    #[serde(default, with = "time::serde::timestamp::option")]
+    pub until: Option<OffsetDateTime>,
+}
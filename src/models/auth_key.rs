@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Persisted form of the keys behind sessions and signed links
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthKeyRing {
+    /// Embedded in every session, replacing it logs everyone out
+    pub session_id: Uuid,
+    /// Oldest first, the last one is the current key
+    pub keys: Vec<AuthKeyRecord>,
+}
+
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthKeyRecord {
+    pub kid: u32,
+    /// Every purpose (cookies, resource signs) derives its own key from it
+    #[serde_as(as = "Base64")]
+    pub material: Vec<u8>,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+    /// Set once a newer key takes over, the key is still accepted for the grace period
+    #[serde(with = "time::serde::timestamp::option")]
+    pub retired_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthKeyInfo {
+    pub kid: u32,
+    pub current: bool,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub retired_at: Option<OffsetDateTime>,
+    /// Only present on retired keys
+    #[serde(with = "time::serde::timestamp::option")]
+    pub accepted_until: Option<OffsetDateTime>,
+}
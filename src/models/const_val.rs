@@ -6,3 +6,10 @@ pub const ECHO_BASIC_AUTH_EXPIRE: time::Duration = time::Duration::hours(1);
 pub const ECHO_CSRF_AUTH_EXPIRE: time::Duration = ECHO_BASIC_AUTH_EXPIRE;
 pub const ECHO_PRE_MFA_AUTH_EXPIRE: time::Duration = ECHO_BASIC_AUTH_EXPIRE;
 pub const ECHO_MFA_AUTH_EXPIRE: time::Duration = time::Duration::minutes(10);
+pub const ECHO_OIDC_FLOW: &str = "echo_oidc";
+pub const ECHO_OIDC_FLOW_EXPIRE: time::Duration = time::Duration::minutes(10);
+/// How long a session checked against the registry is trusted without asking again,
+/// which also bounds how stale `last_seen_at` gets
+pub const ECHO_USER_SESSION_CACHE_TTL: time::Duration = time::Duration::minutes(1);
+/// Same as [`ECHO_USER_SESSION_CACHE_TTL`] for personal access tokens and their `last_used_at`
+pub const ECHO_ACCESS_TOKEN_CACHE_TTL: time::Duration = ECHO_USER_SESSION_CACHE_TTL;
@@ -170,6 +170,66 @@ define_dyn_settings! {
             default_val: true,
             desc: "Whether registration requires invitation"
         },
+        AllowGuest => {
+            typ: bool,
+            default_val: false,
+            desc: "Whether unauthenticated visitors may browse public echos as guests",
+            side_effects: "Resource links handed out to guests are signed and stay valid for a while after disabling!"
+        },
+        UtcOffsetMinutes => {
+            typ: i16,
+            default_val: 0,
+            desc: "Default UTC offset in minutes used by calendar based echo queries, e.g. 480 for UTC+8"
+        },
+        SiteUrl => {
+            typ: String,
+            default_val: "http://localhost:8080".to_string(),
+            desc: "Public base url of this site, used to build absolute links (e.g. in feeds)"
+        },
+        EnableFeeds => {
+            typ: bool,
+            default_val: false,
+            desc: "Whether the RSS and Atom feeds of public echos are served",
+            side_effects: "Resource links embedded in feeds are signed for a long time and stay valid after disabling!"
+        },
+    },
+    Audit => {
+        RetentionDays => {
+            typ: u32,
+            default_val: 365,
+            desc: "Days audit log entries are kept for, 0 keeps them forever"
+        },
+    },
+    Federation => {
+        EnableActivityPub => {
+            typ: bool,
+            default_val: false,
+            desc: "Whether public echos are federated via ActivityPub (WebFinger, actors, inbox and outbox)",
+            side_effects: "Actor urls are derived from `Site.SiteUrl`, changing it afterwards breaks existing followers!"
+        },
+    },
+    Mfa => {
+        RequireForAll => {
+            typ: bool,
+            default_val: true,
+            desc: "Whether every user must set up MFA",
+            side_effects: "Users without MFA can only reach the MFA setup once their grace period is over!"
+        },
+        RequireForAdmins => {
+            typ: bool,
+            default_val: true,
+            desc: "Whether administrators must set up MFA"
+        },
+        RequireForPermissions => {
+            typ: Vec<i64>,
+            default_val: Vec::new(),
+            desc: "Ids of the permissions whose holders must set up MFA"
+        },
+        EnrollmentGraceDays => {
+            typ: u32,
+            default_val: 0,
+            desc: "Days a user may go on without MFA after a policy started to require it"
+        },
     },
     WebAuthn => {
         RpId => {
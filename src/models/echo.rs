@@ -22,7 +22,26 @@ pub struct EchoFullViewRaw {
     pub permission_ids: Option<Json<Vec<i64>>>,
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct EchoCalendarDay {
+    /// Local calendar day in `YYYY-MM-DD` form
+    pub date: String,
+    pub count: i64,
+}
+
+#[derive(Debug, FromRow)]
+pub struct EchoRepostRow {
+    pub echo_id: i64,
+    pub original_echo_id: Option<i64>,
+}
+
+#[derive(Debug, FromRow)]
+pub struct EchoRepostCount {
+    pub echo_id: i64,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum EchoPermission {
     Public,
@@ -30,7 +49,7 @@ pub enum EchoPermission {
     Private,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Echo {
     pub id: i64,
     pub user_id: i64,
@@ -43,6 +62,29 @@ pub struct Echo {
     pub last_modified_at: OffsetDateTime,
 }
 
+/// What the viewer gets to see of the echo a repost points to
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum EchoRepostOrigin {
+    /// Rendered with the viewer's own permissions
+    Visible {
+        echo: Box<Echo>,
+    },
+    /// The viewer may not see the original, so nothing about it is revealed
+    Hidden,
+    Deleted,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EchoView {
+    #[serde(flatten)]
+    pub echo: Echo,
+    pub repost_count: i64,
+    /// Only present on reposts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repost_of: Option<EchoRepostOrigin>,
+}
+
 impl Echo {
     #[cfg(test)]
     pub fn dummy_from_str(content: &str) -> Self {
@@ -0,0 +1,74 @@
+use ahash::HashMap;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportSource {
+    /// [Ech0](https://github.com/lin-snow/Ech0) JSON export or its SQLite database
+    Ech0,
+    /// [Memos](https://github.com/usememos/memos) JSON export or its SQLite database
+    Memos,
+}
+
+impl ImportSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportSource::Ech0 => "ech0",
+            ImportSource::Memos => "memos",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ImportOptions {
+    /// Source author (username, or `users/{id}` for Memos JSON) => local user id,
+    /// unmapped authors fall back to a local user with the same username
+    #[serde(default)]
+    pub user_map: HashMap<String, i64>,
+    /// Owner of the items whose author can not be mapped, such items are skipped otherwise
+    pub default_user_id: Option<i64>,
+    /// Every imported image is gated by an `echo-pm` element, images are skipped without it
+    pub image_permission_id: Option<i64>,
+    /// Memos `PROTECTED` memos become public echos with this permission, or private ones without it
+    pub protected_permission_id: Option<i64>,
+    /// Base url of the source instance, used to download images which are not embedded in the export
+    pub source_base_url: Option<String>,
+    /// Directory holding the local files of the source instance (e.g. Ech0's `data` directory)
+    #[serde(skip)]
+    pub assets_dir: Option<PathBuf>,
+    /// Parse and map everything without writing
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportItemStatus {
+    Imported,
+    /// Only reported in dry runs
+    WouldImport,
+    AlreadyImported,
+    Skipped,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportReportItem {
+    pub source_id: String,
+    pub status: ImportItemStatus,
+    pub echo_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Parts of the item which could not be carried over, e.g. images
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub source: ImportSource,
+    pub total: usize,
+    pub imported: usize,
+    pub skipped: usize,
+    pub items: Vec<ImportReportItem>,
+}
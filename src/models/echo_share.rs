@@ -0,0 +1,35 @@
+use crate::services::states::db::PageQueryCursor;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use sqlx::types::Json;
+use time::OffsetDateTime;
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct EchoShareRow {
+    pub id: i64,
+    pub echo_id: i64,
+    pub owner_id: i64,
+    /// The echo is rendered with exactly these permissions, whoever opens the link
+    pub permission_ids: Json<Vec<i64>>,
+    pub one_time: bool,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub used_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::timestamp")]
+    pub expires_at: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+}
+
+impl PageQueryCursor for EchoShareRow {
+    fn cursor_field(&self) -> i64 {
+        self.id
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EchoShareItem {
+    #[serde(flatten)]
+    pub share: EchoShareRow,
+    /// The link itself, anyone holding it can open the echo
+    pub url: String,
+}
@@ -0,0 +1,22 @@
+use crate::services::states::db::PageQueryCursor;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use time::OffsetDateTime;
+
+/// The user on the other end of a follow, paged by the id of the follow itself
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct FollowItem {
+    pub id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub avatar_res_id: Option<i64>,
+    /// When the follow happened
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+}
+
+impl PageQueryCursor for FollowItem {
+    fn cursor_field(&self) -> i64 {
+        self.id
+    }
+}
@@ -1,3 +1,4 @@
+use crate::models::users::Role;
 use crate::services::states::db::PageQueryCursor;
 use ph::fmph;
 use serde::de::DeserializeOwned;
@@ -14,34 +15,82 @@ pub enum MFAOpType {
     Add = 1,
     Delete = 2,
     Auth = 3,
+    /// Too many failed attempts, further ones are refused for a while
+    Lockout = 4,
 }
 
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[repr(u8)]
 #[serde(rename_all = "lowercase")]
 pub enum MFAAuthMethod {
     Totp = 1,
     Webauthn = 2,
+    /// Single-use codes for when the other methods are lost
+    RecoveryCode = 3,
 }
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct MfaSettings {
     pub user_id: i64,
     pub mfa_enabled: bool,
+    pub passkey_login: bool,
     #[serde(with = "time::serde::timestamp")]
     pub updated_at: OffsetDateTime,
 }
 
+/// Who has to set up MFA, from the `Mfa.*` dynamic settings
+#[derive(Debug)]
+pub struct MfaPolicy {
+    pub require_for_all: bool,
+    pub require_for_admins: bool,
+    pub require_for_permissions: Vec<i64>,
+    pub grace: time::Duration,
+}
+
+#[derive(Debug, FromRow)]
+pub struct MfaRequirement {
+    pub mfa_enabled: bool,
+    /// Whether the policy applies to the user
+    pub required: bool,
+    pub required_since: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct MfaNonCompliantUser {
+    pub user_id: i64,
+    pub username: String,
+    pub role: Role,
+    /// Unknown until the user is next seen
+    #[serde(with = "time::serde::timestamp::option")]
+    pub required_since: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub setup_deadline: Option<OffsetDateTime>,
+}
+
+impl PageQueryCursor for MfaNonCompliantUser {
+    fn cursor_field(&self) -> i64 {
+        self.user_id
+    }
+}
+
 #[derive(Debug)]
 pub struct MfaSettingsOptional {
     pub user_id: i64,
     pub mfa_enabled: Option<bool>,
 }
 
+/// An unused recovery code, `code_hash` is an Argon2id hash of the normalized code
+#[derive(Debug, FromRow)]
+pub struct MfaRecoveryCode {
+    pub id: i64,
+    pub code_hash: String,
+}
+
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct TotpCredential {
     pub id: i64,
     pub user_id: i64,
+    pub label: String,
     pub totp_credential_data: Vec<u8>,
     #[serde(with = "time::serde::timestamp")]
     pub created_at: OffsetDateTime,
@@ -49,6 +98,7 @@ pub struct TotpCredential {
     pub updated_at: OffsetDateTime,
     #[serde(with = "time::serde::timestamp::option")]
     pub last_used_at: Option<OffsetDateTime>,
+    pub last_time_step: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -69,6 +119,8 @@ pub struct WebauthnCredential {
     pub user_name: String,
     pub user_display_name: Option<String>,
     pub credential_data: Vec<u8>,
+    /// Whether the authenticator keeps it as a passkey, unknown for older rows
+    pub discoverable: Option<bool>,
     #[serde(with = "time::serde::timestamp")]
     pub created_at: OffsetDateTime,
     #[serde(with = "time::serde::timestamp")]
@@ -101,7 +153,10 @@ impl PageQueryCursor for MfaAuthLog {
 #[derive(Debug)]
 pub struct NewTotpCredential {
     pub user_id: i64,
+    pub label: String,
     pub totp_credential_data: Vec<u8>,
+    /// Time step of the code that confirmed the setup
+    pub last_time_step: i64,
 }
 
 #[derive(Debug)]
@@ -111,6 +166,7 @@ pub struct NewWebauthnCredential {
     pub user_name: String,
     pub user_display_name: Option<String>,
     pub credential_data: Vec<u8>,
+    pub discoverable: Option<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -134,9 +190,12 @@ pub struct NewMfaAuthLogInfo {
 pub struct MfaInfo {
     pub user_id: i64,
     pub mfa_enabled: bool,
+    /// Passkeys may log in without a password
+    pub passkey_login: bool,
     #[serde(with = "time::serde::timestamp")]
     pub updated_at: OffsetDateTime,
     pub available_methods: Vec<MFAAuthMethod>,
+    pub recovery_codes_left: i64,
 }
 
 pub struct WebAuthnKV {
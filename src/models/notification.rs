@@ -0,0 +1,45 @@
+use crate::models::echo::Echo;
+use crate::services::states::db::PageQueryCursor;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use time::OffsetDateTime;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[repr(u8)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// `actor_id` mentioned the recipient in `echo_id`
+    Mention = 1,
+}
+
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct NotificationRow {
+    pub id: i64,
+    pub kind: NotificationKind,
+    /// `None` once the actor has been deleted
+    pub actor_id: Option<i64>,
+    pub echo_id: Option<i64>,
+    pub is_read: bool,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+}
+
+impl PageQueryCursor for NotificationRow {
+    fn cursor_field(&self) -> i64 {
+        self.id
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NotificationView {
+    #[serde(flatten)]
+    pub notification: NotificationRow,
+    /// Rendered for the recipient, `None` if they can no longer see it
+    pub echo: Option<Echo>,
+}
+
+impl PageQueryCursor for NotificationView {
+    fn cursor_field(&self) -> i64 {
+        self.notification.id
+    }
+}
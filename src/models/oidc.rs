@@ -0,0 +1,64 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use serde_with::{OneOrMany, serde_as};
+
+/// The subset of `/.well-known/openid-configuration` the login flow needs
+#[derive(Debug, Deserialize)]
+pub struct OidcDiscovery {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+/// Only RSA keys are understood, the others are kept so that the set still parses
+#[derive(Debug, Deserialize)]
+pub struct Jwk {
+    pub kty: String,
+    pub kid: Option<String>,
+    #[serde(rename = "use")]
+    pub usage: Option<String>,
+    pub n: Option<String>,
+    pub e: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JwtHeader {
+    pub alg: String,
+    pub kid: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcTokenRes {
+    pub id_token: String,
+}
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    #[serde_as(as = "OneOrMany<_>")]
+    pub aud: Vec<String>,
+    pub exp: i64,
+    pub nbf: Option<i64>,
+    pub nonce: Option<String>,
+    pub azp: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl IdTokenClaims {
+    /// A string claim, `sub` included
+    pub fn claim_str(&self, name: &str) -> Option<&str> {
+        match name {
+            "sub" => Some(&self.sub),
+            name => self.extra.get(name).and_then(Value::as_str),
+        }
+    }
+}
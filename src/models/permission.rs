@@ -52,3 +52,21 @@ pub struct UserAssignedPermission {
     pub revoked_at: Option<OffsetDateTime>,
     pub active: bool,
 }
+
+impl From<RawUserPermissionRow> for UserAssignedPermission {
+    fn from(r: RawUserPermissionRow) -> Self {
+        Self {
+            permission: Permission {
+                id: r.permission_id,
+                description: r.description,
+                color: r.color,
+            },
+            id: r.record_id,
+            exp_time: r.exp_time,
+            assigner_id: r.assigner_id,
+            assigned_at: r.assigned_at,
+            revoked_at: r.revoked_at,
+            active: r.active,
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::services::states::db::PageQueryCursor;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::collections::BTreeSet;
+use time::OffsetDateTime;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[repr(u8)]
+#[serde(rename_all = "snake_case")]
+pub enum UserRelationKind {
+    /// The target can neither see nor interact with the user's echos
+    Block = 1,
+    /// The target's echos are left out of the user's listings
+    Mute = 2,
+}
+
+/// A blocked or muted user
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct UserRelationItem {
+    pub id: i64,
+    pub user_id: i64,
+    pub username: String,
+    pub avatar_res_id: Option<i64>,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+}
+
+impl PageQueryCursor for UserRelationItem {
+    fn cursor_field(&self) -> i64 {
+        self.id
+    }
+}
+
+#[derive(Debug, FromRow)]
+pub struct UserRelationRow {
+    pub user_id: i64,
+    pub target_id: i64,
+    pub kind: UserRelationKind,
+}
+
+/// Everything about a single user the blocks and mutes decide
+#[derive(Debug, Default)]
+pub struct UserRelations {
+    /// Users this user blocks
+    pub blocking: BTreeSet<i64>,
+    /// Users blocking this user
+    pub blocked_by: BTreeSet<i64>,
+    pub muting: BTreeSet<i64>,
+}
+
+impl UserRelations {
+    /// `rows` are the relations of `user_id` and the blocks targeting them
+    pub fn from_rows(user_id: i64, rows: Vec<UserRelationRow>) -> Self {
+        let mut relations = Self::default();
+        for row in rows {
+            match row.kind {
+                UserRelationKind::Block if row.user_id == user_id => {
+                    relations.blocking.insert(row.target_id)
+                }
+                UserRelationKind::Block => relations.blocked_by.insert(row.user_id),
+                UserRelationKind::Mute => relations.muting.insert(row.target_id),
+            };
+        }
+        relations
+    }
+
+    pub fn is_blocked_by(&self, user_id: i64) -> bool {
+        self.blocked_by.contains(&user_id)
+    }
+
+    /// Whether either of the two users blocks the other
+    pub fn is_blocked_with(&self, user_id: i64) -> bool {
+        self.blocked_by.contains(&user_id) || self.blocking.contains(&user_id)
+    }
+
+    /// Authors whose echos are left out of this user's listings
+    pub fn is_hidden(&self, user_id: i64) -> bool {
+        self.blocked_by.contains(&user_id)
+            || self.blocking.contains(&user_id)
+            || self.muting.contains(&user_id)
+    }
+
+    pub fn hidden_user_ids(&self) -> Vec<i64> {
+        let mut ids = self
+            .blocked_by
+            .iter()
+            .chain(&self.blocking)
+            .chain(&self.muting)
+            .copied()
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_user_relations_from_rows() {
+        let row = |user_id, target_id, kind| UserRelationRow {
+            user_id,
+            target_id,
+            kind,
+        };
+        let relations = UserRelations::from_rows(
+            1,
+            vec![
+                row(1, 2, UserRelationKind::Block),
+                row(3, 1, UserRelationKind::Block),
+                row(1, 4, UserRelationKind::Mute),
+                row(1, 2, UserRelationKind::Mute),
+            ],
+        );
+        assert!(relations.is_blocked_by(3));
+        assert!(!relations.is_blocked_by(2));
+        assert!(relations.is_blocked_with(2));
+        assert!(relations.is_hidden(4));
+        assert!(!relations.is_hidden(5));
+        assert_eq!(relations.hidden_user_ids(), vec![2, 3, 4]);
+    }
+}
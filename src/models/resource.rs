@@ -36,6 +36,14 @@ impl ResourceItemRawInfo {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, FromRow)]
+pub struct ResourceItemRaw {
+    pub id: i64,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub info: ResourceItemRawInfo,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow)]
 pub struct ResourceItemWithRefRaw {
     pub id: i64,
@@ -1,5 +1,6 @@
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 use std::fmt::Debug;
 use time::OffsetDateTime;
 use uuid::Uuid;
@@ -32,11 +33,17 @@ where
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BasicAuthData {
     pub user_id: i64,
+    /// Entry of the session registry, cookies signed before it existed carry the nil uuid
+    #[serde(default)]
+    pub session_uuid: Uuid,
 }
 
 impl BasicAuthData {
-    pub fn new(user_id: i64) -> Self {
-        Self { user_id }
+    pub fn new(user_id: i64, session_uuid: Uuid) -> Self {
+        Self {
+            user_id,
+            session_uuid,
+        }
     }
 }
 
@@ -53,3 +60,50 @@ pub type PreMfaAuthSessionData = BaseSession<PreMfaAuthData>;
 pub type MfaAuthData = ();
 
 pub type MfaAuthSessionData = BaseSession<MfaAuthData>;
+
+/// Everything the callback of an OpenID Connect login needs to check and finish it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OidcFlowData {
+    pub provider: String,
+    pub state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+    pub invitation_code: Option<String>,
+    pub device_name: Option<String>,
+    /// Set when a logged in user links the identity instead of logging in with it
+    pub link_user_id: Option<i64>,
+}
+
+pub type OidcFlowSessionData = BaseSession<OidcFlowData>;
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct UserSessionRow {
+    pub id: i64,
+    #[serde(skip)]
+    pub session_uuid: Uuid,
+    pub user_id: i64,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub last_seen_at: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserSessionItem {
+    #[serde(flatten)]
+    pub session: UserSessionRow,
+    /// Whether this is the session the request was made with
+    pub current: bool,
+}
+
+#[derive(Debug)]
+pub struct NewUserSession {
+    pub session_uuid: Uuid,
+    pub user_id: i64,
+    pub device_name: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+}
@@ -0,0 +1,93 @@
+use crate::models::echo::EchoPermission;
+use crate::models::mfa::MfaAuthLog;
+use crate::models::permission::UserAssignedPermission;
+use crate::models::resource::ResourceItemRaw;
+use crate::models::users::User;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use std::sync::Arc;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[repr(u8)]
+#[serde(rename_all = "lowercase")]
+pub enum TakeoutStatus {
+    /// The archive is being built in the background
+    Pending = 1,
+    /// The archive can be downloaded until `expires_at`
+    Ready = 2,
+    Failed = 3,
+    /// The archive has been cleaned up
+    Expired = 4,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct TakeoutRow {
+    pub id: i64,
+    pub user_id: i64,
+    pub status: TakeoutStatus,
+    #[serde(skip)]
+    pub file_uuid: Uuid,
+    pub file_size: Option<i64>,
+    pub error_message: Option<String>,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub finished_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::timestamp::option")]
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+impl TakeoutRow {
+    pub fn file_name(&self) -> String {
+        format!("{}.zip", self.file_uuid)
+    }
+}
+
+/// An echo as stored in the archive, echos keep no revisions so only the latest content exists
+#[derive(Debug, Serialize)]
+pub struct TakeoutEcho {
+    pub id: i64,
+    /// Original of a repost, `None` for plain echos and for reposts of deleted echos
+    pub repost_of: Option<i64>,
+    /// Content as posted, i.e. with the extension placeholders
+    pub content: String,
+    /// Content as rendered for the owner, `None` if the owner can no longer see it
+    pub rendered: Option<String>,
+    pub permission: EchoPermission,
+    pub fav_count: i64,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
+    pub last_modified_at: OffsetDateTime,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TakeoutResource<'a> {
+    #[serde(flatten)]
+    pub resource: &'a ResourceItemRaw,
+    /// Path inside the archive, `None` if the file is gone from the storage
+    pub path: Option<String>,
+}
+
+/// Everything collected before the archive is written
+#[derive(Debug)]
+pub struct TakeoutData {
+    pub profile: Arc<User>,
+    pub echos: Vec<TakeoutEcho>,
+    pub resources: Vec<ResourceItemRaw>,
+    pub permission_records: Vec<UserAssignedPermission>,
+    pub mfa_op_logs: Vec<MfaAuthLog>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TakeoutManifest {
+    pub takeout_id: i64,
+    pub user_id: i64,
+    #[serde(with = "time::serde::timestamp")]
+    pub created_at: OffsetDateTime,
+    pub echo_count: usize,
+    pub resource_count: usize,
+    pub missing_resource_ids: Vec<i64>,
+}
@@ -0,0 +1,73 @@
+use crate::models::activity_pub::ApEchoActivity;
+use crate::models::echo::Echo;
+use serde::Serialize;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimelineEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl TimelineEventKind {
+    /// Name of the SSE event
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimelineEventKind::Created => "created",
+            TimelineEventKind::Updated => "updated",
+            TimelineEventKind::Deleted => "deleted",
+        }
+    }
+}
+
+impl From<ApEchoActivity> for TimelineEventKind {
+    fn from(activity: ApEchoActivity) -> Self {
+        match activity {
+            ApEchoActivity::Create => TimelineEventKind::Created,
+            ApEchoActivity::Update => TimelineEventKind::Updated,
+            ApEchoActivity::Delete => TimelineEventKind::Deleted,
+        }
+    }
+}
+
+/// `{epoch}-{seq}`, the epoch changes with every start of the server, so an id handed out by
+/// an earlier process is never mistaken for one of the current backlog
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TimelineEventId {
+    pub epoch: u32,
+    pub seq: u64,
+}
+
+impl Display for TimelineEventId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:08x}-{}", self.epoch, self.seq)
+    }
+}
+
+impl FromStr for TimelineEventId {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, seq) = s.trim().split_once('-').ok_or(())?;
+        Ok(Self {
+            epoch: u32::from_str_radix(epoch, 16).map_err(|_| ())?,
+            seq: seq.parse().map_err(|_| ())?,
+        })
+    }
+}
+
+/// An echo as it was written, rendered for each subscriber on the way out
+#[derive(Debug)]
+pub struct TimelineEvent {
+    pub id: TimelineEventId,
+    pub kind: TimelineEventKind,
+    pub echo: Echo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineDeletedPayload {
+    pub id: i64,
+}
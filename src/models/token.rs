@@ -1,12 +1,41 @@
+use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use sqlx::types::Json;
 use time::OffsetDateTime;
 
-#[derive(Debug, FromRow)]
+/// What a personal access token may be used for, routes without a scope only take cookies
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub enum TokenScope {
+    #[serde(rename = "echo:read")]
+    EchoRead,
+    #[serde(rename = "echo:write")]
+    EchoWrite,
+    #[serde(rename = "resource:read")]
+    ResourceRead,
+    #[serde(rename = "resource:upload")]
+    ResourceUpload,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
 pub struct AuthTokenRaw {
     pub id: i64,
     pub user_id: i64,
-    pub token: String,
+    pub name: String,
+    /// SHA-256 of the token, the token itself is only shown once on creation
+    #[serde(skip)]
+    pub token_hash: String,
+    pub scopes: Json<Vec<TokenScope>>,
+    #[serde(with = "time::serde::timestamp")]
     pub created_at: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp")]
     pub exp_time: OffsetDateTime,
+    #[serde(with = "time::serde::timestamp::option")]
     pub last_used_at: Option<OffsetDateTime>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct CreatedAuthToken {
+    #[serde(flatten)]
+    pub info: AuthTokenRaw,
+    pub token: String,
+}
@@ -68,3 +68,17 @@ impl UserInternal {
         }
     }
 }
+
+impl User {
+    /// The unauthenticated visitor, it holds no permission and owns nothing.
+    pub fn guest() -> Self {
+        Self {
+            id: 0,
+            username: "guest".to_string(),
+            role: Role::Guest,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            permission_ids: BTreeSet::new(),
+            avatar_res_id: None,
+        }
+    }
+}
@@ -1,32 +1,69 @@
 use crate::echo_layer_builder;
-use crate::routers::echo::{add_echo, delete_echo, list_echo, list_echo_ext, modify_echo};
+use crate::models::token::TokenScope;
+use crate::routers::activity_pub::{
+    get_actor, get_followers, get_note, get_outbox, post_inbox, webfinger,
+};
+use crate::routers::audit::get_audit_logs;
+use crate::routers::echo::{
+    add_echo, delete_echo, get_echo_calendar, list_echo, list_echo_ext, list_echo_on_this_day,
+    list_home_echo, list_public_echo, live_timeline, modify_echo, repost_echo,
+};
+use crate::routers::echo_import::{IMPORT_BODY_LIMIT, import_echos};
+use crate::routers::echo_share::{
+    confirm_share, create_share, list_shares, revoke_share, view_share,
+};
+use crate::routers::feed::{get_atom_feed, get_rss_feed};
+use crate::routers::follow::{follow_user, list_followers, list_following, unfollow_user};
 use crate::routers::invite_code::{create_invite_code, list_invite_codes, revoke_invite_code};
 use crate::routers::mfa::{
-    get_mfa_infos, get_mfa_op_logs, totp_delete, totp_list, totp_setup_finish, totp_setup_start,
-    totp_verify, webauthn_auth_finish, webauthn_auth_start, webauthn_delete, webauthn_list,
+    get_mfa_infos, get_mfa_op_logs, get_mfa_policy_report, passkey_login_finish,
+    passkey_login_start, recovery_code_verify, recovery_codes_regenerate, set_passkey_login,
+    totp_delete, totp_list, totp_rename, totp_setup_finish, totp_setup_start, totp_verify,
+    webauthn_auth_finish, webauthn_auth_start, webauthn_delete, webauthn_list,
     webauthn_setup_finish, webauthn_setup_start,
 };
+use crate::routers::notification::{get_unread_count, list_notifications, mark_read};
+use crate::routers::oidc::{oidc_callback, oidc_link, oidc_login};
 use crate::routers::permission::{
     add_permission, delete_permission, get_permission_info, get_permission_records,
     grant_permission, modify_permission, revoke_permission,
 };
 use crate::routers::resource::{
-    delete_resource, get_resource_by_ids, get_resource_by_maybe_sign, update_resource,
-    upload_chunk, upload_commit, upload_create,
+    delete_resource, get_resource_by_ids, get_resource_by_maybe_sign, get_resource_by_sign,
+    update_resource, upload_chunk, upload_commit, upload_create,
+};
+use crate::routers::settings::{
+    get_dyn_settings, get_static_settings, list_auth_keys, logout_everyone, rotate_auth_keys,
+    set_dyn_settings, set_webauthn_rp,
 };
-use crate::routers::settings::{get_dyn_settings, get_static_settings, set_dyn_settings};
+use crate::routers::takeout::{download_takeout, list_takeouts, request_takeout};
+use crate::routers::token::{create_token, list_tokens, revoke_token};
 use crate::routers::user::{
-    delete_user, fetch_user_info, modify_user_info, user_login, user_register,
+    change_password, delete_user, fetch_user_info, modify_user_info, user_login, user_register,
 };
+use crate::routers::user_relation::{add_relation, list_relations, remove_relation};
+use crate::routers::user_session::{list_sessions, revoke_sessions};
+use crate::services::access_token::AccessTokenService;
+use crate::services::activity_pub::ActivityPubService;
+use crate::services::audit::AuditService;
 use crate::services::echo_baker::EchoBaker;
+use crate::services::echo_import::EchoImportService;
+use crate::services::echo_share::EchoShareService;
 use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::live_timeline::LiveTimelineService;
 use crate::services::mfa::MFAService;
+use crate::services::mfa_policy::MfaPolicyService;
+use crate::services::notification::NotificationService;
+use crate::services::oidc::OidcService;
+use crate::services::password::PasswordService;
 use crate::services::res_manager::ResManagerService;
 use crate::services::states::EchoState;
+use crate::services::takeout::TakeoutService;
 use crate::services::upload_tracker::UploadTrackerService;
 use axum::Router;
+use axum::extract::DefaultBodyLimit;
 use axum::http::{HeaderName, Request};
-use axum::routing::{get, patch, post, put};
+use axum::routing::{delete, get, patch, post, put};
 use std::sync::Arc;
 use tower::ServiceBuilder;
 use tower_http::request_id::{
@@ -35,19 +72,35 @@ use tower_http::request_id::{
 use tower_http::trace::TraceLayer;
 use tracing::info_span;
 
+mod activity_pub;
+mod audit;
 mod echo;
+mod echo_import;
+mod echo_share;
+mod feed;
+mod follow;
 mod invite_code;
 mod mfa;
+mod notification;
+mod oidc;
 mod permission;
 mod resource;
 mod settings;
+mod takeout;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod token;
 mod user;
+mod user_relation;
+mod user_session;
 
 pub async fn router(state: Arc<EchoState>) -> Router {
     // TODO: When more services are added in the future, maybe we can write a `ServiceBuilder`?.
+    let password_service =
+        Arc::new(PasswordService::new(state.clone()).expect("Failed to init PasswordService"));
     let mfa_service = {
         Arc::new(
-            MFAService::new(state.clone())
+            MFAService::new(state.clone(), password_service.clone())
                 .await
                 .expect("Failed to init MFAService"),
         )
@@ -60,11 +113,89 @@ pub async fn router(state: Arc<EchoState>) -> Router {
         )
     };
     let hybrid_cache_service = Arc::new(HybridCacheService::new(state.clone()));
+    let access_token_service = Arc::new(AccessTokenService::new(state.clone()));
+    let mfa_policy_service = Arc::new(MfaPolicyService::new(
+        state.clone(),
+        hybrid_cache_service.clone(),
+    ));
     let echo_baker_service = Arc::new(EchoBaker::new(state.config.perf.echo_cache_capacity));
     let res_manager_service = Arc::new(ResManagerService::new(state.clone()));
+    let activity_pub_service = Arc::new(
+        ActivityPubService::new(
+            state.clone(),
+            hybrid_cache_service.clone(),
+            echo_baker_service.clone(),
+        )
+        .expect("Failed to init ActivityPubService"),
+    );
+    activity_pub_service.spawn_delivery_worker();
+    let takeout_service = Arc::new(TakeoutService::new(
+        state.clone(),
+        hybrid_cache_service.clone(),
+        echo_baker_service.clone(),
+        res_manager_service.clone(),
+    ));
+    takeout_service.spawn_cleanup_worker();
+    let audit_service = Arc::new(AuditService::new(state.clone()));
+    audit_service.spawn_retention_worker();
+    let notification_service = Arc::new(NotificationService::new(
+        state.clone(),
+        hybrid_cache_service.clone(),
+        echo_baker_service.clone(),
+    ));
+    let live_timeline_service = Arc::new(LiveTimelineService::new(
+        state.clone(),
+        hybrid_cache_service.clone(),
+        echo_baker_service.clone(),
+        access_token_service.clone(),
+    ));
+    let echo_share_service = Arc::new(EchoShareService::new(
+        state.clone(),
+        hybrid_cache_service.clone(),
+        echo_baker_service.clone(),
+        res_manager_service.clone(),
+    ));
+    let echo_import_service = Arc::new(
+        EchoImportService::new(state.clone(), echo_baker_service.clone())
+            .expect("Failed to init EchoImportService"),
+    );
+    let oidc_service =
+        Arc::new(OidcService::new(state.clone()).expect("Failed to init OidcService"));
     let raw_layer = echo_layer_builder!(state);
     let basic_layer = echo_layer_builder!(state, b);
-    let full_mfa_layer = echo_layer_builder!(state, b, m);
+    let full_mfa_layer = echo_layer_builder!(state, b, m, mfa_policy_service);
+    let echo_read_layer = echo_layer_builder!(
+        state,
+        b,
+        m,
+        mfa_policy_service,
+        TokenScope::EchoRead,
+        access_token_service
+    );
+    let echo_write_layer = echo_layer_builder!(
+        state,
+        b,
+        m,
+        mfa_policy_service,
+        TokenScope::EchoWrite,
+        access_token_service
+    );
+    let resource_read_layer = echo_layer_builder!(
+        state,
+        b,
+        m,
+        mfa_policy_service,
+        TokenScope::ResourceRead,
+        access_token_service
+    );
+    let resource_upload_layer = echo_layer_builder!(
+        state,
+        b,
+        m,
+        mfa_policy_service,
+        TokenScope::ResourceUpload,
+        access_token_service
+    );
     let user_router = {
         Router::new()
             .merge(
@@ -81,9 +212,37 @@ pub async fn router(state: Arc<EchoState>) -> Router {
             .merge(
                 Router::new()
                     .route("/info", patch(modify_user_info).delete(delete_user))
+                    .route("/password", patch(change_password))
                     .layer(full_mfa_layer()),
             )
-            .with_state((state.clone(), hybrid_cache_service.clone()))
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                password_service.clone(),
+                mfa_policy_service.clone(),
+                audit_service.clone(),
+            ))
+            .merge(
+                Router::new()
+                    .route(
+                        "/relations",
+                        put(add_relation)
+                            .post(list_relations)
+                            .delete(remove_relation),
+                    )
+                    .route("/sessions", post(list_sessions).delete(revoke_sessions))
+                    .layer(full_mfa_layer())
+                    .with_state((state.clone(), hybrid_cache_service.clone())),
+            )
+            .merge(
+                Router::new()
+                    .route(
+                        "/tokens",
+                        put(create_token).post(list_tokens).delete(revoke_token),
+                    )
+                    .layer(full_mfa_layer())
+                    .with_state((state.clone(), access_token_service.clone())),
+            )
     };
     let mfa_router = {
         Router::new()
@@ -94,6 +253,7 @@ pub async fn router(state: Arc<EchoState>) -> Router {
                     .merge(
                         Router::new()
                             .route("/logs", post(get_mfa_op_logs))
+                            .route("/policy/report", post(get_mfa_policy_report))
                             .layer(full_mfa_layer()),
                     ),
             )
@@ -106,7 +266,18 @@ pub async fn router(state: Arc<EchoState>) -> Router {
                     .layer(basic_layer())
                     .merge(
                         Router::new()
-                            .route("/", get(totp_list).delete(totp_delete))
+                            .route("/", get(totp_list).patch(totp_rename).delete(totp_delete))
+                            .layer(full_mfa_layer()),
+                    ),
+            )
+            .nest(
+                "/recovery",
+                Router::new()
+                    .route("/verify", post(recovery_code_verify))
+                    .layer(basic_layer())
+                    .merge(
+                        Router::new()
+                            .route("/regenerate", post(recovery_codes_regenerate))
                             .layer(full_mfa_layer()),
                     ),
             )
@@ -118,13 +289,26 @@ pub async fn router(state: Arc<EchoState>) -> Router {
                     .route("/auth/start", post(webauthn_auth_start))
                     .route("/auth/finish", post(webauthn_auth_finish))
                     .layer(basic_layer())
+                    .merge(
+                        Router::new()
+                            .route("/login/start", post(passkey_login_start))
+                            .route("/login/finish", post(passkey_login_finish))
+                            .layer(raw_layer()),
+                    )
                     .merge(
                         Router::new()
                             .route("/", get(webauthn_list).delete(webauthn_delete))
+                            .route("/passwordless", patch(set_passkey_login))
                             .layer(full_mfa_layer()),
                     ),
             )
-            .with_state((state.clone(), mfa_service, hybrid_cache_service.clone()))
+            .with_state((
+                state.clone(),
+                mfa_service.clone(),
+                hybrid_cache_service.clone(),
+                mfa_policy_service.clone(),
+                audit_service.clone(),
+            ))
     };
     let resource_router = {
         Router::new()
@@ -133,21 +317,30 @@ pub async fn router(state: Arc<EchoState>) -> Router {
                 Router::new()
                     .route("/create", post(upload_create))
                     .route("/chunk", put(upload_chunk))
-                    .route("/commit", post(upload_commit)),
+                    .route("/commit", post(upload_commit))
+                    .layer(resource_upload_layer()),
             )
             .route(
                 "/",
-                get(get_resource_by_maybe_sign)
-                    .post(get_resource_by_ids)
-                    .patch(update_resource)
-                    .delete(delete_resource),
+                get(get_resource_by_maybe_sign).post(get_resource_by_ids),
+            )
+            .layer(resource_read_layer())
+            .merge(
+                Router::new()
+                    .route("/", patch(update_resource).delete(delete_resource))
+                    .layer(full_mfa_layer()),
+            )
+            .merge(
+                Router::new()
+                    .route("/signed", get(get_resource_by_sign))
+                    .layer(raw_layer()),
             )
-            .layer(full_mfa_layer())
             .with_state((
                 state.clone(),
                 upload_tracker_service,
                 hybrid_cache_service.clone(),
                 res_manager_service,
+                audit_service.clone(),
             ))
     };
     let invite_code_router = {
@@ -159,7 +352,11 @@ pub async fn router(state: Arc<EchoState>) -> Router {
                     .delete(revoke_invite_code),
             )
             .layer(full_mfa_layer())
-            .with_state((state.clone(), hybrid_cache_service.clone()))
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                audit_service.clone(),
+            ))
     };
     let permission_router = {
         Router::new()
@@ -177,30 +374,165 @@ pub async fn router(state: Arc<EchoState>) -> Router {
             )
             .route("/records", post(get_permission_records))
             .layer(full_mfa_layer())
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                audit_service.clone(),
+            ))
     };
     let echo_router = {
+        Router::new()
+            .route("/", post(list_echo))
+            .route("/ext", get(list_echo_ext))
+            .route("/home", post(list_home_echo))
+            .route("/on-this-day", post(list_echo_on_this_day))
+            .route("/calendar", post(get_echo_calendar))
+            .route("/live", get(live_timeline))
+            .layer(echo_read_layer())
+            .merge(
+                Router::new()
+                    .route("/", put(add_echo).patch(modify_echo).delete(delete_echo))
+                    .route("/repost", put(repost_echo))
+                    .layer(echo_write_layer()),
+            )
+            .merge(
+                Router::new()
+                    .route("/public", post(list_public_echo))
+                    .layer(raw_layer()),
+            )
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                echo_baker_service.clone(),
+                activity_pub_service.clone(),
+                notification_service.clone(),
+                live_timeline_service,
+            ))
+    };
+    let share_router = {
         Router::new()
             .route(
                 "/",
-                put(add_echo)
-                    .patch(modify_echo)
-                    .delete(delete_echo)
-                    .post(list_echo),
+                put(create_share).post(list_shares).delete(revoke_share),
             )
-            .route("/ext", get(list_echo_ext))
             .layer(full_mfa_layer())
+            .merge(
+                Router::new()
+                    .route("/view", get(view_share).post(confirm_share))
+                    .layer(raw_layer()),
+            )
+            .with_state((state.clone(), echo_share_service))
+    };
+    let import_router = {
+        Router::new()
+            .route("/", post(import_echos))
+            .layer(DefaultBodyLimit::max(IMPORT_BODY_LIMIT))
+            .layer(full_mfa_layer())
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                echo_import_service,
+            ))
+    };
+    let feed_router = {
+        Router::new()
+            .route("/rss", get(get_rss_feed))
+            .route("/atom", get(get_atom_feed))
+            .layer(raw_layer())
             .with_state((
                 state.clone(),
                 hybrid_cache_service.clone(),
                 echo_baker_service,
             ))
     };
+    let activity_pub_router = {
+        Router::new()
+            .route("/.well-known/webfinger", get(webfinger))
+            .nest(
+                "/ap",
+                Router::new()
+                    .route("/users/{user_id}", get(get_actor))
+                    .route("/users/{user_id}/inbox", post(post_inbox))
+                    .route("/users/{user_id}/outbox", get(get_outbox))
+                    .route("/users/{user_id}/followers", get(get_followers))
+                    .route("/echos/{echo_id}", get(get_note)),
+            )
+            .layer(raw_layer())
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                activity_pub_service,
+            ))
+    };
+    let takeout_router = {
+        Router::new()
+            .route("/", post(request_takeout).get(list_takeouts))
+            .layer(full_mfa_layer())
+            .merge(
+                Router::new()
+                    .route("/download", get(download_takeout))
+                    .layer(raw_layer()),
+            )
+            .with_state((state.clone(), takeout_service))
+    };
+    let notification_router = {
+        Router::new()
+            .route("/", post(list_notifications).patch(mark_read))
+            .route("/unread-count", get(get_unread_count))
+            .layer(full_mfa_layer())
+            .with_state((state.clone(), notification_service))
+    };
+    let follow_router = {
+        Router::new()
+            .route("/", put(follow_user).delete(unfollow_user))
+            .route("/following", post(list_following))
+            .route("/followers", post(list_followers))
+            .layer(full_mfa_layer())
+            .with_state((state.clone(), hybrid_cache_service.clone()))
+    };
     let settings_router = {
         Router::new()
             .route("/dynamic", post(get_dyn_settings).patch(set_dyn_settings))
             .route("/static", post(get_static_settings))
+            .route("/keys", post(list_auth_keys).put(rotate_auth_keys))
+            .route("/sessions", delete(logout_everyone))
+            .route("/webauthn", put(set_webauthn_rp))
             .layer(full_mfa_layer())
-            .with_state((state.clone(), hybrid_cache_service.clone()))
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                mfa_service.clone(),
+                audit_service.clone(),
+            ))
+    };
+    let audit_router = {
+        Router::new()
+            .route("/logs", post(get_audit_logs))
+            .layer(full_mfa_layer())
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                audit_service.clone(),
+            ))
+    };
+    let oidc_router = {
+        Router::new()
+            .route("/{provider}/login", get(oidc_login))
+            .route("/{provider}/callback", get(oidc_callback))
+            .layer(raw_layer())
+            .merge(
+                Router::new()
+                    .route("/{provider}/link", get(oidc_link))
+                    .layer(full_mfa_layer()),
+            )
+            .with_state((
+                state.clone(),
+                hybrid_cache_service.clone(),
+                password_service,
+                oidc_service,
+                mfa_policy_service,
+                audit_service,
+            ))
     };
     let trace_header = HeaderName::from_static("x-hananokioku");
     Router::new()
@@ -209,12 +541,21 @@ pub async fn router(state: Arc<EchoState>) -> Router {
             Router::new()
                 .nest("/user", user_router)
                 .nest("/mfa", mfa_router)
+                .nest("/oidc", oidc_router)
                 .nest("/resource", resource_router)
                 .nest("/invite-code", invite_code_router)
                 .nest("/permission", permission_router)
                 .nest("/echo", echo_router)
-                .nest("/settings", settings_router),
+                .nest("/share", share_router)
+                .nest("/import", import_router)
+                .nest("/takeout", takeout_router)
+                .nest("/notification", notification_router)
+                .nest("/feed", feed_router)
+                .nest("/follow", follow_router)
+                .nest("/settings", settings_router)
+                .nest("/audit", audit_router),
         )
+        .merge(activity_pub_router)
         .layer(
             ServiceBuilder::new()
                 .layer(SetRequestIdLayer::new(
@@ -0,0 +1,247 @@
+use crate::models::activity_pub::{
+    AP_CONTENT_TYPE, AP_CONTEXT, ApEchoActivity, WebFingerLink, WebFingerQuery, WebFingerRes,
+};
+use crate::models::api::prelude::*;
+use crate::models::echo::Echo;
+use crate::models::users::UserRow;
+use crate::services::activity_pub::{
+    ActivityPubError, ActivityPubService, ApActorUrls, is_federated,
+};
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use crate::services::states::db::EchoDatabaseExecutor;
+use axum::Json;
+use axum::body::Bytes;
+use axum::extract::{OriginalUri, Path, Query, State};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use url::Url;
+
+pub type ActivityPubRouterState = State<(
+    Arc<EchoState>,
+    Arc<HybridCacheService>,
+    Arc<ActivityPubService>,
+)>;
+
+/// Max number of activities in a single outbox page
+const OUTBOX_PAGE_SIZE: u32 = 20;
+
+fn activity_json(value: Value) -> Response {
+    (
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(AP_CONTENT_TYPE),
+        )],
+        Json(value),
+    )
+        .into_response()
+}
+
+async fn enabled_site_url(ap: &ActivityPubService) -> ApiResult<String> {
+    match ap.enabled_site_url().await {
+        Ok(site_url) => Ok(site_url),
+        Err(ActivityPubError::Disabled) => Err(not_found!("ActivityPub is disabled")),
+        Err(e) => Err(internal!(e, "Failed to get dynamic settings")),
+    }
+}
+
+pub async fn webfinger(
+    State((state, _, ap)): ActivityPubRouterState,
+    Query(query): Query<WebFingerQuery>,
+) -> ApiResult<Response> {
+    let site_url = enabled_site_url(&ap).await?;
+    let site_host = Url::parse(&site_url)
+        .ok()
+        .and_then(|it| it.host_str().map(str::to_string))
+        .ok_or_else(|| internal!("Site url has no host"))?;
+    let (username, host) = query
+        .resource
+        .strip_prefix("acct:")
+        .and_then(|it| it.rsplit_once('@'))
+        .ok_or_else(|| bad_request!("Unsupported resource"))?;
+    if !host.eq_ignore_ascii_case(&site_host) {
+        return Err(not_found!("Unknown host"));
+    }
+    let user: Option<UserRow> = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.users().query_user_by_username(username).await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    let user = user.ok_or_else(|| not_found!("User not found"))?;
+    let actor = ApActorUrls::new(&site_url, user.id).actor;
+    let res = WebFingerRes {
+        subject: format!("acct:{}@{site_host}", user.username),
+        aliases: vec![actor.clone()],
+        links: vec![WebFingerLink {
+            rel: "self",
+            typ: AP_CONTENT_TYPE,
+            href: actor,
+        }],
+    };
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/jrd+json"),
+        )],
+        Json(res),
+    )
+        .into_response())
+}
+
+pub async fn get_actor(
+    State((_, cache, ap)): ActivityPubRouterState,
+    Path(user_id): Path<i64>,
+) -> ApiResult<Response> {
+    let site_url = enabled_site_url(&ap).await?;
+    let user = cache
+        .users
+        .get_user_by_user_id(user_id)
+        .await
+        .map_err(|e| not_found!(e, "User not found"))?;
+    let actor = ap
+        .actor_document(&site_url, &user)
+        .await
+        .map_err(|e| internal!(e, "Failed to render actor"))?;
+    Ok(activity_json(actor))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OutboxQuery {
+    /// Only activities of echos older than this id, enables paging
+    pub before: Option<i64>,
+}
+
+pub async fn get_outbox(
+    State((state, cache, ap)): ActivityPubRouterState,
+    Path(user_id): Path<i64>,
+    Query(query): Query<OutboxQuery>,
+) -> ApiResult<Response> {
+    let site_url = enabled_site_url(&ap).await?;
+    cache
+        .users
+        .get_user_by_user_id(user_id)
+        .await
+        .map_err(|e| not_found!(e, "User not found"))?;
+    let echos: Vec<Echo> = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo()
+                .query_public_echo_feed(Some(user_id), query.before, OUTBOX_PAGE_SIZE)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+    let items = echos
+        .iter()
+        .map(|echo| ap.wrap_activity(&site_url, ApEchoActivity::Create, echo))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| internal!(e, "Failed to render activity"))?;
+    let outbox = ApActorUrls::new(&site_url, user_id).outbox();
+    let mut page = json!({
+        "@context": AP_CONTEXT,
+        "id": match query.before {
+            Some(before) => format!("{outbox}?before={before}"),
+            None => outbox.clone(),
+        },
+        "type": "OrderedCollection",
+        "orderedItems": items,
+    });
+    if echos.len() == OUTBOX_PAGE_SIZE as usize
+        && let Some(last) = echos.last()
+    {
+        page["next"] = json!(format!("{outbox}?before={}", last.id));
+    }
+    Ok(activity_json(page))
+}
+
+pub async fn get_followers(
+    State((state, cache, ap)): ActivityPubRouterState,
+    Path(user_id): Path<i64>,
+) -> ApiResult<Response> {
+    let site_url = enabled_site_url(&ap).await?;
+    cache
+        .users
+        .get_user_by_user_id(user_id)
+        .await
+        .map_err(|e| not_found!(e, "User not found"))?;
+    let count = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.activity_pub().count_followers(user_id).await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to count followers"))?;
+    // Only the size of the collection is exposed, follower lists are nobody's business
+    Ok(activity_json(json!({
+        "@context": AP_CONTEXT,
+        "id": ApActorUrls::new(&site_url, user_id).followers(),
+        "type": "OrderedCollection",
+        "totalItems": count,
+    })))
+}
+
+pub async fn post_inbox(
+    State((_, cache, ap)): ActivityPubRouterState,
+    Path(user_id): Path<i64>,
+    method: Method,
+    // `nest` strips the prefix from `Uri`, but the signature covers the full request target
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> ApiResult<StatusCode> {
+    enabled_site_url(&ap).await?;
+    cache
+        .users
+        .get_user_by_user_id(user_id)
+        .await
+        .map_err(|e| not_found!(e, "User not found"))?;
+    let path_and_query = uri
+        .path_and_query()
+        .map(|it| it.as_str())
+        .unwrap_or(uri.path());
+    match ap
+        .handle_inbox(user_id, &method, path_and_query, &headers, &body)
+        .await
+    {
+        Ok(_) => Ok(StatusCode::ACCEPTED),
+        Err(
+            e @ (ActivityPubError::HttpSignature(_)
+            | ActivityPubError::ActorKeyMismatch
+            | ActivityPubError::PrivateAddress(_)
+            | ActivityPubError::Http(_)),
+        ) => Err(unauthorized!(e, "Invalid http signature")),
+        Err(
+            e @ (ActivityPubError::SerdeJson(_)
+            | ActivityPubError::UrlParse(_)
+            | ActivityPubError::ObjectMismatch),
+        ) => Err(bad_request!(e, "Invalid activity")),
+        Err(e) => Err(internal!(e, "Failed to handle activity")),
+    }
+}
+
+pub async fn get_note(
+    State((state, _, ap)): ActivityPubRouterState,
+    Path(echo_id): Path<i64>,
+) -> ApiResult<Response> {
+    let site_url = enabled_site_url(&ap).await?;
+    let echo: Option<Echo> = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo().query_echo_by_id(echo_id).await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+    let echo = echo
+        .filter(is_federated)
+        .ok_or_else(|| not_found!("Echo not found"))?;
+    let mut note = ap
+        .note_object(&site_url, &echo)
+        .map_err(|e| internal!(e, "Failed to render note"))?;
+    note["@context"] = json!(AP_CONTEXT);
+    Ok(activity_json(note))
+}
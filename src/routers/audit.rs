@@ -0,0 +1,105 @@
+use crate::models::api::prelude::*;
+use crate::models::audit::{AuditLog, AuditLogFilter};
+use crate::models::session::BasicAuthData;
+use crate::models::users::Role;
+use crate::services::audit::AuditService;
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use crate::services::states::db::{PageQueryBinder, PageQueryResult};
+use axum::Json;
+use axum::extract::State;
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub type AuditRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>, Arc<AuditService>)>;
+
+#[derive(Debug, Deserialize)]
+pub struct AuditLogsQueryReq {
+    #[serde(flatten)]
+    page_query: PageQueryBinder,
+    #[serde(flatten)]
+    filter: AuditLogFilter,
+}
+
+pub async fn get_audit_logs(
+    current_user_info: BasicAuthData,
+    State((_, cache, audit)): AuditRouterState,
+    Json(req): Json<AuditLogsQueryReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<AuditLog>>>> {
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    if current_user.role != Role::Admin {
+        return Err(bad_request!("Only admin can view audit logs"));
+    }
+    let list = audit
+        .query(&req.filter, req.page_query)
+        .await
+        .map_err(|e| internal!(e, "Failed to get audit logs"))?;
+    Ok(general_json_res!("OK", list))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient};
+    use axum::http::{Method, StatusCode};
+    use serde_json::{Value, json};
+
+    async fn audit_logs(app: &TestApp, client: &mut TestClient, filter: Value) -> Vec<Value> {
+        let mut body = json!({ "start_after": 0 });
+        body.as_object_mut()
+            .unwrap()
+            .extend(filter.as_object().unwrap().clone());
+        let res = app
+            .send(client, Method::POST, "/api/v1/audit/logs", Some(body))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        serde_json::from_value(res.data()["items"].clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_audit_logs_are_recorded_and_filtered() {
+        let app = TestApp::new().await;
+        let admin_id = app.register("admin").await;
+        let bob_id = app.register("bob").await;
+        let mut admin = app.login("admin").await;
+        let res = app
+            .send(
+                &mut TestClient::default(),
+                Method::POST,
+                "/api/v1/user/login",
+                Some(json!({ "username": "bob", "password_hash": "wrong" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        let res = app
+            .send(
+                &mut admin,
+                Method::DELETE,
+                "/api/v1/user/info?user_id=999",
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND, "{:?}", res.body);
+        let res = app
+            .send(&mut admin, Method::PUT, "/api/v1/settings/keys", None)
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+
+        let failed = audit_logs(&app, &mut admin, json!({ "action": "login_failed" })).await;
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["target_user_id"], bob_id);
+        assert_eq!(failed[0]["after_summary"]["reason"], "wrong_password");
+        let rotations = audit_logs(&app, &mut admin, json!({ "action": "auth_key_rotate" })).await;
+        assert_eq!(rotations.len(), 1);
+        assert_eq!(rotations[0]["actor_id"], admin_id);
+        let about_bob = audit_logs(&app, &mut admin, json!({ "target_user_id": bob_id })).await;
+        assert!(about_bob.iter().all(|it| it["target_user_id"] == bob_id));
+        assert_eq!(about_bob.len(), 1, "{about_bob:?}");
+        // nothing was deleted, so nothing was logged
+        let deletes = audit_logs(&app, &mut admin, json!({ "action": "user_delete" })).await;
+        assert!(deletes.is_empty());
+    }
+}
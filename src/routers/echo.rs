@@ -1,24 +1,189 @@
+use crate::get_batch_tuple;
 use crate::gladiator::ext_plugins::EchoExtMetaPubInfo;
+use crate::gladiator::prelude::{IncomingEchoMention, OutGoingEchoSSRConsCtx};
+use crate::layers::auth::bearer_token;
+use crate::models::activity_pub::ApEchoActivity;
 use crate::models::api::prelude::*;
-use crate::models::echo::Echo;
+use crate::models::dyn_setting::{AllowGuest, UtcOffsetMinutes};
+use crate::models::echo::{Echo, EchoCalendarDay, EchoRepostOrigin, EchoView};
+use crate::models::relation::UserRelations;
 use crate::models::session::BasicAuthData;
-use crate::models::users::Role;
+use crate::models::timeline::TimelineEventKind;
+use crate::models::users::{Role, User};
+use crate::services::activity_pub::ActivityPubService;
 use crate::services::echo_baker::{EchoBaker, EchoBakerError};
 use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::live_timeline::{LiveTimelineService, TimelineCredential};
+use crate::services::notification::NotificationService;
 use crate::services::states::EchoState;
-use crate::services::states::db::{EchoDatabaseExecutor, PageQueryBinder, PageQueryResult};
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
+};
 use ahash::HashMap;
 use axum::Json;
 use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures::Stream;
 use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
 use std::sync::Arc;
+use time::{Date, Duration, OffsetDateTime, UtcOffset};
 
 pub type EchoRouterState = State<(
     Arc<EchoState>,
     Arc<HybridCacheService>,
     Arc<EchoBaker<'static>>,
+    Arc<ActivityPubService>,
+    Arc<NotificationService>,
+    Arc<LiveTimelineService>,
 )>;
 
+/// Send out a written echo to federation (if `ap` is given) and live subscribers.
+/// Both are best effort, a failure must not fail the local write
+async fn announce_echo(
+    state: &EchoState,
+    ap: Option<&ActivityPubService>,
+    live: &LiveTimelineService,
+    activity: ApEchoActivity,
+    echo_id: i64,
+) {
+    let echo = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo().query_echo_by_id(echo_id).await
+        })
+        .await;
+    let result = match echo {
+        Ok(Some(echo)) => {
+            let result = match ap {
+                Some(ap) => ap.federate_echo(activity, &echo).await,
+                None => Ok(()),
+            };
+            live.publish(activity.into(), echo);
+            result
+        }
+        Ok(None) => Ok(()),
+        Err(e) => Err(e.into()),
+    };
+    if let Err(e) = result {
+        tracing::error!("Failed to federate echo {}: {}", echo_id, e);
+    }
+}
+
+/// Mentions are best effort as well, the echo is written either way
+async fn deliver_mentions(
+    notifier: &NotificationService,
+    echo_id: i64,
+    mentions: &[IncomingEchoMention],
+) {
+    if let Err(e) = notifier.deliver_mentions(echo_id, mentions).await {
+        tracing::error!("Failed to deliver mentions of echo {}: {}", echo_id, e);
+    }
+}
+
+async fn get_relations(cache: &HybridCacheService, user_id: i64) -> ApiResult<Arc<UserRelations>> {
+    cache
+        .relations
+        .get_relations(user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch blocks and mutes"))
+}
+
+/// Render echos for `viewer`. The original of a repost is rendered the same way, so a repost
+/// never shows the original to anyone who could not see it anyway, nor to those its author blocks.
+async fn view_echos(
+    state: &Arc<EchoState>,
+    baker: &EchoBaker<'static>,
+    viewer: &User,
+    relations: &UserRelations,
+    echos: Vec<Echo>,
+    no_cache: bool,
+) -> ApiResult<Vec<EchoView>> {
+    let echo_ids = echos.iter().map(|it| it.id).collect::<Vec<_>>();
+    let hidden_user_ids = relations.hidden_user_ids();
+    let (reposts, originals, counts) = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let reposts = exec
+                .echo_repost()
+                .get_reposts_by_echo_ids(&echo_ids)
+                .await?;
+            let original_ids = reposts
+                .iter()
+                .filter_map(|it| it.original_echo_id)
+                .collect::<Vec<_>>();
+            let originals = exec.echo().query_echos_by_ids(&original_ids).await?;
+            let counts = exec
+                .echo_repost()
+                .count_visible_reposts(viewer, &hidden_user_ids, &echo_ids)
+                .await?;
+            Ok::<_, DataBaseError>((reposts, originals, counts))
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch reposts"))?;
+    let render = |mut echo: Echo| -> Result<Echo, EchoBakerError> {
+        echo.content = match echo.has_permission(viewer) {
+            // guests have no session, so their resource links go to the signed route
+            true if viewer.role == Role::Guest => baker.post_inner_echo_with_ctx(
+                Arc::downgrade(state),
+                &echo,
+                OutGoingEchoSSRConsCtx::shared(echo.user_id),
+                &viewer.permission_ids,
+                EchoBaker::all_ext_ids(),
+            )?,
+            true => baker.post_inner_echo(
+                Arc::downgrade(state),
+                &echo,
+                viewer.id,
+                &viewer.permission_ids,
+                EchoBaker::all_ext_ids(),
+                no_cache,
+            )?,
+            false => None,
+        };
+        Ok(echo)
+    };
+    let mut originals = originals
+        .into_iter()
+        .map(|it| (it.id, it))
+        .collect::<HashMap<_, _>>();
+    let reposts = reposts
+        .into_iter()
+        .map(|it| (it.echo_id, it.original_echo_id))
+        .collect::<HashMap<_, _>>();
+    let counts = counts
+        .into_iter()
+        .map(|it| (it.echo_id, it.count))
+        .collect::<HashMap<_, _>>();
+    echos
+        .into_iter()
+        .map(|echo| {
+            let repost_of = match reposts.get(&echo.id) {
+                None => None,
+                Some(original_id) => Some(match original_id.and_then(|id| originals.remove(&id)) {
+                    Some(original)
+                        if original.has_permission(viewer)
+                            && !relations.is_blocked_by(original.user_id) =>
+                    {
+                        EchoRepostOrigin::Visible {
+                            echo: Box::new(render(original)?),
+                        }
+                    }
+                    Some(_) => EchoRepostOrigin::Hidden,
+                    None => EchoRepostOrigin::Deleted,
+                }),
+            };
+            Ok(EchoView {
+                repost_count: counts.get(&echo.id).copied().unwrap_or_default(),
+                echo: render(echo)?,
+                repost_of,
+            })
+        })
+        .collect::<Result<_, EchoBakerError>>()
+        .map_err(|e| internal!(e, "Failed to bake echo content"))
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct EchoInfo {
     content: String,
@@ -34,7 +199,7 @@ pub struct AddEchoReq {
 
 pub async fn add_echo(
     current_user_info: BasicAuthData,
-    State((state, cache, baker)): EchoRouterState,
+    State((state, cache, baker, ap, notifier, live)): EchoRouterState,
     Json(req): Json<AddEchoReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -50,7 +215,7 @@ pub async fn add_echo(
             EchoBaker::all_ext_ids(),
         )
         .map_err(|e| internal!(e, "Failed to add echo"))?;
-    state
+    let echo_id = state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
             exec.echo()
@@ -60,11 +225,14 @@ pub async fn add_echo(
                     baked.res_ids.as_deref().unwrap_or_default(),
                     &req.inner.echo_permission_ids,
                     req.inner.is_private,
+                    None,
                 )
                 .await
         })
         .await
         .map_err(|e| internal!(e, "Failed to add echo"))?;
+    deliver_mentions(&notifier, echo_id, &baked.mentions).await;
+    announce_echo(&state, Some(&ap), &live, ApEchoActivity::Create, echo_id).await;
     Ok(general_json_res!("Echo added successfully"))
 }
 
@@ -77,7 +245,7 @@ pub struct ModifyEchoReq {
 
 pub async fn modify_echo(
     current_user_info: BasicAuthData,
-    State((state, cache, baker)): EchoRouterState,
+    State((state, cache, baker, ap, notifier, live)): EchoRouterState,
     Json(req): Json<ModifyEchoReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -108,6 +276,15 @@ pub async fn modify_echo(
         })
         .await
         .map_err(|e| internal!(e, "Failed to update echo"))?;
+    deliver_mentions(&notifier, req.echo_id, &baked.mentions).await;
+    announce_echo(
+        &state,
+        Some(&ap),
+        &live,
+        ApEchoActivity::Update,
+        req.echo_id,
+    )
+    .await;
     Ok(general_json_res!("Echo updated successfully"))
 }
 
@@ -118,7 +295,7 @@ pub struct DeleteEchoReq {
 
 pub async fn delete_echo(
     current_user_info: BasicAuthData,
-    State((state, cache, _)): EchoRouterState,
+    State((state, cache, _, ap, _, live)): EchoRouterState,
     Json(req): Json<DeleteEchoReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -143,20 +320,137 @@ pub async fn delete_echo(
         {
             Err(bad_request!("Can only delete your own echo"))
         }
-        Some(_) => {
+        Some(echo) => {
             state
                 .db
                 .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.echo_repost().delete_bare_reposts(req.echo_id).await?;
                     exec.echo().delete_echo(req.echo_id).await
                 })
                 .await
                 .map_err(|e| internal!(e, "Failed to delete echo"))?;
+            if let Err(e) = ap.federate_echo(ApEchoActivity::Delete, &echo).await {
+                tracing::error!("Failed to federate echo {}: {}", echo.id, e);
+            }
+            live.publish(TimelineEventKind::Deleted, echo);
             Ok(general_json_res!("Echo deleted successfully"))
         }
         None => Err(bad_request!("Echo not found")),
     }
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RepostEchoReq {
+    original_echo_id: i64,
+    /// Optional comment, posted like the content of an echo
+    #[serde(default)]
+    comment: String,
+    /// Visibility of the repost itself, the original keeps its own
+    #[serde(default)]
+    echo_permission_ids: Vec<i64>,
+    #[serde(default)]
+    is_private: bool,
+}
+
+pub async fn repost_echo(
+    current_user_info: BasicAuthData,
+    State((state, cache, baker, _, notifier, live)): EchoRouterState,
+    Json(req): Json<RepostEchoReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    let relations = get_relations(&cache, current_user_info.user_id).await?;
+    let visible =
+        |echo: &Echo| echo.has_permission(&current_user) && !relations.is_blocked_by(echo.user_id);
+    // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
+    let (original, repost): (Option<Echo>, Option<Option<i64>>) = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let original = exec.echo().query_echo_by_id(req.original_echo_id).await?;
+            let repost = exec
+                .echo_repost()
+                .get_reposts_by_echo_ids(&[req.original_echo_id])
+                .await?
+                .pop()
+                .map(|it| it.original_echo_id);
+            Ok::<_, DataBaseError>((original, repost))
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+    // do not tell hidden echos apart from missing ones, blocked users included
+    let original = original
+        .filter(visible)
+        .ok_or_else(|| bad_request!("Echo not found"))?;
+    // reposting a repost reposts its original
+    let original_echo_id = match repost {
+        None => original.id,
+        Some(None) => return Err(bad_request!("The reposted echo has been deleted")),
+        Some(Some(root_id)) => {
+            // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
+            let root: Option<Echo> = state
+                .db
+                .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.echo().query_echo_by_id(root_id).await
+                })
+                .await
+                .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+            root.filter(visible)
+                .ok_or_else(|| bad_request!("Echo not found"))?
+                .id
+        }
+    };
+    let baked = match req.comment.trim().is_empty() {
+        true => None,
+        // TODO: Allow users to have their own ext
+        false => Some(
+            baker
+                .add_outer_echo(
+                    &req.comment,
+                    &current_user.permission_ids,
+                    EchoBaker::all_ext_ids(),
+                )
+                .map_err(|e| internal!(e, "Failed to add echo"))?,
+        ),
+    };
+    let (comment, res_ids) = match &baked {
+        Some(baked) => (
+            baked.safe_echo.as_str(),
+            baked.res_ids.as_deref().unwrap_or_default(),
+        ),
+        None => ("", Default::default()),
+    };
+    let echo_id = state
+        .db
+        .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let echo_id = exec
+                .echo()
+                .add_echo(
+                    current_user_info.user_id,
+                    comment,
+                    res_ids,
+                    &req.echo_permission_ids,
+                    req.is_private,
+                    None,
+                )
+                .await?;
+            exec.echo_repost()
+                .add_repost(echo_id, original_echo_id)
+                .await?;
+            Ok::<_, DataBaseError>(echo_id)
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to repost echo"))?;
+    if let Some(baked) = &baked {
+        deliver_mentions(&notifier, echo_id, &baked.mentions).await;
+    }
+    // reposts are not federated yet
+    announce_echo(&state, None, &live, ApEchoActivity::Create, echo_id).await;
+    Ok(general_json_res!("Echo reposted successfully"))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ListEchoReq {
     pub user_id: Option<i64>,
@@ -167,43 +461,272 @@ pub struct ListEchoReq {
 
 pub async fn list_echo(
     current_user_info: BasicAuthData,
-    State((state, cache, baker)): EchoRouterState,
+    State((state, cache, baker, _, _, _)): EchoRouterState,
     Json(req): Json<ListEchoReq>,
-) -> ApiResult<Json<GeneralResponse<PageQueryResult<Echo>>>> {
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<EchoView>>>> {
     let current_user = cache
         .users
         .get_user_by_user_id(current_user_info.user_id)
         .await
         .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    let relations = get_relations(&cache, current_user_info.user_id).await?;
+    let hidden_user_ids = relations.hidden_user_ids();
     let mut echos = state
         .db
         .single(async |mut exec: EchoDatabaseExecutor<'_>| {
             exec.echo()
-                .query_user_echo(req.user_id, req.page_query)
+                .query_user_echo(req.user_id, &hidden_user_ids, req.page_query)
                 .await
         })
         .await
         .map_err(|e| internal!(e, "Failed to fetch echo"))?;
-    echos
-        .items
-        .iter_mut()
-        // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
-        .try_for_each(|it: &mut Echo| {
-            it.content = match it.has_permission(&current_user) {
-                true => baker.post_inner_echo(
-                    Arc::downgrade(&state),
-                    it,
-                    current_user_info.user_id,
-                    &current_user.permission_ids,
-                    EchoBaker::all_ext_ids(),
-                    req.no_cache.unwrap_or_default(),
-                )?,
-                false => None,
-            };
-            Ok(())
+    let items = std::mem::take(&mut echos.items);
+    let views = view_echos(
+        &state,
+        &baker,
+        &current_user,
+        &relations,
+        items,
+        req.no_cache.unwrap_or_default(),
+    )
+    .await?;
+    Ok(general_json_res!(
+        "Successfully fetched echos",
+        echos.swap_items(views)
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListHomeEchoReq {
+    pub no_cache: Option<bool>,
+    #[serde(flatten)]
+    pub page_query: PageQueryBinder,
+}
+
+/// Echos of the current user and everyone they follow
+pub async fn list_home_echo(
+    current_user_info: BasicAuthData,
+    State((state, cache, baker, _, _, _)): EchoRouterState,
+    Json(req): Json<ListHomeEchoReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<EchoView>>>> {
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    let relations = get_relations(&cache, current_user_info.user_id).await?;
+    let hidden_user_ids = relations.hidden_user_ids();
+    let mut echos = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo()
+                .query_home_echo(&current_user, &hidden_user_ids, req.page_query)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+    let items = std::mem::take(&mut echos.items);
+    let views = view_echos(
+        &state,
+        &baker,
+        &current_user,
+        &relations,
+        items,
+        req.no_cache.unwrap_or_default(),
+    )
+    .await?;
+    Ok(general_json_res!(
+        "Successfully fetched echos",
+        echos.swap_items(views)
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListPublicEchoReq {
+    pub user_id: Option<i64>,
+    #[serde(flatten)]
+    pub page_query: PageQueryBinder,
+}
+
+/// Public echos for unauthenticated visitors, only served while `Site.AllowGuest` is enabled.
+/// Everything is rendered for [`User::guest`], so gated spans are redacted.
+pub async fn list_public_echo(
+    State((state, cache, baker, _, _, _)): EchoRouterState,
+    Json(req): Json<ListPublicEchoReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<EchoView>>>> {
+    let (allow_guest,) = get_batch_tuple!(cache.dyn_settings, AllowGuest)
+        .map_err(|e| internal!(e, "Failed to get dynamic settings"))?;
+    if !allow_guest {
+        return Err(not_found!("Guest access is disabled"));
+    }
+    let mut echos = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo()
+                .query_public_echo(req.user_id, req.page_query)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+    let items = std::mem::take(&mut echos.items);
+    let views = view_echos(
+        &state,
+        &baker,
+        &User::guest(),
+        &UserRelations::default(),
+        items,
+        true,
+    )
+    .await?;
+    Ok(general_json_res!(
+        "Successfully fetched echos",
+        echos.swap_items(views)
+    ))
+}
+
+/// Upper bound of days a single calendar query may span
+const MAX_CALENDAR_SPAN_DAYS: i64 = 400;
+
+time::serde::format_description!(echo_date, Date, "[year]-[month]-[day]");
+
+/// Resolve the effective UTC offset, falling back to the `Site.UtcOffsetMinutes` dyn setting.
+async fn resolve_utc_offset(
+    cache: &HybridCacheService,
+    maybe_offset_minutes: Option<i16>,
+) -> ApiResult<UtcOffset> {
+    let offset_minutes = match maybe_offset_minutes {
+        Some(it) => it,
+        None => {
+            get_batch_tuple!(cache.dyn_settings, UtcOffsetMinutes)
+                .map_err(|e| internal!(e, "Failed to get dynamic settings"))?
+                .0
+        }
+    };
+    UtcOffset::from_whole_seconds(offset_minutes as i32 * 60)
+        .map_err(|e| bad_request!(e, "Invalid UTC offset"))
+}
+
+/// SQLite date modifier which shifts a unix epoch into the given offset
+fn sqlite_offset_modifier(offset: UtcOffset) -> String {
+    format!("{:+} minutes", offset.whole_minutes())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListEchoOnThisDayReq {
+    pub user_id: Option<i64>,
+    /// Defaults to today in the resolved offset
+    #[serde(default, with = "echo_date::option")]
+    pub date: Option<Date>,
+    pub utc_offset_minutes: Option<i16>,
+    pub no_cache: Option<bool>,
+    #[serde(flatten)]
+    pub page_query: PageQueryBinder,
+}
+
+pub async fn list_echo_on_this_day(
+    current_user_info: BasicAuthData,
+    State((state, cache, baker, _, _, _)): EchoRouterState,
+    Json(req): Json<ListEchoOnThisDayReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<EchoView>>>> {
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    let relations = get_relations(&cache, current_user_info.user_id).await?;
+    let hidden_user_ids = relations.hidden_user_ids();
+    let offset = resolve_utc_offset(&cache, req.utc_offset_minutes).await?;
+    let date = req
+        .date
+        .unwrap_or_else(|| OffsetDateTime::now_utc().to_offset(offset).date());
+    let month_day = format!("{:02}-{:02}", date.month() as u8, date.day());
+    let year = format!("{:04}", date.year());
+    let offset_modifier = sqlite_offset_modifier(offset);
+    let mut echos = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo()
+                .query_echo_on_this_day(
+                    &current_user,
+                    req.user_id,
+                    &hidden_user_ids,
+                    &month_day,
+                    &year,
+                    &offset_modifier,
+                    req.page_query,
+                )
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+    let items = std::mem::take(&mut echos.items);
+    let views = view_echos(
+        &state,
+        &baker,
+        &current_user,
+        &relations,
+        items,
+        req.no_cache.unwrap_or_default(),
+    )
+    .await?;
+    Ok(general_json_res!(
+        "Successfully fetched echos",
+        echos.swap_items(views)
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetEchoCalendarReq {
+    pub user_id: Option<i64>,
+    #[serde(with = "echo_date")]
+    pub from: Date,
+    #[serde(with = "echo_date")]
+    pub to: Date,
+    pub utc_offset_minutes: Option<i16>,
+}
+
+pub async fn get_echo_calendar(
+    current_user_info: BasicAuthData,
+    State((state, cache, _, _, _, _)): EchoRouterState,
+    Json(req): Json<GetEchoCalendarReq>,
+) -> ApiResult<Json<GeneralResponse<Vec<EchoCalendarDay>>>> {
+    if req.from > req.to {
+        return Err(bad_request!("`from` must not be later than `to`"));
+    }
+    if req.to - req.from > Duration::days(MAX_CALENDAR_SPAN_DAYS) {
+        return Err(bad_request!("Calendar range is too large"));
+    }
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    let hidden_user_ids = get_relations(&cache, current_user_info.user_id)
+        .await?
+        .hidden_user_ids();
+    let offset = resolve_utc_offset(&cache, req.utc_offset_minutes).await?;
+    let offset_modifier = sqlite_offset_modifier(offset);
+    let (from, to) = (req.from.to_string(), req.to.to_string());
+    let days = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo()
+                .query_echo_calendar(
+                    &current_user,
+                    req.user_id,
+                    &hidden_user_ids,
+                    &from,
+                    &to,
+                    &offset_modifier,
+                )
+                .await
         })
-        .map_err(|e: EchoBakerError| internal!(e, "Failed to bake echo content"))?;
-    Ok(general_json_res!("Successfully fetched echos", echos))
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo calendar"))?;
+    Ok(general_json_res!(
+        "Successfully fetched echo calendar",
+        days
+    ))
 }
 
 pub async fn list_echo_ext(
@@ -214,3 +737,385 @@ pub async fn list_echo_ext(
         EchoBaker::all_ext_metas()
     ))
 }
+
+/// Keeps proxies from dropping idle connections
+const LIVE_TIMELINE_HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// Server-Sent Events of created, updated and deleted echos. EventSource resumes
+/// by itself through `Last-Event-ID`, a `resync` event asks the client to refetch instead.
+pub async fn live_timeline(
+    current_user_info: BasicAuthData,
+    State((_, _, _, _, _, live)): EchoRouterState,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers.get("last-event-id").and_then(|it| it.to_str().ok());
+    // the scoped layer prefers a bearer token over cookies as well
+    let credential = match bearer_token(&headers) {
+        Some(bearer) => TimelineCredential::Token(bearer),
+        None => TimelineCredential::Session(current_user_info.session_uuid),
+    };
+    Sse::new(live.subscribe(current_user_info.user_id, credential, last_event_id))
+        .keep_alive(KeepAlive::new().interval(LIVE_TIMELINE_HEARTBEAT))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient, TestRes};
+    use axum::body::BodyDataStream;
+    use axum::http::{Method, StatusCode};
+    use futures::StreamExt;
+    use serde_json::{Value, json};
+
+    fn echo_ids(res: &TestRes) -> Vec<i64> {
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        res.data()["items"]
+            .as_array()
+            .expect("Missing items")
+            .iter()
+            .map(|it| it["id"].as_i64().expect("Missing id"))
+            .collect()
+    }
+
+    async fn set_created_at(app: &TestApp, echo_id: i64, unix: i64) {
+        sqlx::query("UPDATE echos SET created_at = ? WHERE id = ?")
+            .bind(unix)
+            .bind(echo_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+    }
+
+    async fn repost_count(
+        app: &TestApp,
+        viewer: &mut TestClient,
+        author: i64,
+        echo_id: i64,
+    ) -> i64 {
+        let body = json!({ "user_id": author, "no_cache": true, "start_after": 0 });
+        let res = app
+            .send(viewer, Method::POST, "/api/v1/echo", Some(body))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        res.data()["items"]
+            .as_array()
+            .expect("Missing items")
+            .iter()
+            .find(|it| it["id"] == echo_id)
+            .expect("Echo not listed")["repost_count"]
+            .as_i64()
+            .expect("Missing repost_count")
+    }
+
+    #[tokio::test]
+    async fn test_on_this_day_and_calendar() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        app.register("bob").await;
+        app.register("carol").await;
+        let (mut bob, mut carol) = (app.login("bob").await, app.login("carol").await);
+        let bob_public = app.add_echo(&mut bob, "bob public", &[], false).await;
+        let bob_private = app.add_echo(&mut bob, "bob private", &[], true).await;
+        let carol_private = app.add_echo(&mut carol, "carol private", &[], true).await;
+        let bob_evening = app.add_echo(&mut bob, "bob evening", &[], false).await;
+        // 2023-06-15 12:00 UTC, and 20:00 UTC for the evening one
+        for id in [bob_public, bob_private, carol_private] {
+            set_created_at(&app, id, 1_686_830_400).await;
+        }
+        set_created_at(&app, bob_evening, 1_686_859_200).await;
+
+        let on_this_day = |date: &'static str, offset: i16| json!({ "date": date, "utc_offset_minutes": offset, "start_after": 0 });
+        let res = app
+            .send(
+                &mut carol,
+                Method::POST,
+                "/api/v1/echo/on-this-day",
+                Some(on_this_day("2025-06-15", 0)),
+            )
+            .await;
+        assert_eq!(echo_ids(&res), [bob_public, carol_private, bob_evening]);
+        // the evening echo is already on the next day in UTC+8
+        let res = app
+            .send(
+                &mut carol,
+                Method::POST,
+                "/api/v1/echo/on-this-day",
+                Some(on_this_day("2025-06-16", 480)),
+            )
+            .await;
+        assert_eq!(echo_ids(&res), [bob_evening]);
+        // only previous years count
+        let res = app
+            .send(
+                &mut carol,
+                Method::POST,
+                "/api/v1/echo/on-this-day",
+                Some(on_this_day("2023-06-15", 0)),
+            )
+            .await;
+        assert_eq!(echo_ids(&res), Vec::<i64>::new());
+
+        let res = app
+            .send(
+                &mut carol,
+                Method::POST,
+                "/api/v1/echo/calendar",
+                Some(
+                    json!({ "from": "2023-06-14", "to": "2023-06-16", "utc_offset_minutes": 480 }),
+                ),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(
+            res.data(),
+            &json!([
+                { "date": "2023-06-15", "count": 2 },
+                { "date": "2023-06-16", "count": 1 },
+            ])
+        );
+        let res = app
+            .send(
+                &mut carol,
+                Method::POST,
+                "/api/v1/echo/calendar",
+                Some(json!({ "from": "2023-06-16", "to": "2023-06-14" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_repost_count_only_counts_visible_reposts() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let bob_id = app.register("bob").await;
+        app.register("carol").await;
+        let dave_id = app.register("dave").await;
+        let mut alice = app.login("alice").await;
+        let (mut bob, mut carol, mut dave) = (
+            app.login("bob").await,
+            app.login("carol").await,
+            app.login("dave").await,
+        );
+        let secret = app.add_permission(&mut alice, "secret").await;
+        app.grant_permission(&mut alice, dave_id, secret).await;
+        let original = app.add_echo(&mut bob, "original", &[], false).await;
+        for (mut client, permission_ids, is_private) in [
+            (carol.clone(), vec![], false),
+            (carol.clone(), vec![], true),
+            (dave.clone(), vec![secret], false),
+        ] {
+            let body = json!({
+                "original_echo_id": original,
+                "echo_permission_ids": permission_ids,
+                "is_private": is_private,
+            });
+            let res = app
+                .send(&mut client, Method::PUT, "/api/v1/echo/repost", Some(body))
+                .await;
+            assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        }
+        assert_eq!(repost_count(&app, &mut bob, bob_id, original).await, 1);
+        assert_eq!(repost_count(&app, &mut carol, bob_id, original).await, 2);
+        assert_eq!(repost_count(&app, &mut dave, bob_id, original).await, 2);
+        app.grant_permission(&mut alice, bob_id, secret).await;
+        assert_eq!(repost_count(&app, &mut bob, bob_id, original).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_guest_only_sees_public_echos() {
+        let app = TestApp::build(|_| {}, &[("Site.AllowGuest", "true")]).await;
+        app.register("alice").await;
+        let bob_id = app.register("bob").await;
+        let mut alice = app.login("alice").await;
+        let mut bob = app.login("bob").await;
+        let secret = app.add_permission(&mut alice, "secret").await;
+        app.grant_permission(&mut alice, bob_id, secret).await;
+        let public = app.add_echo(&mut bob, "public", &[], false).await;
+        app.add_echo(&mut bob, "private", &[], true).await;
+        app.add_echo(&mut bob, "permissioned", &[secret], false)
+            .await;
+        let alice_public = app.add_echo(&mut alice, "alice public", &[], false).await;
+
+        let mut guest = TestClient::default();
+        let res = app
+            .send(
+                &mut guest,
+                Method::POST,
+                "/api/v1/echo/public",
+                Some(json!({ "start_after": 0 })),
+            )
+            .await;
+        assert_eq!(echo_ids(&res), [public, alice_public]);
+        let res = app
+            .send(
+                &mut guest,
+                Method::POST,
+                "/api/v1/echo/public",
+                Some(json!({ "user_id": bob_id, "start_after": 0 })),
+            )
+            .await;
+        assert_eq!(echo_ids(&res), [public]);
+        // the regular list still needs a session
+        let res = app
+            .send(
+                &mut guest,
+                Method::POST,
+                "/api/v1/echo",
+                Some(json!({ "user_id": bob_id, "start_after": 0 })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_guest_access_is_off_by_default() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        app.add_echo(&mut alice, "public", &[], false).await;
+        let res = app
+            .send(
+                &mut TestClient::default(),
+                Method::POST,
+                "/api/v1/echo/public",
+                Some(json!({ "start_after": 0 })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+
+    async fn home_echo_ids(app: &TestApp, client: &mut TestClient) -> Vec<i64> {
+        let body = json!({ "no_cache": true, "start_after": 0 });
+        let res = app
+            .send(client, Method::POST, "/api/v1/echo/home", Some(body))
+            .await;
+        echo_ids(&res)
+    }
+
+    #[tokio::test]
+    async fn test_home_timeline_only_shows_visible_echos() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let bob_id = app.register("bob").await;
+        let carol_id = app.register("carol").await;
+        app.register("dave").await;
+        let mut alice = app.login("alice").await;
+        let (mut bob, mut carol, mut dave) = (
+            app.login("bob").await,
+            app.login("carol").await,
+            app.login("dave").await,
+        );
+        let secret = app.add_permission(&mut alice, "secret").await;
+        for (client, followee) in [(&mut carol, bob_id), (&mut alice, bob_id)] {
+            let res = app
+                .send(
+                    client,
+                    Method::PUT,
+                    "/api/v1/follow",
+                    Some(json!({ "user_id": followee })),
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        }
+        let bob_public = app.add_echo(&mut bob, "bob public", &[], false).await;
+        let bob_private = app.add_echo(&mut bob, "bob private", &[], true).await;
+        let bob_secret = app.add_echo(&mut bob, "bob secret", &[secret], false).await;
+        let carol_private = app.add_echo(&mut carol, "carol private", &[], true).await;
+        // not followed by anyone
+        app.add_echo(&mut dave, "dave public", &[], false).await;
+
+        assert_eq!(
+            home_echo_ids(&app, &mut carol).await,
+            [bob_public, carol_private]
+        );
+        // like `Echo::has_permission`, a gated echo needs its permissions even from its author
+        assert_eq!(
+            home_echo_ids(&app, &mut alice).await,
+            [bob_public, bob_private]
+        );
+        assert_eq!(
+            home_echo_ids(&app, &mut bob).await,
+            [bob_public, bob_private]
+        );
+        app.grant_permission(&mut alice, carol_id, secret).await;
+        assert_eq!(
+            home_echo_ids(&app, &mut carol).await,
+            [bob_public, bob_secret, carol_private]
+        );
+        let res = app
+            .send(
+                &mut carol,
+                Method::PUT,
+                "/api/v1/user/relations",
+                Some(json!({ "user_id": bob_id, "kind": "mute" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(home_echo_ids(&app, &mut carol).await, [carol_private]);
+        assert_eq!(home_echo_ids(&app, &mut dave).await.len(), 1);
+    }
+
+    /// Next event of a live timeline as `(event, data)`, `None` once the stream has ended
+    async fn next_live_event(stream: &mut BodyDataStream) -> Option<(String, Value)> {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), stream.next())
+            .await
+            .expect("No live event in time")?
+            .expect("Failed to read live event");
+        let text = String::from_utf8(chunk.to_vec()).expect("Live event is not utf-8");
+        let field = |name: &str| {
+            text.lines()
+                .find_map(|line| line.strip_prefix(name))
+                .unwrap_or_default()
+                .to_owned()
+        };
+        let data = serde_json::from_str(&field("data: ")).expect("Live event data is not JSON");
+        Some((field("event: "), data))
+    }
+
+    #[tokio::test]
+    async fn test_live_timeline_retracts_hidden_echos_and_ends_on_revoke() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        app.register("bob").await;
+        let mut alice = app.login("alice").await;
+        let mut bob_live = app.login("bob").await;
+        let mut bob = app.login("bob").await;
+        let res = app
+            .open(&mut bob_live, Method::GET, "/api/v1/echo/live")
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let mut stream = res.into_body().into_data_stream();
+
+        let echo_id = app.add_echo(&mut alice, "hello", &[], false).await;
+        let (event, data) = next_live_event(&mut stream).await.unwrap();
+        assert_eq!((event.as_str(), &data["id"]), ("created", &json!(echo_id)));
+        // made private, so it has to leave bob's timeline
+        let body = json!({
+            "echo_id": echo_id,
+            "content": "hello",
+            "echo_permission_ids": [],
+            "is_private": true,
+        });
+        let res = app
+            .send(&mut alice, Method::PATCH, "/api/v1/echo", Some(body))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let (event, data) = next_live_event(&mut stream).await.unwrap();
+        assert_eq!(
+            (event.as_str(), data),
+            ("deleted", json!({ "id": echo_id }))
+        );
+
+        let res = app
+            .send(
+                &mut bob,
+                Method::DELETE,
+                "/api/v1/user/sessions",
+                Some(json!({ "type": "others" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        app.add_echo(&mut alice, "anyone there?", &[], false).await;
+        assert!(next_live_event(&mut stream).await.is_none());
+    }
+}
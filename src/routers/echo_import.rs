@@ -0,0 +1,92 @@
+use crate::models::api::prelude::*;
+use crate::models::echo_import::{ImportOptions, ImportReport, ImportSource};
+use crate::models::session::BasicAuthData;
+use crate::models::users::Role;
+use crate::services::echo_import::{EchoImportError, EchoImportService};
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use axum::Json;
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use serde::Deserialize;
+use std::sync::Arc;
+
+pub type EchoImportRouterState = State<(
+    Arc<EchoState>,
+    Arc<HybridCacheService>,
+    Arc<EchoImportService>,
+)>;
+
+/// Exports are uploaded in one piece, unlike resources
+pub const IMPORT_BODY_LIMIT: usize = 256 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct ImportEchoQuery {
+    pub source: ImportSource,
+    /// `author=user_id` pairs separated by commas
+    pub user_map: Option<String>,
+    pub default_user_id: Option<i64>,
+    pub image_permission_id: Option<i64>,
+    pub protected_permission_id: Option<i64>,
+    pub source_base_url: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+impl ImportEchoQuery {
+    fn options(&self) -> ApiResult<ImportOptions> {
+        let user_map = self
+            .user_map
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|it| !it.trim().is_empty())
+            .map(|pair| {
+                pair.rsplit_once('=')
+                    .and_then(|(author, id)| {
+                        Some((author.trim().to_string(), id.trim().parse().ok()?))
+                    })
+                    .ok_or_else(|| bad_request!(format!("Invalid user mapping: {pair}")))
+            })
+            .collect::<ApiResult<_>>()?;
+        Ok(ImportOptions {
+            user_map,
+            default_user_id: self.default_user_id,
+            image_permission_id: self.image_permission_id,
+            protected_permission_id: self.protected_permission_id,
+            source_base_url: self.source_base_url.clone(),
+            // local files of the source instance are not reachable from here
+            assets_dir: None,
+            dry_run: self.dry_run,
+        })
+    }
+}
+
+/// The body is the raw export, either a JSON document or a SQLite database
+pub async fn import_echos(
+    current_user_info: BasicAuthData,
+    State((_, cache, importer)): EchoImportRouterState,
+    Query(query): Query<ImportEchoQuery>,
+    body: Bytes,
+) -> ApiResult<Json<GeneralResponse<ImportReport>>> {
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    if current_user.role != Role::Admin {
+        return Err(bad_request!("You are not allowed to import echos"));
+    }
+    let options = query.options()?;
+    let report = importer
+        .import_bytes(query.source, &body, &options)
+        .await
+        .map_err(|e| match e {
+            EchoImportError::SerdeJson(_)
+            | EchoImportError::UnsupportedSchema(_)
+            | EchoImportError::SourceDatabase(_)
+            | EchoImportError::InvalidOption(_) => bad_request!(e, "Invalid import"),
+            e => internal!(e, "Failed to import echos"),
+        })?;
+    Ok(general_json_res!("Import finished", report))
+}
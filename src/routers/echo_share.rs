@@ -0,0 +1,222 @@
+use crate::models::api::prelude::*;
+use crate::models::echo::Echo;
+use crate::models::echo_share::EchoShareItem;
+use crate::models::session::BasicAuthData;
+use crate::services::echo_share::{EchoShareError, EchoShareService};
+use crate::services::res_manager::ExchangedShareItem;
+use crate::services::states::EchoState;
+use crate::services::states::db::{PageQueryBinder, PageQueryResult};
+use axum::Json;
+use axum::extract::{Query, State};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use time::Duration;
+
+pub type EchoShareRouterState = State<(Arc<EchoState>, Arc<EchoShareService>)>;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareReq {
+    echo_id: i64,
+    /// Permissions the echo is rendered with for whoever opens the link
+    #[serde(default)]
+    permission_ids: Vec<i64>,
+    /// Seconds until the link expires, a week if absent
+    ttl_secs: Option<i64>,
+    #[serde(default)]
+    one_time: bool,
+}
+
+pub async fn create_share(
+    current_user_info: BasicAuthData,
+    State((_, share)): EchoShareRouterState,
+    Json(req): Json<CreateShareReq>,
+) -> ApiResult<Json<GeneralResponse<EchoShareItem>>> {
+    let item = share
+        .create_share(
+            current_user_info.user_id,
+            req.echo_id,
+            req.permission_ids,
+            req.ttl_secs.map(Duration::seconds),
+            req.one_time,
+        )
+        .await
+        .map_err(|e| match e {
+            EchoShareError::EchoNotFound => bad_request!(e, "Echo not found"),
+            EchoShareError::NotOwner => bad_request!(e, "Can only share your own echo"),
+            EchoShareError::PermissionNotHeld(_) => {
+                bad_request!(e, "Cannot share with permissions you do not hold")
+            }
+            EchoShareError::InvalidTtl => bad_request!(e, "Invalid share link lifetime"),
+            e => internal!(e, "Failed to create share link"),
+        })?;
+    Ok(general_json_res!("Share link created successfully", item))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListSharesReq {
+    echo_id: Option<i64>,
+    #[serde(flatten)]
+    page_query: PageQueryBinder,
+}
+
+pub async fn list_shares(
+    current_user_info: BasicAuthData,
+    State((_, share)): EchoShareRouterState,
+    Json(req): Json<ListSharesReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<EchoShareItem>>>> {
+    let res = share
+        .list_shares(current_user_info.user_id, req.echo_id, req.page_query)
+        .await
+        .map_err(|e| internal!(e, "Failed to list share links"))?;
+    Ok(general_json_res!("Share links fetched successfully", res))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeShareReq {
+    share_id: i64,
+}
+
+pub async fn revoke_share(
+    current_user_info: BasicAuthData,
+    State((_, share)): EchoShareRouterState,
+    Json(req): Json<RevokeShareReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    share
+        .revoke_share(current_user_info.user_id, req.share_id)
+        .await
+        .map_err(|e| match e {
+            EchoShareError::NotFound => not_found!(e, "Share link not found"),
+            e => internal!(e, "Failed to revoke share link"),
+        })?;
+    Ok(general_json_res!("Share link revoked successfully"))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ViewShareRes {
+    /// Absent until a one-time link is confirmed
+    echo: Option<Echo>,
+    needs_confirm: bool,
+}
+
+fn map_open_share_err(e: EchoShareError) -> ApiError {
+    match e {
+        EchoShareError::ResManager(_) => unauthorized!(e, "Failed to verify share sign"),
+        EchoShareError::Unavailable => not_found!(e, "Share link not available"),
+        e => internal!(e, "Failed to open share link"),
+    }
+}
+
+/// The link is the only credential, no session needed. Link previews fetch with `GET`,
+/// so a one-time link only tells that it has to be confirmed through [`confirm_share`].
+pub async fn view_share(
+    State((state, share)): EchoShareRouterState,
+    Query(q): Query<ExchangedShareItem>,
+) -> ApiResult<Json<GeneralResponse<ViewShareRes>>> {
+    if share
+        .peek_share(&q)
+        .await
+        .map_err(map_open_share_err)?
+        .one_time
+    {
+        return Ok(general_json_res!(
+            "Confirm to open this one-time share link",
+            ViewShareRes {
+                echo: None,
+                needs_confirm: true,
+            }
+        ));
+    }
+    confirm_share(State((state, share)), Query(q)).await
+}
+
+/// Opens the link, using up a one-time link
+pub async fn confirm_share(
+    State((_, share)): EchoShareRouterState,
+    Query(q): Query<ExchangedShareItem>,
+) -> ApiResult<Json<GeneralResponse<ViewShareRes>>> {
+    let echo = share.open_share(&q).await.map_err(map_open_share_err)?;
+    Ok(general_json_res!(
+        "Shared echo fetched successfully",
+        ViewShareRes {
+            echo: Some(echo),
+            needs_confirm: false,
+        }
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_one_time_share_link_is_used_up_on_confirm() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let echo_id = app
+            .add_echo(&mut alice, "for your eyes only", &[], true)
+            .await;
+        let res = app
+            .send(
+                &mut alice,
+                Method::PUT,
+                "/api/v1/share",
+                Some(json!({ "echo_id": echo_id, "one_time": true })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let url = res.data()["url"].as_str().expect("Missing url").to_owned();
+        let mut visitor = TestClient::default();
+
+        // previews do not use the link up
+        for _ in 0..2 {
+            let res = app.send(&mut visitor, Method::GET, &url, None).await;
+            assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+            assert_eq!(res.data()["needs_confirm"], true);
+            assert!(res.data()["echo"].is_null());
+        }
+        let res = app.send(&mut visitor, Method::POST, &url, None).await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(res.data()["echo"]["id"], echo_id);
+        for method in [Method::POST, Method::GET] {
+            let res = app.send(&mut visitor, method, &url, None).await;
+            assert_eq!(res.status, StatusCode::NOT_FOUND, "{:?}", res.body);
+        }
+        // a tampered link is refused before anything else
+        let res = app
+            .send(
+                &mut visitor,
+                Method::GET,
+                &url.replace("share_id=", "share_id=9"),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED, "{:?}", res.body);
+    }
+
+    #[tokio::test]
+    async fn test_reusable_share_link_opens_directly() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let echo_id = app.add_echo(&mut alice, "for friends", &[], true).await;
+        let res = app
+            .send(
+                &mut alice,
+                Method::PUT,
+                "/api/v1/share",
+                Some(json!({ "echo_id": echo_id })),
+            )
+            .await;
+        let url = res.data()["url"].as_str().expect("Missing url").to_owned();
+        let mut visitor = TestClient::default();
+        for _ in 0..2 {
+            let res = app.send(&mut visitor, Method::GET, &url, None).await;
+            assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+            assert_eq!(res.data()["needs_confirm"], false);
+            assert_eq!(res.data()["echo"]["id"], echo_id);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::get_batch_tuple;
+use crate::gladiator::prelude::OutGoingEchoSSRConsCtx;
+use crate::models::api::prelude::*;
+use crate::models::dyn_setting::{EnableFeeds, SiteUrl};
+use crate::models::echo::Echo;
+use crate::services::echo_baker::EchoBaker;
+use crate::services::feed::{FeedChannel, FeedEntry, render_atom, render_rss};
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use crate::services::states::db::EchoDatabaseExecutor;
+use ahash::HashMap;
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::SystemTime;
+use time::OffsetDateTime;
+
+pub type FeedRouterState = State<(
+    Arc<EchoState>,
+    Arc<HybridCacheService>,
+    Arc<EchoBaker<'static>>,
+)>;
+
+/// Max number of entries in a single feed document
+const FEED_ITEM_LIMIT: u32 = 50;
+
+#[derive(Debug, Clone, Copy)]
+enum FeedKind {
+    Rss,
+    Atom,
+}
+
+impl FeedKind {
+    fn path(&self) -> &'static str {
+        match self {
+            FeedKind::Rss => "rss",
+            FeedKind::Atom => "atom",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            FeedKind::Rss => "application/rss+xml; charset=utf-8",
+            FeedKind::Atom => "application/atom+xml; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    pub user_id: Option<i64>,
+}
+
+/// Weak validator derived from the ids and modification times of the feed entries
+fn feed_etag(kind: FeedKind, user_id: Option<i64>, echos: &[Echo]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(kind.path());
+    hasher.update(user_id.unwrap_or_default().to_le_bytes());
+    for echo in echos {
+        hasher.update(echo.id.to_le_bytes());
+        hasher.update(echo.last_modified_at.unix_timestamp().to_le_bytes());
+    }
+    format!("W/\"{}\"", hex::encode(&hasher.finalize()[..16]))
+}
+
+fn not_modified(headers: &HeaderMap, etag: &str, last_modified: OffsetDateTime) -> bool {
+    // If-None-Match takes precedence over If-Modified-Since (RFC 9110 13.1.3)
+    if let Some(inm) = headers.get(header::IF_NONE_MATCH) {
+        return inm
+            .to_str()
+            .map(|v| v.split(',').any(|it| it.trim() == etag || it.trim() == "*"))
+            .unwrap_or(false);
+    }
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .map(|since| OffsetDateTime::from(since).unix_timestamp() >= last_modified.unix_timestamp())
+        .unwrap_or(false)
+}
+
+async fn serve_feed(
+    kind: FeedKind,
+    state: Arc<EchoState>,
+    cache: Arc<HybridCacheService>,
+    baker: Arc<EchoBaker<'static>>,
+    query: FeedQuery,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let (enable_feeds, site_url) = get_batch_tuple!(cache.dyn_settings, EnableFeeds, SiteUrl)
+        .map_err(|e| internal!(e, "Failed to get dynamic settings"))?;
+    if !enable_feeds {
+        return Err(not_found!("Feeds are disabled"));
+    }
+    let site_url = site_url.trim_end_matches('/').to_string();
+    // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
+    let echos: Vec<Echo> = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.echo()
+                .query_public_echo_feed(query.user_id, None, FEED_ITEM_LIMIT)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch echo"))?;
+    let last_modified = echos
+        .iter()
+        .map(|it| it.last_modified_at)
+        .max()
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let etag = feed_etag(kind, query.user_id, &echos);
+    let validators = [
+        (header::ETAG, etag.clone()),
+        (
+            header::LAST_MODIFIED,
+            httpdate::fmt_http_date(SystemTime::from(last_modified)),
+        ),
+    ];
+    if not_modified(&headers, &etag, last_modified) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
+    }
+    let feed_owner = match query.user_id {
+        Some(user_id) => Some(
+            cache
+                .users
+                .get_user_by_user_id(user_id)
+                .await
+                .map_err(|e| bad_request!(e, "User not found"))?,
+        ),
+        None => None,
+    };
+    let mut authors = HashMap::default();
+    let mut entries = Vec::with_capacity(echos.len());
+    for echo in &echos {
+        let author = match authors.get(&echo.user_id) {
+            Some(author) => Arc::clone(author),
+            None => {
+                let author = cache
+                    .users
+                    .get_user_by_user_id(echo.user_id)
+                    .await
+                    .map_err(|e| internal!(e, "Failed to fetch user"))?;
+                authors.insert(echo.user_id, author.clone());
+                author
+            }
+        };
+        let ctx = OutGoingEchoSSRConsCtx::public(echo.user_id, &site_url);
+        // Empty permission set, so every gated span is redacted
+        let content_html = baker
+            .post_inner_echo_with_ctx(
+                Arc::downgrade(&state),
+                echo,
+                ctx,
+                &[] as &[i64],
+                EchoBaker::all_ext_ids(),
+            )
+            .map_err(|e| internal!(e, "Failed to bake echo content"))?
+            .unwrap_or_default();
+        entries.push(FeedEntry {
+            link: format!("{site_url}/echo/{}", echo.id),
+            title: format!("{} #{}", author.username, echo.id),
+            author: author.username.clone(),
+            content_html,
+            published: echo.created_at,
+            updated: echo.last_modified_at,
+        });
+    }
+    let (title, self_link) = match &feed_owner {
+        Some(owner) => (
+            format!("Echo · {}", owner.username),
+            format!(
+                "{site_url}/api/v1/feed/{}?user_id={}",
+                kind.path(),
+                owner.id
+            ),
+        ),
+        None => (
+            "Echo".to_string(),
+            format!("{site_url}/api/v1/feed/{}", kind.path()),
+        ),
+    };
+    let channel = FeedChannel {
+        title,
+        link: site_url.clone(),
+        self_link,
+        description: "Public echos".to_string(),
+        updated: last_modified,
+    };
+    let body = match kind {
+        FeedKind::Rss => render_rss(&channel, &entries),
+        FeedKind::Atom => render_atom(&channel, &entries),
+    }
+    .map_err(|e| internal!(e, "Failed to render feed"))?;
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(kind.content_type()),
+        )],
+        validators,
+        body,
+    )
+        .into_response())
+}
+
+pub async fn get_rss_feed(
+    State((state, cache, baker)): FeedRouterState,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    serve_feed(FeedKind::Rss, state, cache, baker, query, headers).await
+}
+
+pub async fn get_atom_feed(
+    State((state, cache, baker)): FeedRouterState,
+    Query(query): Query<FeedQuery>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    serve_feed(FeedKind::Atom, state, cache, baker, query, headers).await
+}
@@ -0,0 +1,125 @@
+use crate::models::api::prelude::*;
+use crate::models::follow::FollowItem;
+use crate::models::session::BasicAuthData;
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
+};
+use axum::Json;
+use axum::extract::State;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub type FollowRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>)>;
+
+#[derive(Debug, Deserialize)]
+pub struct FollowReq {
+    user_id: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FollowRes {
+    /// `false` if nothing changed, e.g. the user was followed already
+    changed: bool,
+}
+
+pub async fn follow_user(
+    current_user_info: BasicAuthData,
+    State((state, cache)): FollowRouterState,
+    Json(req): Json<FollowReq>,
+) -> ApiResult<Json<GeneralResponse<FollowRes>>> {
+    if req.user_id == current_user_info.user_id {
+        return Err(bad_request!("You cannot follow yourself"));
+    }
+    let relations = cache
+        .relations
+        .get_relations(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch blocks and mutes"))?;
+    if relations.is_blocked_with(req.user_id) {
+        return Err(forbidden!("You cannot follow this user"));
+    }
+    let changed = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.follow()
+                .follow(current_user_info.user_id, req.user_id)
+                .await
+        })
+        .await
+        .map_err(|e| match e {
+            DataBaseError::ForeignKeyViolation { .. } => not_found!("User not found"),
+            e => internal!(e, "Failed to follow user"),
+        })?;
+    Ok(general_json_res!(
+        "Successfully followed user",
+        FollowRes { changed }
+    ))
+}
+
+pub async fn unfollow_user(
+    current_user_info: BasicAuthData,
+    State((state, _)): FollowRouterState,
+    Json(req): Json<FollowReq>,
+) -> ApiResult<Json<GeneralResponse<FollowRes>>> {
+    let changed = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.follow()
+                .unfollow(current_user_info.user_id, req.user_id)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to unfollow user"))?;
+    Ok(general_json_res!(
+        "Successfully unfollowed user",
+        FollowRes { changed }
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListFollowsReq {
+    /// Defaults to the current user
+    user_id: Option<i64>,
+    #[serde(flatten)]
+    page_query: PageQueryBinder,
+}
+
+pub async fn list_following(
+    current_user_info: BasicAuthData,
+    State((state, _)): FollowRouterState,
+    Json(req): Json<ListFollowsReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<FollowItem>>>> {
+    let user_id = req.user_id.unwrap_or(current_user_info.user_id);
+    let following = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.follow().list_following(user_id, req.page_query).await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to list following"))?;
+    Ok(general_json_res!(
+        "Successfully listed following",
+        following
+    ))
+}
+
+pub async fn list_followers(
+    current_user_info: BasicAuthData,
+    State((state, _)): FollowRouterState,
+    Json(req): Json<ListFollowsReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<FollowItem>>>> {
+    let user_id = req.user_id.unwrap_or(current_user_info.user_id);
+    let followers = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.follow().list_followers(user_id, req.page_query).await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to list followers"))?;
+    Ok(general_json_res!(
+        "Successfully listed followers",
+        followers
+    ))
+}
@@ -1,7 +1,10 @@
+use crate::layers::client_info::ClientInfo;
 use crate::models::api::prelude::*;
+use crate::models::audit::{AuditAction, NewAuditLog};
 use crate::models::invite_code::InviteCodeRaw;
 use crate::models::session::BasicAuthData;
 use crate::models::users::Role;
+use crate::services::audit::AuditService;
 use crate::services::hybrid_cache::HybridCacheService;
 use crate::services::states::EchoState;
 use crate::services::states::db::{EchoDatabaseExecutor, PageQueryBinder, PageQueryResult};
@@ -10,10 +13,12 @@ use axum::extract::State;
 use rand::Rng;
 use rand::distr::Alphanumeric;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 use time::OffsetDateTime;
 
-pub type InviteCodeRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>)>;
+pub type InviteCodeRouterState =
+    State<(Arc<EchoState>, Arc<HybridCacheService>, Arc<AuditService>)>;
 
 #[derive(Debug, Deserialize)]
 pub struct CreateInviteCodeReq {
@@ -29,8 +34,9 @@ pub struct CreateInviteCodeRes {
 }
 
 pub async fn create_invite_code(
+    client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((state, cache)): InviteCodeRouterState,
+    State((state, cache, audit)): InviteCodeRouterState,
     Json(req): Json<CreateInviteCodeReq>,
 ) -> ApiResult<Json<GeneralResponse<CreateInviteCodeRes>>> {
     let current_user = cache
@@ -62,6 +68,15 @@ pub async fn create_invite_code(
         })
         .await
         .map_err(|e| internal!(e, "Failed to create invitation code"))?;
+    // The code itself stays out of the log, it is still usable
+    let log = NewAuditLog::new(
+        AuditAction::InviteCodeCreate,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .target(id.to_string())
+    .after(json!({ "exp_time": exp_unix }));
+    audit.record(log).await;
     Ok(general_json_res!(
         "Invitation code created",
         CreateInviteCodeRes { id, code, exp_time }
@@ -76,7 +91,7 @@ pub struct InviteCodeListQueryReq {
 
 pub async fn list_invite_codes(
     current_user_info: BasicAuthData,
-    State((state, cache)): InviteCodeRouterState,
+    State((state, cache, _)): InviteCodeRouterState,
     Json(req): Json<InviteCodeListQueryReq>,
 ) -> ApiResult<Json<GeneralResponse<PageQueryResult<InviteCodeRaw>>>> {
     let current_user = cache
@@ -105,8 +120,9 @@ pub struct InvalidateInviteCodeReq {
 }
 
 pub async fn revoke_invite_code(
+    client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((state, cache)): InviteCodeRouterState,
+    State((state, cache, audit)): InviteCodeRouterState,
     Json(req): Json<InvalidateInviteCodeReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -131,5 +147,12 @@ pub async fn revoke_invite_code(
         })
         .await
         .map_err(|e| internal!(e, "Failed to invalidate invitation codes"))?;
+    let log = NewAuditLog::new(
+        AuditAction::InviteCodeRevoke,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .before(json!({ "codes": req.code }));
+    audit.record(log).await;
     Ok(general_json_res!("Invitation code invalidated"))
 }
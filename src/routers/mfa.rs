@@ -1,27 +1,37 @@
 use crate::layers::client_info::ClientInfo;
 use crate::layers::session::SessionHelper;
 use crate::models::api::prelude::*;
+use crate::models::audit::{AuditAction, NewAuditLog};
 use crate::models::mfa::{
-    MFAAuthMethod, MFAOpType, MfaAuthLog, MfaInfo, NewMfaAuthLog, NewMfaAuthLogInfo,
-    WebauthnCredential,
+    MFAAuthMethod, MFAOpType, MfaAuthLog, MfaInfo, MfaNonCompliantUser, NewMfaAuthLog,
+    NewMfaAuthLogInfo, WebauthnCredential,
 };
 use crate::models::session::BasicAuthData;
 use crate::models::users::Role;
+use crate::routers::user::{UserLoginRes, start_user_session};
+use crate::services::audit::AuditService;
 use crate::services::hybrid_cache::HybridCacheService;
-use crate::services::mfa::MFAService;
+use crate::services::mfa::{MFAService, TotpCheck};
+use crate::services::mfa_policy::MfaPolicyService;
 use crate::services::states::EchoState;
 use crate::services::states::cache::MokaExpiration;
-use crate::services::states::db::{
-    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
-};
+use crate::services::states::config::MfaConfig;
+use crate::services::states::db::{EchoDatabaseExecutor, PageQueryBinder, PageQueryResult};
 use axum::Json;
 use axum::extract::{Query, State};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 use time::{Duration, OffsetDateTime};
 use webauthn_rs::prelude::*;
 
-pub type MFARouterState = State<(Arc<EchoState>, Arc<MFAService>, Arc<HybridCacheService>)>;
+pub type MFARouterState = State<(
+    Arc<EchoState>,
+    Arc<MFAService>,
+    Arc<HybridCacheService>,
+    Arc<MfaPolicyService>,
+    Arc<AuditService>,
+)>;
 
 #[derive(Debug, Serialize)]
 pub struct TotpSetupRes {
@@ -29,33 +39,36 @@ pub struct TotpSetupRes {
     totp_uri: String,
 }
 
+/// A password session may only set up the first factor, adding another one to an account
+/// which already has MFA would otherwise get around it
+async fn check_may_add_totp(
+    state: &EchoState,
+    session: &SessionHelper,
+    user_id: i64,
+) -> ApiResult<()> {
+    let mfa_enabled = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| exec.mfa().mfa_enabled(user_id).await)
+        .await
+        .map_err(|e| internal!(e, "Failed to check if MFA is enabled for user"))?;
+    if mfa_enabled {
+        session.extract_mfa_auth()?;
+        session.require_fresh_mfa(user_id).await?;
+    }
+    Ok(())
+}
+
 pub async fn totp_setup_start(
+    session: SessionHelper,
     current_user_info: BasicAuthData,
-    State((state, mfa_service, cache)): MFARouterState,
+    State((state, mfa_service, cache, _, _)): MFARouterState,
 ) -> ApiResult<Json<GeneralResponse<TotpSetupRes>>> {
-    let current_user_id = current_user_info.user_id;
+    check_may_add_totp(&state, &session, current_user_info.user_id).await?;
     let current_user = cache
         .users
         .get_user_by_user_id(current_user_info.user_id)
         .await
         .map_err(|e| internal!(e, "Failed to fetch user"))?;
-    state
-        .db
-        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
-            if let Some(info) = exec
-                .mfa()
-                .list_user_totp_credential(current_user_id)
-                .await
-                .map_err(|e| internal!(e, "Failed to get MFA info"))?
-            {
-                return Err(conflict!(format!(
-                    "TOTP already configured at {}, last used at {:?}",
-                    info.created_at, info.last_used_at
-                )));
-            }
-            Ok(())
-        })
-        .await?;
     let totp = Arc::new(
         mfa_service
             .generate_totp(current_user.username.clone())
@@ -78,19 +91,37 @@ pub async fn totp_setup_start(
     ))
 }
 
+#[derive(Debug, Serialize)]
+pub struct MfaSetupRes {
+    /// Only present when the user had no recovery codes left
+    recovery_codes: Option<Vec<String>>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TotpFinishReq {
     totp_sess: Uuid,
     code: String,
+    /// Tells the authenticators of a user apart
+    label: String,
+}
+
+fn check_totp_label(label: &str) -> ApiResult<String> {
+    let label = label.trim();
+    if label.is_empty() || label.chars().count() > 64 {
+        return Err(bad_request!("Label must be 1 to 64 characters long"));
+    }
+    Ok(label.to_string())
 }
 
 pub async fn totp_setup_finish(
     session: SessionHelper,
     client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((state, mfa_service, _)): MFARouterState,
+    State((state, mfa_service, _, _, _)): MFARouterState,
     Json(req): Json<TotpFinishReq>,
-) -> ApiResult<Json<GeneralResponse<()>>> {
+) -> ApiResult<Json<GeneralResponse<MfaSetupRes>>> {
+    check_may_add_totp(&state, &session, current_user_info.user_id).await?;
+    let label = check_totp_label(&req.label)?;
     let (_, totp) = state
         .cache
         .get_totp_flow(req.totp_sess)
@@ -98,33 +129,48 @@ pub async fn totp_setup_finish(
         .ok_or_else(|| {
             bad_request!("TOTP setup session not found or expired, please start over")
         })?;
-    let current = totp
-        .generate_current()
-        .map_err(|e| internal!(e, "Failed to generate current TOTP from cached secret"))?;
-    if current != req.code {
+    let TotpCheck::Accepted(time_step) = mfa_service
+        .check_totp(&totp, &req.code, None)
+        .map_err(|e| internal!(e, "Failed to check TOTP code"))?
+    else {
         return Err(bad_request!("Invalid TOTP code"));
-    }
-    state
+    };
+    let prepared =
+        prepare_initial_recovery_codes(&state, &mfa_service, current_user_info.user_id).await?;
+    let recovery_codes = state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
             let cred = mfa_service
-                .save_totp(current_user_info.user_id, &totp)
+                .save_totp(current_user_info.user_id, label, &totp, time_step)
                 .map_err(|e| internal!(e, "Failed to serialize TOTP"))?;
             exec.mfa()
-                .insert_totp_credential(cred, client_info.ip_address, client_info.user_agent)
+                .insert_totp_credential(
+                    cred,
+                    client_info.ip_address.clone(),
+                    client_info.user_agent.clone(),
+                )
                 .await
                 .map_err(|e| internal!(e, "Failed to save TOTP credential"))?;
             exec.mfa()
                 .enable_mfa(current_user_info.user_id)
                 .await
                 .map_err(|e| internal!(e, "Failed to enable mfa"))?;
-            Ok::<_, ApiError>(())
+            issue_initial_recovery_codes(
+                &mut exec,
+                prepared,
+                client_info,
+                current_user_info.user_id,
+            )
+            .await
         })
         .await?;
     session
         .sign_pre_mfa()
         .map_err(|e| internal!(e, "Failed to sign pre-MFA auth session after setup TOTP"))?;
-    Ok(general_json_res!("TOTP configured successfully"))
+    Ok(general_json_res!(
+        "TOTP configured successfully",
+        MfaSetupRes { recovery_codes }
+    ))
 }
 
 #[derive(Debug, Deserialize)]
@@ -132,75 +178,201 @@ pub struct TotpVerifyReq {
     code: String,
 }
 
+/// Whether the user is still locked out of `auth_method` after too many failures
+async fn is_mfa_locked_out(
+    exec: &mut EchoDatabaseExecutor<'_>,
+    config: &MfaConfig,
+    user_id: i64,
+    auth_method: MFAAuthMethod,
+) -> ApiResult<bool> {
+    let lockout = Duration::minutes(config.lockout_minutes as i64);
+    let locked_at = exec
+        .mfa()
+        .get_last_lockout(user_id, auth_method)
+        .await
+        .map_err(|e| internal!(e, "Failed to load MFA lockout"))?;
+    Ok(locked_at.is_some_and(|t| t + lockout > OffsetDateTime::now_utc()))
+}
+
+/// Logs a failed attempt and locks the user out once there are too many in a row
+async fn record_mfa_failure(
+    exec: &mut EchoDatabaseExecutor<'_>,
+    config: &MfaConfig,
+    client_info: &ClientInfo,
+    user_id: i64,
+    auth_method: MFAAuthMethod,
+    credential_id: Option<i64>,
+    error_message: &str,
+) -> ApiResult<()> {
+    let new_log = |op_type, error_message| NewMfaAuthLog {
+        user_id,
+        op_type,
+        info: NewMfaAuthLogInfo {
+            auth_method,
+            is_success: false,
+            ip_address: client_info.ip_address.clone(),
+            user_agent: client_info.user_agent.clone(),
+            credential_id,
+            error_message: Some(error_message),
+        },
+    };
+    exec.mfa()
+        .insert_mfa_op_access_log(user_id, new_log(MFAOpType::Auth, error_message.to_string()))
+        .await
+        .map_err(|e| internal!(e, "Failed to insert MFA log"))?;
+    let lockout = Duration::minutes(config.lockout_minutes as i64);
+    let failures = exec
+        .mfa()
+        .count_recent_auth_failures(user_id, auth_method, OffsetDateTime::now_utc() - lockout)
+        .await
+        .map_err(|e| internal!(e, "Failed to count MFA failures"))?;
+    if failures >= config.max_failures as i64 {
+        exec.mfa()
+            .insert_mfa_op_access_log(
+                user_id,
+                new_log(
+                    MFAOpType::Lockout,
+                    format!("Locked out after {failures} failures"),
+                ),
+            )
+            .await
+            .map_err(|e| internal!(e, "Failed to insert MFA log"))?;
+    }
+    Ok(())
+}
+
+/// Recovery codes for a user who has none left, hashed ahead of the transaction which
+/// stores them, as hashing takes a while
+async fn prepare_initial_recovery_codes(
+    state: &EchoState,
+    mfa_service: &MFAService,
+    user_id: i64,
+) -> ApiResult<Option<(Vec<String>, Vec<String>)>> {
+    let left = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.mfa().count_recovery_codes_left(user_id).await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to count recovery codes"))?;
+    if left > 0 {
+        return Ok(None);
+    }
+    let generated = mfa_service
+        .generate_recovery_codes()
+        .await
+        .map_err(|e| internal!(e, "Failed to generate recovery codes"))?;
+    Ok(Some(generated))
+}
+
+/// The first enrolled method also hands out recovery codes, they are only ever shown this once
+async fn issue_initial_recovery_codes(
+    exec: &mut EchoDatabaseExecutor<'_>,
+    prepared: Option<(Vec<String>, Vec<String>)>,
+    client_info: ClientInfo,
+    user_id: i64,
+) -> ApiResult<Option<Vec<String>>> {
+    let Some((codes, hashes)) = prepared else {
+        return Ok(None);
+    };
+    let left = exec
+        .mfa()
+        .count_recovery_codes_left(user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to count recovery codes"))?;
+    if left > 0 {
+        return Ok(None);
+    }
+    exec.mfa()
+        .replace_recovery_codes(
+            user_id,
+            &hashes,
+            client_info.ip_address,
+            client_info.user_agent,
+        )
+        .await
+        .map_err(|e| internal!(e, "Failed to save recovery codes"))?;
+    Ok(Some(codes))
+}
+
 pub async fn totp_verify(
     session: SessionHelper,
     current_user_info: BasicAuthData,
-    State((state, mfa_service, _)): MFARouterState,
+    State((state, mfa_service, _, _, _)): MFARouterState,
     client_info: ClientInfo,
     Json(req): Json<TotpVerifyReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let user_id = current_user_info.user_id;
-    state
+    let mfa_config = &state.config.mfa;
+    // failures are logged whatever the outcome, so the verdict is only raised after the commit
+    let verdict = state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
-            let cred = exec
+            if is_mfa_locked_out(&mut exec, mfa_config, user_id, MFAAuthMethod::Totp).await? {
+                return Ok(Err(too_many_requests!(
+                    "Too many failed TOTP attempts, please try again later"
+                )));
+            }
+            let creds = exec
                 .mfa()
-                .list_user_totp_credential(user_id)
+                .list_user_totp_credentials(user_id)
                 .await
-                .map_err(|e| internal!(e, "Failed to load TOTP credential"))?
-                .ok_or_else(|| bad_request!("TOTP is not configured"))?;
-            let totp = mfa_service
-                .load_totp(&cred.totp_credential_data)
-                .map_err(|e| internal!(e, "Failed to decode TOTP"))?;
-            let current = totp
-                .generate_current()
-                .map_err(|e| internal!(e, "Failed to generate current TOTP"))?;
-            if current != req.code {
-                exec.mfa()
-                    .insert_mfa_op_access_log(
-                        user_id,
-                        NewMfaAuthLog {
-                            user_id,
-                            op_type: MFAOpType::Auth,
-                            info: NewMfaAuthLogInfo {
-                                auth_method: MFAAuthMethod::Totp,
-                                is_success: false,
-                                ip_address: client_info.ip_address,
-                                user_agent: client_info.user_agent,
-                                credential_id: Some(cred.id),
-                                error_message: Some("Invalid TOTP code".to_string()),
-                            },
-                        },
-                    )
-                    .await
-                    .map_err(|e| internal!(e, "Failed to insert MFA log"))?;
-                return Err(bad_request!("Invalid TOTP code"));
+                .map_err(|e| internal!(e, "Failed to load TOTP credentials"))?;
+            if creds.is_empty() {
+                return Err(bad_request!("TOTP is not configured"));
             }
-            exec.mfa()
-                .update_totp_last_used(user_id)
-                .await
-                .map_err(|e| internal!(e, "Failed to update last used"))?;
-            exec.mfa()
-                .insert_mfa_op_access_log(
-                    user_id,
-                    NewMfaAuthLog {
-                        user_id,
-                        op_type: MFAOpType::Auth,
-                        info: NewMfaAuthLogInfo {
-                            auth_method: MFAAuthMethod::Totp,
-                            is_success: true,
-                            ip_address: client_info.ip_address,
-                            user_agent: client_info.user_agent,
-                            credential_id: Some(cred.id),
-                            error_message: None,
-                        },
-                    },
-                )
-                .await
-                .map_err(|e| internal!(e, "Failed to insert MFA log"))?;
-            Ok(())
+            let mut error_message = "Invalid TOTP code";
+            for cred in creds {
+                let totp = mfa_service
+                    .load_totp(&cred.totp_credential_data)
+                    .map_err(|e| internal!(e, "Failed to decode TOTP"))?;
+                let check = mfa_service
+                    .check_totp(&totp, &req.code, cred.last_time_step)
+                    .map_err(|e| internal!(e, "Failed to check TOTP code"))?;
+                match check {
+                    TotpCheck::Accepted(time_step) => {
+                        exec.mfa()
+                            .update_totp_last_used(cred.id, time_step)
+                            .await
+                            .map_err(|e| internal!(e, "Failed to update last used"))?;
+                        exec.mfa()
+                            .insert_mfa_op_access_log(
+                                user_id,
+                                NewMfaAuthLog {
+                                    user_id,
+                                    op_type: MFAOpType::Auth,
+                                    info: NewMfaAuthLogInfo {
+                                        auth_method: MFAAuthMethod::Totp,
+                                        is_success: true,
+                                        ip_address: client_info.ip_address,
+                                        user_agent: client_info.user_agent,
+                                        credential_id: Some(cred.id),
+                                        error_message: None,
+                                    },
+                                },
+                            )
+                            .await
+                            .map_err(|e| internal!(e, "Failed to insert MFA log"))?;
+                        return Ok(Ok(()));
+                    }
+                    TotpCheck::Replayed => error_message = "TOTP code already used",
+                    TotpCheck::Invalid => {}
+                }
+            }
+            record_mfa_failure(
+                &mut exec,
+                mfa_config,
+                &client_info,
+                user_id,
+                MFAAuthMethod::Totp,
+                None,
+                error_message,
+            )
+            .await?;
+            Ok::<_, ApiError>(Err(bad_request!(msg = error_message)))
         })
         .await?;
+    verdict?;
     session
         .sign_mfa()
         .map_err(|e| internal!(e, "Failed to sign MFA session"))?;
@@ -210,6 +382,7 @@ pub async fn totp_verify(
 #[derive(Debug, Serialize)]
 pub struct TotpListItem {
     id: i64,
+    label: String,
     #[serde(with = "time::serde::timestamp")]
     created_at: OffsetDateTime,
     #[serde(with = "time::serde::timestamp")]
@@ -230,7 +403,7 @@ pub struct TotpListQueryReq {
 
 pub async fn totp_list(
     current_user_info: BasicAuthData,
-    State((state, _, cache)): MFARouterState,
+    State((state, _, cache, _, _)): MFARouterState,
     Query(q): Query<TotpListQueryReq>,
 ) -> ApiResult<Json<GeneralResponse<TotpListRes>>> {
     let current_user = cache
@@ -245,30 +418,70 @@ pub async fn totp_list(
     let list: Vec<TotpListItem> = state
         .db
         .single(async |mut exec: EchoDatabaseExecutor<'_>| {
-            exec.mfa().list_user_totp_credential(q.user_id).await
+            exec.mfa().list_user_totp_credentials(q.user_id).await
         })
         .await
         .map_err(|e| internal!(e, "Failed to list TOTP"))?
+        .into_iter()
         .map(|c| TotpListItem {
             id: c.id,
+            label: c.label,
             created_at: c.created_at,
             updated_at: c.updated_at,
             last_used_at: c.last_used_at,
         })
-        .into_iter()
         .collect();
     Ok(general_json_res!("OK", TotpListRes { list }))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct RenameTotpReq {
+    credential_id: i64,
+    label: String,
+}
+
+pub async fn totp_rename(
+    current_user_info: BasicAuthData,
+    State((state, _, cache, _, _)): MFARouterState,
+    Json(req): Json<RenameTotpReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    let label = check_totp_label(&req.label)?;
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    state
+        .db
+        .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let cred = exec
+                .mfa()
+                .get_totp_credential_by_id(req.credential_id)
+                .await
+                .map_err(|e| internal!(e, "Failed to query TOTP"))?
+                .ok_or_else(|| not_found!("TOTP not found"))?;
+            if cred.user_id != current_user_info.user_id && current_user.role != Role::Admin {
+                return Err(bad_request!("Cannot rename others' TOTP"));
+            }
+            exec.mfa()
+                .rename_totp_credential(req.credential_id, &label)
+                .await
+                .map_err(|e| internal!(e, "Failed to rename TOTP"))
+        })
+        .await?;
+    Ok(general_json_res!("TOTP renamed"))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct DeleteTotpReq {
-    user_id: i64,
+    credential_id: i64,
 }
 
 pub async fn totp_delete(
+    session: SessionHelper,
     current_user_info: BasicAuthData,
     client_info: ClientInfo,
-    State((state, _, cache)): MFARouterState,
+    State((state, _, cache, _, _)): MFARouterState,
     Json(req): Json<DeleteTotpReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -276,25 +489,37 @@ pub async fn totp_delete(
         .get_user_by_user_id(current_user_info.user_id)
         .await
         .map_err(|e| internal!(e, "Failed to fetch user"))?;
-    if req.user_id != current_user_info.user_id && current_user.role != Role::Admin {
-        return Err(bad_request!("Cannot list TOTP for other users"));
-    }
+    session.require_fresh_mfa(current_user_info.user_id).await?;
     state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
-            exec.mfa()
-                .delete_totp_credential(req.user_id, client_info.ip_address, client_info.user_agent)
-                .await?;
-            Ok::<_, DataBaseError>(())
+            let cred = exec
+                .mfa()
+                .get_totp_credential_by_id(req.credential_id)
+                .await
+                .map_err(|e| internal!(e, "Failed to query TOTP"))?;
+            if let Some(cred) = cred {
+                if cred.user_id != current_user_info.user_id && current_user.role != Role::Admin {
+                    return Err(bad_request!("Cannot delete others' TOTP"));
+                }
+                exec.mfa()
+                    .delete_totp_credential(
+                        req.credential_id,
+                        client_info.ip_address,
+                        client_info.user_agent,
+                    )
+                    .await
+                    .map_err(|e| internal!(e, "Failed to delete TOTP"))?;
+            }
+            Ok(())
         })
-        .await
-        .map_err(|e| internal!(e, "Failed to delete TOTP"))?;
+        .await?;
     Ok(general_json_res!("TOTP deleted"))
 }
 
 pub async fn webauthn_setup_start(
     current_user_info: BasicAuthData,
-    State((state, mfa_service, cache)): MFARouterState,
+    State((state, mfa_service, cache, _, _)): MFARouterState,
 ) -> ApiResult<Json<GeneralResponse<CreationChallengeResponse>>> {
     let current_user = cache
         .users
@@ -325,9 +550,9 @@ pub async fn webauthn_setup_finish(
     session: SessionHelper,
     current_user_info: BasicAuthData,
     client_info: ClientInfo,
-    State((state, mfa_service, cache)): MFARouterState,
+    State((state, mfa_service, cache, _, _)): MFARouterState,
     Json(req): Json<RegisterPublicKeyCredential>,
-) -> ApiResult<Json<GeneralResponse<()>>> {
+) -> ApiResult<Json<GeneralResponse<MfaSetupRes>>> {
     let current_user = cache
         .users
         .get_user_by_user_id(current_user_info.user_id)
@@ -341,18 +566,30 @@ pub async fn webauthn_setup_finish(
         )
         .await
         .map_err(|e| internal!(e, "Failed to finish passkey registration"))?;
-    state
+    let prepared =
+        prepare_initial_recovery_codes(&state, &mfa_service, current_user_info.user_id).await?;
+    let recovery_codes = state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
             exec.mfa()
-                .insert_webauthn_credential(cred, client_info.ip_address, client_info.user_agent)
+                .insert_webauthn_credential(
+                    cred,
+                    client_info.ip_address.clone(),
+                    client_info.user_agent.clone(),
+                )
                 .await
                 .map_err(|e| internal!(e, "Failed to insert passkey"))?;
             exec.mfa()
                 .enable_mfa(current_user_info.user_id)
                 .await
                 .map_err(|e| internal!(e, "Failed to enable mfa"))?;
-            Ok::<_, ApiError>(())
+            issue_initial_recovery_codes(
+                &mut exec,
+                prepared,
+                client_info,
+                current_user_info.user_id,
+            )
+            .await
         })
         .await?;
     session.sign_pre_mfa().map_err(|e| {
@@ -361,12 +598,15 @@ pub async fn webauthn_setup_finish(
             "Failed to sign pre-MFA auth session after setup webauthn"
         )
     })?;
-    Ok(general_json_res!("Passkey registered"))
+    Ok(general_json_res!(
+        "Passkey registered",
+        MfaSetupRes { recovery_codes }
+    ))
 }
 
 pub async fn webauthn_auth_start(
     current_user_info: BasicAuthData,
-    State((state, mfa_service, cache)): MFARouterState,
+    State((state, mfa_service, cache, _, _)): MFARouterState,
 ) -> ApiResult<Json<GeneralResponse<RequestChallengeResponse>>> {
     let current_user = cache
         .users
@@ -400,7 +640,7 @@ pub async fn webauthn_auth_start(
 pub async fn webauthn_auth_finish(
     session: SessionHelper,
     current_user_info: BasicAuthData,
-    State((state, mfa_service, cache)): MFARouterState,
+    State((state, mfa_service, cache, _, _)): MFARouterState,
     client_info: ClientInfo,
     Json(req): Json<PublicKeyCredential>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
@@ -471,6 +711,192 @@ pub async fn webauthn_auth_finish(
     Ok(general_json_res!("MFA verified"))
 }
 
+#[derive(Debug, Serialize)]
+pub struct PasskeyLoginStartRes {
+    login_sess: Uuid,
+    challenge: RequestChallengeResponse,
+}
+
+pub async fn passkey_login_start(
+    State((_, mfa_service, _, _, _)): MFARouterState,
+) -> ApiResult<Json<GeneralResponse<PasskeyLoginStartRes>>> {
+    let (login_sess, challenge) = mfa_service
+        .start_passkey_login()
+        .await
+        .map_err(|e| internal!(e, "Failed to start passkey login"))?;
+    Ok(general_json_res!(
+        "OK",
+        PasskeyLoginStartRes {
+            login_sess,
+            challenge
+        }
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginFinishReq {
+    login_sess: Uuid,
+    credential: PublicKeyCredential,
+    /// Shown in the session list to tell devices apart
+    device_name: Option<String>,
+}
+
+pub async fn passkey_login_finish(
+    session: SessionHelper,
+    client_info: ClientInfo,
+    State((state, mfa_service, _, mfa_policy, audit)): MFARouterState,
+    Json(req): Json<PasskeyLoginFinishReq>,
+) -> ApiResult<Json<GeneralResponse<UserLoginRes>>> {
+    let failure = |reason: &str| {
+        NewAuditLog::new(AuditAction::LoginFailed, None, &client_info)
+            .after(json!({ "method": "passkey", "reason": reason }))
+    };
+    let user_unique_uuid = match mfa_service.identify_passkey_login(&req.credential) {
+        Ok(user_unique_uuid) => user_unique_uuid,
+        Err(e) => {
+            audit.record(failure("invalid_response")).await;
+            return Err(bad_request!(e, "Invalid passkey response"));
+        }
+    };
+    let looked_up = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let Some(user_id) = exec
+                .mfa()
+                .get_user_id_by_webauthn_handle(user_unique_uuid)
+                .await
+                .map_err(|e| internal!(e, "Failed to query passkey"))?
+            else {
+                return Ok(Err((
+                    failure("unknown_passkey"),
+                    unauthorized!("Passkey is not registered"),
+                )));
+            };
+            let enabled = exec
+                .mfa()
+                .passkey_login_enabled(user_id)
+                .await
+                .map_err(|e| internal!(e, "Failed to query MFA settings"))?;
+            if !enabled {
+                return Ok(Err((
+                    failure("passkey_login_disabled").target_user(user_id),
+                    unauthorized!("Passkey login is disabled for this user"),
+                )));
+            }
+            let existing = exec
+                .mfa()
+                .list_user_webauthn_credentials(user_id)
+                .await
+                .map_err(|e| internal!(e, "Failed to list existing passkeys"))?
+                .into_iter()
+                .filter(|c| c.discoverable == Some(true))
+                .collect::<Vec<_>>();
+            Ok::<_, ApiError>(Ok((user_id, existing)))
+        })
+        .await?;
+    let (user_id, existing) = match looked_up {
+        Ok(found) => found,
+        Err((log, e)) => {
+            audit.record(log).await;
+            return Err(e);
+        }
+    };
+    let verdict = state
+        .db
+        .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let (credential_id, error) = match mfa_service
+                .finish_passkey_login(req.login_sess, &req.credential, existing)
+                .await
+            {
+                Ok((_, credential_id)) => {
+                    exec.mfa()
+                        .update_webauthn_last_used(credential_id)
+                        .await
+                        .map_err(|e| internal!(e, "Failed to update passkey last used"))?;
+                    (Some(credential_id), None)
+                }
+                Err(e) => (None, Some(e)),
+            };
+            exec.mfa()
+                .insert_mfa_op_access_log(
+                    user_id,
+                    NewMfaAuthLog {
+                        user_id,
+                        op_type: MFAOpType::Auth,
+                        info: NewMfaAuthLogInfo {
+                            auth_method: MFAAuthMethod::Webauthn,
+                            is_success: error.is_none(),
+                            ip_address: client_info.ip_address.clone(),
+                            user_agent: client_info.user_agent.clone(),
+                            credential_id,
+                            error_message: error.as_ref().map(|e| format!("{}", e)),
+                        },
+                    },
+                )
+                .await
+                .map_err(|e| internal!(e, "Failed to insert MFA log"))?;
+            Ok::<_, ApiError>(match error {
+                Some(e) => Err(unauthorized!(e, "Failed to finish passkey login")),
+                None => Ok(()),
+            })
+        })
+        .await?;
+    if let Err(e) = verdict {
+        let log = failure("verification_failed").target_user(user_id);
+        audit.record(log).await;
+        return Err(e);
+    }
+    // a passkey always verifies the user, so it counts as both factors
+    let mut res = start_user_session(
+        &state,
+        &mfa_policy,
+        &audit,
+        &session,
+        client_info,
+        user_id,
+        req.device_name,
+    )
+    .await?;
+    session
+        .sign_mfa()
+        .map_err(|e| internal!(e, "Failed to sign MFA session"))?;
+    res.need_mfa = false;
+    Ok(general_json_res!("User logged in successfully", res))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLoginSettingReq {
+    enabled: bool,
+}
+
+pub async fn set_passkey_login(
+    current_user_info: BasicAuthData,
+    State((state, _, _, _, _)): MFARouterState,
+    Json(req): Json<PasskeyLoginSettingReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            if req.enabled
+                && !exec
+                    .mfa()
+                    .has_discoverable_webauthn_credential(current_user_info.user_id)
+                    .await
+                    .map_err(|e| internal!(e, "Failed to query passkeys"))?
+            {
+                return Err(bad_request!(
+                    "Passkey login needs a passkey stored on the authenticator, register one first"
+                ));
+            }
+            exec.mfa()
+                .set_passkey_login(current_user_info.user_id, req.enabled)
+                .await
+                .map_err(|e| internal!(e, "Failed to update passkey login setting"))
+        })
+        .await?;
+    Ok(general_json_res!("Passkey login setting updated"))
+}
+
 #[derive(Debug, Serialize)]
 pub struct WebauthnListRes {
     list: Vec<WebauthnCredentialInfo>,
@@ -480,6 +906,7 @@ pub struct WebauthnListRes {
 pub struct WebauthnCredentialInfo {
     id: i64,
     user_display_name: Option<String>,
+    discoverable: Option<bool>,
     #[serde(with = "time::serde::timestamp")]
     created_at: OffsetDateTime,
     #[serde(with = "time::serde::timestamp")]
@@ -495,7 +922,7 @@ pub struct WebauthnListQuery {
 
 pub async fn webauthn_list(
     current_user_info: BasicAuthData,
-    State((state, _, cache)): MFARouterState,
+    State((state, _, cache, _, _)): MFARouterState,
     Query(q): Query<WebauthnListQuery>,
 ) -> ApiResult<Json<GeneralResponse<WebauthnListRes>>> {
     let current_user = cache
@@ -518,6 +945,7 @@ pub async fn webauthn_list(
         .map(|c| WebauthnCredentialInfo {
             id: c.id,
             user_display_name: c.user_display_name,
+            discoverable: c.discoverable,
             created_at: c.created_at,
             updated_at: c.updated_at,
             last_used_at: c.last_used_at,
@@ -532,8 +960,9 @@ pub struct DeleteWebauthnQuery {
 }
 
 pub async fn webauthn_delete(
+    session: SessionHelper,
     current_user_info: BasicAuthData,
-    State((state, _, cache)): MFARouterState,
+    State((state, _, cache, _, _)): MFARouterState,
     client_info: ClientInfo,
     Query(q): Query<DeleteWebauthnQuery>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
@@ -542,6 +971,7 @@ pub async fn webauthn_delete(
         .get_user_by_user_id(current_user_info.user_id)
         .await
         .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    session.require_fresh_mfa(current_user_info.user_id).await?;
     state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
@@ -569,6 +999,135 @@ pub async fn webauthn_delete(
     Ok(general_json_res!("Passkey deleted"))
 }
 
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesRes {
+    recovery_codes: Vec<String>,
+}
+
+pub async fn recovery_codes_regenerate(
+    session: SessionHelper,
+    current_user_info: BasicAuthData,
+    client_info: ClientInfo,
+    State((state, mfa_service, _, _, _)): MFARouterState,
+) -> ApiResult<Json<GeneralResponse<RecoveryCodesRes>>> {
+    session.require_fresh_mfa(current_user_info.user_id).await?;
+    let (recovery_codes, hashes) = mfa_service
+        .generate_recovery_codes()
+        .await
+        .map_err(|e| internal!(e, "Failed to generate recovery codes"))?;
+    state
+        .db
+        .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.mfa()
+                .replace_recovery_codes(
+                    current_user_info.user_id,
+                    &hashes,
+                    client_info.ip_address,
+                    client_info.user_agent,
+                )
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to save recovery codes"))?;
+    Ok(general_json_res!(
+        "Recovery codes regenerated",
+        RecoveryCodesRes { recovery_codes }
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RecoveryCodeVerifyReq {
+    code: String,
+}
+
+pub async fn recovery_code_verify(
+    session: SessionHelper,
+    current_user_info: BasicAuthData,
+    State((state, mfa_service, _, _, _)): MFARouterState,
+    client_info: ClientInfo,
+    Json(req): Json<RecoveryCodeVerifyReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    let user_id = current_user_info.user_id;
+    let mfa_config = &state.config.mfa;
+    let method = MFAAuthMethod::RecoveryCode;
+    let locked_out =
+        || too_many_requests!("Too many failed recovery code attempts, please try again later");
+    // the codes are salted, so each unused one is checked in turn outside of the transaction
+    let (is_locked_out, unused) = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let is_locked_out = is_mfa_locked_out(&mut exec, mfa_config, user_id, method).await?;
+            let unused = exec
+                .mfa()
+                .list_unused_recovery_codes(user_id)
+                .await
+                .map_err(|e| internal!(e, "Failed to load recovery codes"))?;
+            Ok::<_, ApiError>((is_locked_out, unused))
+        })
+        .await?;
+    if is_locked_out {
+        return Err(locked_out());
+    }
+    let matched = mfa_service
+        .find_recovery_code(&unused, &req.code)
+        .await
+        .map_err(|e| internal!(e, "Failed to check recovery code"))?;
+    let verdict = state
+        .db
+        .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+            if is_mfa_locked_out(&mut exec, mfa_config, user_id, method).await? {
+                return Ok(Err(locked_out()));
+            }
+            let code_id = match matched {
+                Some(code_id) => exec
+                    .mfa()
+                    .use_recovery_code(user_id, code_id)
+                    .await
+                    .map_err(|e| internal!(e, "Failed to use recovery code"))?,
+                None => None,
+            };
+            let Some(code_id) = code_id else {
+                let error_message = "Invalid recovery code";
+                record_mfa_failure(
+                    &mut exec,
+                    mfa_config,
+                    &client_info,
+                    user_id,
+                    method,
+                    None,
+                    error_message,
+                )
+                .await?;
+                return Ok(Err(bad_request!(msg = error_message)));
+            };
+            exec.mfa()
+                .insert_mfa_op_access_log(
+                    user_id,
+                    NewMfaAuthLog {
+                        user_id,
+                        op_type: MFAOpType::Auth,
+                        info: NewMfaAuthLogInfo {
+                            auth_method: method,
+                            is_success: true,
+                            ip_address: client_info.ip_address,
+                            user_agent: client_info.user_agent,
+                            credential_id: Some(code_id),
+                            error_message: None,
+                        },
+                    },
+                )
+                .await
+                .map_err(|e| internal!(e, "Failed to insert MFA log"))?;
+            Ok::<_, ApiError>(Ok(()))
+        })
+        .await?;
+    verdict?;
+    session
+        .sign_mfa()
+        .map_err(|e| internal!(e, "Failed to sign MFA session"))?;
+    Ok(general_json_res!("MFA verified"))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GetMfaInfoReq {
     user_ids: Vec<i64>,
@@ -576,7 +1135,7 @@ pub struct GetMfaInfoReq {
 
 pub async fn get_mfa_infos(
     current_user_info: BasicAuthData,
-    State((state, _, cache)): MFARouterState,
+    State((state, _, cache, _, _)): MFARouterState,
     Json(req): Json<GetMfaInfoReq>,
 ) -> ApiResult<Json<GeneralResponse<Vec<MfaInfo>>>> {
     let current_user = cache
@@ -615,7 +1174,7 @@ pub struct MfaLogsQueryReq {
 
 pub async fn get_mfa_op_logs(
     current_user_info: BasicAuthData,
-    State((state, _, cache)): MFARouterState,
+    State((state, _, cache, _, _)): MFARouterState,
     Json(req): Json<MfaLogsQueryReq>,
 ) -> ApiResult<Json<GeneralResponse<PageQueryResult<MfaAuthLog>>>> {
     let current_user = cache
@@ -637,3 +1196,249 @@ pub async fn get_mfa_op_logs(
         .map_err(|e| internal!(&e, "Failed to get MFA operation logs!"))?;
     Ok(general_json_res!("OK", list))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct MfaPolicyReportReq {
+    #[serde(flatten)]
+    page_query: PageQueryBinder,
+}
+
+/// Users the MFA policy requires MFA from who have not set it up yet
+pub async fn get_mfa_policy_report(
+    current_user_info: BasicAuthData,
+    State((_, _, cache, mfa_policy, _)): MFARouterState,
+    Json(req): Json<MfaPolicyReportReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<MfaNonCompliantUser>>>> {
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    if current_user.role != Role::Admin {
+        return Err(bad_request!("Only admin can view the MFA policy report"));
+    }
+    let list = mfa_policy
+        .non_compliant_users(req.page_query)
+        .await
+        .map_err(|e| internal!(e, "Failed to get MFA policy report"))?;
+    Ok(general_json_res!("OK", list))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient, TestRes};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use sha2::{Digest, Sha256};
+
+    async fn verify_recovery_code(
+        app: &TestApp,
+        client: &mut TestClient,
+        code: &str,
+    ) -> StatusCode {
+        app.send(
+            client,
+            Method::POST,
+            "/api/v1/mfa/recovery/verify",
+            Some(json!({ "code": code })),
+        )
+        .await
+        .status
+    }
+
+    #[tokio::test]
+    async fn test_recovery_codes_are_salted_and_single_use() {
+        let app = TestApp::new().await;
+        let alice_id = app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let codes = app.enroll_mfa(&mut alice).await;
+        let hashes: Vec<String> =
+            sqlx::query_scalar("SELECT code_hash FROM mfa_recovery_codes WHERE user_id = ?")
+                .bind(alice_id)
+                .fetch_all(&app.pool)
+                .await
+                .unwrap();
+        assert_eq!(hashes.len(), codes.len());
+        assert!(hashes.iter().all(|it| it.starts_with("$argon2id$")));
+
+        let mut phone = app.login("alice").await;
+        assert_eq!(
+            verify_recovery_code(&app, &mut phone, "aaaaa-aaaaa").await,
+            StatusCode::BAD_REQUEST
+        );
+        // typed in sloppily
+        let sloppy = format!(" {} ", codes[0].to_uppercase().replace('-', ""));
+        assert_eq!(
+            verify_recovery_code(&app, &mut phone, &sloppy).await,
+            StatusCode::OK
+        );
+        let mut laptop = app.login("alice").await;
+        assert_eq!(
+            verify_recovery_code(&app, &mut laptop, &codes[0]).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            verify_recovery_code(&app, &mut laptop, &codes[1]).await,
+            StatusCode::OK
+        );
+
+        // codes issued before salting keep working until they are used
+        let legacy_hash = hex::encode(Sha256::digest(b"legacycode"));
+        sqlx::query("INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(alice_id)
+            .bind(legacy_hash)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let mut tablet = app.login("alice").await;
+        assert_eq!(
+            verify_recovery_code(&app, &mut tablet, "legac-ycode").await,
+            StatusCode::OK
+        );
+        let mut desktop = app.login("alice").await;
+        assert_eq!(
+            verify_recovery_code(&app, &mut desktop, "legac-ycode").await,
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login_needs_a_discoverable_credential() {
+        let app = TestApp::new().await;
+        let alice_id = app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let codes = app.enroll_mfa(&mut alice).await;
+        app.verify_mfa(&mut alice, &codes[0]).await;
+        sqlx::query(
+            "INSERT INTO webauthn_credentials (user_id, user_unique_uuid, user_name, credential_data) VALUES (?, ?, ?, ?)",
+        )
+        .bind(alice_id)
+        .bind(uuid::Uuid::new_v4())
+        .bind("alice")
+        .bind(Vec::<u8>::new())
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        let enable = json!({ "enabled": true });
+
+        // the browser never reported whether the key is resident
+        let res = app
+            .send(
+                &mut alice,
+                Method::PATCH,
+                "/api/v1/mfa/webauthn/passwordless",
+                Some(enable.clone()),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        sqlx::query("UPDATE webauthn_credentials SET discoverable = 1 WHERE user_id = ?")
+            .bind(alice_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let res = app
+            .send(
+                &mut alice,
+                Method::PATCH,
+                "/api/v1/mfa/webauthn/passwordless",
+                Some(enable),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+    }
+
+    async fn delete_totp(app: &TestApp, client: &mut TestClient, user_id: i64) -> TestRes {
+        let credential_id: i64 =
+            sqlx::query_scalar("SELECT id FROM totp_credentials WHERE user_id = ?")
+                .bind(user_id)
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        app.send(
+            client,
+            Method::DELETE,
+            "/api/v1/mfa/totp",
+            Some(json!({ "credential_id": credential_id })),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_stale_mfa_cookie_needs_step_up() {
+        let app = TestApp::build(|cfg| cfg.mfa.step_up_minutes = 0, &[]).await;
+        let alice_id = app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let codes = app.enroll_mfa(&mut alice).await;
+        app.verify_mfa(&mut alice, &codes[0]).await;
+        let res = delete_totp(&app, &mut alice, alice_id).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN, "{:?}", res.body);
+        assert_eq!(res.code(), Some(14100));
+        // routes without the freshness requirement still take the cookie
+        let uri = format!("/api/v1/mfa/totp?user_id={alice_id}");
+        let res = app.send(&mut alice, Method::GET, &uri, None).await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+
+        let app = TestApp::new().await;
+        let alice_id = app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let codes = app.enroll_mfa(&mut alice).await;
+        app.verify_mfa(&mut alice, &codes[0]).await;
+        let res = delete_totp(&app, &mut alice, alice_id).await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+    }
+
+    #[tokio::test]
+    async fn test_second_totp_needs_mfa() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let codes = app.enroll_mfa(&mut alice).await;
+        app.verify_mfa(&mut alice, &codes[0]).await;
+
+        // a stolen password must not be enough to bring an own authenticator
+        let mut thief = app.login("alice").await;
+        let res = app
+            .send(
+                &mut thief,
+                Method::POST,
+                "/api/v1/mfa/totp/setup/start",
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED, "{:?}", res.body);
+        let res = app
+            .send(
+                &mut alice,
+                Method::POST,
+                "/api/v1/mfa/totp/setup/start",
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let uri = res.data()["totp_uri"].as_str().unwrap();
+        let code = totp_rs::TOTP::from_url_unchecked(uri)
+            .unwrap()
+            .generate_current()
+            .unwrap();
+        let body = json!({ "totp_sess": res.data()["totp_sess"], "code": code, "label": "Phone" });
+        let res = app
+            .send(
+                &mut thief,
+                Method::POST,
+                "/api/v1/mfa/totp/setup/finish",
+                Some(body.clone()),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED, "{:?}", res.body);
+        let res = app
+            .send(
+                &mut alice,
+                Method::POST,
+                "/api/v1/mfa/totp/setup/finish",
+                Some(body),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+    }
+}
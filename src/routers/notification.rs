@@ -0,0 +1,87 @@
+use crate::models::api::prelude::*;
+use crate::models::notification::NotificationView;
+use crate::models::session::BasicAuthData;
+use crate::services::notification::NotificationService;
+use crate::services::states::EchoState;
+use crate::services::states::db::{EchoDatabaseExecutor, PageQueryBinder, PageQueryResult};
+use axum::Json;
+use axum::extract::State;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub type NotificationRouterState = State<(Arc<EchoState>, Arc<NotificationService>)>;
+
+#[derive(Debug, Deserialize)]
+pub struct ListNotificationsReq {
+    #[serde(default)]
+    pub unread_only: bool,
+    #[serde(flatten)]
+    pub page_query: PageQueryBinder,
+}
+
+pub async fn list_notifications(
+    current_user_info: BasicAuthData,
+    State((_, notifier)): NotificationRouterState,
+    Json(req): Json<ListNotificationsReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<NotificationView>>>> {
+    let res = notifier
+        .list_notifications(current_user_info.user_id, req.unread_only, req.page_query)
+        .await
+        .map_err(|e| internal!(e, "Failed to list notifications"))?;
+    Ok(general_json_res!("Notifications fetched successfully", res))
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadCountRes {
+    pub unread: i64,
+}
+
+pub async fn get_unread_count(
+    current_user_info: BasicAuthData,
+    State((state, _)): NotificationRouterState,
+) -> ApiResult<Json<GeneralResponse<UnreadCountRes>>> {
+    let unread = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.notification()
+                .count_unread(current_user_info.user_id)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to count unread notifications"))?;
+    Ok(general_json_res!(
+        "Unread count fetched successfully",
+        UnreadCountRes { unread }
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MarkReadReq {
+    /// Marks every notification as read if absent
+    pub notification_ids: Option<Vec<i64>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarkReadRes {
+    pub marked: u64,
+}
+
+pub async fn mark_read(
+    current_user_info: BasicAuthData,
+    State((state, _)): NotificationRouterState,
+    Json(req): Json<MarkReadReq>,
+) -> ApiResult<Json<GeneralResponse<MarkReadRes>>> {
+    let marked = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.notification()
+                .mark_read(current_user_info.user_id, req.notification_ids.as_deref())
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to mark notifications as read"))?;
+    Ok(general_json_res!(
+        "Notifications marked as read",
+        MarkReadRes { marked }
+    ))
+}
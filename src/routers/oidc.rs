@@ -0,0 +1,521 @@
+use crate::get_batch_tuple;
+use crate::layers::client_info::ClientInfo;
+use crate::layers::session::SessionHelper;
+use crate::models::api::prelude::*;
+use crate::models::audit::{AuditAction, NewAuditLog};
+use crate::models::dyn_setting::{AllowRegister, RegisterNeedInvitationCode};
+use crate::models::oidc::IdTokenClaims;
+use crate::models::session::{BasicAuthData, OidcFlowData};
+use crate::routers::user::{register_user, start_user_session};
+use crate::services::audit::AuditService;
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::mfa_policy::MfaPolicyService;
+use crate::services::oidc::{OidcError, OidcService};
+use crate::services::password::PasswordService;
+use crate::services::states::EchoState;
+use crate::services::states::config::OidcProviderConfig;
+use crate::services::states::db::{DataBaseError, EchoDatabaseExecutor};
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::response::{IntoResponse, Redirect, Response};
+use base64::{Engine as _, engine::general_purpose as b64_general_engine};
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+
+pub type OidcRouterState = State<(
+    Arc<EchoState>,
+    Arc<HybridCacheService>,
+    Arc<PasswordService>,
+    Arc<OidcService>,
+    Arc<MfaPolicyService>,
+    Arc<AuditService>,
+)>;
+
+fn map_oidc_error(e: OidcError) -> ApiError {
+    match e {
+        OidcError::UnknownProvider(_) => not_found!(e, "Unknown OpenID Connect provider"),
+        OidcError::TokenEndpoint { .. }
+        | OidcError::MalformedToken
+        | OidcError::UnsupportedAlgorithm(_)
+        | OidcError::UnknownKey
+        | OidcError::VerifyFailed
+        | OidcError::InvalidClaim(_) => unauthorized!(e, "OpenID Connect login failed"),
+        e => internal!(e, "Failed to talk to the OpenID Connect provider"),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcLoginQuery {
+    /// Used if the login ends up registering a new user
+    invitation_code: Option<String>,
+    device_name: Option<String>,
+}
+
+pub async fn oidc_login(
+    session: SessionHelper,
+    State((_, _, _, oidc, _, _)): OidcRouterState,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcLoginQuery>,
+) -> ApiResult<Redirect> {
+    let (url, flow) = oidc
+        .authorization_url(&provider, query.invitation_code, query.device_name, None)
+        .await
+        .map_err(map_oidc_error)?;
+    session
+        .start_oidc_flow(flow)
+        .map_err(|e| internal!(e, "Failed to sign OpenID Connect flow"))?;
+    Ok(Redirect::to(url.as_str()))
+}
+
+/// Link an identity at `provider` to the current user, who can log in with it from then on.
+/// This is how existing accounts get an identity, the login never links by username.
+pub async fn oidc_link(
+    session: SessionHelper,
+    current_user_info: BasicAuthData,
+    State((_, _, _, oidc, _, _)): OidcRouterState,
+    Path(provider): Path<String>,
+) -> ApiResult<Redirect> {
+    session.require_fresh_mfa(current_user_info.user_id).await?;
+    let (url, flow) = oidc
+        .authorization_url(&provider, None, None, Some(current_user_info.user_id))
+        .await
+        .map_err(map_oidc_error)?;
+    session
+        .start_oidc_flow(flow)
+        .map_err(|e| internal!(e, "Failed to sign OpenID Connect flow"))?;
+    Ok(Redirect::to(url.as_str()))
+}
+
+/// Send the browser on to `post_login_redirect` with `query` appended
+fn post_login_redirect(config: &OidcProviderConfig, query: &str) -> Option<Response> {
+    let url = config.post_login_redirect.as_ref()?;
+    let separator = if url.contains('?') { '&' } else { '?' };
+    Some(Redirect::to(&format!("{url}{separator}{query}")).into_response())
+}
+
+/// Everything up to trusting the provider's claims, failures come with the reason to audit
+async fn verify_callback(
+    session: &SessionHelper,
+    oidc: &OidcService,
+    provider: &str,
+    query: OidcCallbackQuery,
+) -> Result<(OidcFlowData, IdTokenClaims), (&'static str, ApiError)> {
+    let flow = session
+        .take_oidc_flow()
+        .map_err(|e| {
+            (
+                "no_flow",
+                bad_request!(e, "No OpenID Connect login in progress"),
+            )
+        })?
+        .inner;
+    if flow.provider != provider || query.state.as_ref() != Some(&flow.state) {
+        return Err((
+            "state_mismatch",
+            bad_request!("OpenID Connect state mismatch"),
+        ));
+    }
+    if query.error.is_some() {
+        return Err((
+            "provider_refused",
+            unauthorized!("The provider refused the login"),
+        ));
+    }
+    let Some(code) = query.code else {
+        return Err(("missing_code", bad_request!("Missing authorization code")));
+    };
+    let claims = oidc
+        .finish(&flow, &code)
+        .await
+        .map_err(|e| ("invalid_response", map_oidc_error(e)))?;
+    Ok((flow, claims))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+pub async fn oidc_callback(
+    session: SessionHelper,
+    client_info: ClientInfo,
+    State((state, cache, passwords, oidc, mfa_policy, audit)): OidcRouterState,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> ApiResult<Response> {
+    let (flow, claims) = match verify_callback(&session, &oidc, &provider, query).await {
+        Ok(verified) => verified,
+        Err((reason, e)) => {
+            let log = NewAuditLog::new(AuditAction::LoginFailed, None, &client_info)
+                .target(provider.as_str())
+                .after(json!({ "method": "oidc", "reason": reason }));
+            audit.record(log).await;
+            return Err(e);
+        }
+    };
+    let config = oidc.provider(&provider).map_err(map_oidc_error)?;
+    let linked_user_id = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.oidc()
+                .get_linked_user_id(&config.name, &claims.sub)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to query linked identity"))?;
+    if let Some(link_user_id) = flow.link_user_id {
+        // the flow cookie alone is not enough, the session which started linking has to finish it
+        let auth = session.extract_basic_auth().await?;
+        if auth.inner.user_id != link_user_id {
+            return Err(unauthorized!("The session which started linking has ended"));
+        }
+        match linked_user_id {
+            Some(user_id) if user_id == link_user_id => {}
+            Some(_) => return Err(conflict!("The identity is linked to another user")),
+            None => state
+                .db
+                .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.oidc()
+                        .link_identity(&config.name, &claims.sub, link_user_id)
+                        .await
+                })
+                .await
+                .map_err(|e| match e {
+                    DataBaseError::UniqueViolation { .. } => {
+                        conflict!("The identity is linked to another user")
+                    }
+                    _ => internal!(e, "Failed to link identity"),
+                })?,
+        }
+        let res: Json<GeneralResponse<()>> = general_json_res!("Identity linked successfully");
+        return Ok(
+            post_login_redirect(config, "linked=true").unwrap_or_else(|| res.into_response())
+        );
+    }
+    let user_id = match linked_user_id {
+        Some(user_id) => user_id,
+        None => {
+            // never link to an existing user by name, anyone could claim it at the provider
+            let username = claims
+                .claim_str(&config.username_claim)
+                .ok_or_else(|| bad_request!("The provider did not share a username"))?;
+            let reg_settings = get_batch_tuple!(
+                cache.dyn_settings,
+                AllowRegister,
+                RegisterNeedInvitationCode
+            )
+            .map_err(|e| internal!(e, "Failed to get dynamic settings"))?;
+            // the account can only be logged into through the provider until a password is set
+            let unusable_password =
+                b64_general_engine::URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+            let password_hash = passwords
+                .hash(&unusable_password)
+                .await
+                .map_err(|e| internal!(e, "Failed to hash password"))?;
+            state
+                .db
+                .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    let taken = exec
+                        .users()
+                        .query_user_by_username(username)
+                        .await
+                        .map_err(|e| internal!(e, "Failed to query user"))?
+                        .is_some();
+                    if taken {
+                        return Err(conflict!(
+                            "Username already exists, log in and link the identity to that account instead"
+                        ));
+                    }
+                    let user_id = register_user(
+                        &mut exec,
+                        reg_settings,
+                        username,
+                        &password_hash,
+                        flow.invitation_code,
+                    )
+                    .await?;
+                    exec.oidc()
+                        .link_identity(&config.name, &claims.sub, user_id)
+                        .await
+                        .map_err(|e| internal!(e, "Failed to link identity"))?;
+                    Ok::<_, ApiError>(user_id)
+                })
+                .await?
+        }
+    };
+    let res = start_user_session(
+        &state,
+        &mfa_policy,
+        &audit,
+        &session,
+        client_info,
+        user_id,
+        flow.device_name,
+    )
+    .await?;
+    let query = format!(
+        "need_mfa={}&mfa_setup_required={}",
+        res.need_mfa, res.mfa_setup_required
+    );
+    let res = general_json_res!("User logged in successfully", res);
+    Ok(post_login_redirect(config, &query).unwrap_or_else(|| res.into_response()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient, TestRes};
+    use crate::services::states::config::OidcProviderConfig;
+    use axum::extract::{Form, State};
+    use axum::http::{Method, StatusCode, header};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use base64::{Engine as _, engine::general_purpose as b64_general_engine};
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use serde_json::{Value, json};
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use url::Url;
+
+    /// Who the mock provider logs in once it sees the code it handed out
+    struct Grant {
+        nonce: String,
+        code_challenge: String,
+        sub: String,
+        username: String,
+    }
+
+    /// An OpenID Connect provider serving discovery, its key set and the token endpoint
+    struct MockIdp {
+        issuer: String,
+        key: PKey<Private>,
+        grants: Mutex<HashMap<String, Grant>>,
+    }
+
+    impl MockIdp {
+        async fn start() -> Arc<Self> {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let idp = Arc::new(Self {
+                issuer: format!("http://{}", listener.local_addr().unwrap()),
+                key: PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+                grants: Mutex::default(),
+            });
+            let router = Router::new()
+                .route("/.well-known/openid-configuration", get(Self::discovery))
+                .route("/jwks", get(Self::jwks))
+                .route("/token", post(Self::token))
+                .with_state(idp.clone());
+            tokio::spawn(async move { axum::serve(listener, router).await });
+            idp
+        }
+
+        async fn discovery(State(idp): State<Arc<Self>>) -> Json<Value> {
+            Json(json!({
+                "issuer": idp.issuer,
+                "authorization_endpoint": format!("{}/authorize", idp.issuer),
+                "token_endpoint": format!("{}/token", idp.issuer),
+                "jwks_uri": format!("{}/jwks", idp.issuer),
+            }))
+        }
+
+        async fn jwks(State(idp): State<Arc<Self>>) -> Json<Value> {
+            let engine = b64_general_engine::URL_SAFE_NO_PAD;
+            let rsa = idp.key.rsa().unwrap();
+            Json(json!({ "keys": [{
+                "kty": "RSA",
+                "kid": "k1",
+                "use": "sig",
+                "n": engine.encode(rsa.n().to_vec()),
+                "e": engine.encode(rsa.e().to_vec()),
+            }] }))
+        }
+
+        async fn token(
+            State(idp): State<Arc<Self>>,
+            Form(form): Form<HashMap<String, String>>,
+        ) -> Result<Json<Value>, StatusCode> {
+            let grant = idp
+                .grants
+                .lock()
+                .unwrap()
+                .remove(&form["code"])
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            let challenge = b64_general_engine::URL_SAFE_NO_PAD
+                .encode(Sha256::digest(form["code_verifier"].as_bytes()));
+            if challenge != grant.code_challenge || form["client_id"] != "echo" {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            let now = time::OffsetDateTime::now_utc().unix_timestamp();
+            let claims = json!({
+                "iss": idp.issuer,
+                "sub": grant.sub,
+                "aud": "echo",
+                "exp": now + 300,
+                "nonce": grant.nonce,
+                "preferred_username": grant.username,
+            });
+            Ok(Json(json!({ "id_token": idp.sign(&claims) })))
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            let engine = b64_general_engine::URL_SAFE_NO_PAD;
+            let header = engine.encode(json!({ "alg": "RS256", "kid": "k1" }).to_string());
+            let payload = engine.encode(claims.to_string());
+            let mut signer = Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+            signer
+                .update(format!("{header}.{payload}").as_bytes())
+                .unwrap();
+            let signature = engine.encode(signer.sign_to_vec().unwrap());
+            format!("{header}.{payload}.{signature}")
+        }
+
+        /// Play the user logging in at the provider, returns the callback uri with the code
+        fn authorize(&self, redirect: &TestRes, sub: &str, username: &str) -> String {
+            assert_eq!(
+                redirect.status,
+                StatusCode::SEE_OTHER,
+                "{:?}",
+                redirect.body
+            );
+            let location = redirect.headers[header::LOCATION].to_str().unwrap();
+            let url = Url::parse(location).unwrap();
+            assert!(location.starts_with(&format!("{}/authorize", self.issuer)));
+            let params = url.query_pairs().into_owned().collect::<HashMap<_, _>>();
+            let code = format!("code-{}", rand::random::<u64>());
+            let grant = Grant {
+                nonce: params["nonce"].clone(),
+                code_challenge: params["code_challenge"].clone(),
+                sub: sub.to_owned(),
+                username: username.to_owned(),
+            };
+            self.grants.lock().unwrap().insert(code.clone(), grant);
+            format!(
+                "/api/v1/oidc/mock/callback?code={code}&state={}",
+                params["state"]
+            )
+        }
+    }
+
+    async fn test_app(idp: &MockIdp) -> TestApp {
+        let issuer = idp.issuer.clone();
+        TestApp::build(
+            move |cfg| {
+                cfg.oidc.providers = vec![OidcProviderConfig {
+                    name: "mock".to_string(),
+                    issuer,
+                    client_id: "echo".to_string(),
+                    redirect_url: "http://echo.test/api/v1/oidc/mock/callback".to_string(),
+                    ..Default::default()
+                }]
+            },
+            &[],
+        )
+        .await
+    }
+
+    /// Runs the whole login at the provider as `sub`
+    async fn oidc_login(
+        app: &TestApp,
+        idp: &MockIdp,
+        sub: &str,
+        username: &str,
+    ) -> (TestClient, TestRes) {
+        let mut client = TestClient::default();
+        let res = app
+            .send(&mut client, Method::GET, "/api/v1/oidc/mock/login", None)
+            .await;
+        let callback = idp.authorize(&res, sub, username);
+        let res = app.send(&mut client, Method::GET, &callback, None).await;
+        (client, res)
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login_registers_and_logs_in() {
+        let idp = MockIdp::start().await;
+        let app = test_app(&idp).await;
+        app.register("alice").await;
+        let (mut carol, res) = oidc_login(&app, &idp, "sub-carol", "carol").await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let carol_id = res.data()["user_id"].as_i64().unwrap();
+        let res = app
+            .send(
+                &mut carol,
+                Method::GET,
+                &format!("/api/v1/user/info?user_id={carol_id}"),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(res.data()["username"], "carol");
+        // the second login finds the linked identity, whatever the username claim says
+        let (_, res) = oidc_login(&app, &idp, "sub-carol", "renamed").await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(res.data()["user_id"], carol_id);
+
+        // a callback replayed without the flow cookie goes nowhere
+        let mut client = TestClient::default();
+        let res = app
+            .send(&mut client, Method::GET, "/api/v1/oidc/mock/login", None)
+            .await;
+        let callback = idp.authorize(&res, "sub-mallory", "mallory");
+        let res = app
+            .send(&mut TestClient::default(), Method::GET, &callback, None)
+            .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        let reason: String = sqlx::query_scalar(
+            "SELECT json_extract(after_summary, '$.reason') FROM audit_logs WHERE action = 2 AND target = 'mock'",
+        )
+        .fetch_one(&app.pool)
+        .await
+        .unwrap();
+        assert_eq!(reason, "no_flow");
+    }
+
+    #[tokio::test]
+    async fn test_oidc_link_to_existing_user() {
+        let idp = MockIdp::start().await;
+        let app = test_app(&idp).await;
+        let alice_id = app.register("alice").await;
+        // the provider saying "alice" does not make it alice
+        let (_, res) = oidc_login(&app, &idp, "sub-alice", "alice").await;
+        assert_eq!(res.status, StatusCode::CONFLICT, "{:?}", res.body);
+
+        let mut alice = app.login("alice").await;
+        let res = app
+            .send(&mut alice, Method::GET, "/api/v1/oidc/mock/link", None)
+            .await;
+        let callback = idp.authorize(&res, "sub-alice", "whatever");
+        let res = app.send(&mut alice, Method::GET, &callback, None).await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let (_, res) = oidc_login(&app, &idp, "sub-alice", "alice").await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(res.data()["user_id"], alice_id);
+
+        // an identity belongs to a single user
+        let (_, res) = oidc_login(&app, &idp, "sub-bob", "bob").await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let res = app
+            .send(&mut alice, Method::GET, "/api/v1/oidc/mock/link", None)
+            .await;
+        let callback = idp.authorize(&res, "sub-bob", "bob");
+        let res = app.send(&mut alice, Method::GET, &callback, None).await;
+        assert_eq!(res.status, StatusCode::CONFLICT, "{:?}", res.body);
+
+        // linking needs a session
+        let res = app
+            .send(
+                &mut TestClient::default(),
+                Method::GET,
+                "/api/v1/oidc/mock/link",
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+    }
+}
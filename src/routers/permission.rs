@@ -1,7 +1,10 @@
+use crate::layers::client_info::ClientInfo;
 use crate::models::api::prelude::*;
+use crate::models::audit::{AuditAction, NewAuditLog};
 use crate::models::permission::{Permission, UserAssignedPermission};
 use crate::models::session::BasicAuthData;
 use crate::models::users::Role;
+use crate::services::audit::AuditService;
 use crate::services::hybrid_cache::HybridCacheService;
 use crate::services::states::EchoState;
 use crate::services::states::db::{EchoDatabaseExecutor, PageQueryBinder, PageQueryResult};
@@ -9,10 +12,12 @@ use ahash::HashSet;
 use axum::Json;
 use axum::extract::State;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
 use time::OffsetDateTime;
 
-pub type PermissionRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>)>;
+pub type PermissionRouterState =
+    State<(Arc<EchoState>, Arc<HybridCacheService>, Arc<AuditService>)>;
 
 #[derive(Debug, Deserialize)]
 pub struct AddPermissionReq {
@@ -27,7 +32,7 @@ pub struct AddPermissionRes {
 
 pub async fn add_permission(
     current_user_info: BasicAuthData,
-    State((state, cache)): PermissionRouterState,
+    State((state, cache, _)): PermissionRouterState,
     Json(req): Json<AddPermissionReq>,
 ) -> ApiResult<Json<GeneralResponse<AddPermissionRes>>> {
     let current_user = cache
@@ -58,7 +63,7 @@ pub async fn add_permission(
 
 pub async fn modify_permission(
     current_user_info: BasicAuthData,
-    State((state, cache)): PermissionRouterState,
+    State((state, cache, _)): PermissionRouterState,
     Json(req): Json<Permission>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -86,7 +91,7 @@ pub async fn modify_permission(
 
 pub async fn delete_permission(
     current_user_info: BasicAuthData,
-    State((state, cache)): PermissionRouterState,
+    State((state, cache, _)): PermissionRouterState,
     Json(req): Json<Permission>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -117,8 +122,9 @@ pub struct GrantPermissionReq {
 
 // TODO: 1. Determine whether the user already possesses this permission (set processing).
 pub async fn grant_permission(
+    client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((_, cache)): PermissionRouterState,
+    State((_, cache, audit)): PermissionRouterState,
     Json(req): Json<GrantPermissionReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -139,12 +145,23 @@ pub async fn grant_permission(
         )
         .await
         .map_err(|e| bad_request!(e, "Failed to grant permission"))?;
+    let log = NewAuditLog::new(
+        AuditAction::PermissionGrant,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .target_user(req.user_id)
+    .after(json!({
+        "permission_ids": req.permission_ids,
+        "exp_time": req.exp_time.map(|t| t.unix_timestamp()),
+    }));
+    audit.record(log).await;
     Ok(general_json_res!("Permission granted"))
 }
 
 pub async fn get_permission_info(
     current_user_info: BasicAuthData,
-    State((state, cache)): PermissionRouterState,
+    State((state, cache, _)): PermissionRouterState,
 ) -> ApiResult<Json<GeneralResponse<HashSet<Permission>>>> {
     let current_user = cache
         .users
@@ -173,8 +190,9 @@ pub struct RevokePermissionReq {
 }
 
 pub async fn revoke_permission(
+    client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((_, cache)): PermissionRouterState,
+    State((_, cache, audit)): PermissionRouterState,
     Json(req): Json<RevokePermissionReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -190,6 +208,14 @@ pub async fn revoke_permission(
         .revoke_user_permission(req.user_id, &req.permission_ids)
         .await
         .map_err(|e| bad_request!(e, "Failed to revoke permissions"))?;
+    let log = NewAuditLog::new(
+        AuditAction::PermissionRevoke,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .target_user(req.user_id)
+    .before(json!({ "permission_ids": req.permission_ids }));
+    audit.record(log).await;
     Ok(general_json_res!("Permissions revoked"))
 }
 
@@ -201,7 +227,7 @@ pub struct GetPermissionRecordsReq {
 
 pub async fn get_permission_records(
     current_user_info: BasicAuthData,
-    State((state, cache)): PermissionRouterState,
+    State((state, cache, _)): PermissionRouterState,
     Json(req): Json<GetPermissionRecordsReq>,
 ) -> ApiResult<Json<GeneralResponse<PageQueryResult<UserAssignedPermission>>>> {
     let current_user = cache
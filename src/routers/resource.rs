@@ -1,6 +1,8 @@
 use crate::get_batch_tuple;
+use crate::layers::client_info::ClientInfo;
 use crate::models::DiffOwned;
 use crate::models::api::prelude::*;
+use crate::models::audit::{AuditAction, NewAuditLog};
 use crate::models::dyn_setting::{AllowMimeTypes, MaxFileSize, UploadChunkSize};
 use crate::models::resource::{
     ResourceItemRawInfo, ResourceItemWithRefRaw, ResourceReferenceInner, ResourceTarget,
@@ -8,6 +10,7 @@ use crate::models::resource::{
 };
 use crate::models::session::BasicAuthData;
 use crate::models::users::Role;
+use crate::services::audit::AuditService;
 use crate::services::hybrid_cache::HybridCacheService;
 use crate::services::res_manager::{ExchangedResourceItem, ResManagerService};
 use crate::services::states::EchoState;
@@ -21,6 +24,7 @@ use axum::extract::{Query, Request, State};
 use axum::http::Response;
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::io;
 use std::sync::Arc;
 use tokio_util::codec::FramedRead;
@@ -35,6 +39,7 @@ pub type ResourceRouterState = State<(
     Arc<UploadTrackerService>,
     Arc<HybridCacheService>,
     Arc<ResManagerService>,
+    Arc<AuditService>,
 )>;
 
 #[derive(Debug, Deserialize)]
@@ -49,7 +54,7 @@ pub struct UploadCreateResp {
 }
 
 pub async fn upload_create(
-    State((_, upload_tracker, cache, _)): ResourceRouterState,
+    State((_, upload_tracker, cache, _, _)): ResourceRouterState,
     Json(req): Json<UploadCreateReq>,
 ) -> ApiResult<Json<GeneralResponse<UploadCreateResp>>> {
     let meta = &req.file_meta;
@@ -91,7 +96,7 @@ pub struct UploadChunkQuery {
 }
 
 pub async fn upload_chunk(
-    State((state, upload_tracker, cache, _)): ResourceRouterState,
+    State((state, upload_tracker, cache, _, _)): ResourceRouterState,
     Query(q): Query<UploadChunkQuery>,
     body: Body,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
@@ -131,7 +136,7 @@ pub struct UploadCommitRes {
 
 pub async fn upload_commit(
     current_user_info: BasicAuthData,
-    State((state, upload_tracker, _, _)): ResourceRouterState,
+    State((state, upload_tracker, _, _, _)): ResourceRouterState,
     Json(req): Json<UploadCommitReq>,
 ) -> ApiResult<Json<GeneralResponse<UploadCommitRes>>> {
     // Pessimistic lock
@@ -192,7 +197,7 @@ pub struct UpdateResourceReq {
 // TODO: allow everyone & permission check (yay we also need uploader_id)
 pub async fn update_resource(
     current_user_info: BasicAuthData,
-    State((_, _, cache, _)): ResourceRouterState,
+    State((_, _, cache, _, _)): ResourceRouterState,
     Json(req): Json<UpdateResourceReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -218,8 +223,9 @@ pub struct DeleteResourceQuery {
 
 // TODO: allow everyone & permission check (yay we also need uploader_id)
 pub async fn delete_resource(
+    client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((_, _, cache, _)): ResourceRouterState,
+    State((_, _, cache, _, audit)): ResourceRouterState,
     Query(req): Query<DeleteResourceQuery>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -235,6 +241,13 @@ pub async fn delete_resource(
         .delete_resources_batch(&req.resources)
         .await
         .map_err(|e| internal!(e, "Failed to delete resource in cache"))?;
+    let log = NewAuditLog::new(
+        AuditAction::ResourceDelete,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .before(json!({ "resources": req.resources }));
+    audit.record(log).await;
     Ok(general_json_res!("Resource deleted successfully"))
 }
 
@@ -251,7 +264,7 @@ pub struct GetResourceByIdsRes {
 // Internal route
 pub async fn get_resource_by_ids(
     current_user_info: BasicAuthData,
-    State((_, _, cache, _)): ResourceRouterState,
+    State((_, _, cache, _, _)): ResourceRouterState,
     Json(req): Json<GetResourceByIdsReq>,
 ) -> ApiResult<Json<GeneralResponse<GetResourceByIdsRes>>> {
     let current_user = cache
@@ -275,7 +288,7 @@ pub async fn get_resource_by_ids(
 
 pub async fn get_resource_by_maybe_sign(
     current_user_info: BasicAuthData,
-    State((state, _, cache, res_manager)): ResourceRouterState,
+    State((state, _, cache, res_manager, _)): ResourceRouterState,
     Query(q): Query<ExchangedResourceItem>,
     req: Request,
 ) -> ApiResult<Response<ServeFileSystemResponseBody>> {
@@ -320,3 +333,123 @@ pub async fn get_resource_by_maybe_sign(
         .await
         .map_err(|e| internal!(e, "Failed to serve resource file"))
 }
+
+/// Public variant of [`get_resource_by_maybe_sign`] for links handed out to
+/// unauthenticated clients (e.g. feed readers), a valid public sign is mandatory.
+pub async fn get_resource_by_sign(
+    State((state, _, cache, res_manager, _)): ResourceRouterState,
+    Query(q): Query<ExchangedResourceItem>,
+    req: Request,
+) -> ApiResult<Response<ServeFileSystemResponseBody>> {
+    let cred = q
+        .cred
+        .as_ref()
+        .ok_or_else(|| unauthorized!("Missing resource sign"))?;
+    let res_id = res_manager
+        .verify_public(q.res_id, cred)
+        .map_err(|e| unauthorized!(e, "Failed to verify exchanged resource item"))?
+        .res_id;
+    let res = cache
+        .resources
+        .get_resources_by_id(&[res_id])
+        .await
+        .map_err(|e| internal!(e, "Failed to get resource by id from cache"))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| bad_request!("Resource not found"))?;
+    let path = state
+        .config
+        .resource
+        .local_storage_path
+        .join(res.info.file_name());
+    ServeFile::new(path)
+        .oneshot(req)
+        .await
+        .map_err(|e| internal!(e, "Failed to serve resource file"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient};
+    use crate::services::res_manager::{ExchangedResourceItem, ResManagerService};
+    use axum::http::{Method, StatusCode};
+    use time::Duration;
+    use uuid::Uuid;
+
+    /// A file on disk referenced by an echo, the way a committed upload leaves it
+    async fn add_resource(app: &TestApp, uploader_id: i64) -> i64 {
+        let res_uuid = Uuid::new_v4();
+        let storage = &app.state.config.resource.local_storage_path;
+        tokio::fs::create_dir_all(storage).await.unwrap();
+        tokio::fs::write(storage.join(format!("{res_uuid}.png")), b"png")
+            .await
+            .unwrap();
+        let res_id = sqlx::query(
+            "INSERT INTO resources (uploader_id, res_name, res_uuid, res_ext) VALUES (?, 'a.png', ?, 'png')",
+        )
+        .bind(uploader_id)
+        .bind(res_uuid)
+        .execute(&app.pool)
+        .await
+        .unwrap()
+        .last_insert_rowid();
+        sqlx::query(
+            "INSERT INTO resource_references (res_id, target_id, target_type) VALUES (?, 1, 1)",
+        )
+        .bind(res_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        res_id
+    }
+
+    #[tokio::test]
+    async fn test_signed_route_only_takes_public_signs() {
+        let app = TestApp::new().await;
+        let user_id = app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let mut guest = TestClient::default();
+        let res_id = add_resource(&app, user_id).await;
+        let res_manager = ResManagerService::new(app.state.clone());
+        let url = |item: ExchangedResourceItem, base: &str| item.to_url(Some(base)).unwrap();
+
+        let session_sign = res_manager
+            .sign(user_id, Duration::minutes(10), res_id)
+            .unwrap();
+        let res = app
+            .send(
+                &mut guest,
+                Method::GET,
+                &url(session_sign, "/api/v1/resource/signed"),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+
+        let session_sign = res_manager
+            .sign(user_id, Duration::minutes(10), res_id)
+            .unwrap();
+        let res = app
+            .send(
+                &mut alice,
+                Method::GET,
+                &url(session_sign, "/api/v1/resource"),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+
+        let public_sign = res_manager
+            .sign_public(user_id, Duration::days(30), res_id)
+            .unwrap();
+        let res = app
+            .send(
+                &mut guest,
+                Method::GET,
+                &url(public_sign, "/api/v1/resource/signed"),
+                None,
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK);
+    }
+}
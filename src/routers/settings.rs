@@ -1,16 +1,34 @@
+use crate::get_batch_tuple;
+use crate::layers::client_info::ClientInfo;
+use crate::layers::session::SessionHelper;
 use crate::models::api::prelude::*;
-use crate::models::dyn_setting::{DynSettingCollector, DynSettingsKvMap, DynSettingsValue};
+use crate::models::audit::{AuditAction, NewAuditLog};
+use crate::models::auth_key::AuthKeyInfo;
+use crate::models::dyn_setting::{
+    DynSetting, DynSettingCollector, DynSettingsKvMap, DynSettingsValue, EnrollmentGraceDays,
+    RequireForAdmins, RequireForAll, RequireForPermissions, RpId, RpName, RpOrigin,
+};
+use crate::models::session::BasicAuthData;
+use crate::models::users::Role;
+use crate::services::audit::AuditService;
 use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::mfa::{MFAService, MFAServiceError};
 use crate::services::states::EchoState;
 use crate::services::states::config::AppConfig;
 use crate::services::states::db::EchoDatabaseExecutor;
 use axum::Json;
 use axum::extract::State;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::borrow::Cow;
 use std::sync::Arc;
 
-pub type DynSettingsRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>)>;
+pub type DynSettingsRouterState = State<(
+    Arc<EchoState>,
+    Arc<HybridCacheService>,
+    Arc<MFAService>,
+    Arc<AuditService>,
+)>;
 
 #[derive(Debug, Deserialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
@@ -38,7 +56,7 @@ pub struct GetDynSettingsRes {
 }
 
 pub async fn get_dyn_settings(
-    State((state, cache)): DynSettingsRouterState,
+    State((state, cache, _, _)): DynSettingsRouterState,
     Json(req): Json<GetDynSettingsReq>,
 ) -> ApiResult<Json<GeneralResponse<GetDynSettingsRes>>> {
     let res = state
@@ -77,28 +95,157 @@ pub struct SetDynSettingsReq {
     overwrite: Option<bool>,
 }
 
+/// Settings which weaken MFA or move the relying party, as sensitive as deleting a credential
+fn needs_step_up(key: &str) -> bool {
+    [
+        RequireForAll.key(),
+        RequireForAdmins.key(),
+        RequireForPermissions.key(),
+        EnrollmentGraceDays.key(),
+        RpId.key(),
+        RpOrigin.key(),
+        RpName.key(),
+    ]
+    .contains(&key)
+}
+
 pub async fn set_dyn_settings(
-    State((_, cache)): DynSettingsRouterState,
+    session: SessionHelper,
+    client_info: ClientInfo,
+    current_user_info: BasicAuthData,
+    State((state, cache, mfa_service, audit)): DynSettingsRouterState,
     Json(req): Json<SetDynSettingsReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
+    check_admin(&current_user_info, &cache).await?;
+    if needs_step_up(&req.key) {
+        session.require_fresh_mfa(current_user_info.user_id).await?;
+    }
     DynSettingCollector::try_parse(&req.key, &req.new_value)
         .ok_or_else(|| internal!("Cannot find the given key"))?
         .map_err(|e| internal!(e, "Failed to parse new value for the given key"))?;
-    cache
-        .dyn_settings
-        .set_with_str(&req.key, &req.new_value, req.overwrite.unwrap_or_default())
-        .await
-        .map_err(|e| internal!(e, "Failed to set dyn setting"))?;
+    let overwrite = req.overwrite.unwrap_or_default();
+    let old_value = get_dyn_setting_value(&state, &cache, &req.key).await?;
+    // the relying party is only replaced as a whole, and never with an invalid one
+    if overwrite && [RpId.key(), RpOrigin.key(), RpName.key()].contains(&req.key.as_str()) {
+        let (rp_id, rp_origin, rp_name): (String, String, String) =
+            get_batch_tuple!(cache.dyn_settings, RpId, RpOrigin, RpName)
+                .map_err(|e| internal!(e, "Failed to get dynamic settings"))?;
+        let new_value = req.new_value.as_str();
+        let (rp_id, rp_origin, rp_name) = match req.key.as_str() {
+            k if k == RpId.key() => (new_value, rp_origin.as_str(), rp_name.as_str()),
+            k if k == RpOrigin.key() => (rp_id.as_str(), new_value, rp_name.as_str()),
+            _ => (rp_id.as_str(), rp_origin.as_str(), new_value),
+        };
+        reconfigure_relying_party(&mfa_service, &cache, rp_id, rp_origin, rp_name).await?;
+    } else {
+        cache
+            .dyn_settings
+            .set_with_str(&req.key, &req.new_value, overwrite)
+            .await
+            .map_err(|e| internal!(e, "Failed to set dyn setting"))?;
+    }
+    // without overwrite an existing value is kept, so only what actually changed is logged
+    let new_value = get_dyn_setting_value(&state, &cache, &req.key).await?;
+    if new_value != old_value {
+        let log = NewAuditLog::new(
+            AuditAction::DynSettingChange,
+            Some(current_user_info.user_id),
+            &client_info,
+        )
+        .target(req.key)
+        .before(json!({ "value": old_value }))
+        .after(json!({ "value": new_value }));
+        audit.record(log).await;
+    }
     Ok(general_json_res!("Successfully set dyn setting", ()))
 }
 
+async fn get_dyn_setting_value(
+    state: &EchoState,
+    cache: &HybridCacheService,
+    key: &str,
+) -> ApiResult<String> {
+    let key = key.to_owned();
+    state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            cache.dyn_settings.get_with_str(key, &mut exec).await
+        })
+        .await
+        .map(|v| v.val)
+        .map_err(|e| internal!(e, "Failed to get dyn setting"))
+}
+
+async fn reconfigure_relying_party(
+    mfa_service: &MFAService,
+    cache: &HybridCacheService,
+    rp_id: &str,
+    rp_origin: &str,
+    rp_name: &str,
+) -> ApiResult<()> {
+    mfa_service
+        .reconfigure_relying_party(cache, rp_id, rp_origin, rp_name)
+        .await
+        .map_err(|e| match e {
+            MFAServiceError::Authn(e) => bad_request!(e, "Invalid WebAuthn relying party"),
+            e => internal!(e, "Failed to change WebAuthn relying party"),
+        })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetWebauthnRpReq {
+    rp_id: String,
+    rp_origin: String,
+    rp_name: String,
+}
+
+/// Moving to another domain changes the id and the origin together, which one at a time
+/// through the dynamic settings would pass through an invalid combination
+pub async fn set_webauthn_rp(
+    session: SessionHelper,
+    client_info: ClientInfo,
+    current_user_info: BasicAuthData,
+    State((_, cache, mfa_service, audit)): DynSettingsRouterState,
+    Json(req): Json<SetWebauthnRpReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    check_admin(&current_user_info, &cache).await?;
+    session.require_fresh_mfa(current_user_info.user_id).await?;
+    let (rp_id, rp_origin, rp_name): (String, String, String) =
+        get_batch_tuple!(cache.dyn_settings, RpId, RpOrigin, RpName)
+            .map_err(|e| internal!(e, "Failed to get dynamic settings"))?;
+    reconfigure_relying_party(
+        &mfa_service,
+        &cache,
+        &req.rp_id,
+        &req.rp_origin,
+        &req.rp_name,
+    )
+    .await?;
+    let log = NewAuditLog::new(
+        AuditAction::DynSettingChange,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .target("WebAuthn")
+    .before(json!({ "rp_id": rp_id, "rp_origin": rp_origin, "rp_name": rp_name }))
+    .after(json!({
+        "rp_id": req.rp_id,
+        "rp_origin": req.rp_origin,
+        "rp_name": req.rp_name,
+    }));
+    audit.record(log).await;
+    Ok(general_json_res!(
+        "Successfully changed WebAuthn relying party"
+    ))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct GetStaticSettingsReq {
     is_default: bool,
 }
 
 pub async fn get_static_settings(
-    State((state, _)): DynSettingsRouterState,
+    State((state, _, _, _)): DynSettingsRouterState,
     Json(req): Json<GetStaticSettingsReq>,
 ) -> ApiResult<Json<GeneralResponse<Arc<AppConfig>>>> {
     let app_config = match req.is_default {
@@ -110,3 +257,136 @@ pub async fn get_static_settings(
         app_config
     ))
 }
+
+async fn check_admin(
+    current_user_info: &BasicAuthData,
+    cache: &HybridCacheService,
+) -> ApiResult<()> {
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    if current_user.role != Role::Admin {
+        return Err(bad_request!("Only admin can manage server settings"));
+    }
+    Ok(())
+}
+
+pub async fn list_auth_keys(
+    current_user_info: BasicAuthData,
+    State((state, cache, _, _)): DynSettingsRouterState,
+) -> ApiResult<Json<GeneralResponse<Vec<AuthKeyInfo>>>> {
+    check_admin(&current_user_info, &cache).await?;
+    Ok(general_json_res!(
+        "Successfully listed auth keys",
+        state.auth.key_infos().await
+    ))
+}
+
+#[derive(Debug, Serialize)]
+pub struct RotateAuthKeysRes {
+    kid: u32,
+}
+
+pub async fn rotate_auth_keys(
+    session: SessionHelper,
+    client_info: ClientInfo,
+    current_user_info: BasicAuthData,
+    State((state, cache, _, audit)): DynSettingsRouterState,
+) -> ApiResult<Json<GeneralResponse<RotateAuthKeysRes>>> {
+    check_admin(&current_user_info, &cache).await?;
+    session.require_fresh_mfa(current_user_info.user_id).await?;
+    let kid = state
+        .auth
+        .rotate(&state.db)
+        .await
+        .map_err(|e| internal!(e, "Failed to rotate auth keys"))?;
+    let log = NewAuditLog::new(
+        AuditAction::AuthKeyRotate,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .target(kid.to_string());
+    audit.record(log).await;
+    Ok(general_json_res!(
+        "Successfully rotated auth keys",
+        RotateAuthKeysRes { kid }
+    ))
+}
+
+/// Everyone including the caller has to log in again afterwards
+pub async fn logout_everyone(
+    client_info: ClientInfo,
+    current_user_info: BasicAuthData,
+    State((state, cache, _, audit)): DynSettingsRouterState,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    check_admin(&current_user_info, &cache).await?;
+    state
+        .auth
+        .logout_everyone(&state.db)
+        .await
+        .map_err(|e| internal!(e, "Failed to log out everyone"))?;
+    let log = NewAuditLog::new(
+        AuditAction::LogoutEveryone,
+        Some(current_user_info.user_id),
+        &client_info,
+    );
+    audit.record(log).await;
+    Ok(general_json_res!("Successfully logged out everyone", ()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient, TestRes};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    async fn set_setting(
+        app: &TestApp,
+        client: &mut TestClient,
+        key: &str,
+        value: &str,
+    ) -> TestRes {
+        let body = json!({ "key": key, "new_value": value, "overwrite": true });
+        app.send(
+            client,
+            Method::PATCH,
+            "/api/v1/settings/dynamic",
+            Some(body),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_set_dyn_settings_needs_admin() {
+        let app = TestApp::new().await;
+        app.register("admin").await;
+        app.register("bob").await;
+        let mut bob = app.login("bob").await;
+        let res = set_setting(&app, &mut bob, "Mfa.RequireForAll", "false").await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+        let mut admin = app.login("admin").await;
+        let res = set_setting(&app, &mut admin, "Site.AllowGuest", "true").await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+    }
+
+    #[tokio::test]
+    async fn test_mfa_settings_need_fresh_mfa() {
+        let app = TestApp::build(|cfg| cfg.mfa.step_up_minutes = 0, &[]).await;
+        app.register("admin").await;
+        let mut admin = app.login("admin").await;
+        let codes = app.enroll_mfa(&mut admin).await;
+        app.verify_mfa(&mut admin, &codes[0]).await;
+        for (key, value) in [
+            ("Mfa.RequireForAll", "false"),
+            ("WebAuthn.RpOrigin", "https://echo.example"),
+        ] {
+            let res = set_setting(&app, &mut admin, key, value).await;
+            assert_eq!(res.status, StatusCode::FORBIDDEN, "{key}: {:?}", res.body);
+            assert_eq!(res.code(), Some(14100));
+        }
+        let res = set_setting(&app, &mut admin, "Site.AllowGuest", "true").await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+    }
+}
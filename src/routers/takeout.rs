@@ -0,0 +1,82 @@
+use crate::models::api::prelude::*;
+use crate::models::session::BasicAuthData;
+use crate::services::res_manager::ExchangedTakeoutItem;
+use crate::services::states::EchoState;
+use crate::services::takeout::{TakeoutError, TakeoutItem, TakeoutService};
+use axum::Json;
+use axum::extract::{Query, Request, State};
+use axum::http::{HeaderValue, Response, header};
+use serde::Serialize;
+use std::sync::Arc;
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+use tower_http::services::fs::ServeFileSystemResponseBody;
+
+pub type TakeoutRouterState = State<(Arc<EchoState>, Arc<TakeoutService>)>;
+
+#[derive(Debug, Serialize)]
+pub struct RequestTakeoutRes {
+    pub takeout_id: i64,
+}
+
+pub async fn request_takeout(
+    current_user_info: BasicAuthData,
+    State((_, takeout)): TakeoutRouterState,
+) -> ApiResult<Json<GeneralResponse<RequestTakeoutRes>>> {
+    let takeout_id = takeout
+        .request_takeout(current_user_info.user_id)
+        .await
+        .map_err(|e| match e {
+            TakeoutError::AlreadyPending => conflict!(e, "Takeout already in progress"),
+            e => internal!(e, "Failed to request takeout"),
+        })?;
+    Ok(general_json_res!(
+        "Takeout requested, the archive is being built",
+        RequestTakeoutRes { takeout_id }
+    ))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListTakeoutsRes {
+    pub list: Vec<TakeoutItem>,
+}
+
+pub async fn list_takeouts(
+    current_user_info: BasicAuthData,
+    State((_, takeout)): TakeoutRouterState,
+) -> ApiResult<Json<GeneralResponse<ListTakeoutsRes>>> {
+    let list = takeout
+        .list_takeouts(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to list takeouts"))?;
+    Ok(general_json_res!(
+        "Takeouts fetched successfully",
+        ListTakeoutsRes { list }
+    ))
+}
+
+/// The signed link is the only credential, so it can be handed to a download manager
+pub async fn download_takeout(
+    State((_, takeout)): TakeoutRouterState,
+    Query(q): Query<ExchangedTakeoutItem>,
+    req: Request,
+) -> ApiResult<Response<ServeFileSystemResponseBody>> {
+    let (row, path) = takeout.resolve_download(&q).await.map_err(|e| match e {
+        TakeoutError::ResManager(_) => unauthorized!(e, "Failed to verify takeout sign"),
+        TakeoutError::NotFound | TakeoutError::NotAvailable => {
+            not_found!(e, "Takeout archive not available")
+        }
+        e => internal!(e, "Failed to resolve takeout"),
+    })?;
+    let mut res = ServeFile::new(path)
+        .oneshot(req)
+        .await
+        .map_err(|e| internal!(e, "Failed to serve takeout archive"))?;
+    let disposition = format!(r#"attachment; filename="echo-takeout-{}.zip""#, row.id);
+    // SAFETY: the value is plain ascii
+    res.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_str(&disposition).unwrap(),
+    );
+    Ok(res)
+}
@@ -0,0 +1,353 @@
+//! Drives the whole router against a throwaway database, for handler tests
+
+use crate::routers::router;
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use crate::services::states::auth::AuthState;
+use crate::services::states::cache::CacheState;
+use crate::services::states::config::{AppConfig, PasswordConfig};
+use crate::services::states::db::{DataBaseState, EchoDatabaseExecutor};
+use ahash::HashMap;
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::http::{HeaderMap, Method, Request, StatusCode, header};
+use axum::response::Response;
+use serde_json::Value;
+use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePoolOptions};
+use std::sync::Arc;
+use tempfile::TempDir;
+use tokio_util::sync::CancellationToken;
+use tower::ServiceExt;
+
+pub const TEST_PASSWORD: &str = "correct horse battery staple";
+
+/// Nobody is held to MFA unless a test turns the policy back on
+const TEST_DYN_SETTINGS: &[(&str, &str)] = &[
+    ("Mfa.RequireForAll", "false"),
+    ("Mfa.RequireForAdmins", "false"),
+    ("Site.RegisterNeedInvitationCode", "false"),
+];
+
+pub struct TestApp {
+    pub state: Arc<EchoState>,
+    /// Same database as the app, for fixtures the API has no way to set up
+    pub pool: SqlitePool,
+    router: Router,
+    _dir: TempDir,
+}
+
+/// Cookies of one client, sent back the way a browser would
+#[derive(Debug, Default, Clone)]
+pub struct TestClient {
+    cookies: HashMap<String, String>,
+    pub bearer: Option<String>,
+}
+
+pub struct TestRes {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Value,
+}
+
+impl TestRes {
+    /// `code` of an error response, `None` for the ones without
+    pub fn code(&self) -> Option<i64> {
+        self.body["code"].as_i64()
+    }
+
+    pub fn data(&self) -> &Value {
+        &self.body["data"]
+    }
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::build(|_| {}, &[]).await
+    }
+
+    /// `dyn_settings` are applied on top of the test defaults before the router starts
+    pub async fn build(
+        configure: impl FnOnce(&mut AppConfig),
+        dyn_settings: &[(&'static str, &str)],
+    ) -> Self {
+        let dir = tempfile::tempdir().expect("Failed to create temp dir");
+        let mut config = AppConfig::default();
+        config.auth.key_file = dir.path().join("echo.keys");
+        config.resource.local_storage_path = dir.path().join("uploads");
+        // the cheapest parameters argon2 accepts, hashing is not what is under test
+        config.password = PasswordConfig {
+            argon2_memory_kib: 8,
+            argon2_iterations: 1,
+            argon2_parallelism: 1,
+        };
+        configure(&mut config);
+        let opts = SqliteConnectOptions::new()
+            .filename(dir.path().join("echo.db"))
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(opts)
+            .await
+            .expect("Failed to open test database");
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .expect("Failed to run migrations");
+        let db = DataBaseState::new(pool.clone());
+        db.transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.dyn_settings().initialise().await
+        })
+        .await
+        .expect("Failed to initialise dyn settings");
+        let auth = AuthState::load(&config, &db)
+            .await
+            .expect("Failed to load auth keys");
+        let state = Arc::new(EchoState {
+            db,
+            cache: CacheState::new(),
+            auth,
+            config: Arc::new(config),
+            shutdown: CancellationToken::new(),
+        });
+        let settings = TEST_DYN_SETTINGS
+            .iter()
+            .chain(dyn_settings)
+            .copied()
+            .collect::<Vec<_>>();
+        HybridCacheService::new(state.clone())
+            .dyn_settings
+            .set_many_with_str(&settings, true)
+            .await
+            .expect("Failed to apply test dyn settings");
+        let router = router(state.clone()).await;
+        Self {
+            state,
+            pool,
+            router,
+            _dir: dir,
+        }
+    }
+
+    fn request(
+        client: &TestClient,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> Request<Body> {
+        let mut req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::USER_AGENT, "echo-test");
+        if !client.cookies.is_empty() {
+            let cookies = client
+                .cookies
+                .iter()
+                .map(|(k, v)| format!("{k}={v}"))
+                .collect::<Vec<_>>()
+                .join("; ");
+            req = req.header(header::COOKIE, cookies);
+        }
+        if let Some(bearer) = &client.bearer {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {bearer}"));
+        }
+        match body {
+            Some(body) => req
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => req.body(Body::empty()),
+        }
+        .expect("Failed to build request")
+    }
+
+    /// Runs the request through the router and keeps the cookies it sets
+    async fn respond(&self, client: &mut TestClient, req: Request<Body>) -> Response {
+        let res = self
+            .router
+            .clone()
+            .oneshot(req)
+            .await
+            .expect("Router is infallible");
+        for set_cookie in res.headers().get_all(header::SET_COOKIE) {
+            let set_cookie = set_cookie.to_str().expect("Invalid Set-Cookie header");
+            let cookie = cookie::Cookie::parse(set_cookie).expect("Invalid Set-Cookie header");
+            let removed =
+                cookie.value().is_empty() || cookie.max_age().is_some_and(|age| age.is_zero());
+            match removed {
+                true => client.cookies.remove(cookie.name()),
+                false => client
+                    .cookies
+                    .insert(cookie.name().to_owned(), cookie.value().to_owned()),
+            };
+        }
+        res
+    }
+
+    /// Like [`Self::send`], but hands back the response as is, for bodies which never end
+    pub async fn open(&self, client: &mut TestClient, method: Method, uri: &str) -> Response {
+        let req = Self::request(client, method, uri, None);
+        self.respond(client, req).await
+    }
+
+    pub async fn send(
+        &self,
+        client: &mut TestClient,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> TestRes {
+        let req = Self::request(client, method, uri, body);
+        let res = self.respond(client, req).await;
+        let status = res.status();
+        let headers = res.headers().clone();
+        let bytes = to_bytes(res.into_body(), usize::MAX)
+            .await
+            .expect("Failed to read response body");
+        let body = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        TestRes {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// The first user registered becomes the admin
+    pub async fn register(&self, username: &str) -> i64 {
+        let res = self
+            .send(
+                &mut TestClient::default(),
+                Method::POST,
+                "/api/v1/user/register",
+                Some(serde_json::json!({ "username": username, "password_hash": TEST_PASSWORD })),
+            )
+            .await;
+        assert_eq!(
+            res.status,
+            StatusCode::OK,
+            "register {username}: {:?}",
+            res.body
+        );
+        res.data()["user_id"].as_i64().expect("Missing user_id")
+    }
+
+    pub async fn login(&self, username: &str) -> TestClient {
+        let mut client = TestClient::default();
+        let res = self
+            .send(
+                &mut client,
+                Method::POST,
+                "/api/v1/user/login",
+                Some(serde_json::json!({ "username": username, "password_hash": TEST_PASSWORD })),
+            )
+            .await;
+        assert_eq!(
+            res.status,
+            StatusCode::OK,
+            "login {username}: {:?}",
+            res.body
+        );
+        client
+    }
+
+    /// Sets up TOTP and returns the recovery codes, the session still has to verify MFA
+    pub async fn enroll_mfa(&self, client: &mut TestClient) -> Vec<String> {
+        let res = self
+            .send(client, Method::POST, "/api/v1/mfa/totp/setup/start", None)
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "totp setup: {:?}", res.body);
+        let uri = res.data()["totp_uri"].as_str().expect("Missing totp_uri");
+        let code = totp_rs::TOTP::from_url_unchecked(uri)
+            .expect("Invalid TOTP uri")
+            .generate_current()
+            .expect("Failed to generate TOTP code");
+        let body = serde_json::json!({
+            "totp_sess": res.data()["totp_sess"],
+            "code": code,
+            "label": "Test",
+        });
+        let res = self
+            .send(
+                client,
+                Method::POST,
+                "/api/v1/mfa/totp/setup/finish",
+                Some(body),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "totp finish: {:?}", res.body);
+        serde_json::from_value(res.data()["recovery_codes"].clone())
+            .expect("Missing recovery codes")
+    }
+
+    /// Verifies MFA with one of the codes from [`Self::enroll_mfa`]
+    pub async fn verify_mfa(&self, client: &mut TestClient, recovery_code: &str) {
+        let res = self
+            .send(
+                client,
+                Method::POST,
+                "/api/v1/mfa/recovery/verify",
+                Some(serde_json::json!({ "code": recovery_code })),
+            )
+            .await;
+        assert_eq!(
+            res.status,
+            StatusCode::OK,
+            "recovery verify: {:?}",
+            res.body
+        );
+    }
+
+    /// Returns the id of the new echo
+    pub async fn add_echo(
+        &self,
+        client: &mut TestClient,
+        content: &str,
+        permission_ids: &[i64],
+        is_private: bool,
+    ) -> i64 {
+        let body = serde_json::json!({
+            "content": content,
+            "echo_permission_ids": permission_ids,
+            "is_private": is_private,
+        });
+        let res = self
+            .send(client, Method::PUT, "/api/v1/echo", Some(body))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "add echo: {:?}", res.body);
+        sqlx::query_scalar("SELECT MAX(id) FROM echos")
+            .fetch_one(&self.pool)
+            .await
+            .expect("Failed to get echo id")
+    }
+
+    /// Returns the id of the new permission
+    pub async fn add_permission(&self, admin: &mut TestClient, description: &str) -> i64 {
+        let color = rand::random_range(0..=0x00FF_FFFF);
+        let body = serde_json::json!({ "description": description, "color": color });
+        let res = self
+            .send(admin, Method::PUT, "/api/v1/permission/item", Some(body))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "add permission: {:?}", res.body);
+        sqlx::query_scalar("SELECT MAX(id) FROM permissions")
+            .fetch_one(&self.pool)
+            .await
+            .expect("Failed to get permission id")
+    }
+
+    pub async fn grant_permission(&self, admin: &mut TestClient, user_id: i64, permission_id: i64) {
+        let body = serde_json::json!({
+            "user_id": user_id,
+            "permission_ids": [permission_id],
+            "exp_time": null,
+        });
+        let res = self
+            .send(admin, Method::PUT, "/api/v1/permission", Some(body))
+            .await;
+        assert_eq!(
+            res.status,
+            StatusCode::OK,
+            "grant permission: {:?}",
+            res.body
+        );
+    }
+}
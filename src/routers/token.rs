@@ -0,0 +1,173 @@
+use crate::layers::session::SessionHelper;
+use crate::models::api::prelude::*;
+use crate::models::session::BasicAuthData;
+use crate::models::token::{AuthTokenRaw, CreatedAuthToken, TokenScope};
+use crate::services::access_token::{AccessTokenError, AccessTokenService};
+use crate::services::states::EchoState;
+use axum::Json;
+use axum::extract::State;
+use serde::Deserialize;
+use std::sync::Arc;
+use time::Duration;
+
+pub type TokenRouterState = State<(Arc<EchoState>, Arc<AccessTokenService>)>;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenReq {
+    name: String,
+    scopes: Vec<TokenScope>,
+    /// Seconds until the token expires, 30 days if absent
+    ttl_secs: Option<i64>,
+}
+
+pub async fn create_token(
+    session: SessionHelper,
+    current_user_info: BasicAuthData,
+    State((_, tokens)): TokenRouterState,
+    Json(req): Json<CreateTokenReq>,
+) -> ApiResult<Json<GeneralResponse<CreatedAuthToken>>> {
+    session.require_fresh_mfa(current_user_info.user_id).await?;
+    let created = tokens
+        .create_token(
+            current_user_info.user_id,
+            &req.name,
+            req.scopes,
+            req.ttl_secs.map(Duration::seconds),
+        )
+        .await
+        .map_err(|e| match e {
+            AccessTokenError::InvalidTtl
+            | AccessTokenError::InvalidName
+            | AccessTokenError::NoScope => bad_request!(e, "Invalid token"),
+            e => internal!(e, "Failed to create token"),
+        })?;
+    Ok(general_json_res!(
+        "Token created successfully, it will not be shown again",
+        created
+    ))
+}
+
+pub async fn list_tokens(
+    current_user_info: BasicAuthData,
+    State((_, tokens)): TokenRouterState,
+) -> ApiResult<Json<GeneralResponse<Vec<AuthTokenRaw>>>> {
+    let list = tokens
+        .list_tokens(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to list tokens"))?;
+    Ok(general_json_res!("Tokens fetched successfully", list))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeTokenReq {
+    id: i64,
+}
+
+pub async fn revoke_token(
+    current_user_info: BasicAuthData,
+    State((_, tokens)): TokenRouterState,
+    Json(req): Json<RevokeTokenReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    tokens
+        .revoke_token(current_user_info.user_id, req.id)
+        .await
+        .map_err(|e| match e {
+            AccessTokenError::NotFound => not_found!(e, "Token not found"),
+            e => internal!(e, "Failed to revoke token"),
+        })?;
+    Ok(general_json_res!("Token revoked successfully", ()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    /// Returns the token id and a client which only carries the token
+    async fn create_token(app: &TestApp, owner: &mut TestClient, scope: &str) -> (i64, TestClient) {
+        let body = json!({ "name": "script", "scopes": [scope] });
+        let res = app
+            .send(owner, Method::PUT, "/api/v1/user/tokens", Some(body))
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let mut bearer = TestClient::default();
+        bearer.bearer = res.data()["token"].as_str().map(str::to_owned);
+        (res.data()["id"].as_i64().expect("Missing id"), bearer)
+    }
+
+    async fn list_echos(app: &TestApp, client: &mut TestClient) -> StatusCode {
+        let body = json!({ "user_id": 1, "start_after": 0 });
+        app.send(client, Method::POST, "/api/v1/echo", Some(body))
+            .await
+            .status
+    }
+
+    #[tokio::test]
+    async fn test_access_token_scope_and_expiry() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let (_, mut reader) = create_token(&app, &mut alice, "echo:read").await;
+        assert_eq!(list_echos(&app, &mut reader).await, StatusCode::OK);
+        let res = app
+            .send(
+                &mut reader,
+                Method::PUT,
+                "/api/v1/echo",
+                Some(json!({ "content": "hi", "echo_permission_ids": [], "is_private": false })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+
+        let (expired_id, mut expired) = create_token(&app, &mut alice, "echo:read").await;
+        let exp_type: String =
+            sqlx::query_scalar("SELECT typeof(exp_time) FROM auth_tokens WHERE id = ?")
+                .bind(expired_id)
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        assert_eq!(exp_type, "integer");
+        sqlx::query("UPDATE auth_tokens SET exp_time = strftime('%s', 'now') - 1 WHERE id = ?")
+            .bind(expired_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            list_echos(&app, &mut expired).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(list_echos(&app, &mut reader).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_access_token_is_held_to_the_mfa_policy() {
+        let app = TestApp::build(
+            |_| {},
+            &[
+                ("Mfa.RequireForAll", "true"),
+                ("Mfa.EnrollmentGraceDays", "1"),
+            ],
+        )
+        .await;
+        let alice_id = app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        // still within the grace period
+        let (_, mut reader) = create_token(&app, &mut alice, "echo:read").await;
+        assert_eq!(list_echos(&app, &mut reader).await, StatusCode::OK);
+
+        sqlx::query(
+            "UPDATE mfa_infos SET mfa_required_since = strftime('%s', 'now') - 2 * 86400 WHERE user_id = ?",
+        )
+        .bind(alice_id)
+        .execute(&app.pool)
+        .await
+        .unwrap();
+        let body = json!({ "user_id": 1, "start_after": 0 });
+        let res = app
+            .send(&mut reader, Method::POST, "/api/v1/echo", Some(body))
+            .await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+        assert_eq!(res.code(), Some(14000));
+    }
+}
@@ -1,18 +1,91 @@
 use crate::get_batch_tuple;
+use crate::layers::client_info::ClientInfo;
 use crate::layers::session::SessionHelper;
 use crate::models::api::prelude::*;
+use crate::models::audit::{AuditAction, NewAuditLog};
+use crate::models::const_val::ECHO_BASIC_AUTH_EXPIRE;
 use crate::models::dyn_setting::{AllowRegister, RegisterNeedInvitationCode};
-use crate::models::session::BasicAuthData;
+use crate::models::session::{BasicAuthData, NewUserSession};
 use crate::models::users::{Role, User, UserInternal, UserRowOptional};
-use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::audit::AuditService;
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::mfa_policy::{MfaCompliance, MfaPolicyService};
+use crate::services::password::{PasswordCheck, PasswordService};
 use crate::services::states::EchoState;
 use crate::services::states::db::{DataBaseError, EchoDatabaseExecutor};
 use axum::Json;
 use axum::extract::{Query, State};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::sync::Arc;
+use time::OffsetDateTime;
+use uuid::Uuid;
 
-pub type UserRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>)>;
+pub type UserRouterState = State<(
+    Arc<EchoState>,
+    Arc<HybridCacheService>,
+    Arc<PasswordService>,
+    Arc<MfaPolicyService>,
+    Arc<AuditService>,
+)>;
+
+/// Checks the registration settings and adds the user, shared by every way of signing up.
+/// The first user becomes the admin, `password_hash` must be hashed already.
+pub(crate) async fn register_user(
+    exec: &mut EchoDatabaseExecutor<'_>,
+    (allow_reg, reg_need_invite): (bool, bool),
+    username: &str,
+    password_hash: &str,
+    invitation_code: Option<String>,
+) -> ApiResult<i64> {
+    let user_count = exec
+        .users()
+        .get_user_count()
+        .await
+        .map_err(|e| internal!(e, "Failed to get user count"))?;
+    let (permission, brand_new_server) = match user_count {
+        0 => (Role::Admin, true),
+        _ => (Role::User, false),
+    };
+    if !(allow_reg || brand_new_server) {
+        return Err(bad_request!("User registration is not allowed"));
+    }
+    match (reg_need_invite, &invitation_code, brand_new_server) {
+        (true, Some(code), false) => {
+            let code = exec
+                .invite_code()
+                .get_invite_code_by_code(code)
+                .await
+                .map_err(|e| internal!(e, "Failed to query invitation code from database"))?
+                .ok_or_else(|| bad_request!("Cannot find this invitation code"))?;
+            if !code.is_valid() {
+                return Err(bad_request!("This invitation code is not valid"));
+            }
+        }
+        (true, None, false) => {
+            return Err(bad_request!("Invitation code is required for registration"));
+        }
+        _ => {}
+    }
+    let registered_user_id = exec
+        .users()
+        .add_user(username, password_hash, permission)
+        .await
+        .map_err(|e| match e {
+            DataBaseError::UniqueViolation { .. } => conflict!("Username already exists!"),
+            _ => internal!(e, "Failed to register user"),
+        })?;
+    if let Some(code) = invitation_code
+        && reg_need_invite
+        && !brand_new_server
+    {
+        exec.invite_code()
+            .revoke_invite_code(&[(code, registered_user_id)])
+            .await
+            .map_err(|e| internal!(e, "Failed to use invitation code!"))?;
+    }
+    Ok(registered_user_id)
+}
 
 #[derive(Debug, Deserialize)]
 pub struct UserRegisterReq {
@@ -27,7 +100,7 @@ pub struct UserRegisterRes {
 }
 
 pub async fn user_register(
-    State((state, cache)): UserRouterState,
+    State((state, cache, passwords, _, _)): UserRouterState,
     Json(req): Json<UserRegisterReq>,
 ) -> ApiResult<Json<GeneralResponse<UserRegisterRes>>> {
     let (allow_reg, reg_need_invite) = get_batch_tuple!(
@@ -36,56 +109,21 @@ pub async fn user_register(
         RegisterNeedInvitationCode
     )
     .map_err(|e| internal!(e, "Failed to get dynamic settings"))?;
+    let password_hash = passwords
+        .hash(&req.password_hash)
+        .await
+        .map_err(|e| internal!(e, "Failed to hash password"))?;
     let registered_user_id = state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
-            let user_count = exec
-                .users()
-                .get_user_count()
-                .await
-                .map_err(|e| internal!(e, "Failed to get user count"))?;
-            let (permission, brand_new_server) = match user_count {
-                0 => (Role::Admin, true),
-                _ => (Role::User, false),
-            };
-            if !(allow_reg || brand_new_server) {
-                return Err(bad_request!("User registration is not allowed"));
-            }
-            match (reg_need_invite, &req.invitation_code, brand_new_server) {
-                (true, Some(code), false) => {
-                    let code = exec
-                        .invite_code()
-                        .get_invite_code_by_code(&code)
-                        .await
-                        .map_err(|e| internal!(e, "Failed to query invitation code from database"))?
-                        .ok_or_else(|| bad_request!("Cannot find this invitation code"))?;
-                    if !code.is_valid() {
-                        return Err(bad_request!("This invitation code is not valid"));
-                    }
-                }
-                (true, None, false) => {
-                    return Err(bad_request!("Invitation code is required for registration"));
-                }
-                _ => {}
-            }
-            let registered_user_id = exec
-                .users()
-                .add_user(&req.username, &req.password_hash, permission)
-                .await
-                .map_err(|e| match e {
-                    DataBaseError::UniqueViolation { .. } => conflict!("Username already exists!"),
-                    _ => internal!(e, "Failed to register user"),
-                })?;
-            if let Some(code) = req.invitation_code
-                && reg_need_invite
-                && !brand_new_server
-            {
-                exec.invite_code()
-                    .revoke_invite_code(&[(code, registered_user_id)])
-                    .await
-                    .map_err(|e| internal!(e, "Failed to use invitation code!"))?;
-            }
-            Ok(registered_user_id)
+            register_user(
+                &mut exec,
+                (allow_reg, reg_need_invite),
+                &req.username,
+                &password_hash,
+                req.invitation_code,
+            )
+            .await
         })
         .await?;
     Ok(general_json_res!(
@@ -96,75 +134,201 @@ pub async fn user_register(
     ))
 }
 
+/// Shared tail of every login: register the session, sign its cookies, ask for MFA when
+/// the user has it enabled and tell users without it what the MFA policy expects of them
+pub(crate) async fn start_user_session(
+    state: &Arc<EchoState>,
+    mfa_policy: &MfaPolicyService,
+    audit: &AuditService,
+    session: &SessionHelper,
+    client_info: ClientInfo,
+    user_id: i64,
+    device_name: Option<String>,
+) -> ApiResult<UserLoginRes> {
+    let session_uuid = Uuid::new_v4();
+    let audit_log = NewAuditLog::new(AuditAction::Login, Some(user_id), &client_info)
+        .target_user(user_id)
+        .after(json!({ "session_uuid": session_uuid, "device_name": device_name }));
+    let new_session = NewUserSession {
+        session_uuid,
+        user_id,
+        device_name,
+        ip_address: client_info.ip_address,
+        user_agent: client_info.user_agent,
+    };
+    let need_mfa = state
+        .db
+        .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let need_mfa = exec
+                .mfa()
+                .mfa_enabled(user_id)
+                .await
+                .map_err(|e| internal!(e, "Failed to check if MFA is enabled for user"))?;
+            let mut sessions = exec.user_session();
+            let expired_before = OffsetDateTime::now_utc() - ECHO_BASIC_AUTH_EXPIRE;
+            sessions
+                .prune_sessions(user_id, expired_before)
+                .await
+                .map_err(|e| internal!(e, "Failed to register session"))?;
+            sessions
+                .add_session(new_session)
+                .await
+                .map_err(|e| internal!(e, "Failed to register session"))?;
+            Ok::<_, ApiError>(need_mfa)
+        })
+        .await?;
+    session
+        .sign_basic_and_csrf_auth(user_id, session_uuid)
+        .map_err(|e| internal!(e, "Failed to sign basic auth session after user login"))?;
+    if need_mfa {
+        session
+            .sign_pre_mfa()
+            .map_err(|e| internal!(e, "Failed to sign pre-MFA auth session after user login"))?;
+    }
+    audit.record(audit_log).await;
+    let mut res = UserLoginRes {
+        user_id,
+        need_mfa,
+        mfa_setup_required: false,
+        mfa_setup_deadline: None,
+    };
+    if !need_mfa {
+        match mfa_policy
+            .check(user_id)
+            .await
+            .map_err(|e| internal!(e, "Failed to check MFA policy"))?
+        {
+            MfaCompliance::Grace(deadline) => res.mfa_setup_deadline = Some(deadline),
+            MfaCompliance::Overdue => res.mfa_setup_required = true,
+            MfaCompliance::Enrolled | MfaCompliance::NotRequired => {}
+        }
+    }
+    Ok(res)
+}
+
 #[derive(Debug, Deserialize)]
 pub struct UserLoginReq {
     pub username: String,
     pub password_hash: String,
+    /// Shown in the session list to tell devices apart
+    pub device_name: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct UserLoginRes {
-    user_id: i64,
-    need_mfa: bool,
+    pub user_id: i64,
+    pub need_mfa: bool,
+    /// The MFA policy applies and its grace period is over, only the MFA setup is reachable
+    pub mfa_setup_required: bool,
+    /// The MFA policy applies and MFA must be set up before this
+    #[serde(with = "time::serde::timestamp::option")]
+    pub mfa_setup_deadline: Option<OffsetDateTime>,
 }
 
 pub async fn user_login(
     session: SessionHelper,
-    State((state, _)): UserRouterState,
+    client_info: ClientInfo,
+    State((state, _, passwords, mfa_policy, audit)): UserRouterState,
     Json(req): Json<UserLoginReq>,
 ) -> ApiResult<Json<GeneralResponse<UserLoginRes>>> {
     // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
-    let (user, need_mfa): (UserInternal, bool) = state
+    let user: Option<UserInternal> = state
         .db
         .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
-            let user_row = exec
+            let Some(user_row) = exec
                 .users()
                 .query_user_by_username(&req.username)
                 .await
                 .map_err(|e| internal!(e, "Failed to query user from database"))?
-                .ok_or_else(|| bad_request!("User not found"))?;
+            else {
+                return Ok(None);
+            };
             let user_permission = exec
                 .permission()
                 .combined_query_user_permission(user_row.id, &user_row.role)
                 .await?;
-            let user = UserInternal {
+            Ok::<_, ApiError>(Some(UserInternal {
                 inner: user_row,
                 permissions: user_permission,
-            };
-            if user.inner.password_hash != req.password_hash {
-                return Err(unauthorized!("Incorrect password"));
-            }
-            let need_mfa = exec
-                .mfa()
-                .mfa_enabled(user.inner.id)
-                .await
-                .map_err(|e| internal!(e, "Failed to check if MFA is enabled for user"))?;
-            Ok((user, need_mfa))
+            }))
         })
         .await?;
-    session
-        .sign_basic_and_csrf_auth(user.inner.id)
-        .map_err(|e| {
-            internal!(
-                e,
-                "Failed to sign basic auth session after user registration"
-            )
-        })?;
-    if need_mfa {
-        session.sign_pre_mfa().map_err(|e| {
-            internal!(
-                e,
-                "Failed to sign pre-MFA auth session after user registration"
-            )
-        })?;
+    let failure = NewAuditLog::new(AuditAction::LoginFailed, None, &client_info)
+        .target(req.username.as_str());
+    let Some(user) = user else {
+        audit
+            .record(failure.after(json!({ "reason": "unknown_user" })))
+            .await;
+        return Err(bad_request!("User not found"));
+    };
+    let check = passwords
+        .check(&user.inner.password_hash, &req.password_hash)
+        .await
+        .map_err(|e| internal!(e, "Failed to verify password"))?;
+    let PasswordCheck::Match { needs_rehash } = check else {
+        let failure = failure
+            .target_user(user.inner.id)
+            .after(json!({ "reason": "wrong_password" }));
+        audit.record(failure).await;
+        return Err(unauthorized!("Incorrect password"));
+    };
+    if needs_rehash {
+        upgrade_password_hash(
+            &state,
+            &passwords,
+            user.inner.id,
+            &user.inner.password_hash,
+            &req.password_hash,
+        )
+        .await;
     }
-    Ok(general_json_res!(
-        "User logged in successfully",
-        UserLoginRes {
-            user_id: user.inner.id,
-            need_mfa
+    let res = start_user_session(
+        &state,
+        &mfa_policy,
+        &audit,
+        &session,
+        client_info,
+        user.inner.id,
+        req.device_name,
+    )
+    .await?;
+    Ok(general_json_res!("User logged in successfully", res))
+}
+
+/// Rewrite a legacy or outdated hash once the password is known. Best effort, the login
+/// succeeds either way and the next one tries again.
+async fn upgrade_password_hash(
+    state: &EchoState,
+    passwords: &PasswordService,
+    user_id: i64,
+    old_hash: &str,
+    password: &str,
+) {
+    let new_hash = match passwords.hash(password).await {
+        Ok(new_hash) => new_hash,
+        Err(e) => {
+            tracing::error!("Failed to rehash password of user {}: {}", user_id, e);
+            return;
         }
-    ))
+    };
+    let result = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.users()
+                .swap_password_hash(user_id, old_hash, &new_hash)
+                .await
+        })
+        .await;
+    match result {
+        Ok(()) => tracing::info!("Upgraded password hash of user {}", user_id),
+        // changed concurrently, nothing left to upgrade
+        Err(DataBaseError::NoAffectedRows(_)) => {}
+        Err(e) => tracing::error!(
+            "Failed to store rehashed password of user {}: {}",
+            user_id,
+            e
+        ),
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -175,7 +339,7 @@ pub struct FetchUserInfoQuery {
 pub async fn fetch_user_info(
     current_user_info: BasicAuthData,
     Query(q): Query<FetchUserInfoQuery>,
-    State((_, cache)): UserRouterState,
+    State((_, cache, _, _, _)): UserRouterState,
 ) -> ApiResult<Json<GeneralResponse<Arc<User>>>> {
     let user = cache
         .users
@@ -206,8 +370,10 @@ pub struct ModifyUserInfoReq {
 }
 
 pub async fn modify_user_info(
+    session: SessionHelper,
+    client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((_, cache)): UserRouterState,
+    State((_, cache, passwords, _, audit)): UserRouterState,
     Json(req): Json<ModifyUserInfoReq>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -226,10 +392,40 @@ pub async fn modify_user_info(
     {
         return Err(bad_request!("You are not allowed to change your own role"));
     }
+    let role_change = match req.inner.role {
+        Some(req_role) => {
+            let old_role = cache
+                .users
+                .get_user_by_user_id(req.user_id)
+                .await
+                .map_err(|e| internal!(e, "Failed to fetch user"))?
+                .role;
+            (old_role != req_role).then_some((old_role, req_role))
+        }
+        None => None,
+    };
+    if role_change.is_some() || req.inner.password_hash.is_some() {
+        session.require_fresh_mfa(current_user_info.user_id).await?;
+    }
+    // resetting is for admins only, everyone changes their own password with the old one
+    let password_hash = match req.inner.password_hash {
+        Some(_) if req.user_id == current_user_info.user_id => {
+            return Err(bad_request!(
+                "Use the password endpoint to change your own password"
+            ));
+        }
+        Some(password) => Some(
+            passwords
+                .hash(&password)
+                .await
+                .map_err(|e| internal!(e, "Failed to hash password"))?,
+        ),
+        None => None,
+    };
     let upd_row = UserRowOptional {
         id: req.user_id,
         username: req.inner.username,
-        password_hash: req.inner.password_hash,
+        password_hash,
         role: req.inner.role,
         avatar_res_id: req.inner.avatar_res_id,
     };
@@ -238,6 +434,17 @@ pub async fn modify_user_info(
         .update_user(upd_row)
         .await
         .map_err(|e| internal!(e, "Failed to update user info"))?;
+    if let Some((old_role, new_role)) = role_change {
+        let log = NewAuditLog::new(
+            AuditAction::RoleChange,
+            Some(current_user_info.user_id),
+            &client_info,
+        )
+        .target_user(req.user_id)
+        .before(json!({ "role": old_role }))
+        .after(json!({ "role": new_role }));
+        audit.record(log).await;
+    }
     Ok(general_json_res!("User info updated successfully"))
 }
 
@@ -247,8 +454,10 @@ pub struct DeleteUserQuery {
 }
 
 pub async fn delete_user(
+    session: SessionHelper,
+    client_info: ClientInfo,
     current_user_info: BasicAuthData,
-    State((_, cache)): UserRouterState,
+    State((_, cache, _, _, audit)): UserRouterState,
     Query(req): Query<DeleteUserQuery>,
 ) -> ApiResult<Json<GeneralResponse<()>>> {
     let current_user = cache
@@ -262,10 +471,151 @@ pub async fn delete_user(
     if req.user_id == current_user_info.user_id {
         return Err(bad_request!("You are not allowed to delete yourself!"));
     }
+    session.require_fresh_mfa(current_user_info.user_id).await?;
+    let deleted = cache
+        .users
+        .get_user_by_user_id(req.user_id)
+        .await
+        .map_err(|e| match e {
+            HybridCacheError::ItemNotFound => not_found!(e, "User not found"),
+            e => internal!(e, "Failed to fetch user"),
+        })?;
     cache
         .users
         .remove_user_by_id(req.user_id)
         .await
         .map_err(|e| internal!(e, "Failed to delete user"))?;
+    let log = NewAuditLog::new(
+        AuditAction::UserDelete,
+        Some(current_user_info.user_id),
+        &client_info,
+    )
+    .target_user(req.user_id)
+    .before(json!({ "username": deleted.username, "role": deleted.role }));
+    audit.record(log).await;
     Ok(general_json_res!("User deleted successfully"))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordReq {
+    pub old_password_hash: String,
+    pub new_password_hash: String,
+}
+
+pub async fn change_password(
+    session: SessionHelper,
+    current_user_info: BasicAuthData,
+    State((state, cache, passwords, _, _)): UserRouterState,
+    Json(req): Json<ChangePasswordReq>,
+) -> ApiResult<Json<GeneralResponse<()>>> {
+    // before the old password, so a stolen session cannot use this to probe it
+    session.require_fresh_mfa(current_user_info.user_id).await?;
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    let user_row = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.users()
+                .query_user_by_username(&current_user.username)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to query user from database"))?
+        .ok_or_else(|| bad_request!("User not found"))?;
+    let check = passwords
+        .check(&user_row.password_hash, &req.old_password_hash)
+        .await
+        .map_err(|e| internal!(e, "Failed to verify password"))?;
+    if !check.is_match() {
+        return Err(unauthorized!("Incorrect password"));
+    }
+    let new_hash = passwords
+        .hash(&req.new_password_hash)
+        .await
+        .map_err(|e| internal!(e, "Failed to hash password"))?;
+    state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.users()
+                .swap_password_hash(user_row.id, &user_row.password_hash, &new_hash)
+                .await
+        })
+        .await
+        .map_err(|e| match e {
+            DataBaseError::NoAffectedRows(_) => {
+                conflict!("Password has been changed concurrently, please try again")
+            }
+            _ => internal!(e, "Failed to change password"),
+        })?;
+    Ok(general_json_res!("Password changed successfully"))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TEST_PASSWORD, TestApp, TestClient};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    fn change_password_body(old: &str, new: &str) -> serde_json::Value {
+        json!({ "old_password_hash": old, "new_password_hash": new })
+    }
+
+    #[tokio::test]
+    async fn test_change_password_checks_old_password() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let res = app
+            .send(
+                &mut alice,
+                Method::PATCH,
+                "/api/v1/user/password",
+                Some(change_password_body("wrong", "new password")),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::UNAUTHORIZED);
+        let res = app
+            .send(
+                &mut alice,
+                Method::PATCH,
+                "/api/v1/user/password",
+                Some(change_password_body(TEST_PASSWORD, "new password")),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let res = app
+            .send(
+                &mut TestClient::default(),
+                Method::POST,
+                "/api/v1/user/login",
+                Some(json!({ "username": "alice", "password_hash": "new password" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+    }
+
+    #[tokio::test]
+    async fn test_change_password_requires_fresh_mfa() {
+        let app = TestApp::build(|cfg| cfg.mfa.step_up_minutes = 0, &[]).await;
+        app.register("alice").await;
+        let mut alice = app.login("alice").await;
+        let codes = app.enroll_mfa(&mut alice).await;
+        app.verify_mfa(&mut alice, &codes[0]).await;
+        // the step-up is asked for before the old password is looked at
+        for old in ["wrong", TEST_PASSWORD] {
+            let res = app
+                .send(
+                    &mut alice,
+                    Method::PATCH,
+                    "/api/v1/user/password",
+                    Some(change_password_body(old, "new password")),
+                )
+                .await;
+            assert_eq!(res.status, StatusCode::FORBIDDEN, "{:?}", res.body);
+            assert_eq!(res.code(), Some(14100));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::models::api::prelude::*;
+use crate::models::relation::{UserRelationItem, UserRelationKind};
+use crate::models::session::BasicAuthData;
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::states::EchoState;
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
+};
+use axum::Json;
+use axum::extract::State;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub type UserRelationRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>)>;
+
+#[derive(Debug, Deserialize)]
+pub struct UserRelationReq {
+    user_id: i64,
+    kind: UserRelationKind,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserRelationRes {
+    /// `false` if nothing changed, e.g. the user was blocked already
+    changed: bool,
+}
+
+pub async fn add_relation(
+    current_user_info: BasicAuthData,
+    State((_, cache)): UserRelationRouterState,
+    Json(req): Json<UserRelationReq>,
+) -> ApiResult<Json<GeneralResponse<UserRelationRes>>> {
+    if req.user_id == current_user_info.user_id {
+        return Err(bad_request!("You cannot block or mute yourself"));
+    }
+    let changed = cache
+        .relations
+        .add_relation(current_user_info.user_id, req.user_id, req.kind)
+        .await
+        .map_err(|e| match e {
+            HybridCacheError::DatabaseError(DataBaseError::ForeignKeyViolation { .. }) => {
+                not_found!("User not found")
+            }
+            e => internal!(e, "Failed to add relation"),
+        })?;
+    Ok(general_json_res!(
+        "Successfully added relation",
+        UserRelationRes { changed }
+    ))
+}
+
+pub async fn remove_relation(
+    current_user_info: BasicAuthData,
+    State((_, cache)): UserRelationRouterState,
+    Json(req): Json<UserRelationReq>,
+) -> ApiResult<Json<GeneralResponse<UserRelationRes>>> {
+    let changed = cache
+        .relations
+        .remove_relation(current_user_info.user_id, req.user_id, req.kind)
+        .await
+        .map_err(|e| internal!(e, "Failed to remove relation"))?;
+    Ok(general_json_res!(
+        "Successfully removed relation",
+        UserRelationRes { changed }
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListRelationsReq {
+    kind: UserRelationKind,
+    #[serde(flatten)]
+    page_query: PageQueryBinder,
+}
+
+pub async fn list_relations(
+    current_user_info: BasicAuthData,
+    State((state, _)): UserRelationRouterState,
+    Json(req): Json<ListRelationsReq>,
+) -> ApiResult<Json<GeneralResponse<PageQueryResult<UserRelationItem>>>> {
+    let relations = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.user_relation()
+                .list_relations(current_user_info.user_id, req.kind, req.page_query)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to list relations"))?;
+    Ok(general_json_res!(
+        "Successfully listed relations",
+        relations
+    ))
+}
@@ -0,0 +1,220 @@
+use crate::models::api::prelude::*;
+use crate::models::const_val::ECHO_BASIC_AUTH_EXPIRE;
+use crate::models::session::{BasicAuthData, UserSessionItem};
+use crate::models::users::Role;
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use crate::services::states::db::EchoDatabaseExecutor;
+use axum::Json;
+use axum::extract::State;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+pub type UserSessionRouterState = State<(Arc<EchoState>, Arc<HybridCacheService>)>;
+
+/// The user whose sessions are managed, only admins may pick someone else
+async fn target_user_id(
+    current_user_info: &BasicAuthData,
+    cache: &HybridCacheService,
+    user_id: Option<i64>,
+) -> ApiResult<i64> {
+    let user_id = match user_id {
+        Some(user_id) if user_id != current_user_info.user_id => user_id,
+        _ => return Ok(current_user_info.user_id),
+    };
+    let current_user = cache
+        .users
+        .get_user_by_user_id(current_user_info.user_id)
+        .await
+        .map_err(|e| internal!(e, "Failed to fetch user"))?;
+    if current_user.role != Role::Admin {
+        return Err(bad_request!(
+            "You are not allowed to manage other users' sessions"
+        ));
+    }
+    Ok(user_id)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListSessionsReq {
+    user_id: Option<i64>,
+}
+
+pub async fn list_sessions(
+    current_user_info: BasicAuthData,
+    State((state, cache)): UserSessionRouterState,
+    Json(req): Json<ListSessionsReq>,
+) -> ApiResult<Json<GeneralResponse<Vec<UserSessionItem>>>> {
+    let user_id = target_user_id(&current_user_info, &cache, req.user_id).await?;
+    let expired_before = OffsetDateTime::now_utc() - ECHO_BASIC_AUTH_EXPIRE;
+    let sessions = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            exec.user_session()
+                .list_sessions(user_id, expired_before)
+                .await
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to list sessions"))?
+        .into_iter()
+        .map(|session| UserSessionItem {
+            current: session.session_uuid == current_user_info.session_uuid,
+            session,
+        })
+        .collect();
+    Ok(general_json_res!("Successfully listed sessions", sessions))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RevokeSessionsTarget {
+    Single {
+        id: i64,
+    },
+    /// Every session but the one making the request
+    Others,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeSessionsReq {
+    user_id: Option<i64>,
+    #[serde(flatten)]
+    target: RevokeSessionsTarget,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeSessionsRes {
+    revoked: usize,
+}
+
+pub async fn revoke_sessions(
+    current_user_info: BasicAuthData,
+    State((state, cache)): UserSessionRouterState,
+    Json(req): Json<RevokeSessionsReq>,
+) -> ApiResult<Json<GeneralResponse<RevokeSessionsRes>>> {
+    let user_id = target_user_id(&current_user_info, &cache, req.user_id).await?;
+    let now = OffsetDateTime::now_utc();
+    let revoked = state
+        .db
+        .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+            let mut sessions = exec.user_session();
+            match req.target {
+                RevokeSessionsTarget::Single { id } => sessions
+                    .revoke_session(user_id, id, now)
+                    .await
+                    .map(|it| it.into_iter().collect::<Vec<_>>()),
+                RevokeSessionsTarget::Others => {
+                    let keep = (user_id == current_user_info.user_id)
+                        .then_some(current_user_info.session_uuid);
+                    sessions.revoke_other_sessions(user_id, keep, now).await
+                }
+            }
+        })
+        .await
+        .map_err(|e| internal!(e, "Failed to revoke sessions"))?;
+    if let RevokeSessionsTarget::Single { .. } = req.target
+        && revoked.is_empty()
+    {
+        return Err(not_found!("Session not found"));
+    }
+    for session_uuid in &revoked {
+        state.cache.invalidate_user_session(*session_uuid).await;
+    }
+    Ok(general_json_res!(
+        "Successfully revoked sessions",
+        RevokeSessionsRes {
+            revoked: revoked.len()
+        }
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::routers::test_util::{TestApp, TestClient};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    /// Ids of the other sessions of `client`'s user, oldest first
+    async fn other_session_ids(app: &TestApp, client: &mut TestClient) -> Vec<i64> {
+        let res = app
+            .send(
+                client,
+                Method::POST,
+                "/api/v1/user/sessions",
+                Some(json!({})),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        let mut ids = res
+            .data()
+            .as_array()
+            .expect("Missing sessions")
+            .iter()
+            .filter(|it| it["current"] == false)
+            .map(|it| it["id"].as_i64().expect("Missing id"))
+            .collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    async fn fetch_info(app: &TestApp, client: &mut TestClient) -> StatusCode {
+        app.send(client, Method::GET, "/api/v1/user/info?user_id=1", None)
+            .await
+            .status
+    }
+
+    #[tokio::test]
+    async fn test_revoked_session_cookie_is_rejected() {
+        let app = TestApp::new().await;
+        app.register("alice").await;
+        app.register("bob").await;
+        let mut laptop = app.login("alice").await;
+        let mut phone = app.login("alice").await;
+        let mut tablet = app.login("alice").await;
+        let mut bob = app.login("bob").await;
+        assert_eq!(fetch_info(&app, &mut phone).await, StatusCode::OK);
+
+        let others = other_session_ids(&app, &mut laptop).await;
+        assert_eq!(others.len(), 2);
+        // nobody but the owner and admins may touch a session
+        let res = app
+            .send(
+                &mut bob,
+                Method::DELETE,
+                "/api/v1/user/sessions",
+                Some(json!({ "type": "single", "id": others[0] })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        assert_eq!(fetch_info(&app, &mut phone).await, StatusCode::OK);
+
+        let res = app
+            .send(
+                &mut laptop,
+                Method::DELETE,
+                "/api/v1/user/sessions",
+                Some(json!({ "type": "single", "id": others[0] })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(fetch_info(&app, &mut phone).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(fetch_info(&app, &mut tablet).await, StatusCode::OK);
+
+        let res = app
+            .send(
+                &mut laptop,
+                Method::DELETE,
+                "/api/v1/user/sessions",
+                Some(json!({ "type": "others" })),
+            )
+            .await;
+        assert_eq!(res.status, StatusCode::OK, "{:?}", res.body);
+        assert_eq!(res.data()["revoked"], 1);
+        assert_eq!(
+            fetch_info(&app, &mut tablet).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(fetch_info(&app, &mut laptop).await, StatusCode::OK);
+    }
+}
@@ -1,6 +1,18 @@
+pub mod access_token;
+pub mod activity_pub;
+pub mod audit;
 pub mod echo_baker;
+pub mod echo_import;
+pub mod echo_share;
+pub mod feed;
 pub mod hybrid_cache;
+pub mod live_timeline;
 pub mod mfa;
+pub mod mfa_policy;
+pub mod notification;
+pub mod oidc;
+pub mod password;
 pub mod res_manager;
 pub mod states;
+pub mod takeout;
 pub mod upload_tracker;
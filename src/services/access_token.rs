@@ -0,0 +1,136 @@
+use crate::models::const_val::ECHO_ACCESS_TOKEN_CACHE_TTL;
+use crate::models::token::{AuthTokenRaw, CreatedAuthToken, TokenScope};
+use crate::services::states::EchoState;
+use crate::services::states::cache::MokaExpiration;
+use crate::services::states::db::{DataBaseError, EchoDatabaseExecutor};
+use base64::{Engine as _, engine::general_purpose as b64_general_engine};
+use echo_macros::EchoBusinessError;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+/// Makes the tokens easy to spot in configs and secret scanners
+const TOKEN_PREFIX: &str = "echo_pat_";
+const TOKEN_DEFAULT_TTL: Duration = Duration::days(30);
+const TOKEN_MAX_TTL: Duration = Duration::days(365);
+const TOKEN_NAME_MAX_LEN: usize = 64;
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum AccessTokenError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error("A token must expire within {} days", TOKEN_MAX_TTL.whole_days())]
+    InvalidTtl,
+    #[error("A token name must be 1 to {TOKEN_NAME_MAX_LEN} characters long")]
+    InvalidName,
+    #[error("A token needs at least one scope")]
+    NoScope,
+    #[error("Token not found")]
+    NotFound,
+}
+
+pub type AccessTokenResult<T> = Result<T, AccessTokenError>;
+
+/// Personal access tokens, which let scripts and bots call the scoped routes without cookies
+pub struct AccessTokenService {
+    state: Arc<EchoState>,
+}
+
+impl AccessTokenService {
+    pub fn new(state: Arc<EchoState>) -> Self {
+        Self { state }
+    }
+
+    fn hash(token: &str) -> String {
+        hex::encode(Sha256::digest(token.as_bytes()))
+    }
+
+    /// The returned token is never stored and cannot be shown again
+    pub async fn create_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        mut scopes: Vec<TokenScope>,
+        ttl: Option<Duration>,
+    ) -> AccessTokenResult<CreatedAuthToken> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > TOKEN_NAME_MAX_LEN {
+            return Err(AccessTokenError::InvalidName);
+        }
+        let ttl = ttl.unwrap_or(TOKEN_DEFAULT_TTL);
+        if !ttl.is_positive() || ttl > TOKEN_MAX_TTL {
+            return Err(AccessTokenError::InvalidTtl);
+        }
+        scopes.sort_unstable();
+        scopes.dedup();
+        if scopes.is_empty() {
+            return Err(AccessTokenError::NoScope);
+        }
+        let token = format!(
+            "{TOKEN_PREFIX}{}",
+            b64_general_engine::URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+        );
+        let token_hash = Self::hash(&token);
+        let exp_time = OffsetDateTime::now_utc() + ttl;
+        let info = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.token()
+                    .insert_user_token(user_id, name, &token_hash, &scopes, exp_time)
+                    .await
+            })
+            .await?;
+        Ok(CreatedAuthToken { info, token })
+    }
+
+    pub async fn list_tokens(&self, user_id: i64) -> AccessTokenResult<Vec<AuthTokenRaw>> {
+        Ok(self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.token().get_user_token(user_id).await
+            })
+            .await?)
+    }
+
+    pub async fn revoke_token(&self, user_id: i64, token_id: i64) -> AccessTokenResult<()> {
+        let token_hash = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.token().invalidate_user_token(user_id, token_id).await
+            })
+            .await?
+            .ok_or(AccessTokenError::NotFound)?;
+        self.state.cache.invalidate_access_token(token_hash).await;
+        Ok(())
+    }
+
+    /// The token behind a bearer credential, `None` if it is unknown, revoked or expired.
+    /// `last_used_at` is only refreshed when the cached lookup runs out.
+    pub async fn authenticate(&self, token: &str) -> AccessTokenResult<Option<Arc<AuthTokenRaw>>> {
+        let token_hash = Self::hash(token);
+        let now = OffsetDateTime::now_utc();
+        let cache = &self.state.cache;
+        if let Some((_, info)) = cache.get_access_token(token_hash.clone()).await {
+            return Ok((info.exp_time > now).then_some(info));
+        }
+        let info = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.token().use_token(&token_hash, now).await
+            })
+            .await?
+            .filter(|info| info.exp_time > now)
+            .map(Arc::new);
+        if let Some(info) = &info {
+            let exp = MokaExpiration::new(ECHO_ACCESS_TOKEN_CACHE_TTL);
+            cache
+                .set_access_token(token_hash, (exp, info.clone()))
+                .await;
+        }
+        Ok(info)
+    }
+}
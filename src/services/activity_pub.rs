@@ -0,0 +1,868 @@
+pub mod http_signature;
+pub mod remote_addr;
+
+use crate::get_batch_tuple;
+use crate::gladiator::prelude::OutGoingEchoSSRConsCtx;
+use crate::models::activity_pub::{
+    AP_CONTENT_TYPE, AP_CONTEXT, AP_PUBLIC, AP_SECURITY_CONTEXT, ApActorKeyRow, ApDeliveryRow,
+    ApEchoActivity, IncomingActivity, NewApFollower, RemoteActor,
+};
+use crate::models::dyn_setting::{EnableActivityPub, SiteUrl};
+use crate::models::echo::{Echo, EchoPermission};
+use crate::models::users::User;
+use crate::services::activity_pub::http_signature::{
+    HttpSignatureError, generate_key_pair, parse_signature_header, sign_request, verify_request,
+};
+use crate::services::activity_pub::remote_addr::{
+    PublicOnlyResolver, is_public_url, public_only_redirects,
+};
+use crate::services::echo_baker::{EchoBaker, EchoBakerError};
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::states::EchoState;
+use crate::services::states::db::{DataBaseError, EchoDatabaseExecutor};
+use axum::http::{HeaderMap, Method, header};
+use echo_macros::EchoBusinessError;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::Notify;
+use url::Url;
+use uuid::Uuid;
+
+/// How often the delivery worker looks for due deliveries without being notified
+const DELIVERY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+const DELIVERY_BATCH_SIZE: u32 = 32;
+/// Deliveries are dropped after this many failed attempts (~1.5 days with the backoff below)
+const DELIVERY_MAX_ATTEMPTS: i64 = 10;
+const DELIVERY_MAX_BACKOFF: Duration = Duration::hours(6);
+const HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum ActivityPubError {
+    #[error("ActivityPub federation is disabled")]
+    Disabled,
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error(transparent)]
+    HybridCache(#[from] HybridCacheError),
+    #[error(transparent)]
+    HttpSignature(#[from] HttpSignatureError),
+    #[error(transparent)]
+    EchoBaker(#[from] EchoBakerError),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+    #[error(transparent)]
+    UrlParse(#[from] url::ParseError),
+    #[error(transparent)]
+    TimeFormat(#[from] time::error::Format),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+    #[error("Remote inbox responded with status {0}")]
+    DeliveryStatus(u16),
+    #[error("Signature key does not belong to the activity actor")]
+    ActorKeyMismatch,
+    #[error("Activity object does not target this actor")]
+    ObjectMismatch,
+    #[error("Refusing to reach {0}, it is not a public address")]
+    PrivateAddress(String),
+}
+
+pub type ActivityPubResult<T> = Result<T, ActivityPubError>;
+
+impl ActivityPubError {
+    /// Whether a failed delivery is worth another attempt
+    fn is_retryable(&self) -> bool {
+        match self {
+            ActivityPubError::DeliveryStatus(status) => {
+                matches!(status, 408 | 429) || *status >= 500
+            }
+            ActivityPubError::Http(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Urls of a local actor, every url is derived from `Site.SiteUrl` and the user id
+/// so that renaming a user does not break federation.
+pub struct ApActorUrls {
+    pub actor: String,
+}
+
+impl ApActorUrls {
+    pub fn new(site_url: &str, user_id: i64) -> Self {
+        Self {
+            actor: format!("{}/ap/users/{user_id}", site_url.trim_end_matches('/')),
+        }
+    }
+
+    pub fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor)
+    }
+
+    pub fn inbox(&self) -> String {
+        format!("{}/inbox", self.actor)
+    }
+
+    pub fn outbox(&self) -> String {
+        format!("{}/outbox", self.actor)
+    }
+
+    pub fn followers(&self) -> String {
+        format!("{}/followers", self.actor)
+    }
+}
+
+pub fn note_url(site_url: &str, echo_id: i64) -> String {
+    format!("{}/ap/echos/{echo_id}", site_url.trim_end_matches('/'))
+}
+
+/// Echos are only federated while everyone may read them
+pub fn is_federated(echo: &Echo) -> bool {
+    match &echo.permission {
+        EchoPermission::Public => true,
+        EchoPermission::WithPermissions { permissions } => permissions.is_empty(),
+        EchoPermission::Private => false,
+    }
+}
+
+/// Sign and POST a single activity to a remote inbox
+pub async fn deliver(
+    client: &reqwest::Client,
+    inbox_url: &str,
+    payload: &str,
+    key_id: &str,
+    private_key_pem: &str,
+) -> ActivityPubResult<()> {
+    let url = Url::parse(inbox_url)?;
+    let signed = sign_request(
+        &Method::POST,
+        &url,
+        Some(payload.as_bytes()),
+        key_id,
+        private_key_pem,
+    )?;
+    let mut req = client
+        .post(url)
+        .header(header::CONTENT_TYPE, AP_CONTENT_TYPE)
+        .body(payload.to_owned());
+    for (name, value) in signed {
+        req = req.header(name, value);
+    }
+    let res = req.send().await?;
+    match res.status() {
+        status if status.is_success() => Ok(()),
+        status => Err(ActivityPubError::DeliveryStatus(status.as_u16())),
+    }
+}
+
+pub struct ActivityPubService {
+    state: Arc<EchoState>,
+    cache: Arc<HybridCacheService>,
+    baker: Arc<EchoBaker<'static>>,
+    client: reqwest::Client,
+    delivery_notify: Notify,
+}
+
+impl ActivityPubService {
+    pub fn new(
+        state: Arc<EchoState>,
+        cache: Arc<HybridCacheService>,
+        baker: Arc<EchoBaker<'static>>,
+    ) -> ActivityPubResult<Self> {
+        let mut client = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .user_agent(concat!("echo/", env!("CARGO_PKG_VERSION")));
+        if !state.config.activity_pub.allow_private_addresses {
+            client = client
+                .dns_resolver(Arc::new(PublicOnlyResolver))
+                .redirect(public_only_redirects());
+        }
+        let client = client.build()?;
+        Ok(Self {
+            state,
+            cache,
+            baker,
+            client,
+            delivery_notify: Notify::new(),
+        })
+    }
+
+    /// Returns the site url (without trailing slash) when federation is enabled
+    pub async fn enabled_site_url(&self) -> ActivityPubResult<String> {
+        let (enabled, site_url) =
+            get_batch_tuple!(self.cache.dyn_settings, EnableActivityPub, SiteUrl)?;
+        enabled.ok_or(ActivityPubError::Disabled)?;
+        Ok(site_url.trim_end_matches('/').to_string())
+    }
+
+    /// Get the signing key of a local actor, lazily generated on first use
+    pub async fn actor_key(&self, user_id: i64) -> ActivityPubResult<ApActorKeyRow> {
+        let existing: Option<ApActorKeyRow> = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.activity_pub().get_actor_key(user_id).await
+            })
+            .await?;
+        if let Some(key) = existing {
+            return Ok(key);
+        }
+        let (private_pem, public_pem) = tokio::task::spawn_blocking(generate_key_pair).await??;
+        let key = self
+            .state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let mut repo = exec.activity_pub();
+                repo.insert_actor_key_if_absent(user_id, &private_pem, &public_pem)
+                    .await?;
+                repo.get_actor_key(user_id)
+                    .await?
+                    .ok_or(DataBaseError::RowNotFound(
+                        std::backtrace::Backtrace::capture(),
+                    ))
+            })
+            .await?;
+        Ok(key)
+    }
+
+    pub async fn actor_document(&self, site_url: &str, user: &User) -> ActivityPubResult<Value> {
+        let key = self.actor_key(user.id).await?;
+        let urls = ApActorUrls::new(site_url, user.id);
+        Ok(json!({
+            "@context": [AP_CONTEXT, AP_SECURITY_CONTEXT],
+            "id": urls.actor,
+            "type": "Person",
+            "preferredUsername": user.username,
+            "name": user.username,
+            "url": urls.actor,
+            "inbox": urls.inbox(),
+            "outbox": urls.outbox(),
+            "followers": urls.followers(),
+            "published": user.created_at.format(&Rfc3339)?,
+            "publicKey": {
+                "id": urls.key_id(),
+                "owner": urls.actor,
+                "publicKeyPem": key.public_key_pem,
+            },
+        }))
+    }
+
+    /// Render a public echo as a `Note`, gated spans are redacted with an empty permission set
+    pub fn note_object(&self, site_url: &str, echo: &Echo) -> ActivityPubResult<Value> {
+        let urls = ApActorUrls::new(site_url, echo.user_id);
+        let content = self
+            .baker
+            .post_inner_echo_with_ctx(
+                Arc::downgrade(&self.state),
+                echo,
+                OutGoingEchoSSRConsCtx::public(echo.user_id, site_url),
+                &[] as &[i64],
+                EchoBaker::all_ext_ids(),
+            )?
+            .unwrap_or_default();
+        let mut note = json!({
+            "id": note_url(site_url, echo.id),
+            "type": "Note",
+            "attributedTo": urls.actor,
+            "content": content,
+            "published": echo.created_at.format(&Rfc3339)?,
+            "to": [AP_PUBLIC],
+            "cc": [urls.followers()],
+        });
+        if echo.last_modified_at > echo.created_at {
+            note["updated"] = json!(echo.last_modified_at.format(&Rfc3339)?);
+        }
+        Ok(note)
+    }
+
+    pub fn wrap_activity(
+        &self,
+        site_url: &str,
+        activity: ApEchoActivity,
+        echo: &Echo,
+    ) -> ActivityPubResult<Value> {
+        let urls = ApActorUrls::new(site_url, echo.user_id);
+        let note_id = note_url(site_url, echo.id);
+        let (typ, id, object) = match activity {
+            ApEchoActivity::Create => (
+                "Create",
+                format!("{note_id}/activity"),
+                self.note_object(site_url, echo)?,
+            ),
+            ApEchoActivity::Update => (
+                "Update",
+                format!(
+                    "{note_id}/update/{}",
+                    echo.last_modified_at.unix_timestamp()
+                ),
+                self.note_object(site_url, echo)?,
+            ),
+            ApEchoActivity::Delete => (
+                "Delete",
+                format!("{note_id}/delete"),
+                json!({ "id": note_id, "type": "Tombstone" }),
+            ),
+        };
+        Ok(json!({
+            "@context": AP_CONTEXT,
+            "id": id,
+            "type": typ,
+            "actor": urls.actor,
+            "to": [AP_PUBLIC],
+            "cc": [urls.followers()],
+            "object": object,
+        }))
+    }
+
+    async fn enqueue(
+        &self,
+        user_id: i64,
+        inboxes: &[String],
+        payload: &str,
+    ) -> ActivityPubResult<()> {
+        if inboxes.is_empty() {
+            return Ok(());
+        }
+        self.state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                for inbox in inboxes {
+                    exec.activity_pub()
+                        .enqueue_delivery(user_id, inbox, payload)
+                        .await?;
+                }
+                Ok::<_, DataBaseError>(())
+            })
+            .await?;
+        self.delivery_notify.notify_one();
+        Ok(())
+    }
+
+    /// Queue the activity for every follower of the echo's author,
+    /// no-op if federation is disabled or the echo is not public.
+    pub async fn federate_echo(
+        &self,
+        activity: ApEchoActivity,
+        echo: &Echo,
+    ) -> ActivityPubResult<()> {
+        let site_url = match self.enabled_site_url().await {
+            Err(ActivityPubError::Disabled) => return Ok(()),
+            other => other?,
+        };
+        let activity = match (activity, is_federated(echo)) {
+            (_, true) => activity,
+            // an echo which is no longer public has to disappear from remote timelines
+            (ApEchoActivity::Update, false) => ApEchoActivity::Delete,
+            // private echos were never sent out, so there is nothing to retract
+            (ApEchoActivity::Create | ApEchoActivity::Delete, false) => return Ok(()),
+        };
+        let payload = serde_json::to_string(&self.wrap_activity(&site_url, activity, echo)?)?;
+        let inboxes = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.activity_pub()
+                    .list_follower_inboxes(echo.user_id)
+                    .await
+            })
+            .await?;
+        self.enqueue(echo.user_id, &inboxes, &payload).await
+    }
+
+    /// Parse a remote url, refusing private address literals unless the config allows them,
+    /// names resolving to private addresses are refused by the client itself.
+    fn remote_url(&self, url: &str) -> ActivityPubResult<Url> {
+        let url = Url::parse(url)?;
+        if !self.state.config.activity_pub.allow_private_addresses && !is_public_url(&url) {
+            return Err(ActivityPubError::PrivateAddress(url.to_string()));
+        }
+        Ok(url)
+    }
+
+    pub async fn fetch_remote_actor(&self, actor_url: &str) -> ActivityPubResult<RemoteActor> {
+        let res = self
+            .client
+            .get(self.remote_url(actor_url)?)
+            .header(header::ACCEPT, AP_CONTENT_TYPE)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(res.json::<RemoteActor>().await?)
+    }
+
+    /// Verify and dispatch an activity posted to the inbox of `user_id`
+    pub async fn handle_inbox(
+        &self,
+        user_id: i64,
+        method: &Method,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> ActivityPubResult<()> {
+        let site_url = self.enabled_site_url().await?;
+        let activity = serde_json::from_slice::<IncomingActivity>(body)?;
+        let signature = parse_signature_header(headers)?;
+        // the key is looked up through the actor, so a key of another server can never vouch for it
+        if Url::parse(&signature.key_id)?.origin() != Url::parse(&activity.actor)?.origin() {
+            return Err(ActivityPubError::ActorKeyMismatch);
+        }
+        let remote = self.fetch_remote_actor(&activity.actor).await?;
+        if remote.id != activity.actor
+            || remote.public_key.owner != activity.actor
+            || remote.public_key.id != signature.key_id
+        {
+            return Err(ActivityPubError::ActorKeyMismatch);
+        }
+        verify_request(
+            &signature,
+            method,
+            path_and_query,
+            headers,
+            body,
+            &remote.public_key.public_key_pem,
+        )?;
+        let urls = ApActorUrls::new(&site_url, user_id);
+        match activity.typ.as_str() {
+            "Follow" => {
+                if activity.object_id() != Some(urls.actor.as_str()) {
+                    return Err(ActivityPubError::ObjectMismatch);
+                }
+                let shared_inbox = remote
+                    .endpoints
+                    .as_ref()
+                    .and_then(|e| e.shared_inbox.as_deref());
+                self.state
+                    .db
+                    .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                        exec.activity_pub()
+                            .upsert_follower(NewApFollower {
+                                user_id,
+                                actor_url: &remote.id,
+                                inbox_url: &remote.inbox,
+                                shared_inbox_url: shared_inbox,
+                            })
+                            .await
+                    })
+                    .await?;
+                let accept = json!({
+                    "@context": AP_CONTEXT,
+                    "id": format!("{}#accepts/follows/{}", urls.actor, Uuid::new_v4()),
+                    "type": "Accept",
+                    "actor": urls.actor,
+                    "object": serde_json::from_slice::<Value>(body)?,
+                });
+                self.enqueue(
+                    user_id,
+                    std::slice::from_ref(&remote.inbox),
+                    &serde_json::to_string(&accept)?,
+                )
+                .await?;
+                tracing::info!("{} followed local actor {}", remote.id, user_id);
+            }
+            "Undo" if matches!(activity.object_type(), Some("Follow") | None) => {
+                self.state
+                    .db
+                    .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                        exec.activity_pub()
+                            .remove_follower(user_id, &remote.id)
+                            .await
+                    })
+                    .await?;
+                tracing::info!("{} unfollowed local actor {}", remote.id, user_id);
+            }
+            other => tracing::debug!("Ignore unsupported activity type: {}", other),
+        }
+        Ok(())
+    }
+
+    async fn process_delivery(
+        &self,
+        site_url: &str,
+        delivery: &ApDeliveryRow,
+    ) -> ActivityPubResult<()> {
+        let inbox_url = self.remote_url(&delivery.inbox_url)?;
+        let key = self.actor_key(delivery.user_id).await?;
+        let key_id = ApActorUrls::new(site_url, delivery.user_id).key_id();
+        deliver(
+            &self.client,
+            inbox_url.as_str(),
+            &delivery.payload,
+            &key_id,
+            &key.private_key_pem,
+        )
+        .await
+    }
+
+    /// Deliver every due activity once, failed ones are rescheduled with exponential backoff
+    pub async fn deliver_due(&self) -> ActivityPubResult<usize> {
+        let (site_url,) = get_batch_tuple!(self.cache.dyn_settings, SiteUrl)?;
+        let now = OffsetDateTime::now_utc();
+        let due = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.activity_pub()
+                    .fetch_due_deliveries(now, DELIVERY_BATCH_SIZE)
+                    .await
+            })
+            .await?;
+        for delivery in &due {
+            let result = self.process_delivery(&site_url, delivery).await;
+            self.state
+                .db
+                .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    let mut repo = exec.activity_pub();
+                    match &result {
+                        Ok(_) => repo.remove_delivery(delivery.id).await,
+                        Err(e)
+                            if e.is_retryable()
+                                && delivery.attempts + 1 < DELIVERY_MAX_ATTEMPTS =>
+                        {
+                            let backoff = (Duration::seconds(30)
+                                * 2i32.pow(delivery.attempts as u32))
+                            .min(DELIVERY_MAX_BACKOFF);
+                            tracing::warn!(
+                                "Delivery {} to {} failed, retry in {}: {}",
+                                delivery.id,
+                                delivery.inbox_url,
+                                backoff,
+                                e
+                            );
+                            repo.reschedule_delivery(delivery.id, now + backoff, &e.to_string())
+                                .await
+                        }
+                        Err(e) => {
+                            tracing::error!(
+                                "Delivery {} to {} dropped after {} attempts: {}",
+                                delivery.id,
+                                delivery.inbox_url,
+                                delivery.attempts + 1,
+                                e
+                            );
+                            repo.remove_delivery(delivery.id).await
+                        }
+                    }
+                })
+                .await?;
+        }
+        Ok(due.len())
+    }
+
+    /// Run the delivery queue until the server shuts down
+    pub fn spawn_delivery_worker(self: &Arc<Self>) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let shutdown = this.state.shutdown.clone();
+            loop {
+                match this.deliver_due().await {
+                    // a full batch means there may be more due deliveries
+                    Ok(n) if n as u32 == DELIVERY_BATCH_SIZE => continue,
+                    Ok(_) => {}
+                    Err(e) => tracing::error!("ActivityPub delivery worker error: {}", e),
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = this.delivery_notify.notified() => {},
+                    _ = tokio::time::sleep(DELIVERY_POLL_INTERVAL) => {},
+                }
+            }
+            tracing::info!("ActivityPub delivery worker stopped.");
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::routers::test_util::TestApp;
+    use crate::services::activity_pub::http_signature::generate_key_pair;
+    use axum::body::Bytes;
+    use axum::extract::State;
+    use axum::http::{StatusCode, Uri};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::sync::mpsc;
+
+    const SITE_URL: &str = "http://echo.test";
+
+    /// A stand-in remote inbox which only accepts correctly signed requests
+    async fn spawn_stand_in_inbox(public_pem: String) -> (String, mpsc::Receiver<Value>) {
+        let (tx, rx) = mpsc::channel(4);
+        let app = Router::new()
+            .route(
+                "/inbox",
+                post(
+                    async |State((public_pem, tx)): State<(String, mpsc::Sender<Value>)>,
+                           uri: Uri,
+                           headers: HeaderMap,
+                           body: Bytes| {
+                        let verified = parse_signature_header(&headers).and_then(|sig| {
+                            verify_request(
+                                &sig,
+                                &Method::POST,
+                                uri.path_and_query().unwrap().as_str(),
+                                &headers,
+                                &body,
+                                &public_pem,
+                            )
+                        });
+                        match verified {
+                            Ok(_) => {
+                                tx.send(serde_json::from_slice(&body).unwrap())
+                                    .await
+                                    .unwrap();
+                                StatusCode::ACCEPTED
+                            }
+                            Err(_) => StatusCode::UNAUTHORIZED,
+                        }
+                    },
+                ),
+            )
+            .with_state((public_pem, tx));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/inbox"), rx)
+    }
+
+    #[tokio::test]
+    async fn test_deliver_to_stand_in_inbox() {
+        let (private_pem, public_pem) = generate_key_pair().unwrap();
+        let (other_private_pem, _) = generate_key_pair().unwrap();
+        let (inbox, mut rx) = spawn_stand_in_inbox(public_pem).await;
+        let client = reqwest::Client::new();
+        let payload = json!({ "type": "Create", "object": { "type": "Note" } }).to_string();
+        deliver(&client, &inbox, &payload, "k#main-key", &private_pem)
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await.unwrap()["type"], "Create");
+        let wrong_key = deliver(&client, &inbox, &payload, "k#main-key", &other_private_pem).await;
+        assert!(matches!(
+            wrong_key,
+            Err(ActivityPubError::DeliveryStatus(401))
+        ));
+        assert!(!wrong_key.unwrap_err().is_retryable());
+    }
+
+    /// A remote inbox which is always unavailable, counts the attempts
+    async fn spawn_unavailable_inbox() -> (String, Arc<AtomicUsize>) {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/inbox",
+                post(async |State(hits): State<Arc<AtomicUsize>>| {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    StatusCode::SERVICE_UNAVAILABLE
+                }),
+            )
+            .with_state(hits.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (format!("http://{addr}/inbox"), hits)
+    }
+
+    /// A stand-in remote actor, its document is served at `/actor`
+    async fn spawn_stand_in_actor(public_pem: &str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let actor = format!("http://{}/actor", listener.local_addr().unwrap());
+        let doc = json!({
+            "id": actor,
+            "type": "Person",
+            "inbox": format!("{actor}/inbox"),
+            "publicKey": {
+                "id": format!("{actor}#main-key"),
+                "owner": actor,
+                "publicKeyPem": public_pem,
+            },
+        });
+        let app = Router::new()
+            .route("/actor", get(async |State(doc): State<Value>| Json(doc)))
+            .with_state(doc);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        actor
+    }
+
+    /// Federating app with a service of its own, so the tests drive the delivery queue
+    async fn federating_app(allow_private_addresses: bool) -> (TestApp, ActivityPubService) {
+        let app = TestApp::build(
+            |cfg| cfg.activity_pub.allow_private_addresses = allow_private_addresses,
+            &[
+                ("Federation.EnableActivityPub", "true"),
+                ("Site.SiteUrl", SITE_URL),
+            ],
+        )
+        .await;
+        // stops the delivery worker of the app itself
+        app.state.shutdown.cancel();
+        let ap = ActivityPubService::new(
+            app.state.clone(),
+            Arc::new(HybridCacheService::new(app.state.clone())),
+            Arc::new(EchoBaker::new(8)),
+        )
+        .unwrap();
+        (app, ap)
+    }
+
+    /// Sign `activity` as the remote actor would and post it to the inbox of `user_id`
+    async fn post_to_inbox(
+        ap: &ActivityPubService,
+        user_id: i64,
+        activity: &Value,
+        key_id: &str,
+        private_pem: &str,
+    ) -> ActivityPubResult<()> {
+        let inbox = Url::parse(&ApActorUrls::new(SITE_URL, user_id).inbox()).unwrap();
+        let body = activity.to_string();
+        let mut headers = HeaderMap::new();
+        let signed = sign_request(
+            &Method::POST,
+            &inbox,
+            Some(body.as_bytes()),
+            key_id,
+            private_pem,
+        )
+        .unwrap();
+        for (name, value) in signed {
+            headers.insert(name, value.parse().unwrap());
+        }
+        ap.handle_inbox(
+            user_id,
+            &Method::POST,
+            inbox.path(),
+            &headers,
+            body.as_bytes(),
+        )
+        .await
+    }
+
+    /// `(attempts, next_attempt_at)` of every queued delivery
+    async fn deliveries(app: &TestApp) -> Vec<(i64, i64)> {
+        sqlx::query_as("SELECT attempts, next_attempt_at FROM ap_deliveries")
+            .fetch_all(&app.pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_backs_off_then_is_dropped() {
+        let (app, ap) = federating_app(true).await;
+        let user_id = app.register("alice").await;
+        let (inbox, hits) = spawn_unavailable_inbox().await;
+        ap.enqueue(user_id, std::slice::from_ref(&inbox), "{}")
+            .await
+            .unwrap();
+
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        assert_eq!(ap.deliver_due().await.unwrap(), 1);
+        let queued = deliveries(&app).await;
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].0, 1);
+        assert!((now + 29..=now + 31).contains(&queued[0].1));
+        // not due again before the backoff has passed
+        assert_eq!(ap.deliver_due().await.unwrap(), 0);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+
+        sqlx::query("UPDATE ap_deliveries SET attempts = 3, next_attempt_at = 0")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        assert_eq!(ap.deliver_due().await.unwrap(), 1);
+        let queued = deliveries(&app).await;
+        assert_eq!(queued[0].0, 4);
+        assert!((now + 239..=now + 241).contains(&queued[0].1));
+
+        sqlx::query("UPDATE ap_deliveries SET attempts = 9, next_attempt_at = 0")
+            .execute(&app.pool)
+            .await
+            .unwrap();
+        assert_eq!(ap.deliver_due().await.unwrap(), 1);
+        assert!(deliveries(&app).await.is_empty());
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_to_private_address_is_refused() {
+        let (app, ap) = federating_app(false).await;
+        let user_id = app.register("alice").await;
+        let (inbox, hits) = spawn_unavailable_inbox().await;
+        ap.enqueue(user_id, std::slice::from_ref(&inbox), "{}")
+            .await
+            .unwrap();
+        assert_eq!(ap.deliver_due().await.unwrap(), 1);
+        // refused for good, not retried
+        assert!(deliveries(&app).await.is_empty());
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+        let localhost = inbox.replace("127.0.0.1", "localhost");
+        assert!(ap.fetch_remote_actor(&localhost).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_inbox_follow_and_undo() {
+        let (app, ap) = federating_app(true).await;
+        let user_id = app.register("alice").await;
+        let local_actor = ApActorUrls::new(SITE_URL, user_id).actor;
+        let (private_pem, public_pem) = generate_key_pair().unwrap();
+        let (other_private_pem, other_public_pem) = generate_key_pair().unwrap();
+        let actor = spawn_stand_in_actor(&public_pem).await;
+        let impostor = spawn_stand_in_actor(&other_public_pem).await;
+        let key_id = format!("{actor}#main-key");
+        let follow = json!({
+            "id": format!("{actor}/follows/1"),
+            "type": "Follow",
+            "actor": actor,
+            "object": local_actor,
+        });
+        let followers = async || {
+            sqlx::query_scalar::<_, String>("SELECT actor_url FROM ap_followers")
+                .fetch_all(&app.pool)
+                .await
+                .unwrap()
+        };
+
+        let wrong_key = post_to_inbox(&ap, user_id, &follow, &key_id, &other_private_pem).await;
+        assert!(matches!(wrong_key, Err(ActivityPubError::HttpSignature(_))));
+        // a key of another server, even though it signed correctly
+        let foreign_key = format!("{impostor}#main-key");
+        let foreign = post_to_inbox(&ap, user_id, &follow, &foreign_key, &other_private_pem).await;
+        assert!(matches!(foreign, Err(ActivityPubError::ActorKeyMismatch)));
+        // same server, but not the key the actor document names
+        let other_key = format!("{actor}#other-key");
+        let other = post_to_inbox(&ap, user_id, &follow, &other_key, &private_pem).await;
+        assert!(matches!(other, Err(ActivityPubError::ActorKeyMismatch)));
+        assert!(followers().await.is_empty());
+
+        post_to_inbox(&ap, user_id, &follow, &key_id, &private_pem)
+            .await
+            .unwrap();
+        assert_eq!(followers().await, vec![actor.clone()]);
+        let accept: (String, String) =
+            sqlx::query_as("SELECT inbox_url, payload FROM ap_deliveries")
+                .fetch_one(&app.pool)
+                .await
+                .unwrap();
+        assert_eq!(accept.0, format!("{actor}/inbox"));
+        let accept = serde_json::from_str::<Value>(&accept.1).unwrap();
+        assert_eq!(accept["type"], "Accept");
+        assert_eq!(accept["object"]["id"], follow["id"]);
+
+        let undo = json!({
+            "id": format!("{actor}/follows/1/undo"),
+            "type": "Undo",
+            "actor": actor,
+            "object": follow,
+        });
+        let forged = post_to_inbox(&ap, user_id, &undo, &key_id, &other_private_pem).await;
+        assert!(matches!(forged, Err(ActivityPubError::HttpSignature(_))));
+        assert_eq!(followers().await.len(), 1);
+        post_to_inbox(&ap, user_id, &undo, &key_id, &private_pem)
+            .await
+            .unwrap();
+        assert!(followers().await.is_empty());
+    }
+}
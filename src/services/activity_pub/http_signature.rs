@@ -0,0 +1,270 @@
+//! Minimal `draft-cavage-http-signatures` (rsa-sha256) as spoken by Mastodon and friends.
+use axum::http::{HeaderMap, HeaderName, Method, header};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use echo_macros::EchoBusinessError;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::sign::{Signer, Verifier};
+use sha2::{Digest, Sha256};
+use std::time::SystemTime;
+use url::{Position, Url};
+
+/// Accepted clock skew between us and the remote instance
+const MAX_DATE_SKEW_SECS: u64 = 12 * 60 * 60;
+const RSA_KEY_BITS: u32 = 2048;
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum HttpSignatureError {
+    #[error(transparent)]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error("Missing signature header")]
+    MissingSignature,
+    #[error("Malformed signature header")]
+    MalformedSignature,
+    #[error("Unsupported signature algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("Signed header is missing from the request: {0}")]
+    MissingSignedHeader(String),
+    #[error("Required header is not covered by the signature: {0}")]
+    UnsignedRequiredHeader(&'static str),
+    #[error("Request date is missing or out of range")]
+    InvalidDate,
+    #[error("Digest does not match the request body")]
+    DigestMismatch,
+    #[error("Signature verification failed")]
+    VerifyFailed,
+    #[error("Url has no host")]
+    MissingHost,
+}
+
+pub type HttpSignatureResult<T> = Result<T, HttpSignatureError>;
+
+#[derive(Debug)]
+pub struct ParsedSignature {
+    pub key_id: String,
+    pub headers: Vec<String>,
+    pub signature: Vec<u8>,
+}
+
+/// Returns `(private_key_pem, public_key_pem)`
+pub fn generate_key_pair() -> HttpSignatureResult<(String, String)> {
+    let pkey = PKey::from_rsa(Rsa::generate(RSA_KEY_BITS)?)?;
+    let private_pem = String::from_utf8_lossy(&pkey.private_key_to_pem_pkcs8()?).into_owned();
+    let public_pem = String::from_utf8_lossy(&pkey.public_key_to_pem()?).into_owned();
+    Ok((private_pem, public_pem))
+}
+
+pub fn body_digest(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64_STANDARD.encode(Sha256::digest(body)))
+}
+
+fn signing_string<'a>(
+    names: impl IntoIterator<Item = &'a str>,
+    mut header_value: impl FnMut(&str) -> HttpSignatureResult<String>,
+) -> HttpSignatureResult<String> {
+    let lines = names
+        .into_iter()
+        .map(|name| Ok(format!("{name}: {}", header_value(name)?)))
+        .collect::<HttpSignatureResult<Vec<_>>>()?;
+    Ok(lines.join("\n"))
+}
+
+fn request_target(method: &Method, path_and_query: &str) -> String {
+    format!(
+        "{} {}",
+        method.as_str().to_ascii_lowercase(),
+        path_and_query
+    )
+}
+
+/// Headers (including `Signature`) which must be attached to the outgoing request
+pub fn sign_request(
+    method: &Method,
+    url: &Url,
+    body: Option<&[u8]>,
+    key_id: &str,
+    private_key_pem: &str,
+) -> HttpSignatureResult<Vec<(HeaderName, String)>> {
+    let host = url
+        .host_str()
+        .map(|_| url[Position::BeforeHost..Position::AfterPort].to_string())
+        .ok_or(HttpSignatureError::MissingHost)?;
+    let mut out = vec![
+        (header::HOST, host),
+        (header::DATE, httpdate::fmt_http_date(SystemTime::now())),
+    ];
+    if let Some(body) = body {
+        out.push((HeaderName::from_static("digest"), body_digest(body)));
+    }
+    let target = request_target(method, &url[Position::BeforePath..Position::AfterQuery]);
+    let names = std::iter::once("(request-target)")
+        .chain(out.iter().map(|(name, _)| name.as_str()))
+        .collect::<Vec<_>>();
+    let to_sign = signing_string(names.iter().copied(), |name| match name {
+        "(request-target)" => Ok(target.clone()),
+        _ => Ok(out
+            .iter()
+            .find(|(n, _)| n.as_str() == name)
+            .map(|(_, v)| v.clone())
+            .unwrap_or_default()),
+    })?;
+    let pkey = PKey::private_key_from_pem(private_key_pem.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+    signer.update(to_sign.as_bytes())?;
+    let signature = BASE64_STANDARD.encode(signer.sign_to_vec()?);
+    out.push((
+        HeaderName::from_static("signature"),
+        format!(
+            r#"keyId="{key_id}",algorithm="rsa-sha256",headers="{}",signature="{signature}""#,
+            names.join(" ")
+        ),
+    ));
+    Ok(out)
+}
+
+pub fn parse_signature_header(headers: &HeaderMap) -> HttpSignatureResult<ParsedSignature> {
+    let raw = headers
+        .get("signature")
+        .and_then(|v| v.to_str().ok())
+        .ok_or(HttpSignatureError::MissingSignature)?;
+    let (mut key_id, mut algorithm, mut signed, mut signature) = (None, None, None, None);
+    for part in raw.split(',') {
+        let (k, v) = part
+            .trim()
+            .split_once('=')
+            .ok_or(HttpSignatureError::MalformedSignature)?;
+        let v = v.trim_matches('"');
+        match k {
+            "keyId" => key_id = Some(v.to_string()),
+            "algorithm" => algorithm = Some(v.to_string()),
+            "headers" => signed = Some(v.split(' ').map(str::to_ascii_lowercase).collect()),
+            "signature" => {
+                signature = Some(
+                    BASE64_STANDARD
+                        .decode(v)
+                        .map_err(|_| HttpSignatureError::MalformedSignature)?,
+                )
+            }
+            _ => {}
+        }
+    }
+    // `hs2019` is what newer implementations send for the very same rsa-sha256 scheme
+    if let Some(alg) = algorithm
+        && alg != "rsa-sha256"
+        && alg != "hs2019"
+    {
+        return Err(HttpSignatureError::UnsupportedAlgorithm(alg));
+    }
+    Ok(ParsedSignature {
+        key_id: key_id.ok_or(HttpSignatureError::MalformedSignature)?,
+        // the spec defaults to `date` only, which we reject later anyway
+        headers: signed.unwrap_or_else(|| vec!["date".to_string()]),
+        signature: signature.ok_or(HttpSignatureError::MalformedSignature)?,
+    })
+}
+
+/// Verify a parsed signature against the incoming request,
+/// a body carrying request must cover its `digest` and the digest must match.
+pub fn verify_request(
+    parsed: &ParsedSignature,
+    method: &Method,
+    path_and_query: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+    public_key_pem: &str,
+) -> HttpSignatureResult<()> {
+    let covers = |name: &str| parsed.headers.iter().any(|it| it == name);
+    for required in ["(request-target)", "host", "date"] {
+        if !covers(required) {
+            return Err(HttpSignatureError::UnsignedRequiredHeader(required));
+        }
+    }
+    let date = headers
+        .get(header::DATE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| httpdate::parse_http_date(v).ok())
+        .ok_or(HttpSignatureError::InvalidDate)?;
+    let now = SystemTime::now();
+    let skew = now
+        .duration_since(date)
+        .or_else(|_| date.duration_since(now))
+        .map_err(|_| HttpSignatureError::InvalidDate)?;
+    if skew.as_secs() > MAX_DATE_SKEW_SECS {
+        return Err(HttpSignatureError::InvalidDate);
+    }
+    if !body.is_empty() {
+        if !covers("digest") {
+            return Err(HttpSignatureError::UnsignedRequiredHeader("digest"));
+        }
+        let digest = headers
+            .get("digest")
+            .and_then(|v| v.to_str().ok())
+            .ok_or(HttpSignatureError::DigestMismatch)?;
+        if digest != body_digest(body) {
+            return Err(HttpSignatureError::DigestMismatch);
+        }
+    }
+    let target = request_target(method, path_and_query);
+    let to_verify = signing_string(
+        parsed.headers.iter().map(String::as_str),
+        |name| match name {
+            "(request-target)" => Ok(target.clone()),
+            _ => headers
+                .get_all(name)
+                .iter()
+                .map(|v| v.to_str().map(str::trim))
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .filter(|it| !it.is_empty())
+                .map(|it| it.join(", "))
+                .ok_or_else(|| HttpSignatureError::MissingSignedHeader(name.to_string())),
+        },
+    )?;
+    let pkey = PKey::public_key_from_pem(public_key_pem.as_bytes())?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &pkey)?;
+    verifier.update(to_verify.as_bytes())?;
+    verifier
+        .verify(&parsed.signature)?
+        .ok_or(HttpSignatureError::VerifyFailed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn to_header_map(signed: Vec<(HeaderName, String)>) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in signed {
+            headers.insert(name, HeaderValue::from_str(&value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (private_pem, public_pem) = generate_key_pair().unwrap();
+        let url = Url::parse("http://127.0.0.1:8080/inbox?x=1").unwrap();
+        let body = br#"{"type":"Create"}"#;
+        let headers = to_header_map(
+            sign_request(&Method::POST, &url, Some(body), "k#main-key", &private_pem).unwrap(),
+        );
+        assert_eq!(headers.get(header::HOST).unwrap(), "127.0.0.1:8080");
+        let parsed = parse_signature_header(&headers).unwrap();
+        assert_eq!(parsed.key_id, "k#main-key");
+        let verify = |path: &str, body: &[u8]| {
+            verify_request(&parsed, &Method::POST, path, &headers, body, &public_pem)
+        };
+        assert!(verify("/inbox?x=1", body).is_ok());
+        assert!(matches!(
+            verify("/inbox?x=2", body),
+            Err(HttpSignatureError::VerifyFailed)
+        ));
+        assert!(matches!(
+            verify("/inbox?x=1", br#"{"type":"Delete"}"#),
+            Err(HttpSignatureError::DigestMismatch)
+        ));
+    }
+}
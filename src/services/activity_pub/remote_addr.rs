@@ -0,0 +1,107 @@
+//! Keeps requests to remote instances off the loopback and private networks of the host,
+//! actor and inbox urls are chosen by whoever posts to the inbox.
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::{Attempt, Policy};
+use std::net::{IpAddr, Ipv4Addr};
+use url::{Host, Url};
+
+const MAX_REDIRECTS: usize = 10;
+
+fn is_shared_v4(ip: Ipv4Addr) -> bool {
+    // 100.64.0.0/10, carrier-grade NAT
+    let [a, b, ..] = ip.octets();
+    a == 100 && (b & 0xc0) == 64
+}
+
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || is_shared_v4(ip))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_ip(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Whether the url may be requested, hosts given by name are checked once resolved
+pub fn is_public_url(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+        && match url.host() {
+            Some(Host::Domain(_)) => true,
+            Some(Host::Ipv4(ip)) => is_public_ip(IpAddr::V4(ip)),
+            Some(Host::Ipv6(ip)) => is_public_ip(IpAddr::V6(ip)),
+            None => false,
+        }
+}
+
+/// Resolves names to their public addresses only
+pub struct PublicOnlyResolver;
+
+impl Resolve for PublicOnlyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Redirects are followed as usual, as long as they do not point at a private address literal
+pub fn public_only_redirects() -> Policy {
+    Policy::custom(|attempt: Attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if !is_public_url(attempt.url()) {
+            let err = format!("refused redirect to {}", attempt.url());
+            attempt.error(err)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_private_urls_are_refused() {
+        for url in [
+            "http://127.0.0.1/inbox",
+            "http://10.1.2.3/inbox",
+            "http://192.168.0.1/inbox",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/inbox",
+            "http://[::1]/inbox",
+            "http://[fd00::1]/inbox",
+            "http://[::ffff:127.0.0.1]/inbox",
+            "file:///etc/passwd",
+        ] {
+            assert!(!is_public_url(&Url::parse(url).unwrap()), "{url}");
+        }
+        for url in ["https://example.com/inbox", "http://93.184.215.14/inbox"] {
+            assert!(is_public_url(&Url::parse(url).unwrap()), "{url}");
+        }
+    }
+}
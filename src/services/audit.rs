@@ -0,0 +1,102 @@
+use crate::get_batch_tuple_pure;
+use crate::models::audit::{AuditLog, AuditLogFilter, NewAuditLog};
+use crate::models::dyn_setting::RetentionDays;
+use crate::services::states::EchoState;
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
+};
+use echo_macros::EchoBusinessError;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+const AUDIT_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum AuditError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+}
+
+pub type AuditResult<T> = Result<T, AuditError>;
+
+/// The security audit log of administrative and account actions
+pub struct AuditService {
+    state: Arc<EchoState>,
+}
+
+impl AuditService {
+    pub fn new(state: Arc<EchoState>) -> Self {
+        Self { state }
+    }
+
+    /// Best effort, the action has already happened when it is recorded, so failing to
+    /// record it only shows up in the server logs
+    pub async fn record(&self, log: NewAuditLog) {
+        let action = log.action;
+        let res = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.audit().insert_audit_log(log).await
+            })
+            .await;
+        if let Err(e) = res {
+            tracing::error!("Failed to record audit log of {:?}: {}", action, e);
+        }
+    }
+
+    pub async fn query(
+        &self,
+        filter: &AuditLogFilter,
+        page: PageQueryBinder,
+    ) -> AuditResult<PageQueryResult<AuditLog>> {
+        let res = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.audit().get_audit_logs_page(filter, page).await
+            })
+            .await?;
+        Ok(res)
+    }
+
+    async fn sweep_expired(&self) -> AuditResult<()> {
+        let (retention_days,): (u32,) = get_batch_tuple_pure!(&self.state.db, RetentionDays)?;
+        if retention_days == 0 {
+            return Ok(());
+        }
+        let before = OffsetDateTime::now_utc() - Duration::days(retention_days as i64);
+        let removed = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.audit().delete_audit_logs_before(before).await
+            })
+            .await?;
+        if removed > 0 {
+            tracing::info!(
+                "Removed {} audit log entries older than {}",
+                removed,
+                before
+            );
+        }
+        Ok(())
+    }
+
+    pub fn spawn_retention_worker(self: &Arc<Self>) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let shutdown = this.state.shutdown.clone();
+            loop {
+                if let Err(e) = this.sweep_expired().await {
+                    tracing::error!("Audit log retention worker error: {}", e);
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(AUDIT_SWEEP_INTERVAL) => {},
+                }
+            }
+            tracing::info!("Audit log retention worker stopped.");
+        });
+    }
+}
@@ -26,6 +26,7 @@ pub type EchoBakerResult<T> = Result<T, EchoBakerError>;
 pub struct AddOuterEchoRes {
     pub safe_echo: String,
     pub res_ids: Option<SmallVec<[i64; 5]>>,
+    pub mentions: Vec<IncomingEchoMention>,
 }
 
 pub struct EchoBaker<'a> {
@@ -78,7 +79,8 @@ impl<'a> EchoBaker<'a> {
         let safe_echo = self.builder.clean(echo).to_string();
         let mut checker = IncomingEchoCheckCons::new();
         let mut res_ids = IncomingEchoResExtractorCons::new();
-        let mut chain = hlist![&mut checker, &mut res_ids, GladiatorNoopEnd];
+        let mut mentions = IncomingEchoMentionExtractorCons::new();
+        let mut chain = hlist![&mut checker, &mut res_ids, &mut mentions, GladiatorNoopEnd];
         ts.transform(&safe_echo, &mut chain)?;
         if let Some(err) = checker.error_ref() {
             tracing::error!("Add outer echo SSR error: {:?}", err);
@@ -87,6 +89,7 @@ impl<'a> EchoBaker<'a> {
         Ok(AddOuterEchoRes {
             safe_echo,
             res_ids: res_ids.res_ids_take(),
+            mentions: mentions.mentions_take(),
         })
     }
 
@@ -115,6 +118,40 @@ impl<'a> EchoBaker<'a> {
                 return Ok(Some(cached.get().clone()));
             }
         }
+        let output = self.post_inner_echo_with_ctx(
+            state,
+            echo,
+            OutGoingEchoSSRConsCtx::new(current_user_id),
+            current_user_permissions,
+            ext_ids,
+        )?;
+        if let Some(echo_hash) = echo_cache_hash
+            && let Some(output) = &output
+        {
+            let _ = self.cache.put_sync(echo_hash, output.clone());
+        }
+        Ok(output)
+    }
+
+    /// Same as [`Self::post_inner_echo`] but with a caller provided SSR context,
+    /// the output depends on the context, so it always bypasses the echo cache.
+    pub fn post_inner_echo_with_ctx<P, E>(
+        &self,
+        state: WeakArc<EchoState>,
+        echo: &Echo,
+        ctx: OutGoingEchoSSRConsCtx,
+        current_user_permissions: P,
+        ext_ids: E,
+    ) -> EchoBakerResult<Option<String>>
+    where
+        P: IntoIterator,
+        P::Item: Borrow<i64>,
+        E: IntoIterator,
+        E::Item: Borrow<u32>,
+    {
+        let Some(content) = echo.content.as_ref() else {
+            return Ok(None);
+        };
         let permissions = current_user_permissions
             .into_iter()
             .map(|x| x.borrow().to_string())
@@ -124,11 +161,8 @@ impl<'a> EchoBaker<'a> {
             .map(|x| x.borrow().to_string())
             .collect();
         let ts = GladiatorTransformer::new(&permissions, &ext_ids);
-        let safe_echo = self
-            .builder
-            .clean(echo.content.as_ref().unwrap())
-            .to_string();
-        let mut ssr_cons = OutGoingEchoSSRCons::new(state, current_user_id);
+        let safe_echo = self.builder.clean(content).to_string();
+        let mut ssr_cons = OutGoingEchoSSRCons::new_with_ctx(state, ctx);
         let mut chain = hlist![OutGoingEchoFilterCons, &mut ssr_cons, GladiatorCollectEnd];
         let output = ts.transform(&safe_echo, &mut chain)?;
         // TODO: use ammonia to filter final result again?
@@ -136,9 +170,6 @@ impl<'a> EchoBaker<'a> {
             tracing::error!("Post inner echo SSR error: {:?}", err);
             return Err(EchoBakerError::GladiatorPostInner);
         }
-        if let Some(echo_hash) = echo_cache_hash {
-            let _ = self.cache.put_sync(echo_hash, output.clone());
-        }
         Ok(Some(output))
     }
 
@@ -0,0 +1,545 @@
+mod ech0;
+mod markdown;
+mod memos;
+
+use crate::models::echo_import::{
+    ImportItemStatus, ImportOptions, ImportReport, ImportReportItem, ImportSource,
+};
+use crate::models::resource::ResourceItemRawInfo;
+use crate::models::users::UserRow;
+use crate::services::echo_baker::{EchoBaker, EchoBakerError};
+use crate::services::echo_import::markdown::markdown_to_tiptap;
+use crate::services::states::EchoState;
+use crate::services::states::db::{DataBaseError, EchoDatabaseExecutor};
+use ahash::HashMap;
+use echo_macros::EchoBusinessError;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Connection, SqliteConnection};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+/// Images larger than this are reported instead of imported
+const MAX_IMAGE_SIZE: usize = 32 * 1024 * 1024;
+const HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum EchoImportError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error("Failed to read the source database: {0}")]
+    SourceDatabase(#[from] sqlx::Error),
+    #[error("Unsupported source database schema: {0}")]
+    UnsupportedSchema(String),
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    EchoBaker(#[from] EchoBakerError),
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+    #[error("Invalid import option: {0}")]
+    InvalidOption(String),
+    #[error("Image is not available: {0}")]
+    ImageUnavailable(String),
+}
+
+pub type EchoImportResult<T> = Result<T, EchoImportError>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ImportedVisibility {
+    Public,
+    /// Visible to every signed-in user of the source instance (Memos only)
+    Protected,
+    Private,
+}
+
+/// Where to find an image, tried in field order
+#[derive(Debug)]
+pub struct ImportedImage {
+    pub name: String,
+    pub blob: Option<Vec<u8>>,
+    /// Relative to `ImportOptions::assets_dir`
+    pub local_path: Option<String>,
+    /// Absolute, or relative to `ImportOptions::source_base_url`
+    pub url: Option<String>,
+}
+
+/// A source item, normalized but not yet mapped onto this instance
+#[derive(Debug)]
+pub struct ImportedEcho {
+    pub author: String,
+    /// Markdown
+    pub content: String,
+    pub visibility: ImportedVisibility,
+    pub created_at: OffsetDateTime,
+    pub images: Vec<ImportedImage>,
+    pub warnings: Vec<String>,
+}
+
+/// Source id and the item, or why it could not be parsed
+pub type ParsedItem = (String, EchoImportResult<ImportedEcho>);
+
+struct FetchedImage {
+    name: String,
+    data: Vec<u8>,
+    ext: &'static str,
+}
+
+impl ImportReportItem {
+    fn skipped(source_id: String, reason: impl ToString, warnings: Vec<String>) -> Self {
+        Self {
+            source_id,
+            status: ImportItemStatus::Skipped,
+            echo_id: None,
+            reason: Some(reason.to_string()),
+            warnings,
+        }
+    }
+}
+
+pub struct EchoImportService {
+    state: Arc<EchoState>,
+    baker: Arc<EchoBaker<'static>>,
+    client: reqwest::Client,
+}
+
+impl EchoImportService {
+    pub fn new(state: Arc<EchoState>, baker: Arc<EchoBaker<'static>>) -> EchoImportResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .user_agent(concat!("echo/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        Ok(Self {
+            state,
+            baker,
+            client,
+        })
+    }
+
+    /// Import a JSON export or a SQLite database, the format is sniffed from the content
+    pub async fn import_file(
+        &self,
+        source: ImportSource,
+        path: &Path,
+        options: &ImportOptions,
+    ) -> EchoImportResult<ImportReport> {
+        let items = match tokio::fs::read(path).await? {
+            data if data.starts_with(SQLITE_MAGIC) => self.parse_sqlite(source, path).await?,
+            data => Self::parse_json(source, &data)?,
+        };
+        self.import_items(source, items, options).await
+    }
+
+    pub async fn import_bytes(
+        &self,
+        source: ImportSource,
+        data: &[u8],
+        options: &ImportOptions,
+    ) -> EchoImportResult<ImportReport> {
+        let items = match data.starts_with(SQLITE_MAGIC) {
+            true => {
+                let mut tmp = tempfile::NamedTempFile::new()?;
+                tmp.write_all(data)?;
+                tmp.flush()?;
+                self.parse_sqlite(source, tmp.path()).await?
+            }
+            false => Self::parse_json(source, data)?,
+        };
+        self.import_items(source, items, options).await
+    }
+
+    fn parse_json(source: ImportSource, data: &[u8]) -> EchoImportResult<Vec<ParsedItem>> {
+        match source {
+            ImportSource::Ech0 => ech0::parse_json(data),
+            ImportSource::Memos => memos::parse_json(data),
+        }
+    }
+
+    async fn parse_sqlite(
+        &self,
+        source: ImportSource,
+        path: &Path,
+    ) -> EchoImportResult<Vec<ParsedItem>> {
+        let opts = SqliteConnectOptions::new()
+            .filename(path)
+            .read_only(true)
+            .immutable(true);
+        let mut conn = SqliteConnection::connect_with(&opts).await?;
+        let items = match source {
+            ImportSource::Ech0 => ech0::parse_sqlite(&mut conn).await,
+            ImportSource::Memos => memos::parse_sqlite(&mut conn).await,
+        };
+        conn.close().await?;
+        items
+    }
+
+    async fn validate_options(&self, options: &ImportOptions) -> EchoImportResult<()> {
+        let user_ids = options
+            .user_map
+            .values()
+            .copied()
+            .chain(options.default_user_id)
+            .collect::<Vec<_>>();
+        let permission_ids = options
+            .image_permission_id
+            .into_iter()
+            .chain(options.protected_permission_id)
+            .collect::<Vec<_>>();
+        self.state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                if let Some(id) = exec.users().check_user_exists(&user_ids).await? {
+                    return Err(EchoImportError::InvalidOption(format!(
+                        "user {id} does not exist"
+                    )));
+                }
+                if let Some(id) = exec
+                    .permission()
+                    .check_permission_exists(&permission_ids)
+                    .await?
+                {
+                    return Err(EchoImportError::InvalidOption(format!(
+                        "permission {id} does not exist"
+                    )));
+                }
+                Ok(())
+            })
+            .await
+    }
+
+    async fn resolve_author(
+        &self,
+        author: &str,
+        options: &ImportOptions,
+        resolved: &mut HashMap<String, Option<i64>>,
+    ) -> EchoImportResult<Option<i64>> {
+        if let Some(user_id) = resolved.get(author) {
+            return Ok(*user_id);
+        }
+        let user_id = match options.user_map.get(author) {
+            Some(user_id) => Some(*user_id),
+            None => {
+                // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
+                let same_name: Option<UserRow> = self
+                    .state
+                    .db
+                    .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                        exec.users().query_user_by_username(author).await
+                    })
+                    .await?;
+                same_name.map(|it| it.id).or(options.default_user_id)
+            }
+        };
+        resolved.insert(author.to_string(), user_id);
+        Ok(user_id)
+    }
+
+    async fn fetch_image(
+        &self,
+        image: &ImportedImage,
+        options: &ImportOptions,
+    ) -> EchoImportResult<FetchedImage> {
+        let local = image
+            .local_path
+            .as_deref()
+            .zip(options.assets_dir.as_deref())
+            .map(|(path, dir)| dir.join(path));
+        let data = match (&image.blob, local) {
+            (Some(blob), _) => blob.clone(),
+            (None, Some(local)) if tokio::fs::try_exists(&local).await? => {
+                tokio::fs::read(&local).await?
+            }
+            _ => {
+                let url = image
+                    .url
+                    .as_deref()
+                    .ok_or_else(|| EchoImportError::ImageUnavailable(image.name.clone()))?;
+                let url = match (
+                    url.starts_with("http://") || url.starts_with("https://"),
+                    &options.source_base_url,
+                ) {
+                    (true, _) => url.to_string(),
+                    (false, Some(base)) => format!(
+                        "{}/{}",
+                        base.trim_end_matches('/'),
+                        url.trim_start_matches('/')
+                    ),
+                    (false, None) => {
+                        return Err(EchoImportError::ImageUnavailable(format!(
+                            "{url} (no assets dir or source base url)"
+                        )));
+                    }
+                };
+                let res = self.client.get(&url).send().await?.error_for_status()?;
+                if res.content_length().unwrap_or_default() > MAX_IMAGE_SIZE as u64 {
+                    return Err(EchoImportError::ImageUnavailable(format!(
+                        "{url} is too large"
+                    )));
+                }
+                res.bytes().await?.to_vec()
+            }
+        };
+        if data.len() > MAX_IMAGE_SIZE {
+            return Err(EchoImportError::ImageUnavailable(format!(
+                "{} is too large",
+                image.name
+            )));
+        }
+        match infer::get(&data) {
+            Some(typ) if typ.matcher_type() == infer::MatcherType::Image => Ok(FetchedImage {
+                name: image.name.clone(),
+                data,
+                ext: typ.extension(),
+            }),
+            _ => Err(EchoImportError::ImageUnavailable(format!(
+                "{} is not an image",
+                image.name
+            ))),
+        }
+    }
+
+    /// Store the images like a committed upload, returns `(res_uuid, path)` for each of them
+    async fn store_images(
+        &self,
+        images: &[FetchedImage],
+    ) -> EchoImportResult<Vec<(Uuid, PathBuf)>> {
+        let storage = &self.state.config.resource.local_storage_path;
+        tokio::fs::create_dir_all(storage).await?;
+        let mut stored = Vec::with_capacity(images.len());
+        for image in images {
+            let res_uuid = Uuid::new_v4();
+            let path = storage.join(format!("{res_uuid}.{}", image.ext));
+            if let Err(e) = tokio::fs::write(&path, &image.data).await {
+                Self::remove_stored(&stored).await;
+                return Err(e.into());
+            }
+            stored.push((res_uuid, path));
+        }
+        Ok(stored)
+    }
+
+    async fn remove_stored(stored: &[(Uuid, PathBuf)]) {
+        for (_, path) in stored {
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                tracing::warn!("Failed to remove imported file {}: {}", path.display(), e);
+            }
+        }
+    }
+
+    async fn import_one(
+        &self,
+        source: ImportSource,
+        source_id: &str,
+        item: ImportedEcho,
+        options: &ImportOptions,
+        resolved: &mut HashMap<String, Option<i64>>,
+    ) -> EchoImportResult<ImportReportItem> {
+        let mut warnings = item.warnings;
+        // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
+        let imported: Option<i64> = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.echo_import()
+                    .get_imported_echo_id(source.as_str(), source_id)
+                    .await
+            })
+            .await?;
+        if let Some(echo_id) = imported {
+            return Ok(ImportReportItem {
+                source_id: source_id.to_string(),
+                status: ImportItemStatus::AlreadyImported,
+                echo_id: Some(echo_id),
+                reason: None,
+                warnings: Vec::new(),
+            });
+        }
+        let Some(user_id) = self.resolve_author(&item.author, options, resolved).await? else {
+            let reason = format!("Author `{}` is not mapped to any user", item.author);
+            return Ok(ImportReportItem::skipped(
+                source_id.to_string(),
+                reason,
+                warnings,
+            ));
+        };
+        let converted = markdown_to_tiptap(&item.content);
+        let mut images = item.images;
+        images.extend(converted.image_urls.into_iter().map(|url| ImportedImage {
+            name: url.rsplit('/').next().unwrap_or_default().to_string(),
+            blob: None,
+            local_path: None,
+            url: Some(url),
+        }));
+        let mut fetched = Vec::with_capacity(images.len());
+        match options.image_permission_id {
+            Some(_) => {
+                for image in &images {
+                    match self.fetch_image(image, options).await {
+                        Ok(image) => fetched.push(image),
+                        Err(e) => warnings.push(format!("Image `{}` skipped: {e}", image.name)),
+                    }
+                }
+            }
+            None if !images.is_empty() => warnings.push(format!(
+                "{} image(s) skipped: no image permission given",
+                images.len()
+            )),
+            None => {}
+        }
+        if converted.html.is_empty() && fetched.is_empty() {
+            return Ok(ImportReportItem::skipped(
+                source_id.to_string(),
+                "Nothing left to import",
+                warnings,
+            ));
+        }
+        let (is_private, permission_ids) = match (item.visibility, options.protected_permission_id)
+        {
+            (ImportedVisibility::Public, _) => (false, Vec::new()),
+            (ImportedVisibility::Protected, Some(pm)) => (false, vec![pm]),
+            (ImportedVisibility::Protected, None) => {
+                warnings.push("Protected memo imported as private".to_string());
+                (true, Vec::new())
+            }
+            (ImportedVisibility::Private, _) => (true, Vec::new()),
+        };
+        if options.dry_run {
+            return Ok(ImportReportItem {
+                source_id: source_id.to_string(),
+                status: ImportItemStatus::WouldImport,
+                echo_id: None,
+                reason: None,
+                warnings,
+            });
+        }
+        let stored = self.store_images(&fetched).await?;
+        let result = self
+            .state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let mut html = converted.html;
+                for ((res_uuid, _), image) in stored.iter().zip(&fetched) {
+                    let res_id = exec
+                        .resources()
+                        .add_resource(ResourceItemRawInfo {
+                            uploader_id: user_id,
+                            res_name: image.name.clone(),
+                            res_uuid: *res_uuid,
+                            res_ext: image.ext.to_string(),
+                        })
+                        .await?;
+                    // SAFETY: writing into a `String` never fails
+                    let _ = write!(
+                        html,
+                        r#"<div echo-pm="{}" echo-ext-id="1" echo-ext-meta-res-id="{res_id}"></div>"#,
+                        options.image_permission_id.unwrap_or_default()
+                    );
+                }
+                let baked = self.baker.add_outer_echo(
+                    &html,
+                    options.image_permission_id,
+                    EchoBaker::all_ext_ids(),
+                )?;
+                let echo_id = exec
+                    .echo()
+                    .add_echo(
+                        user_id,
+                        &baked.safe_echo,
+                        baked.res_ids.as_deref().unwrap_or_default(),
+                        &permission_ids,
+                        is_private,
+                        Some(item.created_at),
+                    )
+                    .await?;
+                exec.echo_import()
+                    .record_import(source.as_str(), source_id, echo_id)
+                    .await?;
+                Ok::<_, EchoImportError>(echo_id)
+            })
+            .await;
+        match result {
+            Ok(echo_id) => Ok(ImportReportItem {
+                source_id: source_id.to_string(),
+                status: ImportItemStatus::Imported,
+                echo_id: Some(echo_id),
+                reason: None,
+                warnings,
+            }),
+            Err(e) => {
+                Self::remove_stored(&stored).await;
+                Ok(ImportReportItem::skipped(
+                    source_id.to_string(),
+                    e,
+                    warnings,
+                ))
+            }
+        }
+    }
+
+    /// Items are imported oldest first so that echo ids follow the original timeline,
+    /// a failing item is reported and never aborts the whole import.
+    async fn import_items(
+        &self,
+        source: ImportSource,
+        items: Vec<ParsedItem>,
+        options: &ImportOptions,
+    ) -> EchoImportResult<ImportReport> {
+        self.validate_options(options).await?;
+        let total = items.len();
+        let (mut parsed, failed): (Vec<_>, Vec<_>) =
+            items.into_iter().partition(|(_, it)| it.is_ok());
+        parsed.sort_by_key(|(_, it)| it.as_ref().map(|it| it.created_at).ok());
+        let mut report_items = failed
+            .into_iter()
+            .filter_map(|(source_id, it)| it.err().map(|e| (source_id, e)))
+            .map(|(source_id, e)| ImportReportItem::skipped(source_id, e, Vec::new()))
+            .collect::<Vec<_>>();
+        let mut resolved = HashMap::default();
+        for (source_id, item) in parsed {
+            let Ok(item) = item else { continue };
+            let report_item = match self
+                .import_one(source, &source_id, item, options, &mut resolved)
+                .await
+            {
+                Ok(report_item) => report_item,
+                Err(e) => ImportReportItem::skipped(source_id, e, Vec::new()),
+            };
+            report_items.push(report_item);
+        }
+        let imported = report_items
+            .iter()
+            .filter(|it| {
+                matches!(
+                    it.status,
+                    ImportItemStatus::Imported | ImportItemStatus::WouldImport
+                )
+            })
+            .count();
+        let skipped = report_items
+            .iter()
+            .filter(|it| matches!(it.status, ImportItemStatus::Skipped))
+            .count();
+        tracing::info!(
+            "Import from {} finished: {} total, {} imported, {} skipped",
+            source.as_str(),
+            total,
+            imported,
+            skipped
+        );
+        Ok(ImportReport {
+            source,
+            total,
+            imported,
+            skipped,
+            items: report_items,
+        })
+    }
+}
@@ -0,0 +1,246 @@
+//! [Ech0](https://github.com/lin-snow/Ech0): JSON from its echo page api, or its (GORM managed) SQLite database.
+use crate::services::echo_import::{
+    EchoImportError, EchoImportResult, ImportedEcho, ImportedImage, ImportedVisibility, ParsedItem,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use sqlx::{FromRow, SqliteConnection};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Debug, Deserialize, FromRow)]
+struct Ech0Echo {
+    id: i64,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    private: bool,
+    #[serde(default)]
+    extension: Option<String>,
+    #[serde(default)]
+    extension_type: Option<String>,
+    created_at: String,
+    #[serde(default)]
+    #[sqlx(skip)]
+    images: Vec<Ech0Image>,
+}
+
+#[derive(Debug, Deserialize, FromRow)]
+struct Ech0Image {
+    #[serde(default)]
+    #[sqlx(default)]
+    message_id: i64,
+    image_url: String,
+    #[serde(default)]
+    image_source: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ech0Page {
+    items: Vec<Ech0Echo>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Ech0JsonExport {
+    List(Vec<Ech0Echo>),
+    /// Wrapped response of `/api/echo/page`
+    Page {
+        data: Ech0Page,
+    },
+}
+
+/// GORM writes `2006-01-02 15:04:05.999999999-07:00` into SQLite, the api speaks RFC 3339
+fn parse_ech0_time(raw: &str) -> EchoImportResult<OffsetDateTime> {
+    if let Ok(ts) = raw.trim().parse::<i64>() {
+        return OffsetDateTime::from_unix_timestamp(ts)
+            .map_err(|_| EchoImportError::InvalidTimestamp(raw.to_string()));
+    }
+    let normalized = raw.trim().replacen(' ', "T", 1);
+    OffsetDateTime::parse(&normalized, &Rfc3339)
+        .map_err(|_| EchoImportError::InvalidTimestamp(raw.to_string()))
+}
+
+/// Ech0 extensions have no counterpart, the linkable ones are kept as a trailing link
+fn extension_link(typ: &str, extension: &str) -> Option<(String, String)> {
+    let extension = extension.trim();
+    match typ {
+        "WEBSITE" => {
+            let site = serde_json::from_str::<Value>(extension).ok()?;
+            let url = site.get("site")?.as_str()?.to_string();
+            let title = site
+                .get("title")
+                .and_then(Value::as_str)
+                .filter(|it| !it.is_empty())
+                .unwrap_or(&url)
+                .to_string();
+            Some((url, title))
+        }
+        "VIDEO" if extension.starts_with("BV") || extension.starts_with("av") => Some((
+            format!("https://www.bilibili.com/video/{extension}"),
+            extension.to_string(),
+        )),
+        _ if extension.starts_with("https://") || extension.starts_with("http://") => {
+            Some((extension.to_string(), extension.to_string()))
+        }
+        _ => None,
+    }
+}
+
+impl Ech0Echo {
+    fn into_parsed(self) -> ParsedItem {
+        let source_id = self.id.to_string();
+        let created_at = match parse_ech0_time(&self.created_at) {
+            Ok(created_at) => created_at,
+            Err(e) => return (source_id, Err(e)),
+        };
+        let mut warnings = Vec::new();
+        let mut content = self.content;
+        if let Some(typ) = self.extension_type.as_deref().filter(|it| !it.is_empty())
+            && let Some(extension) = self.extension.as_deref().filter(|it| !it.is_empty())
+        {
+            match extension_link(typ, extension) {
+                Some((url, title)) => {
+                    let title = title.replace('[', "\\[").replace(']', "\\]");
+                    content.push_str(&format!("\n\n[{title}](<{url}>)"));
+                }
+                None => warnings.push(format!("Unsupported {typ} extension dropped")),
+            }
+        }
+        let images = self
+            .images
+            .into_iter()
+            .map(|image| {
+                let name = image
+                    .image_url
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                let local_path = (image.image_source == "local")
+                    .then(|| image.image_url.trim_start_matches('/').to_string());
+                ImportedImage {
+                    name,
+                    blob: None,
+                    local_path,
+                    url: Some(image.image_url),
+                }
+            })
+            .collect();
+        let echo = ImportedEcho {
+            author: self.username,
+            content,
+            visibility: match self.private {
+                true => ImportedVisibility::Private,
+                false => ImportedVisibility::Public,
+            },
+            created_at,
+            images,
+            warnings,
+        };
+        (source_id, Ok(echo))
+    }
+}
+
+pub fn parse_json(data: &[u8]) -> EchoImportResult<Vec<ParsedItem>> {
+    let echos = match serde_json::from_slice::<Ech0JsonExport>(data)? {
+        Ech0JsonExport::List(echos) => echos,
+        Ech0JsonExport::Page { data } => data.items,
+    };
+    Ok(echos.into_iter().map(Ech0Echo::into_parsed).collect())
+}
+
+// The schema belongs to Ech0, so the queries can not be checked at compile time.
+pub async fn parse_sqlite(conn: &mut SqliteConnection) -> EchoImportResult<Vec<ParsedItem>> {
+    let mut echos = sqlx::query_as::<_, Ech0Echo>(
+        // language=sql
+        r#"
+            SELECT
+              e.id,
+              e.content,
+              COALESCE(NULLIF(e.username, ''), u.username, '') AS username,
+              e.private,
+              e.extension,
+              e.extension_type,
+              CAST(e.created_at AS TEXT) AS created_at
+            FROM echos AS e
+            LEFT JOIN users AS u ON u.id = e.user_id
+            ORDER BY e.id
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| EchoImportError::UnsupportedSchema(e.to_string()))?;
+    let has_images_table = sqlx::query_scalar::<_, bool>(
+        // language=sql
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'images')",
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    // Ech0 before the `images` table kept a single image on the echo itself
+    let images = match has_images_table {
+        true => {
+            sqlx::query_as::<_, Ech0Image>(
+                // language=sql
+                r#"
+                    SELECT message_id, image_url, COALESCE(image_source, '') AS image_source
+                    FROM images
+                    WHERE image_url IS NOT NULL AND image_url <> ''
+                    ORDER BY id
+                "#,
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+        false => {
+            sqlx::query_as::<_, Ech0Image>(
+                // language=sql
+                r#"
+                    SELECT id AS message_id, image_url, COALESCE(image_source, '') AS image_source
+                    FROM echos
+                    WHERE image_url IS NOT NULL AND image_url <> ''
+                "#,
+            )
+            .fetch_all(&mut *conn)
+            .await
+        }
+    }
+    .map_err(|e| EchoImportError::UnsupportedSchema(e.to_string()))?;
+    for image in images {
+        if let Ok(idx) = echos.binary_search_by_key(&image.message_id, |it| it.id) {
+            echos[idx].images.push(image);
+        }
+    }
+    Ok(echos.into_iter().map(Ech0Echo::into_parsed).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_ech0_json() {
+        let data = br#"{"code":1,"data":{"total":1,"items":[{
+            "id": 7, "content": "hello", "username": "alice", "private": false,
+            "images": [{"image_url": "/images/a.png", "image_source": "local"}],
+            "extension": "{\"title\":\"Blog\",\"site\":\"https://example.com\"}",
+            "extension_type": "WEBSITE", "created_at": "2025-03-01T10:20:30.5+08:00"
+        }]}}"#;
+        let echos = parse_json(data).unwrap();
+        assert_eq!(echos.len(), 1);
+        let (source_id, echo) = &echos[0];
+        let echo = echo.as_ref().unwrap();
+        assert_eq!(source_id, "7");
+        assert_eq!(echo.content, "hello\n\n[Blog](<https://example.com>)");
+        assert_eq!(echo.created_at.unix_timestamp(), 1_740_795_630);
+        assert_eq!(echo.images[0].local_path.as_deref(), Some("images/a.png"));
+        assert_eq!(
+            parse_ech0_time("2025-03-01 10:20:30.123456789+08:00")
+                .unwrap()
+                .unix_timestamp(),
+            1_740_795_630
+        );
+    }
+}
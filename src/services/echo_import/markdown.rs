@@ -0,0 +1,142 @@
+//! Markdown (as written in Ech0 and Memos) => the tiptap html subset accepted by `EchoBaker`.
+use crate::services::feed::escape_xml;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd};
+use std::fmt::Write;
+
+#[derive(Debug, Default)]
+pub struct ConvertedMarkdown {
+    pub html: String,
+    /// Urls of the images referenced inline, they are uploaded as echo resources instead
+    pub image_urls: Vec<String>,
+}
+
+fn heading_tag(level: HeadingLevel) -> &'static str {
+    match level {
+        HeadingLevel::H1 => "h1",
+        HeadingLevel::H2 => "h2",
+        HeadingLevel::H3 => "h3",
+        HeadingLevel::H4 => "h4",
+        HeadingLevel::H5 => "h5",
+        HeadingLevel::H6 => "h6",
+    }
+}
+
+pub fn markdown_to_tiptap(markdown: &str) -> ConvertedMarkdown {
+    let options =
+        Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS | Options::ENABLE_TABLES;
+    let mut out = ConvertedMarkdown::default();
+    let html = &mut out.html;
+    // alt text of images is dropped together with the image
+    let mut in_image = 0usize;
+    let mut first_cell = false;
+    for event in Parser::new_ext(markdown, options) {
+        if in_image > 0 {
+            match event {
+                Event::Start(Tag::Image { .. }) => in_image += 1,
+                Event::End(TagEnd::Image) => in_image -= 1,
+                _ => {}
+            }
+            continue;
+        }
+        // SAFETY: writing into a `String` never fails
+        let _ = match event {
+            Event::Start(tag) => match tag {
+                Tag::Paragraph => write!(html, "<p>"),
+                Tag::Heading { level, .. } => write!(html, "<{}>", heading_tag(level)),
+                Tag::BlockQuote(_) => write!(html, "<blockquote>"),
+                Tag::CodeBlock(CodeBlockKind::Fenced(lang)) if !lang.is_empty() => {
+                    write!(
+                        html,
+                        r#"<pre><code class="language-{}">"#,
+                        escape_xml(&lang)
+                    )
+                }
+                Tag::CodeBlock(_) => write!(html, "<pre><code>"),
+                Tag::List(Some(_)) => write!(html, "<ol>"),
+                Tag::List(None) => write!(html, "<ul>"),
+                Tag::Item => write!(html, "<li>"),
+                // tables are not part of the subset, every row becomes a paragraph
+                Tag::TableRow | Tag::TableHead => {
+                    first_cell = true;
+                    write!(html, "<p>")
+                }
+                Tag::TableCell => {
+                    let sep = if first_cell { "" } else { " | " };
+                    first_cell = false;
+                    write!(html, "{sep}")
+                }
+                Tag::Emphasis => write!(html, "<em>"),
+                Tag::Strong => write!(html, "<strong>"),
+                Tag::Strikethrough => write!(html, "<s>"),
+                Tag::Link { dest_url, .. } => {
+                    write!(html, r#"<a href="{}">"#, escape_xml(&dest_url))
+                }
+                Tag::Image { dest_url, .. } => {
+                    in_image = 1;
+                    out.image_urls.push(dest_url.into_string());
+                    Ok(())
+                }
+                _ => Ok(()),
+            },
+            Event::End(tag) => match tag {
+                TagEnd::Paragraph => write!(html, "</p>"),
+                TagEnd::Heading(level) => write!(html, "</{}>", heading_tag(level)),
+                TagEnd::BlockQuote(_) => write!(html, "</blockquote>"),
+                TagEnd::CodeBlock => write!(html, "</code></pre>"),
+                TagEnd::List(true) => write!(html, "</ol>"),
+                TagEnd::List(false) => write!(html, "</ul>"),
+                TagEnd::Item => write!(html, "</li>"),
+                TagEnd::TableRow | TagEnd::TableHead => write!(html, "</p>"),
+                TagEnd::Emphasis => write!(html, "</em>"),
+                TagEnd::Strong => write!(html, "</strong>"),
+                TagEnd::Strikethrough => write!(html, "</s>"),
+                TagEnd::Link => write!(html, "</a>"),
+                _ => Ok(()),
+            },
+            Event::Text(text) | Event::InlineMath(text) | Event::DisplayMath(text) => {
+                write!(html, "{}", escape_xml(&text))
+            }
+            Event::Code(code) => write!(html, "<code>{}</code>", escape_xml(&code)),
+            // raw html is kept as visible text, except for the line breaks Memos users love
+            Event::Html(raw) | Event::InlineHtml(raw) => {
+                match raw.trim().to_ascii_lowercase().as_str() {
+                    "<br>" | "<br/>" | "<br />" => write!(html, "<br>"),
+                    _ => write!(html, "{}", escape_xml(&raw)),
+                }
+            }
+            Event::FootnoteReference(name) => write!(html, "[{}]", escape_xml(&name)),
+            // both Ech0 and Memos render a single newline as a line break
+            Event::SoftBreak | Event::HardBreak => write!(html, "<br>"),
+            Event::Rule => write!(html, "<hr>"),
+            Event::TaskListMarker(checked) => write!(html, "{} ", if checked { "☑" } else { "☐" }),
+        };
+    }
+    // paragraphs which only held images
+    out.html = out.html.replace("<p></p>", "");
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_markdown_to_tiptap() {
+        let converted = markdown_to_tiptap(
+            "# Hi\nline one\nline **two** ~~gone~~ #tag\n\n- [x] done\n\n![cat](/images/cat.png)\n\n```rust\nlet a = 1 < 2;\n```\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n<script>alert(1)</script>",
+        );
+        assert_eq!(
+            converted.html,
+            concat!(
+                "<h1>Hi</h1>",
+                "<p>line one<br>line <strong>two</strong> <s>gone</s> #tag</p>",
+                "<ul><li>☑ done</li></ul>",
+                r#"<pre><code class="language-rust">let a = 1 &lt; 2;"#,
+                "\n</code></pre>",
+                "<p>a | b</p><p>1 | 2</p>",
+                "&lt;script&gt;alert(1)&lt;/script&gt;",
+            )
+        );
+        assert_eq!(converted.image_urls, vec!["/images/cat.png"]);
+    }
+}
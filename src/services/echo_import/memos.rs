@@ -0,0 +1,251 @@
+//! [Memos](https://github.com/usememos/memos): JSON from its `ListMemos` api, or its SQLite database (>= v0.22).
+use crate::services::echo_import::{
+    EchoImportError, EchoImportResult, ImportedEcho, ImportedImage, ImportedVisibility, ParsedItem,
+};
+use serde::Deserialize;
+use sqlx::{FromRow, SqliteConnection};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MemosJsonResource {
+    name: String,
+    filename: String,
+    #[serde(default)]
+    external_link: String,
+    #[serde(default, rename = "type")]
+    typ: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MemosJsonMemo {
+    name: String,
+    creator: String,
+    create_time: String,
+    #[serde(default)]
+    content: String,
+    #[serde(default)]
+    visibility: String,
+    #[serde(default)]
+    state: Option<String>,
+    #[serde(default)]
+    row_status: Option<String>,
+    #[serde(default)]
+    resources: Vec<MemosJsonResource>,
+    /// `resources` were renamed to `attachments` in v0.25
+    #[serde(default)]
+    attachments: Vec<MemosJsonResource>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum MemosJsonExport {
+    List(Vec<MemosJsonMemo>),
+    Page { memos: Vec<MemosJsonMemo> },
+}
+
+#[derive(Debug, FromRow)]
+struct MemosDbMemo {
+    id: i64,
+    username: String,
+    created_ts: i64,
+    row_status: String,
+    content: String,
+    visibility: String,
+}
+
+#[derive(Debug, FromRow)]
+struct MemosDbResource {
+    uid: String,
+    memo_id: i64,
+    filename: String,
+    blob: Option<Vec<u8>>,
+    #[sqlx(rename = "type")]
+    typ: String,
+    storage_type: String,
+    reference: String,
+}
+
+fn memos_visibility(
+    visibility: &str,
+    archived: bool,
+    warnings: &mut Vec<String>,
+) -> ImportedVisibility {
+    if archived {
+        warnings.push("Archived memo imported as private".to_string());
+        return ImportedVisibility::Private;
+    }
+    match visibility {
+        "PUBLIC" => ImportedVisibility::Public,
+        "PROTECTED" => ImportedVisibility::Protected,
+        _ => ImportedVisibility::Private,
+    }
+}
+
+/// Only images survive, every other kind of attachment is reported
+fn keep_image(typ: &str, filename: &str, warnings: &mut Vec<String>) -> bool {
+    match typ.is_empty() || typ.starts_with("image/") {
+        true => true,
+        false => {
+            warnings.push(format!("Attachment `{filename}` ({typ}) dropped"));
+            false
+        }
+    }
+}
+
+impl MemosJsonMemo {
+    fn into_parsed(self) -> ParsedItem {
+        let source_id = self.name.trim_start_matches("memos/").to_string();
+        let created_at = match OffsetDateTime::parse(&self.create_time, &Rfc3339) {
+            Ok(created_at) => created_at,
+            Err(_) => {
+                return (
+                    source_id,
+                    Err(EchoImportError::InvalidTimestamp(self.create_time)),
+                );
+            }
+        };
+        let mut warnings = Vec::new();
+        let archived = [&self.state, &self.row_status]
+            .into_iter()
+            .flatten()
+            .any(|it| it == "ARCHIVED");
+        let visibility = memos_visibility(&self.visibility, archived, &mut warnings);
+        let images = self
+            .resources
+            .into_iter()
+            .chain(self.attachments)
+            .filter(|res| keep_image(&res.typ, &res.filename, &mut warnings))
+            .map(|res| ImportedImage {
+                url: Some(match res.external_link.is_empty() {
+                    true => format!("/file/{}/{}", res.name, res.filename),
+                    false => res.external_link,
+                }),
+                name: res.filename,
+                blob: None,
+                local_path: None,
+            })
+            .collect();
+        let echo = ImportedEcho {
+            author: self.creator,
+            content: self.content,
+            visibility,
+            created_at,
+            images,
+            warnings,
+        };
+        (source_id, Ok(echo))
+    }
+}
+
+pub fn parse_json(data: &[u8]) -> EchoImportResult<Vec<ParsedItem>> {
+    let memos = match serde_json::from_slice::<MemosJsonExport>(data)? {
+        MemosJsonExport::List(memos) => memos,
+        MemosJsonExport::Page { memos } => memos,
+    };
+    Ok(memos.into_iter().map(MemosJsonMemo::into_parsed).collect())
+}
+
+// The schema belongs to Memos, so the queries can not be checked at compile time.
+pub async fn parse_sqlite(conn: &mut SqliteConnection) -> EchoImportResult<Vec<ParsedItem>> {
+    let memos = sqlx::query_as::<_, MemosDbMemo>(
+        // language=sql
+        r#"
+            SELECT
+              m.id,
+              COALESCE(u.username, CAST(m.creator_id AS TEXT)) AS username,
+              m.created_ts,
+              m.row_status,
+              m.content,
+              m.visibility
+            FROM memo AS m
+            LEFT JOIN user AS u ON u.id = m.creator_id
+            ORDER BY m.id
+        "#,
+    )
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| EchoImportError::UnsupportedSchema(e.to_string()))?;
+    // `resource` was renamed to `attachment` in v0.25
+    let resource_table = sqlx::query_scalar::<_, String>(
+        // language=sql
+        r#"
+            SELECT name FROM sqlite_master
+            WHERE type = 'table' AND name IN ('attachment', 'resource')
+            ORDER BY name
+            LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or_else(|| EchoImportError::UnsupportedSchema("no resource table".to_string()))?;
+    let resources = sqlx::query_as::<_, MemosDbResource>(&format!(
+        // language=sql
+        r#"
+            SELECT uid, memo_id, filename, blob, type, storage_type, reference
+            FROM {resource_table}
+            WHERE memo_id IS NOT NULL
+            ORDER BY id
+        "#
+    ))
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| EchoImportError::UnsupportedSchema(e.to_string()))?;
+    let mut parsed = memos
+        .into_iter()
+        .map(|memo| {
+            let source_id = memo.id.to_string();
+            let created_at = match OffsetDateTime::from_unix_timestamp(memo.created_ts) {
+                Ok(created_at) => created_at,
+                Err(_) => {
+                    let raw = memo.created_ts.to_string();
+                    return (
+                        memo.id,
+                        (source_id, Err(EchoImportError::InvalidTimestamp(raw))),
+                    );
+                }
+            };
+            let mut warnings = Vec::new();
+            let archived = memo.row_status == "ARCHIVED";
+            let echo = ImportedEcho {
+                author: memo.username,
+                content: memo.content,
+                visibility: memos_visibility(&memo.visibility, archived, &mut warnings),
+                created_at,
+                images: Vec::new(),
+                warnings,
+            };
+            (memo.id, (source_id, Ok(echo)))
+        })
+        .collect::<Vec<_>>();
+    for res in resources {
+        let Ok(idx) = parsed.binary_search_by_key(&res.memo_id, |(id, _)| *id) else {
+            continue;
+        };
+        let (_, (_, Ok(echo))) = &mut parsed[idx] else {
+            continue;
+        };
+        if !keep_image(&res.typ, &res.filename, &mut echo.warnings) {
+            continue;
+        }
+        let (local_path, url) = match res.storage_type.as_str() {
+            "LOCAL" => (Some(res.reference), None),
+            "EXTERNAL" | "S3" => (None, Some(res.reference)),
+            _ => (None, None),
+        };
+        echo.images.push(ImportedImage {
+            url: url.or_else(|| {
+                Some(format!(
+                    "/file/{resource_table}s/{}/{}",
+                    res.uid, res.filename
+                ))
+            }),
+            name: res.filename,
+            blob: res.blob.filter(|it| !it.is_empty()),
+            local_path,
+        });
+    }
+    Ok(parsed.into_iter().map(|(_, it)| it).collect())
+}
@@ -0,0 +1,220 @@
+use crate::gladiator::prelude::OutGoingEchoSSRConsCtx;
+use crate::models::echo::Echo;
+use crate::models::echo_share::{EchoShareItem, EchoShareRow};
+use crate::services::echo_baker::{EchoBaker, EchoBakerError};
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::res_manager::{ExchangedShareItem, ResManagerService, ResManagerServiceError};
+use crate::services::states::EchoState;
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
+};
+use echo_macros::EchoBusinessError;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+/// Base url of the share links
+pub const SHARE_VIEW_URL: &str = "/api/v1/share/view";
+const SHARE_DEFAULT_TTL: Duration = Duration::days(7);
+const SHARE_MAX_TTL: Duration = Duration::days(30);
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum EchoShareError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error(transparent)]
+    HybridCache(#[from] HybridCacheError),
+    #[error(transparent)]
+    EchoBaker(#[from] EchoBakerError),
+    #[error(transparent)]
+    ResManager(#[from] ResManagerServiceError),
+    #[error("Echo not found")]
+    EchoNotFound,
+    #[error("Only the author can share an echo")]
+    NotOwner,
+    #[error("A share link cannot grant permission {0} which the author does not hold")]
+    PermissionNotHeld(i64),
+    #[error("A share link must expire within {} days", SHARE_MAX_TTL.whole_days())]
+    InvalidTtl,
+    #[error("Share link not found")]
+    NotFound,
+    #[error("The share link has expired, been revoked or already been used")]
+    Unavailable,
+}
+
+pub type EchoShareResult<T> = Result<T, EchoShareError>;
+
+pub struct EchoShareService {
+    state: Arc<EchoState>,
+    cache: Arc<HybridCacheService>,
+    baker: Arc<EchoBaker<'static>>,
+    res_manager: Arc<ResManagerService>,
+}
+
+impl EchoShareService {
+    pub fn new(
+        state: Arc<EchoState>,
+        cache: Arc<HybridCacheService>,
+        baker: Arc<EchoBaker<'static>>,
+        res_manager: Arc<ResManagerService>,
+    ) -> Self {
+        Self {
+            state,
+            cache,
+            baker,
+            res_manager,
+        }
+    }
+
+    fn to_item(&self, share: EchoShareRow) -> EchoShareResult<EchoShareItem> {
+        let url = self
+            .res_manager
+            .sign_share(&share)?
+            .to_url(Some(SHARE_VIEW_URL))?;
+        Ok(EchoShareItem { share, url })
+    }
+
+    /// Share `echo_id` of `user_id`, whoever opens the link sees the echo as if they held
+    /// exactly `permission_ids`, which must be a subset of the author's own permissions
+    pub async fn create_share(
+        &self,
+        user_id: i64,
+        echo_id: i64,
+        mut permission_ids: Vec<i64>,
+        ttl: Option<Duration>,
+        one_time: bool,
+    ) -> EchoShareResult<EchoShareItem> {
+        let ttl = ttl.unwrap_or(SHARE_DEFAULT_TTL);
+        if !ttl.is_positive() || ttl > SHARE_MAX_TTL {
+            return Err(EchoShareError::InvalidTtl);
+        }
+        let user = self.cache.users.get_user_by_user_id(user_id).await?;
+        if let Some(&pid) = permission_ids
+            .iter()
+            .find(|pid| !user.permission_ids.contains(pid))
+        {
+            return Err(EchoShareError::PermissionNotHeld(pid));
+        }
+        permission_ids.sort_unstable();
+        permission_ids.dedup();
+        let echo: Option<Echo> = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.echo().query_echo_by_id(echo_id).await
+            })
+            .await?;
+        match echo {
+            None => return Err(EchoShareError::EchoNotFound),
+            Some(echo) if echo.user_id != user_id => return Err(EchoShareError::NotOwner),
+            Some(_) => {}
+        }
+        let share = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.echo_share()
+                    .add_share(
+                        echo_id,
+                        user_id,
+                        &permission_ids,
+                        one_time,
+                        OffsetDateTime::now_utc() + ttl,
+                    )
+                    .await
+            })
+            .await?;
+        self.to_item(share)
+    }
+
+    pub async fn list_shares(
+        &self,
+        user_id: i64,
+        echo_id: Option<i64>,
+        page: PageQueryBinder,
+    ) -> EchoShareResult<PageQueryResult<EchoShareItem>> {
+        let mut page = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.echo_share()
+                    .list_shares_page(user_id, echo_id, page)
+                    .await
+            })
+            .await?;
+        let items = page
+            .items
+            .drain(..)
+            .map(|share| self.to_item(share))
+            .collect::<EchoShareResult<Vec<_>>>()?;
+        Ok(page.swap_items(items))
+    }
+
+    pub async fn revoke_share(&self, user_id: i64, share_id: i64) -> EchoShareResult<()> {
+        self.state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.echo_share().revoke_share(user_id, share_id).await
+            })
+            .await
+            .map_err(|e| match e {
+                DataBaseError::NoAffectedRows(_) => EchoShareError::NotFound,
+                e => e.into(),
+            })
+    }
+
+    /// The link if it can still be opened, a one-time link is not used up by this
+    pub async fn peek_share(&self, item: &ExchangedShareItem) -> EchoShareResult<EchoShareRow> {
+        let tag = self.res_manager.verify_share(item.share_id, &item.cred)?;
+        let share = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.echo_share()
+                    .get_available_share(tag.share_id, OffsetDateTime::now_utc())
+                    .await
+            })
+            .await?;
+        match share {
+            Some(share)
+                if share.echo_id == tag.echo_id && share.permission_ids.0 == tag.permission_ids =>
+            {
+                Ok(share)
+            }
+            _ => Err(EchoShareError::Unavailable),
+        }
+    }
+
+    /// Render the shared echo with the permissions bound to the link, a one-time link
+    /// is used up by this
+    pub async fn open_share(&self, item: &ExchangedShareItem) -> EchoShareResult<Echo> {
+        let tag = self.res_manager.verify_share(item.share_id, &item.cred)?;
+        let (share, echo): (Option<EchoShareRow>, Option<Echo>) = self
+            .state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let share = exec
+                    .echo_share()
+                    .redeem_share(tag.share_id, OffsetDateTime::now_utc())
+                    .await?;
+                let echo = exec.echo().query_echo_by_id(tag.echo_id).await?;
+                Ok::<_, DataBaseError>((share, echo))
+            })
+            .await?;
+        let (share, mut echo) = match (share, echo) {
+            (Some(share), Some(echo))
+                if share.echo_id == tag.echo_id && share.permission_ids.0 == tag.permission_ids =>
+            {
+                (share, echo)
+            }
+            _ => return Err(EchoShareError::Unavailable),
+        };
+        echo.content = self.baker.post_inner_echo_with_ctx(
+            Arc::downgrade(&self.state),
+            &echo,
+            OutGoingEchoSSRConsCtx::shared(share.owner_id),
+            &share.permission_ids.0,
+            EchoBaker::all_ext_ids(),
+        )?;
+        Ok(echo)
+    }
+}
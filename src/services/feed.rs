@@ -0,0 +1,160 @@
+use echo_macros::EchoBusinessError;
+use std::fmt::Write;
+use time::OffsetDateTime;
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum FeedError {
+    #[error(transparent)]
+    Format(#[from] time::error::Format),
+    #[error(transparent)]
+    Fmt(#[from] std::fmt::Error),
+}
+
+pub type FeedResult<T> = Result<T, FeedError>;
+
+#[derive(Debug)]
+pub struct FeedChannel {
+    pub title: String,
+    /// Html page of this feed
+    pub link: String,
+    /// Url of the feed document itself
+    pub self_link: String,
+    pub description: String,
+    pub updated: OffsetDateTime,
+}
+
+#[derive(Debug)]
+pub struct FeedEntry {
+    pub link: String,
+    pub title: String,
+    pub author: String,
+    pub content_html: String,
+    pub published: OffsetDateTime,
+    pub updated: OffsetDateTime,
+}
+
+/// Escape text for both element content and attribute values
+pub fn escape_xml(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+pub fn render_rss(channel: &FeedChannel, entries: &[FeedEntry]) -> FeedResult<String> {
+    let mut out = String::with_capacity(1024 + entries.len() * 512);
+    out.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    out.push_str(r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom"><channel>"#);
+    write!(
+        out,
+        "<title>{}</title><link>{}</link><description>{}</description><lastBuildDate>{}</lastBuildDate>",
+        escape_xml(&channel.title),
+        escape_xml(&channel.link),
+        escape_xml(&channel.description),
+        channel.updated.format(&Rfc2822)?,
+    )?;
+    write!(
+        out,
+        r#"<atom:link href="{}" rel="self" type="application/rss+xml"/>"#,
+        escape_xml(&channel.self_link)
+    )?;
+    for entry in entries {
+        write!(
+            out,
+            r#"<item><title>{}</title><link>{}</link><guid isPermaLink="true">{}</guid><author>{}</author><pubDate>{}</pubDate><description>{}</description></item>"#,
+            escape_xml(&entry.title),
+            escape_xml(&entry.link),
+            escape_xml(&entry.link),
+            escape_xml(&entry.author),
+            entry.published.format(&Rfc2822)?,
+            escape_xml(&entry.content_html),
+        )?;
+    }
+    out.push_str("</channel></rss>");
+    Ok(out)
+}
+
+pub fn render_atom(channel: &FeedChannel, entries: &[FeedEntry]) -> FeedResult<String> {
+    let mut out = String::with_capacity(1024 + entries.len() * 512);
+    out.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    out.push_str(r#"<feed xmlns="http://www.w3.org/2005/Atom">"#);
+    write!(
+        out,
+        r#"<id>{}</id><title>{}</title><subtitle>{}</subtitle><updated>{}</updated><link href="{}"/><link href="{}" rel="self" type="application/atom+xml"/>"#,
+        escape_xml(&channel.self_link),
+        escape_xml(&channel.title),
+        escape_xml(&channel.description),
+        channel.updated.format(&Rfc3339)?,
+        escape_xml(&channel.link),
+        escape_xml(&channel.self_link),
+    )?;
+    for entry in entries {
+        write!(
+            out,
+            r#"<entry><id>{}</id><title>{}</title><link href="{}"/><author><name>{}</name></author><published>{}</published><updated>{}</updated><content type="html">{}</content></entry>"#,
+            escape_xml(&entry.link),
+            escape_xml(&entry.title),
+            escape_xml(&entry.link),
+            escape_xml(&entry.author),
+            entry.published.format(&Rfc3339)?,
+            entry.updated.format(&Rfc3339)?,
+            escape_xml(&entry.content_html),
+        )?;
+    }
+    out.push_str("</feed>");
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dummy() -> (FeedChannel, Vec<FeedEntry>) {
+        let t = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let channel = FeedChannel {
+            title: "Echo & friends".to_string(),
+            link: "https://echo.example".to_string(),
+            self_link: "https://echo.example/api/v1/feed/rss".to_string(),
+            description: "Public echos".to_string(),
+            updated: t,
+        };
+        let entries = vec![FeedEntry {
+            link: "https://echo.example/echo/1".to_string(),
+            title: "alice #1".to_string(),
+            author: "alice".to_string(),
+            content_html: r#"<p>Hi <img src="/a?x=1&y=2"></p>"#.to_string(),
+            published: t,
+            updated: t,
+        }];
+        (channel, entries)
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">'&'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&apos;&amp;&apos;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn test_render_feeds() {
+        let (channel, entries) = dummy();
+        let rss = render_rss(&channel, &entries).unwrap();
+        assert!(rss.contains("<title>Echo &amp; friends</title>"));
+        assert!(rss.contains("<pubDate>Tue, 14 Nov 2023 22:13:20 +0000</pubDate>"));
+        assert!(rss.contains("&lt;img src=&quot;/a?x=1&amp;y=2&quot;&gt;"));
+        let atom = render_atom(&channel, &entries).unwrap();
+        assert!(atom.contains("<updated>2023-11-14T22:13:20Z</updated>"));
+        assert!(atom.contains(r#"<content type="html">&lt;p&gt;"#));
+    }
+}
@@ -1,8 +1,10 @@
 mod dyn_cache;
+mod relations;
 mod resources;
 mod users;
 
 use crate::services::hybrid_cache::dyn_cache::HybridDynCache;
+use crate::services::hybrid_cache::relations::HybridRelationsCache;
 use crate::services::hybrid_cache::resources::HybridResourcesCache;
 use crate::services::hybrid_cache::users::HybridUsersCache;
 use crate::services::states::EchoState;
@@ -15,6 +17,7 @@ pub struct HybridCacheService {
     pub users: HybridUsersCache,
     pub resources: HybridResourcesCache,
     pub dyn_settings: HybridDynCache,
+    pub relations: HybridRelationsCache,
 }
 
 #[derive(Debug, thiserror::Error, EchoBusinessError)]
@@ -40,11 +43,13 @@ impl HybridCacheService {
         let users = HybridUsersCache::new(&state);
         let resources = HybridResourcesCache::new(&state);
         let dyn_settings = HybridDynCache::new(&state);
+        let relations = HybridRelationsCache::new(&state);
         Self {
             state,
             users,
             resources,
             dyn_settings,
+            relations,
         }
     }
 }
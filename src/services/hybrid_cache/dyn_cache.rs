@@ -147,6 +147,27 @@ impl HybridDynCache {
     ) -> HybridCacheResult<()> {
         self.set_inner(key.to_owned(), value, overwrite).await
     }
+
+    /// For settings that are only valid together, either all of them are set or none
+    pub async fn set_many_with_str(
+        &self,
+        kvs: &[(&'static str, &str)],
+        overwrite: bool,
+    ) -> HybridCacheResult<()> {
+        self.state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                for &(key, value) in kvs {
+                    exec.dyn_settings().set_inner(key, value, overwrite).await?;
+                }
+                Ok::<_, DataBaseError>(())
+            })
+            .await?;
+        for &(key, _) in kvs {
+            self.cache.remove_async(key).await;
+        }
+        Ok(())
+    }
 }
 
 #[macro_export]
@@ -0,0 +1,94 @@
+use crate::models::relation::{UserRelationKind, UserRelations};
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheResult};
+use crate::services::states::EchoState;
+use crate::services::states::db::{DataBaseError, EchoDatabaseExecutor};
+use scc::HashCache;
+use std::sync::Arc;
+
+/// Blocks and mutes per user, a change evicts both ends since a block shows up on either side
+pub struct HybridRelationsCache {
+    state: Arc<EchoState>,
+    cache: HashCache<i64, Arc<UserRelations>>,
+}
+
+impl HybridRelationsCache {
+    pub fn new(state: &Arc<EchoState>) -> Self {
+        let cache = HashCache::with_capacity(0, state.config.perf.user_cache_capacity);
+        Self {
+            cache,
+            state: state.clone(),
+        }
+    }
+
+    pub async fn get_relations(&self, user_id: i64) -> HybridCacheResult<Arc<UserRelations>> {
+        if let Some(relations) = self.cache.get_async(&user_id).await {
+            return Ok(relations.get().clone());
+        }
+        let rows = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.user_relation().get_relations(user_id).await
+            })
+            .await?;
+        let relations = Arc::new(UserRelations::from_rows(user_id, rows));
+        self.cache
+            .put_async(user_id, relations.clone())
+            .await
+            .map_err(|_| HybridCacheError::InsertCacheError)?;
+        Ok(relations)
+    }
+
+    /// Returns `false` if the relation exists already. Blocking someone also drops the follows
+    /// between the two users.
+    pub async fn add_relation(
+        &self,
+        user_id: i64,
+        target_id: i64,
+        kind: UserRelationKind,
+    ) -> HybridCacheResult<bool> {
+        let added = self
+            .state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let added = exec
+                    .user_relation()
+                    .add_relation(user_id, target_id, kind)
+                    .await?;
+                if kind == UserRelationKind::Block {
+                    exec.follow()
+                        .remove_follows_between(user_id, target_id)
+                        .await?;
+                }
+                Ok::<_, DataBaseError>(added)
+            })
+            .await?;
+        self.evict(user_id, target_id).await;
+        Ok(added)
+    }
+
+    /// Returns `false` if there was no such relation
+    pub async fn remove_relation(
+        &self,
+        user_id: i64,
+        target_id: i64,
+        kind: UserRelationKind,
+    ) -> HybridCacheResult<bool> {
+        let removed = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.user_relation()
+                    .remove_relation(user_id, target_id, kind)
+                    .await
+            })
+            .await?;
+        self.evict(user_id, target_id).await;
+        Ok(removed)
+    }
+
+    async fn evict(&self, user_id: i64, target_id: i64) {
+        self.cache.remove_async(&user_id).await;
+        self.cache.remove_async(&target_id).await;
+    }
+}
@@ -0,0 +1,341 @@
+use crate::layers::session::{SessionError, check_user_session};
+use crate::models::echo::Echo;
+use crate::models::timeline::{
+    TimelineDeletedPayload, TimelineEvent, TimelineEventId, TimelineEventKind,
+};
+use crate::services::access_token::AccessTokenService;
+use crate::services::echo_baker::EchoBaker;
+use crate::services::hybrid_cache::HybridCacheService;
+use crate::services::states::EchoState;
+use axum::response::sse::Event;
+use futures::{Stream, StreamExt, stream};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use uuid::Uuid;
+
+/// How many recent events are kept for subscribers resuming with `Last-Event-ID`
+const TIMELINE_BACKLOG_SIZE: usize = 256;
+/// Subscribers falling further behind than this are asked to resync
+const TIMELINE_CHANNEL_SIZE: usize = 128;
+/// Name of the event telling a subscriber to refetch the timeline, sent when the events it
+/// missed are no longer available
+const TIMELINE_RESYNC_EVENT: &str = "resync";
+/// How often an idle subscriber is checked for a revoked session or token, busy ones are
+/// checked on every event as well
+const TIMELINE_AUTH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+struct TimelineBacklog {
+    next_seq: u64,
+    events: VecDeque<Arc<TimelineEvent>>,
+}
+
+impl TimelineBacklog {
+    #[inline]
+    fn latest_seq(&self) -> u64 {
+        self.next_seq - 1
+    }
+
+    /// Events after `seq`, `None` if some of them have already been dropped
+    fn events_after(&self, seq: u64) -> Option<VecDeque<Arc<TimelineEvent>>> {
+        let oldest_seq = self.next_seq - self.events.len() as u64;
+        if seq > self.latest_seq() || seq + 1 < oldest_seq {
+            return None;
+        }
+        Some(
+            self.events
+                .iter()
+                .skip((seq + 1 - oldest_seq) as usize)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+/// What a subscriber signed in with, its stream ends once this is revoked
+pub enum TimelineCredential {
+    Session(Uuid),
+    /// Bearer of a personal access token
+    Token(String),
+}
+
+struct TimelineSubscriber {
+    user_id: i64,
+    credential: TimelineCredential,
+    auth_check: Interval,
+    backlog: VecDeque<Arc<TimelineEvent>>,
+    receiver: broadcast::Receiver<Arc<TimelineEvent>>,
+    /// Latest event id the subscriber should resync to, if it has to
+    resync: Option<TimelineEventId>,
+}
+
+/// Pushes echo changes to connected users, every subscriber only gets the echos it may see,
+/// rendered with its own permissions
+pub struct LiveTimelineService {
+    state: Arc<EchoState>,
+    cache: Arc<HybridCacheService>,
+    baker: Arc<EchoBaker<'static>>,
+    tokens: Arc<AccessTokenService>,
+    epoch: u32,
+    backlog: Mutex<TimelineBacklog>,
+    sender: broadcast::Sender<Arc<TimelineEvent>>,
+}
+
+impl LiveTimelineService {
+    pub fn new(
+        state: Arc<EchoState>,
+        cache: Arc<HybridCacheService>,
+        baker: Arc<EchoBaker<'static>>,
+        tokens: Arc<AccessTokenService>,
+    ) -> Self {
+        let (sender, _) = broadcast::channel(TIMELINE_CHANNEL_SIZE);
+        Self {
+            state,
+            cache,
+            baker,
+            tokens,
+            epoch: rand::random(),
+            backlog: Mutex::new(TimelineBacklog {
+                next_seq: 1,
+                events: VecDeque::with_capacity(TIMELINE_BACKLOG_SIZE),
+            }),
+            sender,
+        }
+    }
+
+    #[inline]
+    fn event_id(&self, seq: u64) -> TimelineEventId {
+        TimelineEventId {
+            epoch: self.epoch,
+            seq,
+        }
+    }
+
+    /// `echo` is the echo after the change, or right before it for deletions
+    pub fn publish(&self, kind: TimelineEventKind, echo: Echo) {
+        // the lock keeps the backlog and the channel in the same order
+        let mut backlog = self.backlog.lock();
+        let event = Arc::new(TimelineEvent {
+            id: self.event_id(backlog.next_seq),
+            kind,
+            echo,
+        });
+        backlog.next_seq += 1;
+        if backlog.events.len() == TIMELINE_BACKLOG_SIZE {
+            backlog.events.pop_front();
+        }
+        backlog.events.push_back(event.clone());
+        // nobody listening is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Events for `user_id`, starting after `last_event_id` if the subscriber is resuming.
+    /// The stream ends once `credential` is revoked or the server shuts down.
+    pub fn subscribe(
+        self: &Arc<Self>,
+        user_id: i64,
+        credential: TimelineCredential,
+        last_event_id: Option<&str>,
+    ) -> impl Stream<Item = Result<Event, Infallible>> + use<> {
+        let subscriber = {
+            let backlog = self.backlog.lock();
+            let receiver = self.sender.subscribe();
+            let missed = match last_event_id.map(str::parse::<TimelineEventId>) {
+                None => Some(VecDeque::new()),
+                Some(Ok(id)) if id.epoch == self.epoch => backlog.events_after(id.seq),
+                Some(_) => None,
+            };
+            let mut auth_check = tokio::time::interval_at(
+                Instant::now() + TIMELINE_AUTH_CHECK_INTERVAL,
+                TIMELINE_AUTH_CHECK_INTERVAL,
+            );
+            auth_check.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            TimelineSubscriber {
+                user_id,
+                credential,
+                auth_check,
+                resync: match missed {
+                    Some(_) => None,
+                    None => Some(self.event_id(backlog.latest_seq())),
+                },
+                backlog: missed.unwrap_or_default(),
+                receiver,
+            }
+        };
+        let this = self.clone();
+        stream::unfold(subscriber, move |mut sub| {
+            let this = this.clone();
+            async move {
+                loop {
+                    if let Some(id) = sub.resync.take() {
+                        if !this.is_signed_in(&sub).await {
+                            return None;
+                        }
+                        let event = Event::default()
+                            .id(id.to_string())
+                            .event(TIMELINE_RESYNC_EVENT)
+                            .data("{}");
+                        return Some((Ok(event), sub));
+                    }
+                    let event = match sub.backlog.pop_front() {
+                        Some(event) => event,
+                        None => tokio::select! {
+                            received = sub.receiver.recv() => match received {
+                                Ok(event) => event,
+                                Err(RecvError::Lagged(n)) => {
+                                    tracing::debug!("Timeline subscriber lagged by {} events", n);
+                                    sub.resync =
+                                        Some(this.event_id(this.backlog.lock().latest_seq()));
+                                    continue;
+                                }
+                                Err(RecvError::Closed) => return None,
+                            },
+                            _ = sub.auth_check.tick() => match this.is_signed_in(&sub).await {
+                                true => continue,
+                                false => return None,
+                            },
+                        },
+                    };
+                    if !this.is_signed_in(&sub).await {
+                        return None;
+                    }
+                    if let Some(event) = this.render_event(sub.user_id, &event).await {
+                        return Some((Ok(event), sub));
+                    }
+                }
+            }
+        })
+        .take_until(self.state.shutdown.clone().cancelled_owned())
+    }
+
+    /// Whether the session or token of the subscriber is still good, failing to tell ends the
+    /// stream as well since EventSource reconnects and signs in again by itself
+    async fn is_signed_in(&self, sub: &TimelineSubscriber) -> bool {
+        let checked = match &sub.credential {
+            TimelineCredential::Session(session_uuid) => {
+                match check_user_session(&self.state, sub.user_id, *session_uuid).await {
+                    Ok(_) => Ok(true),
+                    Err(SessionError::SessionRevoked) => Ok(false),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            TimelineCredential::Token(bearer) => self
+                .tokens
+                .authenticate(bearer)
+                .await
+                .map(|token| token.is_some_and(|token| token.user_id == sub.user_id))
+                .map_err(|e| e.to_string()),
+        };
+        checked.unwrap_or_else(|e| {
+            tracing::error!("Failed to check timeline subscriber {}: {}", sub.user_id, e);
+            false
+        })
+    }
+
+    /// `None` if the subscriber may not see the echo, an update of an echo it can no longer
+    /// see is sent as a deletion, since it may still be on the subscriber's timeline
+    async fn render_event(&self, user_id: i64, event: &TimelineEvent) -> Option<Event> {
+        let viewer = match self.cache.users.get_user_by_user_id(user_id).await {
+            Ok(viewer) => viewer,
+            Err(e) => {
+                tracing::error!("Failed to fetch timeline subscriber {}: {}", user_id, e);
+                return None;
+            }
+        };
+        let visible = event.echo.has_permission(&viewer)
+            && match self.cache.relations.get_relations(user_id).await {
+                Ok(relations) => !relations.is_hidden(event.echo.user_id),
+                Err(e) => {
+                    tracing::error!("Failed to fetch relations of subscriber {}: {}", user_id, e);
+                    return None;
+                }
+            };
+        let kind = match (event.kind, visible) {
+            (kind, true) => kind,
+            (TimelineEventKind::Updated, false) => TimelineEventKind::Deleted,
+            (_, false) => return None,
+        };
+        let data = match kind {
+            TimelineEventKind::Deleted => {
+                serde_json::to_string(&TimelineDeletedPayload { id: event.echo.id })
+            }
+            TimelineEventKind::Created | TimelineEventKind::Updated => {
+                let mut echo = event.echo.clone();
+                // the echo cache is not keyed by viewer, so render it afresh
+                echo.content = match self.baker.post_inner_echo(
+                    Arc::downgrade(&self.state),
+                    &echo,
+                    viewer.id,
+                    &viewer.permission_ids,
+                    EchoBaker::all_ext_ids(),
+                    true,
+                ) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        tracing::error!("Failed to bake timeline echo {}: {}", echo.id, e);
+                        return None;
+                    }
+                };
+                serde_json::to_string(&echo)
+            }
+        };
+        match data {
+            Ok(data) => Some(
+                Event::default()
+                    .id(event.id.to_string())
+                    .event(kind.as_str())
+                    .data(data),
+            ),
+            Err(e) => {
+                tracing::error!("Failed to serialize timeline event: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn backlog_with(seqs: std::ops::RangeInclusive<u64>) -> TimelineBacklog {
+        TimelineBacklog {
+            next_seq: seqs.end() + 1,
+            events: seqs
+                .map(|seq| {
+                    Arc::new(TimelineEvent {
+                        id: TimelineEventId { epoch: 1, seq },
+                        kind: TimelineEventKind::Created,
+                        echo: Echo::dummy_from_str("echo"),
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_events_after() {
+        let backlog = backlog_with(5..=9);
+        let seqs = |seq| {
+            backlog
+                .events_after(seq)
+                .map(|events| events.iter().map(|e| e.id.seq).collect::<Vec<_>>())
+        };
+        assert_eq!(seqs(4), Some(vec![5, 6, 7, 8, 9]));
+        assert_eq!(seqs(7), Some(vec![8, 9]));
+        assert_eq!(seqs(9), Some(vec![]));
+        // event 4 is gone
+        assert_eq!(seqs(3), None);
+        // from the future
+        assert_eq!(seqs(10), None);
+        let id = TimelineEventId {
+            epoch: 0xbeef,
+            seq: 42,
+        };
+        assert_eq!(id.to_string().parse::<TimelineEventId>(), Ok(id));
+    }
+}
@@ -1,18 +1,25 @@
 use crate::get_batch_tuple_pure;
-use crate::models::dyn_setting::{RpId, RpName, RpOrigin};
+use crate::models::dyn_setting::{DynSetting, RpId, RpName, RpOrigin};
 use crate::models::mfa::{
-    NewTotpCredential, NewWebauthnCredential, WebAuthnKV, WebauthnCredential, WebauthnState,
+    MfaRecoveryCode, NewTotpCredential, NewWebauthnCredential, WebAuthnKV, WebauthnCredential,
+    WebauthnState,
 };
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::password::{PasswordError, PasswordService};
+use crate::services::states::EchoState;
 use crate::services::states::cache::MokaExpiration;
 use crate::services::states::db::DataBaseError;
-use crate::services::states::EchoState;
 use bytes::Bytes;
 use echo_macros::EchoBusinessError;
+use parking_lot::RwLock;
 use ph::fmph;
-use rand::{rng, Rng};
+use rand::{Rng, rng};
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
-use time::Duration;
-use totp_rs::{TotpUrlError, TOTP};
+use std::time::SystemTimeError;
+use subtle::ConstantTimeEq;
+use time::{Duration, OffsetDateTime};
+use totp_rs::{TOTP, TotpUrlError};
 use url::Url;
 use webauthn_rs::prelude::*;
 
@@ -20,6 +27,17 @@ use webauthn_rs::prelude::*;
 pub enum TOTPError {
     #[error(transparent)]
     TotpUrl(#[from] TotpUrlError),
+    #[error(transparent)]
+    SystemTime(#[from] SystemTimeError),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum TotpCheck {
+    /// Holds the time step the code was generated for
+    Accepted(i64),
+    Invalid,
+    /// The code is valid but its time step was already used
+    Replayed,
 }
 
 #[derive(Debug, thiserror::Error, EchoBusinessError)]
@@ -28,10 +46,14 @@ pub enum AuthnError {
     WebAuthnInit(String),
     #[error("Invalid Webauthn RP origin")]
     InvalidWebauthnRpOrigin,
+    #[error("The Webauthn RP origin must be https, or http on localhost")]
+    InsecureWebauthnRpOrigin,
     #[error("Invalid Webauthn state")]
     InvalidWebauthnState,
     #[error("Mismatch WebAuthnKV in same state!")]
     MismatchWebAuthnKV,
+    #[error("Passkey is not registered")]
+    UnknownPasskey,
     #[error("WebAuthn error: {0}")]
     WebauthnOther(#[from] WebauthnError),
 }
@@ -48,28 +70,104 @@ pub enum MFAServiceError {
     Totp(#[from] TOTPError),
     #[error(transparent)]
     Authn(#[from] AuthnError),
+    #[error(transparent)]
+    HybridCache(#[from] HybridCacheError),
+    #[error(transparent)]
+    Password(#[from] PasswordError),
 }
 
 pub type MFAServiceResult<T> = Result<T, MFAServiceError>;
 
+const RECOVERY_CODE_COUNT: usize = 10;
+/// Lowercase RFC 4648 base32, so the codes survive being read out or written down
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
 pub struct MFAService {
     state: Arc<EchoState>,
-    webauthn: Webauthn,
+    /// Recovery codes are hashed like passwords, they are just as good for getting in
+    passwords: Arc<PasswordService>,
+    /// Replaced as a whole when the relying party settings change
+    webauthn: RwLock<Arc<Webauthn>>,
+    /// Serializes relying party changes, so the stored settings always match `webauthn`
+    reconfigure: tokio::sync::Mutex<()>,
 }
 
 impl MFAService {
-    pub async fn new(state: Arc<EchoState>) -> MFAServiceResult<Self> {
+    pub async fn new(
+        state: Arc<EchoState>,
+        passwords: Arc<PasswordService>,
+    ) -> MFAServiceResult<Self> {
         let dyn_setting_op = &state.db;
         let (rp_id, rp_origin, rp_name): (String, String, String) =
             get_batch_tuple_pure!(&dyn_setting_op, RpId, RpOrigin, RpName)
                 .map_err(|e| AuthnError::WebAuthnInit(e.to_string()))?;
-        let rp_origin = Url::parse(&rp_origin).map_err(|_| AuthnError::InvalidWebauthnRpOrigin)?;
-        let webauthn = WebauthnBuilder::new(&rp_id, &rp_origin)
+        let webauthn = Self::build_webauthn(&rp_id, &rp_origin, &rp_name)?;
+        Ok(Self {
+            state,
+            passwords,
+            webauthn: RwLock::new(Arc::new(webauthn)),
+            reconfigure: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Also checks that `rp_id` is the origin's domain or one of its parents
+    fn build_webauthn(rp_id: &str, rp_origin: &str, rp_name: &str) -> MFAServiceResult<Webauthn> {
+        let rp_origin = Url::parse(rp_origin).map_err(|_| AuthnError::InvalidWebauthnRpOrigin)?;
+        if rp_origin.path() != "/" || rp_origin.query().is_some() || rp_origin.fragment().is_some()
+        {
+            return Err(AuthnError::InvalidWebauthnRpOrigin.into());
+        }
+        // browsers refuse WebAuthn outside of secure contexts
+        match (rp_origin.scheme(), rp_origin.host_str()) {
+            ("https", _) | ("http", Some("localhost")) => {}
+            _ => return Err(AuthnError::InsecureWebauthnRpOrigin.into()),
+        }
+        let webauthn = WebauthnBuilder::new(rp_id, &rp_origin)
             .map_err(|e| MFAServiceError::Authn(AuthnError::WebauthnOther(e)))?
-            .rp_name(&rp_name)
+            .rp_name(rp_name)
             .build()
             .map_err(|e| MFAServiceError::Authn(AuthnError::WebauthnOther(e)))?;
-        Ok(Self { state, webauthn })
+        Ok(webauthn)
+    }
+
+    fn webauthn(&self) -> Arc<Webauthn> {
+        self.webauthn.read().clone()
+    }
+
+    /// Validates the new relying party before storing it, then switches over to it. Pending
+    /// registrations and authentications were started for the old one and are dropped.
+    pub async fn reconfigure_relying_party(
+        &self,
+        cache: &HybridCacheService,
+        rp_id: &str,
+        rp_origin: &str,
+        rp_name: &str,
+    ) -> MFAServiceResult<()> {
+        let _guard = self.reconfigure.lock().await;
+        let webauthn = Self::build_webauthn(rp_id, rp_origin, rp_name)?;
+        cache
+            .dyn_settings
+            .set_many_with_str(
+                &[
+                    (RpId.key(), rp_id),
+                    (RpOrigin.key(), rp_origin),
+                    (RpName.key(), rp_name),
+                ],
+                true,
+            )
+            .await?;
+        *self.webauthn.write() = Arc::new(webauthn);
+        let cache = &self.state.cache;
+        cache.invalidate_all_passkey_reg_session().await;
+        cache.invalidate_all_passkey_auth_session().await;
+        cache.invalidate_all_passkey_ph_session().await;
+        cache.invalidate_all_passkey_login_session().await;
+        tracing::warn!(
+            "WebAuthn relying party changed to {} ({}), pending ceremonies were dropped",
+            rp_id,
+            rp_origin
+        );
+        Ok(())
     }
 
     pub fn generate_totp(&self, account_name: impl Into<String>) -> MFAServiceResult<TOTP> {
@@ -90,11 +188,85 @@ impl MFAService {
         rmp_serde::from_slice(totp_data).map_err(MFAServiceError::from)
     }
 
-    pub fn save_totp(&self, user_id: i64, totp: &TOTP) -> MFAServiceResult<NewTotpCredential> {
+    pub fn save_totp(
+        &self,
+        user_id: i64,
+        label: String,
+        totp: &TOTP,
+        last_time_step: i64,
+    ) -> MFAServiceResult<NewTotpCredential> {
         let data = rmp_serde::to_vec(totp).map_err(MFAServiceError::from)?;
         Ok(NewTotpCredential {
             user_id,
+            label,
             totp_credential_data: data,
+            last_time_step,
+        })
+    }
+
+    /// Returns the codes to show the user once and their salted hashes to store
+    pub async fn generate_recovery_codes(&self) -> MFAServiceResult<(Vec<String>, Vec<String>)> {
+        let codes = {
+            let mut rng = rng();
+            (0..RECOVERY_CODE_COUNT)
+                .map(|_| {
+                    let chars = (0..10)
+                        .map(|_| RECOVERY_CODE_ALPHABET[rng.random_range(0..32)] as char)
+                        .collect::<String>();
+                    format!("{}-{}", &chars[..5], &chars[5..])
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut hashes = Vec::with_capacity(codes.len());
+        for code in &codes {
+            hashes.push(self.passwords.hash(&normalize_recovery_code(code)).await?);
+        }
+        Ok((codes, hashes))
+    }
+
+    /// The id of the code in `unused` which `code` is, if any
+    pub async fn find_recovery_code(
+        &self,
+        unused: &[MfaRecoveryCode],
+        code: &str,
+    ) -> MFAServiceResult<Option<i64>> {
+        let normalized = normalize_recovery_code(code);
+        // codes issued before they were salted are plain SHA-256 digests
+        let legacy_hash = hex::encode(Sha256::digest(normalized.as_bytes()));
+        for it in unused {
+            let matched = match it.code_hash.starts_with("$argon2") {
+                true => self
+                    .passwords
+                    .check(&it.code_hash, &normalized)
+                    .await?
+                    .is_match(),
+                false => it.code_hash.as_bytes().ct_eq(legacy_hash.as_bytes()).into(),
+            };
+            if matched {
+                return Ok(Some(it.id));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Checks `code` within the configured skew, codes from `last_time_step` or before are replays
+    pub fn check_totp(
+        &self,
+        totp: &TOTP,
+        code: &str,
+        last_time_step: Option<i64>,
+    ) -> MFAServiceResult<TotpCheck> {
+        let mut totp = totp.clone();
+        totp.skew = self.state.config.mfa.totp_skew;
+        if !totp.check_current(code).map_err(TOTPError::from)? {
+            return Ok(TotpCheck::Invalid);
+        }
+        // `check_current` does not tell which time step matched
+        let now = OffsetDateTime::now_utc().unix_timestamp() as u64;
+        Ok(match matched_time_step(&totp, code, now) {
+            Some(step) if last_time_step.is_some_and(|last| step <= last) => TotpCheck::Replayed,
+            Some(step) => TotpCheck::Accepted(step),
+            None => TotpCheck::Invalid,
         })
     }
 
@@ -117,7 +289,7 @@ impl MFAService {
             .transpose()?;
         let user_unique_uuid = Uuid::new_v5(&Uuid::NAMESPACE_DNS, &user_id.to_be_bytes());
         let (ccr, reg_state) = self
-            .webauthn
+            .webauthn()
             .start_passkey_registration(user_unique_uuid, user_name, user_name, exclude_credentials)
             .map_err(AuthnError::from)?;
         let state = WebauthnState {
@@ -152,7 +324,7 @@ impl MFAService {
         let info = rmp_serde::from_slice::<WebauthnState<(Uuid, PasskeyRegistration)>>(&info)
             .map_err(MFAServiceError::from)?;
         let res = self
-            .webauthn
+            .webauthn()
             .finish_passkey_registration(&reg, &info.state.1)
             .map_err(AuthnError::from)?;
         Ok(NewWebauthnCredential {
@@ -161,6 +333,8 @@ impl MFAService {
             user_name: info.user_name,
             user_display_name: None,
             credential_data: rmp_serde::to_vec(&res).map_err(MFAServiceError::from)?,
+            // only the browser can tell, the attestation does not say whether the key is resident
+            discoverable: reg.extensions.cred_props.as_ref().map(|props| props.rk),
         })
     }
 
@@ -180,7 +354,7 @@ impl MFAService {
             .map(|creds| rmp_serde::from_slice::<Passkey>(&creds.credential_data))
             .collect::<Result<Vec<_>, _>>()?;
         let (rcr, auth_state) = self
-            .webauthn
+            .webauthn()
             .start_passkey_authentication(&already_owned_passkey)
             .map_err(AuthnError::from)?;
         let fmph_f = fmph::GOFunction::from(
@@ -233,7 +407,7 @@ impl MFAService {
         let info = rmp_serde::from_slice::<WebauthnState<PasskeyAuthentication>>(&info)
             .map_err(MFAServiceError::from)?;
         let res = self
-            .webauthn
+            .webauthn()
             .finish_passkey_authentication(&auth, &info.state)
             .map_err(AuthnError::from)?;
         let idx =
@@ -245,4 +419,137 @@ impl MFAService {
             .ok_or(MFAServiceError::Authn(AuthnError::MismatchWebAuthnKV))?;
         Ok((res, *id))
     }
+
+    /// Usernameless login with a discoverable credential, the returned id names the login attempt
+    pub async fn start_passkey_login(&self) -> MFAServiceResult<(Uuid, RequestChallengeResponse)> {
+        let (rcr, auth_state) = self
+            .webauthn()
+            .start_discoverable_authentication()
+            .map_err(AuthnError::from)?;
+        let login_sess = Uuid::new_v4();
+        self.state
+            .cache
+            .set_passkey_login_session(
+                login_sess,
+                (
+                    MokaExpiration::new(Duration::minutes(5)),
+                    Bytes::from(rmp_serde::to_vec(&auth_state)?),
+                ),
+            )
+            .await;
+        Ok((login_sess, rcr))
+    }
+
+    /// The user handle the credential was registered with, to look up its owner
+    pub fn identify_passkey_login(&self, auth: &PublicKeyCredential) -> MFAServiceResult<Uuid> {
+        let (user_unique_uuid, _) = self
+            .webauthn()
+            .identify_discoverable_authentication(auth)
+            .map_err(AuthnError::from)?;
+        Ok(user_unique_uuid)
+    }
+
+    /// `owned_passkeys` are the credentials of the user the handle resolved to
+    pub async fn finish_passkey_login(
+        &self,
+        login_sess: Uuid,
+        auth: &PublicKeyCredential,
+        owned_passkeys: Vec<WebauthnCredential>,
+    ) -> MFAServiceResult<(AuthenticationResult, i64)> {
+        let info = self
+            .state
+            .cache
+            .remove_passkey_login_session(login_sess)
+            .await
+            .ok_or(MFAServiceError::Authn(AuthnError::InvalidWebauthnState))?;
+        let auth_state = rmp_serde::from_slice::<DiscoverableAuthentication>(&info)?;
+        let (credential_id, passkey) = owned_passkeys
+            .iter()
+            .map(|c| {
+                rmp_serde::from_slice::<Passkey>(&c.credential_data).map(|passkey| (c.id, passkey))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .find(|(_, passkey)| passkey.cred_id().as_ref() == auth.raw_id.as_ref())
+            .ok_or(MFAServiceError::Authn(AuthnError::UnknownPasskey))?;
+        let res = self
+            .webauthn()
+            .finish_discoverable_authentication(auth, auth_state, &[(&passkey).into()])
+            .map_err(AuthnError::from)?;
+        Ok((res, credential_id))
+    }
+}
+
+/// Case, dashes and spaces do not matter when a code is typed in
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// The newest time step within the skew window `code` was generated for
+fn matched_time_step(totp: &TOTP, code: &str, time: u64) -> Option<i64> {
+    let current = time / totp.step;
+    let skew = totp.skew as u64;
+    (current.saturating_sub(skew)..=current + skew)
+        .rev()
+        .find(|step| {
+            totp.generate(step * totp.step)
+                .as_bytes()
+                .ct_eq(code.as_bytes())
+                .into()
+        })
+        .map(|step| step as i64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_matched_time_step() {
+        let totp = TOTP::new(
+            totp_rs::Algorithm::SHA512,
+            6,
+            1,
+            30,
+            b"0123456789abcdefghij".to_vec(),
+            None,
+            "test".to_string(),
+        )
+        .unwrap();
+        let now = 1_700_000_015;
+        let step = (now / 30) as i64;
+        let previous = totp.generate(now - 30);
+        let current = totp.generate(now);
+        let next = totp.generate(now + 30);
+        assert_eq!(matched_time_step(&totp, &previous, now), Some(step - 1));
+        assert_eq!(matched_time_step(&totp, &current, now), Some(step));
+        assert_eq!(matched_time_step(&totp, &next, now), Some(step + 1));
+        assert_eq!(
+            matched_time_step(&totp, &totp.generate(now - 60), now),
+            None
+        );
+        assert_eq!(matched_time_step(&totp, "", now), None);
+    }
+
+    #[test]
+    fn test_normalize_recovery_code() {
+        let normalized = normalize_recovery_code("abcde-fgh23");
+        assert_eq!(normalized, "abcdefgh23");
+        assert_eq!(normalized, normalize_recovery_code(" ABCDE fgh23 "));
+        assert_ne!(normalized, normalize_recovery_code("abcde-fgh24"));
+    }
+
+    #[test]
+    fn test_build_webauthn() {
+        let build = |rp_id, rp_origin| MFAService::build_webauthn(rp_id, rp_origin, "Echo");
+        assert!(build("localhost", "http://localhost:8080").is_ok());
+        assert!(build("example.com", "https://echo.example.com").is_ok());
+        assert!(build("example.com", "http://example.com").is_err());
+        assert!(build("example.com", "https://example.org").is_err());
+        assert!(build("example.com", "https://example.com/echo").is_err());
+        assert!(build("example.com", "example.com").is_err());
+    }
 }
@@ -0,0 +1,169 @@
+use crate::get_batch_tuple;
+use crate::models::dyn_setting::{
+    EnrollmentGraceDays, RequireForAdmins, RequireForAll, RequireForPermissions,
+};
+use crate::models::mfa::{MfaNonCompliantUser, MfaPolicy, MfaRequirement};
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::states::EchoState;
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
+};
+use echo_macros::EchoBusinessError;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum MfaPolicyError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error(transparent)]
+    Cache(#[from] HybridCacheError),
+}
+
+pub type MfaPolicyResult<T> = Result<T, MfaPolicyError>;
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum MfaCompliance {
+    Enrolled,
+    NotRequired,
+    /// MFA is required but the user may go on without it until the deadline
+    Grace(OffsetDateTime),
+    Overdue,
+}
+
+/// The site-wide MFA enforcement policy, set through the `Mfa` dynamic settings
+pub struct MfaPolicyService {
+    state: Arc<EchoState>,
+    cache: Arc<HybridCacheService>,
+}
+
+impl MfaPolicyService {
+    pub fn new(state: Arc<EchoState>, cache: Arc<HybridCacheService>) -> Self {
+        Self { state, cache }
+    }
+
+    pub async fn load_policy(&self) -> MfaPolicyResult<MfaPolicy> {
+        let (require_for_all, require_for_admins, require_for_permissions, grace_days): (
+            bool,
+            bool,
+            Vec<i64>,
+            u32,
+        ) = get_batch_tuple!(
+            self.cache.dyn_settings,
+            RequireForAll,
+            RequireForAdmins,
+            RequireForPermissions,
+            EnrollmentGraceDays
+        )?;
+        Ok(MfaPolicy {
+            require_for_all,
+            require_for_admins,
+            require_for_permissions,
+            grace: Duration::days(grace_days as i64),
+        })
+    }
+
+    /// Also starts the grace period the first time a policy is seen to apply to the user,
+    /// and forgets it once none does
+    pub async fn check(&self, user_id: i64) -> MfaPolicyResult<MfaCompliance> {
+        let policy = self.load_policy().await?;
+        let now = OffsetDateTime::now_utc();
+        let Some(requirement) = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.mfa().get_mfa_requirement(user_id, &policy).await
+            })
+            .await?
+        else {
+            return Ok(MfaCompliance::NotRequired);
+        };
+        // runs on every request without the MFA cookie, so only write when it changes
+        let since = match (requirement.required, requirement.required_since) {
+            (true, None) if !requirement.mfa_enabled => Some(Some(now)),
+            (false, Some(_)) => Some(None),
+            _ => None,
+        };
+        if let Some(since) = since {
+            self.state
+                .db
+                .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.mfa().set_mfa_required_since(user_id, since).await
+                })
+                .await?;
+        }
+        let since = since.unwrap_or(requirement.required_since);
+        Ok(evaluate(&requirement, since, policy.grace, now))
+    }
+
+    pub async fn non_compliant_users(
+        &self,
+        page: PageQueryBinder,
+    ) -> MfaPolicyResult<PageQueryResult<MfaNonCompliantUser>> {
+        let policy = self.load_policy().await?;
+        let res = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.mfa().get_non_compliant_users_page(&policy, page).await
+            })
+            .await?;
+        Ok(res)
+    }
+}
+
+fn evaluate(
+    requirement: &MfaRequirement,
+    since: Option<OffsetDateTime>,
+    grace: Duration,
+    now: OffsetDateTime,
+) -> MfaCompliance {
+    if requirement.mfa_enabled {
+        return MfaCompliance::Enrolled;
+    }
+    if !requirement.required {
+        return MfaCompliance::NotRequired;
+    }
+    let deadline = since.unwrap_or(now) + grace;
+    if now < deadline {
+        MfaCompliance::Grace(deadline)
+    } else {
+        MfaCompliance::Overdue
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_evaluate() {
+        let now = OffsetDateTime::now_utc();
+        let requirement = |mfa_enabled, required| MfaRequirement {
+            mfa_enabled,
+            required,
+            required_since: None,
+        };
+        let week = Duration::days(7);
+        assert_eq!(
+            evaluate(&requirement(true, true), Some(now), week, now),
+            MfaCompliance::Enrolled
+        );
+        assert_eq!(
+            evaluate(&requirement(false, false), None, week, now),
+            MfaCompliance::NotRequired
+        );
+        assert_eq!(
+            evaluate(&requirement(false, true), Some(now), week, now),
+            MfaCompliance::Grace(now + week)
+        );
+        assert_eq!(
+            evaluate(&requirement(false, true), Some(now - week), week, now),
+            MfaCompliance::Overdue
+        );
+        assert_eq!(
+            evaluate(&requirement(false, true), Some(now), Duration::ZERO, now),
+            MfaCompliance::Overdue
+        );
+    }
+}
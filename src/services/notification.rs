@@ -0,0 +1,162 @@
+use crate::gladiator::prelude::IncomingEchoMention;
+use crate::models::echo::Echo;
+use crate::models::notification::{NotificationKind, NotificationRow, NotificationView};
+use crate::services::echo_baker::{EchoBaker, EchoBakerError};
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::states::EchoState;
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryResult,
+};
+use ahash::HashMap;
+use echo_macros::EchoBusinessError;
+use std::sync::Arc;
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum NotificationError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error(transparent)]
+    HybridCache(#[from] HybridCacheError),
+    #[error(transparent)]
+    EchoBaker(#[from] EchoBakerError),
+}
+
+pub type NotificationResult<T> = Result<T, NotificationError>;
+
+pub struct NotificationService {
+    state: Arc<EchoState>,
+    cache: Arc<HybridCacheService>,
+    baker: Arc<EchoBaker<'static>>,
+}
+
+impl NotificationService {
+    pub fn new(
+        state: Arc<EchoState>,
+        cache: Arc<HybridCacheService>,
+        baker: Arc<EchoBaker<'static>>,
+    ) -> Self {
+        Self {
+            state,
+            cache,
+            baker,
+        }
+    }
+
+    /// Store the mentions of a freshly written echo and notify the users mentioned for the
+    /// first time. A user only counts as mentioned if they can see both the echo and the
+    /// part of it holding the mention and neither of them blocks the other, everything else
+    /// is dropped silently.
+    pub async fn deliver_mentions(
+        &self,
+        echo_id: i64,
+        mentions: &[IncomingEchoMention],
+    ) -> NotificationResult<()> {
+        let usernames = mentions
+            .iter()
+            .map(|it| it.username.as_str())
+            .collect::<Vec<_>>();
+        // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
+        let (echo, users): (Option<Echo>, Vec<(i64, String)>) = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let echo = exec.echo().query_echo_by_id(echo_id).await?;
+                let users = exec.users().query_user_ids_by_usernames(&usernames).await?;
+                Ok::<_, DataBaseError>((echo, users))
+            })
+            .await?;
+        let Some(echo) = echo else {
+            return Ok(());
+        };
+        let mut user_ids = Vec::with_capacity(users.len());
+        for (user_id, username) in users {
+            if user_id == echo.user_id {
+                continue;
+            }
+            let user = self.cache.users.get_user_by_user_id(user_id).await?;
+            let relations = self.cache.relations.get_relations(user_id).await?;
+            let can_see = echo.has_permission(&user)
+                && !relations.is_blocked_with(echo.user_id)
+                && mentions.iter().any(|it| {
+                    it.username == username
+                        && it
+                            .permission_id
+                            .is_none_or(|pid| user.permission_ids.contains(&pid))
+                });
+            if can_see {
+                user_ids.push(user_id);
+            }
+        }
+        self.state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let new_user_ids = exec
+                    .echo_mention()
+                    .replace_mentions(echo.id, &user_ids)
+                    .await?;
+                exec.notification()
+                    .add_notifications(
+                        &new_user_ids,
+                        NotificationKind::Mention,
+                        echo.user_id,
+                        echo.id,
+                    )
+                    .await
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Notifications of `user_id`, with the echos rendered for them
+    pub async fn list_notifications(
+        &self,
+        user_id: i64,
+        unread_only: bool,
+        page: PageQueryBinder,
+    ) -> NotificationResult<PageQueryResult<NotificationView>> {
+        // TODO: RustRover cannot infer the type here, so fxxk u jetbrains!
+        let (mut page, echos): (PageQueryResult<NotificationRow>, Vec<Echo>) = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let page = exec
+                    .notification()
+                    .list_notifications_page(user_id, unread_only, page)
+                    .await?;
+                let echo_ids = page
+                    .items
+                    .iter()
+                    .filter_map(|it| it.echo_id)
+                    .collect::<Vec<_>>();
+                let echos = exec.echo().query_echos_by_ids(&echo_ids).await?;
+                Ok::<_, DataBaseError>((page, echos))
+            })
+            .await?;
+        let viewer = self.cache.users.get_user_by_user_id(user_id).await?;
+        let mut rendered = HashMap::default();
+        // the echo cache is not keyed by viewer, so render each echo afresh
+        for mut echo in echos {
+            if !echo.has_permission(&viewer) {
+                continue;
+            }
+            echo.content = self.baker.post_inner_echo(
+                Arc::downgrade(&self.state),
+                &echo,
+                viewer.id,
+                &viewer.permission_ids,
+                EchoBaker::all_ext_ids(),
+                true,
+            )?;
+            rendered.insert(echo.id, echo);
+        }
+        let mut views = Vec::with_capacity(page.items.len());
+        for notification in page.items.drain(..) {
+            let echo = notification
+                .echo_id
+                .and_then(|id| rendered.get(&id))
+                .cloned();
+            views.push(NotificationView { notification, echo });
+        }
+        Ok(page.swap_items(views))
+    }
+}
@@ -0,0 +1,399 @@
+//! OpenID Connect login through the authorization code flow with PKCE.
+//! Only RSA signed id tokens are accepted, which is what every common provider issues.
+use crate::models::oidc::{IdTokenClaims, Jwks, JwtHeader, OidcDiscovery, OidcTokenRes};
+use crate::models::session::OidcFlowData;
+use crate::services::states::EchoState;
+use crate::services::states::config::OidcProviderConfig;
+use base64::{Engine as _, engine::general_purpose as b64_general_engine};
+use echo_macros::EchoBusinessError;
+use openssl::bn::BigNum;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use time::OffsetDateTime;
+use tokio::sync::{Mutex, OnceCell};
+use url::Url;
+
+const HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// Accepted clock skew between us and the provider
+const MAX_CLOCK_SKEW_SECS: i64 = 60;
+/// An unknown key id triggers a refetch of the key set, but not more often than this
+const JWKS_REFETCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum OidcError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    OpenSsl(#[from] openssl::error::ErrorStack),
+    #[error(transparent)]
+    Base64Decode(#[from] base64::DecodeError),
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+    #[error(transparent)]
+    UrlParse(#[from] url::ParseError),
+    #[error("Unknown OpenID Connect provider: {0}")]
+    UnknownProvider(String),
+    #[error("Discovery document is issued by {0}, not the configured issuer")]
+    IssuerMismatch(String),
+    #[error("Token endpoint answered {status}: {body}")]
+    TokenEndpoint { status: u16, body: String },
+    #[error("Malformed id token")]
+    MalformedToken,
+    #[error("Unsupported id token algorithm: {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("No key of the provider can verify the id token")]
+    UnknownKey,
+    #[error("Id token signature verification failed")]
+    VerifyFailed,
+    #[error("Id token claim `{0}` is invalid")]
+    InvalidClaim(&'static str),
+}
+
+pub type OidcResult<T> = Result<T, OidcError>;
+
+#[derive(Default)]
+struct ProviderCache {
+    discovery: OnceCell<Arc<OidcDiscovery>>,
+    jwks: Mutex<Option<(Instant, Arc<Jwks>)>>,
+}
+
+pub struct OidcService {
+    state: Arc<EchoState>,
+    client: reqwest::Client,
+    /// Filled lazily, so that a provider being down does not keep the server from starting
+    caches: HashMap<String, ProviderCache>,
+}
+
+fn random_b64(len: usize) -> String {
+    let bytes = (0..len).map(|_| rand::random()).collect::<Vec<u8>>();
+    b64_general_engine::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    b64_general_engine::URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn decode_segment(segment: &str) -> OidcResult<Vec<u8>> {
+    Ok(b64_general_engine::URL_SAFE_NO_PAD.decode(segment)?)
+}
+
+/// Check the signature and the claims of an id token against the key set of its issuer
+fn verify_id_token(
+    id_token: &str,
+    jwks: &Jwks,
+    issuer: &str,
+    client_id: &str,
+    nonce: &str,
+    now: i64,
+) -> OidcResult<IdTokenClaims> {
+    let mut segments = id_token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) = (
+        segments.next(),
+        segments.next(),
+        segments.next(),
+        segments.next(),
+    ) else {
+        return Err(OidcError::MalformedToken);
+    };
+    let jwt_header: JwtHeader = serde_json::from_slice(&decode_segment(header)?)?;
+    let digest = match jwt_header.alg.as_str() {
+        "RS256" => MessageDigest::sha256(),
+        "RS384" => MessageDigest::sha384(),
+        "RS512" => MessageDigest::sha512(),
+        alg => return Err(OidcError::UnsupportedAlgorithm(alg.to_string())),
+    };
+    let (n, e) = jwks
+        .keys
+        .iter()
+        .filter(|it| it.kty == "RSA")
+        .filter(|it| it.usage.as_deref().is_none_or(|usage| usage == "sig"))
+        .filter(|it| jwt_header.kid.is_none() || it.kid == jwt_header.kid)
+        .find_map(|it| Some((it.n.as_deref()?, it.e.as_deref()?)))
+        .ok_or(OidcError::UnknownKey)?;
+    let rsa = Rsa::from_public_components(
+        BigNum::from_slice(&decode_segment(n)?)?,
+        BigNum::from_slice(&decode_segment(e)?)?,
+    )?;
+    let pkey = PKey::from_rsa(rsa)?;
+    let mut verifier = Verifier::new(digest, &pkey)?;
+    verifier.update(header.as_bytes())?;
+    verifier.update(b".")?;
+    verifier.update(payload.as_bytes())?;
+    if !verifier.verify(&decode_segment(signature)?)? {
+        return Err(OidcError::VerifyFailed);
+    }
+    let claims: IdTokenClaims = serde_json::from_slice(&decode_segment(payload)?)?;
+    if claims.iss != issuer {
+        return Err(OidcError::InvalidClaim("iss"));
+    }
+    if !claims.aud.iter().any(|it| it == client_id) {
+        return Err(OidcError::InvalidClaim("aud"));
+    }
+    // with several audiences the token must have been issued to us
+    let needs_azp = claims.aud.len() > 1 || claims.azp.is_some();
+    if needs_azp && claims.azp.as_deref() != Some(client_id) {
+        return Err(OidcError::InvalidClaim("azp"));
+    }
+    if claims.exp + MAX_CLOCK_SKEW_SECS <= now {
+        return Err(OidcError::InvalidClaim("exp"));
+    }
+    if claims
+        .nbf
+        .is_some_and(|nbf| nbf - MAX_CLOCK_SKEW_SECS > now)
+    {
+        return Err(OidcError::InvalidClaim("nbf"));
+    }
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(OidcError::InvalidClaim("nonce"));
+    }
+    Ok(claims)
+}
+
+impl OidcService {
+    pub fn new(state: Arc<EchoState>) -> OidcResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .user_agent(concat!("echo/", env!("CARGO_PKG_VERSION")))
+            .build()?;
+        let caches = state
+            .config
+            .oidc
+            .providers
+            .iter()
+            .map(|it| (it.name.clone(), ProviderCache::default()))
+            .collect();
+        Ok(Self {
+            state,
+            client,
+            caches,
+        })
+    }
+
+    pub fn provider(&self, name: &str) -> OidcResult<&OidcProviderConfig> {
+        self.state
+            .config
+            .oidc
+            .providers
+            .iter()
+            .find(|it| it.name == name)
+            .ok_or_else(|| OidcError::UnknownProvider(name.to_string()))
+    }
+
+    fn cache(&self, name: &str) -> OidcResult<&ProviderCache> {
+        self.caches
+            .get(name)
+            .ok_or_else(|| OidcError::UnknownProvider(name.to_string()))
+    }
+
+    async fn discovery(&self, config: &OidcProviderConfig) -> OidcResult<Arc<OidcDiscovery>> {
+        let discovery = self
+            .cache(&config.name)?
+            .discovery
+            .get_or_try_init(async || {
+                let issuer = config.issuer.trim_end_matches('/');
+                let discovery: OidcDiscovery = self
+                    .client
+                    .get(format!("{issuer}/.well-known/openid-configuration"))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                if discovery.issuer.trim_end_matches('/') != issuer {
+                    return Err(OidcError::IssuerMismatch(discovery.issuer));
+                }
+                Ok(Arc::new(discovery))
+            })
+            .await?;
+        Ok(discovery.clone())
+    }
+
+    /// The cached key set, `refresh` refetches it unless that happened just now
+    async fn jwks(
+        &self,
+        config: &OidcProviderConfig,
+        discovery: &OidcDiscovery,
+        refresh: bool,
+    ) -> OidcResult<Arc<Jwks>> {
+        let mut cached = self.cache(&config.name)?.jwks.lock().await;
+        if let Some((fetched_at, jwks)) = &*cached
+            && (!refresh || fetched_at.elapsed() < JWKS_REFETCH_INTERVAL)
+        {
+            return Ok(jwks.clone());
+        }
+        let jwks: Arc<Jwks> = Arc::new(
+            self.client
+                .get(&discovery.jwks_uri)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?,
+        );
+        *cached = Some((Instant::now(), jwks.clone()));
+        Ok(jwks)
+    }
+
+    /// Where to send the browser to log in at `provider`, along with what the callback has to check.
+    /// With `link_user_id` the callback links the identity to that user rather than logging in.
+    pub async fn authorization_url(
+        &self,
+        provider: &str,
+        invitation_code: Option<String>,
+        device_name: Option<String>,
+        link_user_id: Option<i64>,
+    ) -> OidcResult<(Url, OidcFlowData)> {
+        let config = self.provider(provider)?;
+        let discovery = self.discovery(config).await?;
+        let flow = OidcFlowData {
+            provider: config.name.clone(),
+            state: random_b64(32),
+            nonce: random_b64(32),
+            pkce_verifier: random_b64(32),
+            invitation_code,
+            device_name,
+            link_user_id,
+        };
+        let scope = std::iter::once("openid")
+            .chain(
+                config
+                    .scopes
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|it| *it != "openid"),
+            )
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut url = Url::parse(&discovery.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &config.client_id)
+            .append_pair("redirect_uri", &config.redirect_url)
+            .append_pair("scope", &scope)
+            .append_pair("state", &flow.state)
+            .append_pair("nonce", &flow.nonce)
+            .append_pair("code_challenge", &pkce_challenge(&flow.pkce_verifier))
+            .append_pair("code_challenge_method", "S256");
+        Ok((url, flow))
+    }
+
+    /// Redeem the authorization code and return the verified claims of the id token
+    pub async fn finish(&self, flow: &OidcFlowData, code: &str) -> OidcResult<IdTokenClaims> {
+        let config = self.provider(&flow.provider)?;
+        let discovery = self.discovery(config).await?;
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &config.redirect_url),
+            ("client_id", &config.client_id),
+            ("code_verifier", &flow.pkce_verifier),
+        ];
+        let mut req = self.client.post(&discovery.token_endpoint).form(&form);
+        if let Some(secret) = &config.client_secret {
+            // client_secret_basic wants both parts form encoded first
+            let encode =
+                |it: &str| url::form_urlencoded::byte_serialize(it.as_bytes()).collect::<String>();
+            req = req.basic_auth(encode(&config.client_id), Some(encode(secret)));
+        }
+        let res = req.send().await?;
+        if !res.status().is_success() {
+            return Err(OidcError::TokenEndpoint {
+                status: res.status().as_u16(),
+                body: res.text().await.unwrap_or_default(),
+            });
+        }
+        let token: OidcTokenRes = res.json().await?;
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let verify = |jwks: &Jwks| {
+            verify_id_token(
+                &token.id_token,
+                jwks,
+                &discovery.issuer,
+                &config.client_id,
+                &flow.nonce,
+                now,
+            )
+        };
+        match verify(&*self.jwks(config, &discovery, false).await?) {
+            // the provider may have rotated its keys since we fetched them
+            Err(OidcError::UnknownKey) => verify(&*self.jwks(config, &discovery, true).await?),
+            verified => verified,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::oidc::Jwk;
+    use openssl::sign::Signer;
+    use serde_json::json;
+
+    fn sign(pkey: &PKey<openssl::pkey::Private>, claims: &serde_json::Value) -> String {
+        let engine = b64_general_engine::URL_SAFE_NO_PAD;
+        let header = engine.encode(json!({"alg": "RS256", "kid": "k1"}).to_string());
+        let payload = engine.encode(claims.to_string());
+        let mut signer = Signer::new(MessageDigest::sha256(), pkey).unwrap();
+        signer
+            .update(format!("{header}.{payload}").as_bytes())
+            .unwrap();
+        let signature = engine.encode(signer.sign_to_vec().unwrap());
+        format!("{header}.{payload}.{signature}")
+    }
+
+    #[test]
+    fn test_verify_id_token() {
+        let engine = b64_general_engine::URL_SAFE_NO_PAD;
+        let rsa = Rsa::generate(2048).unwrap();
+        let jwks = Jwks {
+            keys: vec![Jwk {
+                kty: "RSA".to_string(),
+                kid: Some("k1".to_string()),
+                usage: Some("sig".to_string()),
+                n: Some(engine.encode(rsa.n().to_vec())),
+                e: Some(engine.encode(rsa.e().to_vec())),
+            }],
+        };
+        let pkey = PKey::from_rsa(rsa).unwrap();
+        let now = 1_700_000_000;
+        let claims = json!({
+            "iss": "https://idp.example",
+            "sub": "u-1",
+            "aud": "echo",
+            "exp": now + 300,
+            "nonce": "n0nce",
+            "preferred_username": "alice",
+        });
+        let token = sign(&pkey, &claims);
+        let verify = |token: &str, nonce: &str, now: i64| {
+            verify_id_token(token, &jwks, "https://idp.example", "echo", nonce, now)
+        };
+        let verified = verify(&token, "n0nce", now).unwrap();
+        assert_eq!(verified.sub, "u-1");
+        assert_eq!(verified.claim_str("preferred_username"), Some("alice"));
+        assert!(matches!(
+            verify(&token, "other", now),
+            Err(OidcError::InvalidClaim("nonce"))
+        ));
+        assert!(matches!(
+            verify(&token, "n0nce", now + 600),
+            Err(OidcError::InvalidClaim("exp"))
+        ));
+        // a payload swapped under the original signature
+        let mut forged = claims.clone();
+        forged["sub"] = json!("u-2");
+        let forged_payload = engine.encode(forged.to_string());
+        let segments = token.split('.').collect::<Vec<_>>();
+        let forged = format!("{}.{forged_payload}.{}", segments[0], segments[2]);
+        assert!(matches!(
+            verify(&forged, "n0nce", now),
+            Err(OidcError::VerifyFailed)
+        ));
+    }
+}
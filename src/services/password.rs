@@ -0,0 +1,151 @@
+use crate::services::states::EchoState;
+use crate::services::states::config::PasswordConfig;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use echo_macros::EchoBusinessError;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum PasswordError {
+    #[error(transparent)]
+    Argon2(#[from] argon2::Error),
+    #[error(transparent)]
+    PasswordHash(#[from] argon2::password_hash::Error),
+    #[error(transparent)]
+    Join(#[from] tokio::task::JoinError),
+}
+
+pub type PasswordResult<T> = Result<T, PasswordError>;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PasswordCheck {
+    Mismatch,
+    /// `needs_rehash` is set for legacy rows and hashes with outdated parameters
+    Match {
+        needs_rehash: bool,
+    },
+}
+
+impl PasswordCheck {
+    #[inline]
+    pub fn is_match(&self) -> bool {
+        matches!(self, PasswordCheck::Match { .. })
+    }
+}
+
+/// Hashes passwords with Argon2id. Rows written before server side hashing hold the client
+/// supplied value as-is, they are still accepted and reported for an upgrade.
+#[derive(Clone)]
+pub struct PasswordService {
+    argon2: Argon2<'static>,
+}
+
+impl PasswordService {
+    pub fn new(state: Arc<EchoState>) -> PasswordResult<Self> {
+        Self::with_config(&state.config.password)
+    }
+
+    fn with_config(config: &PasswordConfig) -> PasswordResult<Self> {
+        let params = Params::new(
+            config.argon2_memory_kib,
+            config.argon2_iterations,
+            config.argon2_parallelism,
+            None,
+        )?;
+        Ok(Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        })
+    }
+
+    fn hash_blocking(&self, password: &str) -> PasswordResult<String> {
+        let salt = SaltString::encode_b64(&rand::random::<[u8; 16]>())?;
+        Ok(self
+            .argon2
+            .hash_password(password.as_bytes(), &salt)?
+            .to_string())
+    }
+
+    fn check_blocking(&self, stored: &str, password: &str) -> PasswordResult<PasswordCheck> {
+        if !stored.starts_with("$argon2") {
+            let matched: bool = stored.as_bytes().ct_eq(password.as_bytes()).into();
+            return Ok(match matched {
+                true => PasswordCheck::Match { needs_rehash: true },
+                false => PasswordCheck::Mismatch,
+            });
+        }
+        let parsed = PasswordHash::new(stored)?;
+        match self.argon2.verify_password(password.as_bytes(), &parsed) {
+            Ok(()) => Ok(PasswordCheck::Match {
+                needs_rehash: !self.is_current(&parsed)?,
+            }),
+            Err(argon2::password_hash::Error::Password) => Ok(PasswordCheck::Mismatch),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether `parsed` was produced with the algorithm and parameters configured now
+    fn is_current(&self, parsed: &PasswordHash<'_>) -> PasswordResult<bool> {
+        let (current, stored) = (self.argon2.params(), Params::try_from(parsed)?);
+        Ok(parsed.algorithm == Algorithm::Argon2id.ident()
+            && parsed.version == Some(Version::V0x13.into())
+            && stored.m_cost() == current.m_cost()
+            && stored.t_cost() == current.t_cost()
+            && stored.p_cost() == current.p_cost())
+    }
+
+    pub async fn hash(&self, password: &str) -> PasswordResult<String> {
+        let (this, password) = (self.clone(), password.to_owned());
+        tokio::task::spawn_blocking(move || this.hash_blocking(&password)).await?
+    }
+
+    pub async fn check(&self, stored: &str, password: &str) -> PasswordResult<PasswordCheck> {
+        let (this, stored, password) = (self.clone(), stored.to_owned(), password.to_owned());
+        tokio::task::spawn_blocking(move || this.check_blocking(&stored, &password)).await?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn service(iterations: u32) -> PasswordService {
+        PasswordService::with_config(&PasswordConfig {
+            argon2_memory_kib: 64,
+            argon2_iterations: iterations,
+            argon2_parallelism: 1,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_password_check() {
+        let svc = service(1);
+        let hashed = svc.hash_blocking("pw").unwrap();
+        assert!(hashed.starts_with("$argon2id$"));
+        assert_eq!(
+            svc.check_blocking(&hashed, "pw").unwrap(),
+            PasswordCheck::Match {
+                needs_rehash: false
+            }
+        );
+        assert_eq!(
+            svc.check_blocking(&hashed, "wp").unwrap(),
+            PasswordCheck::Mismatch
+        );
+        // parameters changed since
+        assert_eq!(
+            service(2).check_blocking(&hashed, "pw").unwrap(),
+            PasswordCheck::Match { needs_rehash: true }
+        );
+        // legacy row
+        assert_eq!(
+            svc.check_blocking("pw", "pw").unwrap(),
+            PasswordCheck::Match { needs_rehash: true }
+        );
+        assert_eq!(
+            svc.check_blocking("pw", "p").unwrap(),
+            PasswordCheck::Mismatch
+        );
+    }
+}
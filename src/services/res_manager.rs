@@ -1,9 +1,13 @@
+use crate::models::echo_share::EchoShareRow;
 use crate::services::states::EchoState;
+use crate::services::states::auth::AuthKeySet;
 use echo_macros::EchoBusinessError;
 use hmac::digest::core_api::CoreWrapper;
 use hmac::{Hmac, HmacCore, Mac};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_with::{
+    DisplayFromStr,
     base64::{Base64, UrlSafe},
     serde_as,
 };
@@ -30,12 +34,22 @@ pub enum ResManagerServiceError {
     MacVerify(#[from] hmac::digest::MacError),
     #[error("Your sign has expired!")]
     SignExpired,
+    #[error("The key {0} of the sign has been retired!")]
+    SignKeyRetired(u32),
     #[error("Overflow occurred when calculating sign expiration time!")]
     SignExpOverflow,
     #[error(
         "The resource ID in the sign does not match the expected one! Expected {expected}, got {got}"
     )]
     ResIdNotMatch { expected: i64, got: i64 },
+    #[error(
+        "The takeout ID in the sign does not match the expected one! Expected {expected}, got {got}"
+    )]
+    TakeoutIdNotMatch { expected: i64, got: i64 },
+    #[error(
+        "The share ID in the sign does not match the expected one! Expected {expected}, got {got}"
+    )]
+    ShareIdNotMatch { expected: i64, got: i64 },
 }
 
 pub type ResManagerServiceResult<T> = Result<T, ResManagerServiceError>;
@@ -65,21 +79,81 @@ pub struct ExchangedResourceItemCred {
     #[serde_as(as = "Base64<UrlSafe>")]
     #[serde(rename = "sora")]
     pub sig: Vec<u8>,
+    /// Key the sig was made with, links signed before keys were persisted carry none
+    #[serde_as(as = "Option<DisplayFromStr>")]
+    #[serde(rename = "kid", default, skip_serializing_if = "Option::is_none")]
+    pub kid: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExchangedTakeoutTag {
+    pub sign_user_id: i64,
+    pub sign_time: OffsetDateTime,
+    pub exp_time: Duration,
+    pub takeout_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExchangedTakeoutItem {
+    #[serde(flatten)]
+    pub cred: ExchangedResourceItemCred,
+    pub takeout_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExchangedShareTag {
+    pub sign_user_id: i64,
+    pub sign_time: OffsetDateTime,
+    pub exp_time: Duration,
+    pub share_id: i64,
+    pub echo_id: i64,
+    pub permission_ids: Vec<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExchangedShareItem {
+    #[serde(flatten)]
+    pub cred: ExchangedResourceItemCred,
+    pub share_id: i64,
+}
+
+fn to_url<T: Serialize>(item: &T, base_url: Option<&str>) -> ResManagerServiceResult<String> {
+    let qs = serde_urlencoded::to_string(item)?;
+    let base = base_url.unwrap_or("/");
+    let url = if qs.is_empty() {
+        base.to_string()
+    } else {
+        format!("{base}{}{qs}", if base.contains('?') { '&' } else { '?' })
+    };
+    Ok(url)
 }
 
 impl ExchangedResourceItem {
     pub fn to_url(&self, base_url: Option<&str>) -> ResManagerServiceResult<String> {
-        let qs = serde_urlencoded::to_string(self)?;
-        let base = base_url.unwrap_or("/");
-        let url = if qs.is_empty() {
-            base.to_string()
-        } else {
-            format!("{base}{}{qs}", if base.contains('?') { '&' } else { '?' })
-        };
-        Ok(url)
+        to_url(self, base_url)
+    }
+}
+
+impl ExchangedTakeoutItem {
+    pub fn to_url(&self, base_url: Option<&str>) -> ResManagerServiceResult<String> {
+        to_url(self, base_url)
+    }
+}
+
+impl ExchangedShareItem {
+    pub fn to_url(&self, base_url: Option<&str>) -> ResManagerServiceResult<String> {
+        to_url(self, base_url)
     }
 }
 
+/// Prefixed to the signed message so that a tag of one kind never verifies as another kind,
+/// resource signs keep the empty domain to stay compatible with the links already handed out.
+const RES_SIGN_DOMAIN: &[u8] = b"";
+/// Resource links handed out to clients without a session, only these open the public route
+const PUBLIC_RES_SIGN_DOMAIN: &[u8] = b"echo-public-res\0";
+const TAKEOUT_SIGN_DOMAIN: &[u8] = b"echo-takeout\0";
+const SHARE_SIGN_DOMAIN: &[u8] = b"echo-share\0";
+
 pub struct ResManagerService {
     state: Arc<EchoState>,
 }
@@ -90,14 +164,61 @@ impl ResManagerService {
     }
 
     #[inline]
-    fn get_mac(&self) -> ResManagerServiceResult<CoreWrapper<HmacCore<Sha256>>> {
-        Ok(Hmac::<Sha256>::new_from_slice(
-            self.state.auth.get_local_res_key(),
-        )?)
+    fn get_mac(keys: &AuthKeySet) -> ResManagerServiceResult<CoreWrapper<HmacCore<Sha256>>> {
+        Ok(Hmac::<Sha256>::new_from_slice(keys.get_local_res_key())?)
     }
 
-    pub fn sign(
+    fn sign_tag<T: Serialize>(
+        &self,
+        domain: &[u8],
+        tag: &T,
+    ) -> ResManagerServiceResult<ExchangedResourceItemCred> {
+        let tag = rmp_serde::encode::to_vec(tag)?;
+        let keys = self.state.auth.keys();
+        let mut mac = Self::get_mac(keys.current())?;
+        mac.update(domain);
+        mac.update(&tag);
+        let sig = mac.finalize().into_bytes().to_vec();
+        Ok(ExchangedResourceItemCred {
+            tag,
+            sig,
+            kid: Some(keys.current().kid()),
+        })
+    }
+
+    fn verify_tag<T: DeserializeOwned>(
+        &self,
+        domain: &[u8],
+        item: &ExchangedResourceItemCred,
+    ) -> ResManagerServiceResult<T> {
+        let keys = self.state.auth.keys();
+        let keys = match item.kid {
+            Some(kid) => keys
+                .accepted_by_kid(kid)
+                .ok_or(ResManagerServiceError::SignKeyRetired(kid))?,
+            None => keys.current(),
+        };
+        let mut mac = Self::get_mac(keys)?;
+        mac.update(domain);
+        mac.update(&item.tag);
+        mac.verify_slice(&item.sig)
+            .map_err(ResManagerServiceError::MacVerify)?;
+        Ok(rmp_serde::decode::from_slice::<T>(&item.tag)?)
+    }
+
+    fn check_exp(sign_time: OffsetDateTime, exp_time: Duration) -> ResManagerServiceResult<()> {
+        let sign_time = sign_time
+            .checked_add(exp_time)
+            .ok_or(ResManagerServiceError::SignExpOverflow)?;
+        if sign_time < OffsetDateTime::now_utc() {
+            return Err(ResManagerServiceError::SignExpired);
+        }
+        Ok(())
+    }
+
+    fn sign_res(
         &self,
+        domain: &[u8],
         user_id: i64,
         exp_time: Duration,
         res_id: i64,
@@ -108,42 +229,127 @@ impl ResManagerService {
             exp_time,
             res_id,
         };
-        let exchange_res = rmp_serde::encode::to_vec(&exchange_res)?;
-        let mut mac = self.get_mac()?;
-        mac.update(&exchange_res);
-        let sig = mac.finalize().into_bytes().to_vec();
         Ok(ExchangedResourceItem {
-            cred: Some(ExchangedResourceItemCred {
-                tag: exchange_res,
-                sig,
-            }),
+            cred: Some(self.sign_tag(domain, &exchange_res)?),
             res_id,
         })
     }
 
-    pub fn verify(
+    fn verify_res(
         &self,
+        domain: &[u8],
         res_id: i64,
         item: &ExchangedResourceItemCred,
     ) -> ResManagerServiceResult<ExchangedResourceTag> {
-        let mut mac = self.get_mac()?;
-        mac.update(&item.tag);
-        mac.verify_slice(&item.sig)
-            .map_err(ResManagerServiceError::MacVerify)?;
-        let tag = rmp_serde::decode::from_slice::<ExchangedResourceTag>(&item.tag)?;
+        let tag = self.verify_tag::<ExchangedResourceTag>(domain, item)?;
         if tag.res_id != res_id {
             return Err(ResManagerServiceError::ResIdNotMatch {
                 expected: tag.res_id,
                 got: res_id,
             });
         }
-        let sign_time = tag
-            .sign_time
-            .checked_add(tag.exp_time)
-            .ok_or(ResManagerServiceError::SignExpOverflow)?;
-        if sign_time < OffsetDateTime::now_utc() {
-            return Err(ResManagerServiceError::SignExpired);
+        Self::check_exp(tag.sign_time, tag.exp_time)?;
+        Ok(tag)
+    }
+
+    pub fn sign(
+        &self,
+        user_id: i64,
+        exp_time: Duration,
+        res_id: i64,
+    ) -> ResManagerServiceResult<ExchangedResourceItem> {
+        self.sign_res(RES_SIGN_DOMAIN, user_id, exp_time, res_id)
+    }
+
+    pub fn verify(
+        &self,
+        res_id: i64,
+        item: &ExchangedResourceItemCred,
+    ) -> ResManagerServiceResult<ExchangedResourceTag> {
+        self.verify_res(RES_SIGN_DOMAIN, res_id, item)
+    }
+
+    /// Sign a link for the public signed resource route (feeds, guests, shares, federation)
+    pub fn sign_public(
+        &self,
+        user_id: i64,
+        exp_time: Duration,
+        res_id: i64,
+    ) -> ResManagerServiceResult<ExchangedResourceItem> {
+        self.sign_res(PUBLIC_RES_SIGN_DOMAIN, user_id, exp_time, res_id)
+    }
+
+    pub fn verify_public(
+        &self,
+        res_id: i64,
+        item: &ExchangedResourceItemCred,
+    ) -> ResManagerServiceResult<ExchangedResourceTag> {
+        self.verify_res(PUBLIC_RES_SIGN_DOMAIN, res_id, item)
+    }
+
+    pub fn sign_takeout(
+        &self,
+        user_id: i64,
+        exp_time: Duration,
+        takeout_id: i64,
+    ) -> ResManagerServiceResult<ExchangedTakeoutItem> {
+        let exchange_takeout = ExchangedTakeoutTag {
+            sign_user_id: user_id,
+            sign_time: OffsetDateTime::now_utc(),
+            exp_time,
+            takeout_id,
+        };
+        Ok(ExchangedTakeoutItem {
+            cred: self.sign_tag(TAKEOUT_SIGN_DOMAIN, &exchange_takeout)?,
+            takeout_id,
+        })
+    }
+
+    pub fn verify_takeout(
+        &self,
+        takeout_id: i64,
+        item: &ExchangedResourceItemCred,
+    ) -> ResManagerServiceResult<ExchangedTakeoutTag> {
+        let tag = self.verify_tag::<ExchangedTakeoutTag>(TAKEOUT_SIGN_DOMAIN, item)?;
+        if tag.takeout_id != takeout_id {
+            return Err(ResManagerServiceError::TakeoutIdNotMatch {
+                expected: tag.takeout_id,
+                got: takeout_id,
+            });
+        }
+        Self::check_exp(tag.sign_time, tag.exp_time)?;
+        Ok(tag)
+    }
+
+    /// Share links are derived from the stored link alone, so the same link can be listed again
+    pub fn sign_share(&self, share: &EchoShareRow) -> ResManagerServiceResult<ExchangedShareItem> {
+        let exchange_share = ExchangedShareTag {
+            sign_user_id: share.owner_id,
+            sign_time: share.created_at,
+            exp_time: share.expires_at - share.created_at,
+            share_id: share.id,
+            echo_id: share.echo_id,
+            permission_ids: share.permission_ids.0.clone(),
+        };
+        Ok(ExchangedShareItem {
+            cred: self.sign_tag(SHARE_SIGN_DOMAIN, &exchange_share)?,
+            share_id: share.id,
+        })
+    }
+
+    pub fn verify_share(
+        &self,
+        share_id: i64,
+        item: &ExchangedResourceItemCred,
+    ) -> ResManagerServiceResult<ExchangedShareTag> {
+        let tag = self.verify_tag::<ExchangedShareTag>(SHARE_SIGN_DOMAIN, item)?;
+        if tag.share_id != share_id {
+            return Err(ResManagerServiceError::ShareIdNotMatch {
+                expected: tag.share_id,
+                got: share_id,
+            });
         }
+        Self::check_exp(tag.sign_time, tag.exp_time)?;
         Ok(tag)
     }
 }
@@ -8,10 +8,13 @@ use cache::CacheState;
 use config::AppConfig;
 use db::DataBaseState;
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 
 pub struct EchoState {
     pub db: DataBaseState,
     pub cache: CacheState,
     pub auth: AuthState,
     pub config: Arc<AppConfig>,
+    /// Cancelled once the server starts its graceful shutdown, background tasks should stop on it
+    pub shutdown: CancellationToken,
 }
@@ -1,28 +1,71 @@
+use crate::models::auth_key::{AuthKeyInfo, AuthKeyRecord, AuthKeyRing};
+use crate::services::states::config::AppConfig;
+use crate::services::states::db::{DataBaseError, DataBaseState, EchoDatabaseExecutor};
+use echo_macros::EchoBusinessError;
+use hmac::{Hmac, Mac};
+use parking_lot::RwLock;
+use sha2::{Sha256, Sha512};
+use std::path::PathBuf;
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
 use uuid::Uuid;
 
-pub struct AuthState {
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum AuthStateError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+}
+
+pub type AuthStateResult<T> = Result<T, AuthStateError>;
+
+/// Length of the key material every purpose key is derived from
+const AUTH_KEY_MATERIAL_LEN: usize = 64;
+
+/// Keys of a single key id, derived per purpose so that none of them can stand in for another
+pub struct AuthKeySet {
+    kid: u32,
+    retired_at: Option<OffsetDateTime>,
     basic_auth_key: cookie::Key,
     csrf_auth_key: cookie::Key,
     pre_mfa_auth_key: cookie::Key,
     mfa_auth_key: cookie::Key,
+    oidc_flow_key: cookie::Key,
     local_res_key: [u8; 32],
-    session_id: Uuid,
 }
 
-impl AuthState {
-    pub fn new() -> Self {
-        let session_id = Uuid::new_v4();
-        tracing::info!("AuthState initialized with session_id: {session_id}");
+fn derive_cookie_key(material: &[u8], purpose: &[u8]) -> cookie::Key {
+    // SAFETY: HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha512>::new_from_slice(material).unwrap();
+    mac.update(purpose);
+    cookie::Key::from(&mac.finalize().into_bytes())
+}
+
+impl AuthKeySet {
+    fn derive(record: &AuthKeyRecord) -> Self {
+        let material = &record.material;
+        // SAFETY: HMAC accepts keys of any length
+        let mut res_mac = Hmac::<Sha256>::new_from_slice(material).unwrap();
+        res_mac.update(b"echo-local-res");
         Self {
-            basic_auth_key: cookie::Key::generate(),
-            csrf_auth_key: cookie::Key::generate(),
-            pre_mfa_auth_key: cookie::Key::generate(),
-            mfa_auth_key: cookie::Key::generate(),
-            local_res_key: rand::random(),
-            session_id,
+            kid: record.kid,
+            retired_at: record.retired_at,
+            basic_auth_key: derive_cookie_key(material, b"echo-basic-auth"),
+            csrf_auth_key: derive_cookie_key(material, b"echo-csrf-auth"),
+            pre_mfa_auth_key: derive_cookie_key(material, b"echo-pre-mfa-auth"),
+            mfa_auth_key: derive_cookie_key(material, b"echo-mfa-auth"),
+            oidc_flow_key: derive_cookie_key(material, b"echo-oidc-flow"),
+            local_res_key: res_mac.finalize().into_bytes().into(),
         }
     }
 
+    pub fn kid(&self) -> u32 {
+        self.kid
+    }
+
     pub fn get_basic_auth_key(&self) -> &cookie::Key {
         &self.basic_auth_key
     }
@@ -39,11 +82,290 @@ impl AuthState {
         &self.mfa_auth_key
     }
 
+    pub fn get_oidc_flow_key(&self) -> &cookie::Key {
+        &self.oidc_flow_key
+    }
+
     pub fn get_local_res_key(&self) -> &[u8; 32] {
         &self.local_res_key
     }
+}
+
+/// Snapshot of the keys in use, a rotation swaps in a new one
+pub struct AuthKeys {
+    session_id: Uuid,
+    grace: Duration,
+    /// Newest first
+    sets: Vec<AuthKeySet>,
+}
 
+impl AuthKeys {
     pub fn get_session_id(&self) -> &Uuid {
-        self.session_id.as_ref()
+        &self.session_id
+    }
+
+    /// The key everything is signed with
+    pub fn current(&self) -> &AuthKeySet {
+        // SAFETY: a key ring is never persisted or loaded without a key
+        &self.sets[0]
+    }
+
+    /// Keys to verify with, newest first. Retired keys drop out once their grace period is over.
+    pub fn accepted(&self) -> impl Iterator<Item = &AuthKeySet> {
+        let now = OffsetDateTime::now_utc();
+        self.sets.iter().filter(move |it| match it.retired_at {
+            Some(retired_at) => retired_at + self.grace > now,
+            None => true,
+        })
+    }
+
+    pub fn accepted_by_kid(&self, kid: u32) -> Option<&AuthKeySet> {
+        self.accepted().find(|it| it.kid == kid)
+    }
+}
+
+enum AuthKeyStore {
+    /// The database is encrypted, so the keys share its protection
+    Database,
+    File(PathBuf),
+}
+
+pub struct AuthState {
+    store: AuthKeyStore,
+    grace: Duration,
+    /// Serializes the modifications of the persisted ring
+    ring: tokio::sync::Mutex<AuthKeyRing>,
+    keys: RwLock<Arc<AuthKeys>>,
+}
+
+impl AuthKeyRing {
+    fn new_record(kid: u32) -> AuthKeyRecord {
+        AuthKeyRecord {
+            kid,
+            material: (0..AUTH_KEY_MATERIAL_LEN).map(|_| rand::random()).collect(),
+            created_at: OffsetDateTime::now_utc(),
+            retired_at: None,
+        }
+    }
+
+    fn generate() -> Self {
+        Self {
+            session_id: Uuid::new_v4(),
+            keys: vec![Self::new_record(1)],
+        }
+    }
+
+    /// Retire the current key in favour of a fresh one, returns the new key id
+    fn rotate(&mut self) -> u32 {
+        let now = OffsetDateTime::now_utc();
+        for record in self.keys.iter_mut() {
+            record.retired_at.get_or_insert(now);
+        }
+        let kid = self.keys.iter().map(|it| it.kid).max().unwrap_or_default() + 1;
+        self.keys.push(Self::new_record(kid));
+        kid
+    }
+
+    /// Drop retired keys whose grace period is over, the current key always stays
+    fn prune(&mut self, grace: Duration) {
+        let now = OffsetDateTime::now_utc();
+        self.keys.retain(|it| {
+            it.retired_at
+                .is_none_or(|retired_at| retired_at + grace > now)
+        });
+    }
+}
+
+impl AuthState {
+    pub async fn load(config: &AppConfig, db: &DataBaseState) -> AuthStateResult<Self> {
+        #[cfg(feature = "sqlcipher")]
+        let store = match config.db.cipher.encrypt {
+            true => AuthKeyStore::Database,
+            false => AuthKeyStore::File(config.auth.key_file.clone()),
+        };
+        #[cfg(not(feature = "sqlcipher"))]
+        let store = AuthKeyStore::File(config.auth.key_file.clone());
+        let grace = Duration::days(config.auth.key_grace_days as i64);
+        let mut ring = match Self::read_ring(&store, db).await? {
+            Some(ring) if !ring.keys.is_empty() => ring,
+            _ => {
+                tracing::warn!("No auth keys found, generating a new key ring...");
+                AuthKeyRing::generate()
+            }
+        };
+        ring.prune(grace);
+        Self::write_ring(&store, db, &ring).await?;
+        tracing::info!(
+            "AuthState initialized with session_id: {}, current kid: {}",
+            ring.session_id,
+            ring.keys.last().map(|it| it.kid).unwrap_or_default()
+        );
+        let keys = Self::snapshot(&ring, grace);
+        Ok(Self {
+            store,
+            grace,
+            ring: tokio::sync::Mutex::new(ring),
+            keys: RwLock::new(keys),
+        })
+    }
+
+    fn snapshot(ring: &AuthKeyRing, grace: Duration) -> Arc<AuthKeys> {
+        Arc::new(AuthKeys {
+            session_id: ring.session_id,
+            grace,
+            sets: ring.keys.iter().rev().map(AuthKeySet::derive).collect(),
+        })
+    }
+
+    async fn read_ring(
+        store: &AuthKeyStore,
+        db: &DataBaseState,
+    ) -> AuthStateResult<Option<AuthKeyRing>> {
+        let raw = match store {
+            AuthKeyStore::Database => {
+                db.single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.auth_key().get_ring().await
+                })
+                .await?
+            }
+            AuthKeyStore::File(path) => match tokio::fs::read_to_string(path).await {
+                Ok(raw) => Some(raw),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            },
+        };
+        Ok(raw.map(|it| serde_json::from_str(&it)).transpose()?)
+    }
+
+    async fn write_ring(
+        store: &AuthKeyStore,
+        db: &DataBaseState,
+        ring: &AuthKeyRing,
+    ) -> AuthStateResult<()> {
+        let raw = serde_json::to_string(ring)?;
+        match store {
+            AuthKeyStore::Database => {
+                db.single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.auth_key().put_ring(&raw).await
+                })
+                .await?
+            }
+            AuthKeyStore::File(path) => {
+                use tokio::io::AsyncWriteExt;
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                // write aside and rename, a crash must never leave a half written ring behind
+                let tmp_path = path.with_extension("tmp");
+                let mut options = tokio::fs::OpenOptions::new();
+                options.write(true).create(true).truncate(true);
+                #[cfg(unix)]
+                options.mode(0o600);
+                let mut file = options.open(&tmp_path).await?;
+                file.write_all(raw.as_bytes()).await?;
+                file.sync_all().await?;
+                tokio::fs::rename(&tmp_path, path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply `f` to the stored ring, persist it and swap the keys in use. The CLI may have
+    /// changed the store behind the running server, so the ring held in memory is not used.
+    async fn modify<T>(
+        &self,
+        db: &DataBaseState,
+        f: impl FnOnce(&mut AuthKeyRing) -> T,
+    ) -> AuthStateResult<T> {
+        let mut ring = self.ring.lock().await;
+        let mut modified = match Self::read_ring(&self.store, db).await? {
+            Some(stored) if !stored.keys.is_empty() => stored,
+            _ => ring.clone(),
+        };
+        let out = f(&mut modified);
+        modified.prune(self.grace);
+        Self::write_ring(&self.store, db, &modified).await?;
+        *self.keys.write() = Self::snapshot(&modified, self.grace);
+        *ring = modified;
+        Ok(out)
+    }
+
+    pub fn keys(&self) -> Arc<AuthKeys> {
+        self.keys.read().clone()
+    }
+
+    /// Sign with a fresh key from now on, the previous ones stay accepted for the grace period.
+    /// Returns the new key id.
+    pub async fn rotate(&self, db: &DataBaseState) -> AuthStateResult<u32> {
+        let kid = self.modify(db, AuthKeyRing::rotate).await?;
+        tracing::warn!("Auth keys rotated, current kid: {}", kid);
+        Ok(kid)
+    }
+
+    /// Invalidate every session at once, signed links are not affected
+    pub async fn logout_everyone(&self, db: &DataBaseState) -> AuthStateResult<()> {
+        self.modify(db, |ring| ring.session_id = Uuid::new_v4())
+            .await?;
+        tracing::warn!("Session epoch bumped, everyone has been logged out");
+        Ok(())
+    }
+
+    pub async fn key_infos(&self) -> Vec<AuthKeyInfo> {
+        let ring = self.ring.lock().await;
+        ring.keys
+            .iter()
+            .rev()
+            .map(|it| AuthKeyInfo {
+                kid: it.kid,
+                current: it.retired_at.is_none(),
+                created_at: it.created_at,
+                retired_at: it.retired_at,
+                accepted_until: it.retired_at.map(|retired_at| retired_at + self.grace),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_ring_rotate() {
+        let grace = Duration::days(1);
+        let mut ring = AuthKeyRing::generate();
+        assert_eq!(ring.rotate(), 2);
+        assert_eq!(ring.rotate(), 3);
+        let keys = AuthState::snapshot(&ring, grace);
+        assert_eq!(keys.current().kid(), 3);
+        assert_eq!(
+            keys.accepted().map(|it| it.kid()).collect::<Vec<_>>(),
+            vec![3, 2, 1]
+        );
+        assert_ne!(
+            keys.current().get_local_res_key(),
+            keys.accepted_by_kid(2).unwrap().get_local_res_key()
+        );
+        // key 1 has been retired for longer than the grace period
+        ring.keys[0].retired_at = Some(OffsetDateTime::now_utc() - Duration::days(2));
+        let keys = AuthState::snapshot(&ring, grace);
+        assert!(keys.accepted_by_kid(1).is_none());
+        ring.prune(grace);
+        assert_eq!(
+            ring.keys.iter().map(|it| it.kid).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_modify_keeps_changes_made_behind_the_server() {
+        let app = crate::routers::test_util::TestApp::new().await;
+        let state = &app.state;
+        // the CLI loads a ring of its own
+        let cli = AuthState::load(&state.config, &state.db).await.unwrap();
+        assert_eq!(cli.rotate(&state.db).await.unwrap(), 2);
+        cli.logout_everyone(&state.db).await.unwrap();
+        assert_eq!(state.auth.rotate(&state.db).await.unwrap(), 3);
+        assert_eq!(state.auth.keys().session_id, cli.keys().session_id);
     }
 }
@@ -131,6 +131,12 @@ where
         self.inner.remove(&k).await.map(|(_, v)| v)
     }
 
+    /// `remove` still sees the entries until the pending tasks ran, so they are run right away
+    pub async fn invalidate_all(&self) {
+        self.inner.invalidate_all();
+        self.inner.run_pending_tasks().await;
+    }
+
     pub async fn run_pending_tasks(&self) {
         self.inner.run_pending_tasks().await;
     }
@@ -205,6 +211,13 @@ where
         self.inner.remove(&cache_key).await.map(|(_, v)| v)
     }
 
+    /// Each group has a cache of its own, so this never touches other groups. `remove` still
+    /// sees the entries until the pending tasks ran, so they are run right away.
+    pub async fn invalidate_all(&self) {
+        self.inner.invalidate_all();
+        self.inner.run_pending_tasks().await;
+    }
+
     pub async fn run_pending_tasks(&self) {
         self.inner.run_pending_tasks().await;
     }
@@ -291,6 +304,9 @@ macro_rules! define_moka_cache {
             $vis async fn [<remove_ $pfx>](&self, key: impl Into<$kty>) -> Option<$vty> {
                 self.$field.remove_with_prefix(stringify!($pfx), key).await
             }
+            $vis async fn [<invalidate_all_ $pfx>](&self) {
+                self.$field.invalidate_all().await
+            }
             $vis async fn [<run_pending_ $pfx _tasks>](&self) {
                 self.$field.run_pending_tasks().await;
             }
@@ -328,6 +344,9 @@ macro_rules! define_moka_cache {
             $vis async fn [<remove_ $pfx>](&self, key: impl Into<$kty>) -> Option<$vty> {
                 self.$field.remove(key).await
             }
+            $vis async fn [<invalidate_all_ $pfx>](&self) {
+                self.$field.invalidate_all().await
+            }
             $vis async fn [<run_pending_ $pfx _tasks>](&self) {
                 self.$field.run_pending_tasks().await;
             }
@@ -350,6 +369,14 @@ define_moka_cache! {
         key_constraint: true,
         max_size: Some(10),
     }
+    // filled by unauthenticated requests, keep room so a burst cannot evict real logins
+    passkey_login_session => {
+        vis: pub,
+        tk: uuid::Uuid,
+        ty: bytes::Bytes,
+        key_constraint: false,
+        max_size: Some(10000),
+    }
     passkey_ph_session => {
         vis: pub,
         tk: i64,
@@ -378,6 +405,20 @@ define_moka_cache! {
         key_constraint: false,
         max_size: Some(10)
     }
+    access_token => {
+        vis: pub,
+        tk: String,
+        ty: Arc<crate::models::token::AuthTokenRaw>,
+        key_constraint: false,
+        max_size: Some(1000),
+    }
+    user_session => {
+        vis: pub,
+        tk: uuid::Uuid,
+        ty: i64,
+        key_constraint: false,
+        max_size: Some(1000),
+    }
 }
 
 #[cfg(test)]
@@ -109,12 +109,122 @@ impl Default for PerfConfig {
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthConfig {
+    /// Where the session and signing keys are kept, unless the database is encrypted
+    pub key_file: PathBuf,
+    /// How long a rotated key is still accepted, should outlive the longest signed link
+    pub key_grace_days: u16,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            key_file: "data/echo.keys".into(),
+            key_grace_days: 30,
+        }
+    }
+}
+
+/// Argon2id cost parameters, stored hashes with other parameters are upgraded on the next login
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordConfig {
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+}
+
+impl Default for PasswordConfig {
+    fn default() -> Self {
+        Self {
+            argon2_memory_kib: 19 * 1024, // 19 MiB
+            argon2_iterations: 2,
+            argon2_parallelism: 1,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaConfig {
+    /// Time steps before and after the current one a TOTP code is still accepted for
+    pub totp_skew: u8,
+    /// Failed attempts in a row with one method before the user is locked out of it
+    pub max_failures: u32,
+    /// How long a lockout lasts, failures older than this are forgotten
+    pub lockout_minutes: u32,
+    /// How recent an MFA verification must be for sensitive operations
+    pub step_up_minutes: u32,
+}
+
+impl Default for MfaConfig {
+    fn default() -> Self {
+        Self {
+            totp_skew: 1,
+            max_failures: 5,
+            lockout_minutes: 15,
+            step_up_minutes: 5,
+        }
+    }
+}
+
+/// An OpenID Connect provider users can sign in with, through the authorization code flow with PKCE
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OidcProviderConfig {
+    /// Identifies the provider in the login urls and the linked identities
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    /// Public clients have none, it is never serialized so that it cannot leak through the settings
+    #[serde(skip_serializing)]
+    pub client_secret: Option<String>,
+    /// Must point at `/api/v1/oidc/{name}/callback` and be registered at the provider
+    pub redirect_url: String,
+    /// Requested on top of `openid`
+    pub scopes: Vec<String>,
+    /// Claim the username of auto provisioned users is taken from
+    pub username_claim: String,
+    /// Where the browser is sent after the callback, the callback answers with JSON if absent
+    pub post_login_redirect: Option<String>,
+}
+
+impl Default for OidcProviderConfig {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            issuer: String::new(),
+            client_id: String::new(),
+            client_secret: None,
+            redirect_url: String::new(),
+            scopes: vec!["profile".to_string()],
+            username_claim: "preferred_username".to_string(),
+            post_login_redirect: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OidcConfig {
+    pub providers: Vec<OidcProviderConfig>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ActivityPubConfig {
+    /// Let remote actors and inboxes live on loopback or private addresses, only for local testing
+    pub allow_private_addresses: bool,
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct AppConfig {
     pub common: CommonConfig,
     pub db: DataBaseConfig,
     pub resource: ResourceConfig,
     pub perf: PerfConfig,
+    pub auth: AuthConfig,
+    pub password: PasswordConfig,
+    pub mfa: MfaConfig,
+    pub oidc: OidcConfig,
+    pub activity_pub: ActivityPubConfig,
 }
 
 impl AppConfig {
@@ -1,19 +1,45 @@
+mod activity_pub;
+mod audit;
+mod auth_key;
 mod dyn_setting;
 mod echo;
+mod echo_import;
+mod echo_mention;
+mod echo_repost;
+mod echo_share;
+mod follow;
 mod invite_code;
 mod mfa;
+mod notification;
+mod oidc;
 mod permission;
 mod resources;
+mod takeout;
 mod token;
+mod user_relation;
+mod user_session;
 mod users;
 
+use crate::services::states::db::activity_pub::ActivityPubRepo;
+use crate::services::states::db::audit::AuditRepo;
+use crate::services::states::db::auth_key::AuthKeyRepo;
 use crate::services::states::db::dyn_setting::DynSettingsRepo;
 use crate::services::states::db::echo::EchoRepo;
+use crate::services::states::db::echo_import::EchoImportRepo;
+use crate::services::states::db::echo_mention::EchoMentionRepo;
+use crate::services::states::db::echo_repost::EchoRepostRepo;
+use crate::services::states::db::echo_share::EchoShareRepo;
+use crate::services::states::db::follow::FollowRepo;
 use crate::services::states::db::invite_code::InviteCodeRepo;
 use crate::services::states::db::mfa::MfaRepo;
+use crate::services::states::db::notification::NotificationRepo;
+use crate::services::states::db::oidc::OidcRepo;
 use crate::services::states::db::permission::PermissionRepo;
 use crate::services::states::db::resources::ResourceRepo;
+use crate::services::states::db::takeout::TakeoutRepo;
 use crate::services::states::db::token::TokenRepo;
+use crate::services::states::db::user_relation::UserRelationRepo;
+use crate::services::states::db::user_session::UserSessionRepo;
 use crate::services::states::db::users::UsersRepo;
 use crate::utils::smart_to_string::SmartStringError;
 use echo_macros::EchoBusinessError;
@@ -198,6 +224,27 @@ impl<'a, E> DataBaseExecutor<'a, E>
 where
     for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
 {
+    #[inline]
+    pub fn activity_pub(&mut self) -> ActivityPubRepo<'_, E> {
+        ActivityPubRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn audit(&mut self) -> AuditRepo<'_, E> {
+        AuditRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn auth_key(&mut self) -> AuthKeyRepo<'_, E> {
+        AuthKeyRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
     #[inline]
     pub fn dyn_settings(&mut self) -> DynSettingsRepo<'_, E> {
         DynSettingsRepo {
@@ -212,6 +259,41 @@ where
         }
     }
 
+    #[inline]
+    pub fn echo_import(&mut self) -> EchoImportRepo<'_, E> {
+        EchoImportRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn echo_mention(&mut self) -> EchoMentionRepo<'_, E> {
+        EchoMentionRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn echo_repost(&mut self) -> EchoRepostRepo<'_, E> {
+        EchoRepostRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn echo_share(&mut self) -> EchoShareRepo<'_, E> {
+        EchoShareRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn follow(&mut self) -> FollowRepo<'_, E> {
+        FollowRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
     #[inline]
     pub fn invite_code(&mut self) -> InviteCodeRepo<'_, E> {
         InviteCodeRepo {
@@ -226,6 +308,20 @@ where
         }
     }
 
+    #[inline]
+    pub fn notification(&mut self) -> NotificationRepo<'_, E> {
+        NotificationRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn oidc(&mut self) -> OidcRepo<'_, E> {
+        OidcRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
     #[inline]
     pub fn permission(&mut self) -> PermissionRepo<'_, E> {
         PermissionRepo {
@@ -240,6 +336,13 @@ where
         }
     }
 
+    #[inline]
+    pub fn takeout(&mut self) -> TakeoutRepo<'_, E> {
+        TakeoutRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
     #[inline]
     pub fn token(&mut self) -> TokenRepo<'_, E> {
         TokenRepo {
@@ -247,6 +350,20 @@ where
         }
     }
 
+    #[inline]
+    pub fn user_relation(&mut self) -> UserRelationRepo<'_, E> {
+        UserRelationRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
+    #[inline]
+    pub fn user_session(&mut self) -> UserSessionRepo<'_, E> {
+        UserSessionRepo {
+            inner: &mut *self.inner,
+        }
+    }
+
     #[inline]
     pub fn users(&mut self) -> UsersRepo<'_, E> {
         UsersRepo {
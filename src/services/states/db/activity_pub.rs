@@ -0,0 +1,185 @@
+use crate::models::activity_pub::{ApActorKeyRow, ApDeliveryRow, NewApFollower};
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt, SqliteQueryResultExt};
+use sqlx::{Executor, Sqlite, query, query_as, query_scalar};
+use time::OffsetDateTime;
+
+pub struct ActivityPubRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> ActivityPubRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub async fn get_actor_key(&mut self, user_id: i64) -> DataBaseResult<Option<ApActorKeyRow>> {
+        query_as!(
+            ApActorKeyRow,
+            "SELECT user_id, private_key_pem, public_key_pem FROM ap_actor_keys WHERE user_id = ?",
+            user_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Keep the existing key if another request won the race
+    pub async fn insert_actor_key_if_absent(
+        &mut self,
+        user_id: i64,
+        private_key_pem: &str,
+        public_key_pem: &str,
+    ) -> DataBaseResult<()> {
+        query!(
+            r#"
+                INSERT INTO ap_actor_keys (user_id, private_key_pem, public_key_pem)
+                VALUES (?, ?, ?)
+                ON CONFLICT (user_id) DO NOTHING
+            "#,
+            user_id,
+            private_key_pem,
+            public_key_pem
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    pub async fn upsert_follower(&mut self, follower: NewApFollower<'_>) -> DataBaseResult<()> {
+        query!(
+            r#"
+                INSERT INTO ap_followers (user_id, actor_url, inbox_url, shared_inbox_url)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT (user_id, actor_url) DO UPDATE SET
+                  inbox_url = excluded.inbox_url,
+                  shared_inbox_url = excluded.shared_inbox_url
+            "#,
+            follower.user_id,
+            follower.actor_url,
+            follower.inbox_url,
+            follower.shared_inbox_url
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    pub async fn remove_follower(&mut self, user_id: i64, actor_url: &str) -> DataBaseResult<()> {
+        query!(
+            "DELETE FROM ap_followers WHERE user_id = ? AND actor_url = ?",
+            user_id,
+            actor_url
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?; // unknown follower is fine
+        Ok(())
+    }
+
+    pub async fn count_followers(&mut self, user_id: i64) -> DataBaseResult<i64> {
+        query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM ap_followers WHERE user_id = ?"#,
+            user_id
+        )
+        .fetch_one(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Distinct delivery targets, shared inboxes collapse followers of the same instance
+    pub async fn list_follower_inboxes(&mut self, user_id: i64) -> DataBaseResult<Vec<String>> {
+        query_scalar!(
+            r#"
+                SELECT DISTINCT COALESCE(shared_inbox_url, inbox_url) AS "inbox!: String"
+                FROM ap_followers
+                WHERE user_id = ?
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn enqueue_delivery(
+        &mut self,
+        user_id: i64,
+        inbox_url: &str,
+        payload: &str,
+    ) -> DataBaseResult<i64> {
+        let res = query!(
+            "INSERT INTO ap_deliveries (user_id, inbox_url, payload) VALUES (?, ?, ?)",
+            user_id,
+            inbox_url,
+            payload
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn fetch_due_deliveries(
+        &mut self,
+        now: OffsetDateTime,
+        limit: u32,
+    ) -> DataBaseResult<Vec<ApDeliveryRow>> {
+        let now = now.unix_timestamp();
+        query_as!(
+            ApDeliveryRow,
+            r#"
+                SELECT
+                  id,
+                  user_id,
+                  inbox_url,
+                  payload,
+                  attempts,
+                  next_attempt_at AS "next_attempt_at: OffsetDateTime"
+                FROM ap_deliveries
+                WHERE next_attempt_at <= ?
+                ORDER BY next_attempt_at, id
+                LIMIT ?
+            "#,
+            now,
+            limit
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn remove_delivery(&mut self, delivery_id: i64) -> DataBaseResult<()> {
+        query!("DELETE FROM ap_deliveries WHERE id = ?", delivery_id)
+            .execute(&mut *self.inner)
+            .await
+            .resolve_affected()?;
+        Ok(())
+    }
+
+    pub async fn reschedule_delivery(
+        &mut self,
+        delivery_id: i64,
+        next_attempt_at: OffsetDateTime,
+        last_error: &str,
+    ) -> DataBaseResult<()> {
+        let next_attempt_at = next_attempt_at.unix_timestamp();
+        query!(
+            r#"
+                UPDATE ap_deliveries
+                SET attempts = attempts + 1, next_attempt_at = ?, last_error = ?
+                WHERE id = ?
+            "#,
+            next_attempt_at,
+            last_error,
+            delivery_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve_affected()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::models::audit::{AuditAction, AuditLog, AuditLogFilter, NewAuditLog};
+use crate::services::states::db::{
+    DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt,
+};
+use sqlx::types::Json;
+use sqlx::{Executor, Sqlite, query, query_as};
+use time::OffsetDateTime;
+
+pub struct AuditRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> AuditRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub async fn insert_audit_log(&mut self, log: NewAuditLog) -> DataBaseResult<i64> {
+        let before_summary = log.before_summary.map(Json);
+        let after_summary = log.after_summary.map(Json);
+        query!(
+            r#"
+                INSERT INTO audit_logs
+                (actor_id, action, target_user_id, target, before_summary, after_summary,
+                 ip_address, user_agent, request_id)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            log.actor_id,
+            log.action,
+            log.target_user_id,
+            log.target,
+            before_summary,
+            after_summary,
+            log.ip_address,
+            log.user_agent,
+            log.request_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|result| result.last_insert_rowid())
+    }
+
+    pub async fn get_audit_logs_page(
+        &mut self,
+        filter: &AuditLogFilter,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<AuditLog>> {
+        let since = filter.since.map(|t| t.unix_timestamp());
+        let until = filter.until.map(|t| t.unix_timestamp());
+        page.query_page_ctx(|pq| async move {
+            query_as!(
+                AuditLog,
+                // language=sql
+                r#"
+                    SELECT
+                        id AS "id!",
+                        actor_id,
+                        action AS "action: AuditAction",
+                        target_user_id,
+                        target,
+                        before_summary AS "before_summary: Json<serde_json::Value>",
+                        after_summary AS "after_summary: Json<serde_json::Value>",
+                        ip_address,
+                        user_agent,
+                        request_id,
+                        time AS "time: OffsetDateTime"
+                    FROM audit_logs
+                    WHERE id > ?1
+                      AND (?2 IS NULL OR actor_id = ?2)
+                      AND (?3 IS NULL OR target_user_id = ?3)
+                      AND (?4 IS NULL OR action = ?4)
+                      AND (?5 IS NULL OR time >= ?5)
+                      AND (?6 IS NULL OR time < ?6)
+                    ORDER BY id
+                    LIMIT ?7
+                "#,
+                pq.start_after,
+                filter.actor_id,
+                filter.target_user_id,
+                filter.action,
+                since,
+                until,
+                pq.limit,
+            )
+            .fetch_all(&mut *self.inner)
+            .await
+        })
+        .await
+    }
+
+    /// Returns how many entries were removed
+    pub async fn delete_audit_logs_before(
+        &mut self,
+        before: OffsetDateTime,
+    ) -> DataBaseResult<u64> {
+        let before = before.unix_timestamp();
+        query!("DELETE FROM audit_logs WHERE time < ?", before)
+            .execute(&mut *self.inner)
+            .await
+            .resolve()
+            .map(|res| res.rows_affected())
+    }
+}
@@ -0,0 +1,35 @@
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
+use sqlx::{Executor, Sqlite, query, query_scalar};
+
+pub struct AuthKeyRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> AuthKeyRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub(in crate::services) async fn get_ring(&mut self) -> DataBaseResult<Option<String>> {
+        query_scalar!("SELECT ring FROM auth_key_ring WHERE id = 1")
+            .fetch_optional(&mut *self.inner)
+            .await
+            .resolve()
+    }
+
+    pub(in crate::services) async fn put_ring(&mut self, ring: &str) -> DataBaseResult<()> {
+        query!(
+            r#"
+                INSERT INTO auth_key_ring (id, ring) VALUES (1, ?1)
+                ON CONFLICT (id) DO UPDATE SET ring = ?1, updated_at = strftime('%s', 'now')
+            "#,
+            ring
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
-use crate::models::echo::{Echo, EchoFullViewRaw};
+use crate::models::echo::{Echo, EchoCalendarDay, EchoFullViewRaw};
 use crate::models::resource::ResourceTarget;
+use crate::models::users::{Role, User};
 use crate::services::states::db::{
     DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt, SqliteQueryResultExt,
 };
@@ -70,12 +71,19 @@ where
         new_resource_ids: &[i64],
         permission_ids: &[i64],
         is_private: bool,
+        created_at: Option<OffsetDateTime>,
     ) -> DataBaseResult<i64> {
+        // `created_at` is only provided when the echo comes from somewhere else, e.g. an import
+        let created_at = created_at.map(OffsetDateTime::unix_timestamp);
         let result = query!(
-            "INSERT INTO echos (user_id, content, is_private) VALUES (?, ?, ?)",
+            r#"
+                INSERT INTO echos (user_id, content, is_private, created_at, last_modified_at)
+                VALUES (?1, ?2, ?3, COALESCE(?4, strftime('%s', 'now')), COALESCE(?4, strftime('%s', 'now')))
+            "#,
             user_id,
             new_content,
-            is_private
+            is_private,
+            created_at
         )
         .execute(&mut *self.inner)
         .await
@@ -155,11 +163,48 @@ where
         Ok(row)
     }
 
+    pub async fn query_echos_by_ids(&mut self, echo_ids: &[i64]) -> DataBaseResult<Vec<Echo>> {
+        if echo_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids_json = serde_json::to_string(echo_ids)?;
+        let rows = query_as!(
+            EchoFullViewRaw,
+            r#"
+                SELECT
+                  e.id,
+                  e.user_id,
+                  e.content,
+                  e.fav_count,
+                  e.is_private AS "is_private: bool",
+                  e.created_at AS "created_at: OffsetDateTime",
+                  e.last_modified_at AS "last_modified_at: OffsetDateTime",
+                  COALESCE((
+                    SELECT json_group_array(ep.permission_id)
+                    FROM echo_permissions AS ep
+                    WHERE ep.echo_id = e.id
+                      AND ep.permission_id IS NOT NULL
+                    ORDER BY ep.permission_id
+                  ), json('[]')) AS "permission_ids: Json<Vec<i64>>"
+                FROM echos AS e
+                WHERE e.id IN (SELECT CAST(value AS INTEGER) FROM json_each(?));
+            "#,
+            ids_json
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
+    /// Echos of `user_id`, or of everyone, leaving out the authors in `hidden_user_ids`
     pub async fn query_user_echo(
         &mut self,
         user_id: Option<i64>,
+        hidden_user_ids: &[i64],
         page: PageQueryBinder,
     ) -> DataBaseResult<PageQueryResult<Echo>> {
+        let hidden_user_ids = serde_json::to_string(hidden_user_ids)?;
         page.query_page_ctx(|pq| async move {
             let rows = query_as!(
                 EchoFullViewRaw,
@@ -181,6 +226,47 @@ where
                       ), json('[]')) AS "permission_ids: Json<Vec<i64>>"
                     FROM echos AS e
                     WHERE (?1 IS NULL OR e.user_id = ?1) AND e.id > ?2
+                      AND e.user_id NOT IN (SELECT value FROM json_each(?4))
+                    ORDER BY e.id
+                    LIMIT ?3;
+                "#,
+                user_id,
+                pq.start_after,
+                pq.limit,
+                hidden_user_ids,
+            )
+            .fetch_all(&mut *self.inner)
+            .await?;
+            let items = rows.into_iter().map(Into::into).collect();
+            Ok(items)
+        })
+        .await
+    }
+
+    /// Same as [`Self::query_user_echo`], but only echos which are neither private nor gated by
+    /// permissions, i.e. what a guest may browse.
+    pub async fn query_public_echo(
+        &mut self,
+        user_id: Option<i64>,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<Echo>> {
+        page.query_page_ctx(|pq| async move {
+            let rows = query_as!(
+                EchoFullViewRaw,
+                r#"
+                    SELECT
+                      e.id,
+                      e.user_id,
+                      e.content,
+                      e.fav_count,
+                      e.is_private AS "is_private: bool",
+                      e.created_at AS "created_at: OffsetDateTime",
+                      e.last_modified_at AS "last_modified_at: OffsetDateTime",
+                      NULL AS "permission_ids: Json<Vec<i64>>"
+                    FROM echos AS e
+                    WHERE (?1 IS NULL OR e.user_id = ?1) AND e.id > ?2
+                      AND e.is_private = 0
+                      AND NOT EXISTS (SELECT 1 FROM echo_permissions AS ep WHERE ep.echo_id = e.id)
                     ORDER BY e.id
                     LIMIT ?3;
                 "#,
@@ -195,4 +281,228 @@ where
         })
         .await
     }
+
+    /// Echos of `viewer` and the users they follow, as far as `viewer` may see them and their
+    /// authors are not in `hidden_user_ids`
+    pub async fn query_home_echo(
+        &mut self,
+        viewer: &User,
+        hidden_user_ids: &[i64],
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<Echo>> {
+        let is_admin = viewer.role == Role::Admin;
+        let viewer_pm_ids = serde_json::to_string(&viewer.permission_ids)?;
+        let hidden_user_ids = serde_json::to_string(hidden_user_ids)?;
+        page.query_page_ctx(|pq| async move {
+            let rows = query_as!(
+                EchoFullViewRaw,
+                r#"
+                    SELECT
+                      e.id,
+                      e.user_id,
+                      e.content,
+                      e.fav_count,
+                      e.is_private AS "is_private: bool",
+                      e.created_at AS "created_at: OffsetDateTime",
+                      e.last_modified_at AS "last_modified_at: OffsetDateTime",
+                      COALESCE((
+                        SELECT json_group_array(ep.permission_id)
+                        FROM echo_permissions AS ep
+                        WHERE ep.echo_id = e.id
+                          AND ep.permission_id IS NOT NULL
+                        ORDER BY ep.permission_id
+                      ), json('[]')) AS "permission_ids: Json<Vec<i64>>"
+                    FROM echos AS e
+                    WHERE (
+                        e.user_id = ?1
+                        OR e.user_id IN (SELECT f.followee_id FROM user_follows AS f WHERE f.follower_id = ?1)
+                      )
+                      AND (
+                        (e.is_private = 1 AND (e.user_id = ?1 OR ?2))
+                        OR (e.is_private = 0 AND NOT EXISTS (
+                          SELECT 1
+                          FROM echo_permissions AS ep
+                          WHERE ep.echo_id = e.id
+                            AND ep.permission_id NOT IN (SELECT value FROM json_each(?3))
+                        ))
+                      )
+                      AND e.user_id NOT IN (SELECT value FROM json_each(?6))
+                      AND e.id > ?4
+                    ORDER BY e.id
+                    LIMIT ?5;
+                "#,
+                viewer.id,
+                is_admin,
+                viewer_pm_ids,
+                pq.start_after,
+                pq.limit,
+                hidden_user_ids,
+            )
+            .fetch_all(&mut *self.inner)
+            .await?;
+            let items = rows.into_iter().map(Into::into).collect();
+            Ok(items)
+        })
+        .await
+    }
+
+    /// Echos visible to `viewer` which were posted on `month_day` (`MM-DD`) of any year before `year`,
+    /// both evaluated in the local time described by `offset_modifier` (e.g. `+480 minutes`).
+    /// Authors in `hidden_user_ids` are left out.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn query_echo_on_this_day(
+        &mut self,
+        viewer: &User,
+        user_id: Option<i64>,
+        hidden_user_ids: &[i64],
+        month_day: &str,
+        year: &str,
+        offset_modifier: &str,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<Echo>> {
+        let is_admin = viewer.role == Role::Admin;
+        let viewer_pm_ids = serde_json::to_string(&viewer.permission_ids)?;
+        let hidden_user_ids = serde_json::to_string(hidden_user_ids)?;
+        page.query_page_ctx(|pq| async move {
+            let rows = query_as!(
+                EchoFullViewRaw,
+                r#"
+                    SELECT
+                      e.id,
+                      e.user_id,
+                      e.content,
+                      e.fav_count,
+                      e.is_private AS "is_private: bool",
+                      e.created_at AS "created_at: OffsetDateTime",
+                      e.last_modified_at AS "last_modified_at: OffsetDateTime",
+                      COALESCE((
+                        SELECT json_group_array(ep.permission_id)
+                        FROM echo_permissions AS ep
+                        WHERE ep.echo_id = e.id
+                          AND ep.permission_id IS NOT NULL
+                        ORDER BY ep.permission_id
+                      ), json('[]')) AS "permission_ids: Json<Vec<i64>>"
+                    FROM echos AS e
+                    WHERE (?1 IS NULL OR e.user_id = ?1)
+                      AND strftime('%m-%d', e.created_at, 'unixepoch', ?2) = ?3
+                      AND strftime('%Y', e.created_at, 'unixepoch', ?2) < ?4
+                      AND (
+                        (e.is_private = 1 AND (e.user_id = ?5 OR ?6))
+                        OR (e.is_private = 0 AND NOT EXISTS (
+                          SELECT 1
+                          FROM echo_permissions AS ep
+                          WHERE ep.echo_id = e.id
+                            AND ep.permission_id NOT IN (SELECT value FROM json_each(?7))
+                        ))
+                      )
+                      AND e.user_id NOT IN (SELECT value FROM json_each(?10))
+                      AND e.id > ?8
+                    ORDER BY e.id
+                    LIMIT ?9;
+                "#,
+                user_id,
+                offset_modifier,
+                month_day,
+                year,
+                viewer.id,
+                is_admin,
+                viewer_pm_ids,
+                pq.start_after,
+                pq.limit,
+                hidden_user_ids,
+            )
+            .fetch_all(&mut *self.inner)
+            .await?;
+            let items = rows.into_iter().map(Into::into).collect();
+            Ok(items)
+        })
+        .await
+    }
+
+    /// Per-day count of echos visible to `viewer` between `from` and `to` (inclusive, `YYYY-MM-DD`),
+    /// days are evaluated in the local time described by `offset_modifier`.
+    /// Authors in `hidden_user_ids` are left out.
+    pub async fn query_echo_calendar(
+        &mut self,
+        viewer: &User,
+        user_id: Option<i64>,
+        hidden_user_ids: &[i64],
+        from: &str,
+        to: &str,
+        offset_modifier: &str,
+    ) -> DataBaseResult<Vec<EchoCalendarDay>> {
+        let is_admin = viewer.role == Role::Admin;
+        let viewer_pm_ids = serde_json::to_string(&viewer.permission_ids)?;
+        let hidden_user_ids = serde_json::to_string(hidden_user_ids)?;
+        let rows = query_as!(
+            EchoCalendarDay,
+            r#"
+                SELECT
+                  date(e.created_at, 'unixepoch', ?2) AS "date!: String",
+                  COUNT(*) AS "count!: i64"
+                FROM echos AS e
+                WHERE (?1 IS NULL OR e.user_id = ?1)
+                  AND date(e.created_at, 'unixepoch', ?2) BETWEEN ?3 AND ?4
+                  AND (
+                    (e.is_private = 1 AND (e.user_id = ?5 OR ?6))
+                    OR (e.is_private = 0 AND NOT EXISTS (
+                      SELECT 1
+                      FROM echo_permissions AS ep
+                      WHERE ep.echo_id = e.id
+                        AND ep.permission_id NOT IN (SELECT value FROM json_each(?7))
+                    ))
+                  )
+                  AND e.user_id NOT IN (SELECT value FROM json_each(?8))
+                GROUP BY 1
+                ORDER BY 1;
+            "#,
+            user_id,
+            offset_modifier,
+            from,
+            to,
+            viewer.id,
+            is_admin,
+            viewer_pm_ids,
+            hidden_user_ids,
+        )
+        .fetch_all(&mut *self.inner)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Latest echos which are visible to everyone, i.e. neither private nor gated by permissions.
+    pub async fn query_public_echo_feed(
+        &mut self,
+        user_id: Option<i64>,
+        before: Option<i64>,
+        limit: u32,
+    ) -> DataBaseResult<Vec<Echo>> {
+        let rows = query_as!(
+            EchoFullViewRaw,
+            r#"
+                SELECT
+                  e.id,
+                  e.user_id,
+                  e.content,
+                  e.fav_count,
+                  e.is_private AS "is_private: bool",
+                  e.created_at AS "created_at: OffsetDateTime",
+                  e.last_modified_at AS "last_modified_at: OffsetDateTime",
+                  json('[]') AS "permission_ids: Json<Vec<i64>>"
+                FROM echos AS e
+                WHERE (?1 IS NULL OR e.user_id = ?1)
+                  AND (?2 IS NULL OR e.id < ?2)
+                  AND e.is_private = 0
+                  AND NOT EXISTS (SELECT 1 FROM echo_permissions AS ep WHERE ep.echo_id = e.id)
+                ORDER BY e.id DESC
+                LIMIT ?3;
+            "#,
+            user_id,
+            before,
+            limit,
+        )
+        .fetch_all(&mut *self.inner)
+        .await?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
 }
@@ -0,0 +1,48 @@
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
+use sqlx::{Executor, Sqlite, query, query_scalar};
+
+pub struct EchoImportRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> EchoImportRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    /// Echo id of an item imported by a previous run
+    pub async fn get_imported_echo_id(
+        &mut self,
+        source: &str,
+        source_id: &str,
+    ) -> DataBaseResult<Option<i64>> {
+        query_scalar!(
+            "SELECT echo_id FROM echo_imports WHERE source = ? AND source_id = ?",
+            source,
+            source_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn record_import(
+        &mut self,
+        source: &str,
+        source_id: &str,
+        echo_id: i64,
+    ) -> DataBaseResult<()> {
+        query!(
+            "INSERT INTO echo_imports (source, source_id, echo_id) VALUES (?, ?, ?)",
+            source,
+            source_id,
+            echo_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+}
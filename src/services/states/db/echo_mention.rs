@@ -0,0 +1,50 @@
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
+use sqlx::{Executor, Sqlite, query, query_scalar};
+
+pub struct EchoMentionRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> EchoMentionRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    /// Replace the mentions of an echo with `user_ids`, returns the users which were not
+    /// mentioned before, so an edit does not notify the same user twice
+    pub async fn replace_mentions(
+        &mut self,
+        echo_id: i64,
+        user_ids: &[i64],
+    ) -> DataBaseResult<Vec<i64>> {
+        let ids_json = serde_json::to_string(user_ids)?;
+        query!(
+            r#"
+                DELETE FROM echo_mentions
+                WHERE echo_id = ?
+                  AND user_id NOT IN (SELECT CAST(value AS INTEGER) FROM json_each(?))
+            "#,
+            echo_id,
+            ids_json
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        // `WHERE true` keeps the upsert clause from being parsed as a join constraint
+        query_scalar!(
+            r#"
+                INSERT INTO echo_mentions (echo_id, user_id)
+                SELECT ?, CAST(value AS INTEGER) FROM json_each(?) WHERE true
+                ON CONFLICT DO NOTHING
+                RETURNING user_id
+            "#,
+            echo_id,
+            ids_json
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+}
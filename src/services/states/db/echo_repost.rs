@@ -0,0 +1,116 @@
+use crate::models::echo::{EchoRepostCount, EchoRepostRow};
+use crate::models::users::{Role, User};
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
+use sqlx::{Executor, Sqlite, query, query_as};
+
+pub struct EchoRepostRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> EchoRepostRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub async fn add_repost(&mut self, echo_id: i64, original_echo_id: i64) -> DataBaseResult<()> {
+        query!(
+            "INSERT INTO echo_reposts (echo_id, original_echo_id) VALUES (?, ?)",
+            echo_id,
+            original_echo_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    /// Repost links of the given echos, echos which are not reposts are left out
+    pub async fn get_reposts_by_echo_ids(
+        &mut self,
+        echo_ids: &[i64],
+    ) -> DataBaseResult<Vec<EchoRepostRow>> {
+        if echo_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids_json = serde_json::to_string(echo_ids)?;
+        query_as!(
+            EchoRepostRow,
+            r#"
+                SELECT
+                  r.echo_id,
+                  r.original_echo_id
+                FROM echo_reposts AS r
+                WHERE r.echo_id IN (SELECT CAST(value AS INTEGER) FROM json_each(?))
+            "#,
+            ids_json
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Only reposts `viewer` may see are counted, authors in `hidden_user_ids` are left out
+    pub async fn count_visible_reposts(
+        &mut self,
+        viewer: &User,
+        hidden_user_ids: &[i64],
+        echo_ids: &[i64],
+    ) -> DataBaseResult<Vec<EchoRepostCount>> {
+        if echo_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let ids_json = serde_json::to_string(echo_ids)?;
+        let is_admin = viewer.role == Role::Admin;
+        let viewer_pm_ids = serde_json::to_string(&viewer.permission_ids)?;
+        let hidden_user_ids = serde_json::to_string(hidden_user_ids)?;
+        query_as!(
+            EchoRepostCount,
+            r#"
+                SELECT
+                  r.original_echo_id AS "echo_id!: i64",
+                  COUNT(*) AS "count!: i64"
+                FROM echo_reposts AS r
+                JOIN echos AS e ON e.id = r.echo_id
+                WHERE r.original_echo_id IN (SELECT CAST(value AS INTEGER) FROM json_each(?1))
+                  AND (
+                    (e.is_private = 1 AND (e.user_id = ?2 OR ?3))
+                    OR (e.is_private = 0 AND NOT EXISTS (
+                      SELECT 1
+                      FROM echo_permissions AS ep
+                      WHERE ep.echo_id = e.id
+                        AND ep.permission_id NOT IN (SELECT value FROM json_each(?4))
+                    ))
+                  )
+                  AND e.user_id NOT IN (SELECT value FROM json_each(?5))
+                GROUP BY r.original_echo_id
+            "#,
+            ids_json,
+            viewer.id,
+            is_admin,
+            viewer_pm_ids,
+            hidden_user_ids,
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Reposts without a comment have nothing left to show once the original is gone,
+    /// so they go together with it. Commented ones keep their comment and lose the link.
+    pub async fn delete_bare_reposts(&mut self, original_echo_id: i64) -> DataBaseResult<()> {
+        query!(
+            r#"
+                DELETE FROM echos
+                WHERE content = ''
+                  AND id IN (SELECT echo_id FROM echo_reposts WHERE original_echo_id = ?)
+            "#,
+            original_echo_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+}
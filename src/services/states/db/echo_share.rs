@@ -0,0 +1,164 @@
+use crate::models::echo_share::EchoShareRow;
+use crate::services::states::db::{
+    DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt, SqliteQueryResultExt,
+};
+use sqlx::types::Json;
+use sqlx::{Executor, Sqlite, query, query_as};
+use time::OffsetDateTime;
+
+pub struct EchoShareRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> EchoShareRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub async fn add_share(
+        &mut self,
+        echo_id: i64,
+        owner_id: i64,
+        permission_ids: &[i64],
+        one_time: bool,
+        expires_at: OffsetDateTime,
+    ) -> DataBaseResult<EchoShareRow> {
+        let permission_ids = serde_json::to_string(permission_ids)?;
+        let expires_at = expires_at.unix_timestamp();
+        query_as!(
+            EchoShareRow,
+            r#"
+                INSERT INTO echo_share_links (echo_id, owner_id, permission_ids, one_time, expires_at)
+                VALUES (?, ?, ?, ?, ?)
+                RETURNING
+                  id AS "id!",
+                  echo_id,
+                  owner_id,
+                  permission_ids AS "permission_ids: Json<Vec<i64>>",
+                  one_time AS "one_time: bool",
+                  used_at AS "used_at: OffsetDateTime",
+                  expires_at AS "expires_at: OffsetDateTime",
+                  created_at AS "created_at: OffsetDateTime"
+            "#,
+            echo_id,
+            owner_id,
+            permission_ids,
+            one_time,
+            expires_at
+        )
+        .fetch_one(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Mark the link as opened and return it, `None` if it is gone, expired or
+    /// a one-time link which has been opened before
+    pub async fn redeem_share(
+        &mut self,
+        share_id: i64,
+        now: OffsetDateTime,
+    ) -> DataBaseResult<Option<EchoShareRow>> {
+        let now = now.unix_timestamp();
+        query_as!(
+            EchoShareRow,
+            r#"
+                UPDATE echo_share_links
+                SET used_at = COALESCE(used_at, ?1)
+                WHERE id = ?2 AND expires_at > ?1 AND (one_time = 0 OR used_at IS NULL)
+                RETURNING
+                  id AS "id!",
+                  echo_id,
+                  owner_id,
+                  permission_ids AS "permission_ids: Json<Vec<i64>>",
+                  one_time AS "one_time: bool",
+                  used_at AS "used_at: OffsetDateTime",
+                  expires_at AS "expires_at: OffsetDateTime",
+                  created_at AS "created_at: OffsetDateTime"
+            "#,
+            now,
+            share_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// The link if it can still be opened, without marking it as opened
+    pub async fn get_available_share(
+        &mut self,
+        share_id: i64,
+        now: OffsetDateTime,
+    ) -> DataBaseResult<Option<EchoShareRow>> {
+        let now = now.unix_timestamp();
+        query_as!(
+            EchoShareRow,
+            r#"
+                SELECT
+                  id AS "id!",
+                  echo_id,
+                  owner_id,
+                  permission_ids AS "permission_ids: Json<Vec<i64>>",
+                  one_time AS "one_time: bool",
+                  used_at AS "used_at: OffsetDateTime",
+                  expires_at AS "expires_at: OffsetDateTime",
+                  created_at AS "created_at: OffsetDateTime"
+                FROM echo_share_links
+                WHERE id = ?2 AND expires_at > ?1 AND (one_time = 0 OR used_at IS NULL)
+            "#,
+            now,
+            share_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn list_shares_page(
+        &mut self,
+        owner_id: i64,
+        echo_id: Option<i64>,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<EchoShareRow>> {
+        page.query_page_ctx(|pq| async move {
+            query_as!(
+                EchoShareRow,
+                r#"
+                    SELECT
+                      id AS "id!",
+                      echo_id,
+                      owner_id,
+                      permission_ids AS "permission_ids: Json<Vec<i64>>",
+                      one_time AS "one_time: bool",
+                      used_at AS "used_at: OffsetDateTime",
+                      expires_at AS "expires_at: OffsetDateTime",
+                      created_at AS "created_at: OffsetDateTime"
+                    FROM echo_share_links
+                    WHERE owner_id = ?1 AND (?2 IS NULL OR echo_id = ?2) AND id > ?3
+                    ORDER BY id
+                    LIMIT ?4
+                "#,
+                owner_id,
+                echo_id,
+                pq.start_after,
+                pq.limit,
+            )
+            .fetch_all(&mut *self.inner)
+            .await
+        })
+        .await
+    }
+
+    pub async fn revoke_share(&mut self, owner_id: i64, share_id: i64) -> DataBaseResult<()> {
+        query!(
+            "DELETE FROM echo_share_links WHERE id = ? AND owner_id = ?",
+            share_id,
+            owner_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve_affected()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use crate::models::follow::FollowItem;
+use crate::services::states::db::{
+    DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt,
+};
+use sqlx::{Executor, Sqlite, query, query_as};
+use time::OffsetDateTime;
+
+pub struct FollowRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> FollowRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    /// Returns `false` if `follower_id` already follows `followee_id`
+    pub async fn follow(&mut self, follower_id: i64, followee_id: i64) -> DataBaseResult<bool> {
+        query!(
+            r#"
+                INSERT INTO user_follows (follower_id, followee_id)
+                VALUES (?, ?)
+                ON CONFLICT (follower_id, followee_id) DO NOTHING
+            "#,
+            follower_id,
+            followee_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|res| res.rows_affected() > 0)
+    }
+
+    /// Returns `false` if `follower_id` did not follow `followee_id`
+    pub async fn unfollow(&mut self, follower_id: i64, followee_id: i64) -> DataBaseResult<bool> {
+        query!(
+            r#"
+                DELETE FROM user_follows
+                WHERE follower_id = ? AND followee_id = ?
+            "#,
+            follower_id,
+            followee_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|res| res.rows_affected() > 0)
+    }
+
+    /// Drop the follows between the two users, in either direction
+    pub async fn remove_follows_between(
+        &mut self,
+        user_id: i64,
+        other_id: i64,
+    ) -> DataBaseResult<()> {
+        query!(
+            r#"
+                DELETE FROM user_follows
+                WHERE (follower_id = ?1 AND followee_id = ?2)
+                   OR (follower_id = ?2 AND followee_id = ?1)
+            "#,
+            user_id,
+            other_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    /// Users followed by `user_id`, in the order they were followed
+    pub async fn list_following(
+        &mut self,
+        user_id: i64,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<FollowItem>> {
+        page.query_page_ctx(|pq| async move {
+            query_as!(
+                FollowItem,
+                r#"
+                    SELECT
+                      f.id AS "id!",
+                      u.id AS "user_id!",
+                      u.username,
+                      u.avatar_res_id,
+                      f.created_at AS "created_at: OffsetDateTime"
+                    FROM user_follows AS f
+                    JOIN users AS u ON u.id = f.followee_id
+                    WHERE f.follower_id = ?1 AND f.id > ?2
+                    ORDER BY f.id
+                    LIMIT ?3
+                "#,
+                user_id,
+                pq.start_after,
+                pq.limit,
+            )
+            .fetch_all(&mut *self.inner)
+            .await
+        })
+        .await
+    }
+
+    /// Users following `user_id`, in the order they followed
+    pub async fn list_followers(
+        &mut self,
+        user_id: i64,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<FollowItem>> {
+        page.query_page_ctx(|pq| async move {
+            query_as!(
+                FollowItem,
+                r#"
+                    SELECT
+                      f.id AS "id!",
+                      u.id AS "user_id!",
+                      u.username,
+                      u.avatar_res_id,
+                      f.created_at AS "created_at: OffsetDateTime"
+                    FROM user_follows AS f
+                    JOIN users AS u ON u.id = f.follower_id
+                    WHERE f.followee_id = ?1 AND f.id > ?2
+                    ORDER BY f.id
+                    LIMIT ?3
+                "#,
+                user_id,
+                pq.start_after,
+                pq.limit,
+            )
+            .fetch_all(&mut *self.inner)
+            .await
+        })
+        .await
+    }
+}
@@ -1,7 +1,9 @@
 use crate::models::mfa::{
-    MFAAuthMethod, MFAOpType, MfaAuthLog, MfaInfo, MfaSettings, NewMfaAuthLog, NewMfaAuthLogInfo,
-    NewTotpCredential, NewWebauthnCredential, TotpCredential, WebauthnCredential,
+    MFAAuthMethod, MFAOpType, MfaAuthLog, MfaInfo, MfaNonCompliantUser, MfaPolicy, MfaRecoveryCode,
+    MfaRequirement, MfaSettings, NewMfaAuthLog, NewMfaAuthLogInfo, NewTotpCredential,
+    NewWebauthnCredential, TotpCredential, WebauthnCredential,
 };
+use crate::models::users::Role;
 use crate::services::states::db::{
     DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt, SqliteQueryResultExt,
 };
@@ -49,6 +51,7 @@ where
                 SELECT
                     user_id,
                     mfa_enabled AS "mfa_enabled: bool",
+                    passkey_login AS "passkey_login: bool",
                     updated_at AS "updated_at: OffsetDateTime"
                 FROM mfa_infos
                 WHERE user_id = ?
@@ -88,6 +91,152 @@ where
         Ok(())
     }
 
+    pub async fn passkey_login_enabled(&mut self, user_id: i64) -> DataBaseResult<bool> {
+        query_scalar!(
+            // language=sql
+            "SELECT passkey_login AS 'passkey_login: bool' FROM mfa_infos WHERE user_id = ?",
+            user_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|opt| opt.unwrap_or(false))
+    }
+
+    pub async fn set_passkey_login(&mut self, user_id: i64, enabled: bool) -> DataBaseResult<()> {
+        query!(
+            // language=sql
+            r#"
+                INSERT INTO mfa_infos (user_id, passkey_login, updated_at)
+                VALUES (?, ?, strftime('%s','now'))
+                ON CONFLICT(user_id) DO UPDATE SET
+                    passkey_login = excluded.passkey_login,
+                    updated_at = excluded.updated_at
+            "#,
+            user_id,
+            enabled
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    /// Whether `policy` requires MFA from the user, `None` if there is no such user
+    pub async fn get_mfa_requirement(
+        &mut self,
+        user_id: i64,
+        policy: &MfaPolicy,
+    ) -> DataBaseResult<Option<MfaRequirement>> {
+        let permission_ids = serde_json::to_string(&policy.require_for_permissions)?;
+        query_as!(
+            MfaRequirement,
+            // language=sql
+            r#"
+                SELECT
+                    COALESCE(mi.mfa_enabled, 0) AS "mfa_enabled!: bool",
+                    (
+                        ?2
+                        OR (u.role = ?3 AND (?4 OR json_array_length(?5) > 0))
+                        OR EXISTS (
+                            SELECT 1
+                            FROM user_permissions AS up
+                            WHERE up.user_id = u.id
+                              AND up.active = 1
+                              AND up.permission_id IN (SELECT value FROM json_each(?5))
+                        )
+                    ) AS "required!: bool",
+                    mi.mfa_required_since AS "required_since?: OffsetDateTime"
+                FROM users AS u
+                LEFT JOIN mfa_infos AS mi ON mi.user_id = u.id
+                WHERE u.id = ?1
+            "#,
+            user_id,
+            policy.require_for_all,
+            Role::Admin,
+            policy.require_for_admins,
+            permission_ids,
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn set_mfa_required_since(
+        &mut self,
+        user_id: i64,
+        since: Option<OffsetDateTime>,
+    ) -> DataBaseResult<()> {
+        let since = since.map(|t| t.unix_timestamp());
+        query!(
+            // language=sql
+            r#"
+                INSERT INTO mfa_infos (user_id, mfa_required_since, updated_at)
+                VALUES (?, ?, strftime('%s','now'))
+                ON CONFLICT(user_id) DO UPDATE SET
+                    mfa_required_since = excluded.mfa_required_since,
+                    updated_at = excluded.updated_at
+            "#,
+            user_id,
+            since
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    /// Users `policy` requires MFA from who have not set it up
+    pub async fn get_non_compliant_users_page(
+        &mut self,
+        policy: &MfaPolicy,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<MfaNonCompliantUser>> {
+        let permission_ids = serde_json::to_string(&policy.require_for_permissions)?;
+        let grace_secs = policy.grace.whole_seconds();
+        page.query_page_ctx(|pq| async move {
+            query_as!(
+                MfaNonCompliantUser,
+                // language=sql
+                r#"
+                    SELECT
+                        u.id AS "user_id!",
+                        u.username,
+                        u.role AS "role: Role",
+                        mi.mfa_required_since AS "required_since?: OffsetDateTime",
+                        mi.mfa_required_since + ?6 AS "setup_deadline?: OffsetDateTime"
+                    FROM users AS u
+                    LEFT JOIN mfa_infos AS mi ON mi.user_id = u.id
+                    WHERE u.id > ?7
+                      AND COALESCE(mi.mfa_enabled, 0) = 0
+                      AND (
+                        ?2
+                        OR (u.role = ?3 AND (?4 OR json_array_length(?5) > 0))
+                        OR EXISTS (
+                            SELECT 1
+                            FROM user_permissions AS up
+                            WHERE up.user_id = u.id
+                              AND up.active = 1
+                              AND up.permission_id IN (SELECT value FROM json_each(?5))
+                        )
+                      )
+                    ORDER BY u.id
+                    LIMIT ?1
+                "#,
+                pq.limit,
+                policy.require_for_all,
+                Role::Admin,
+                policy.require_for_admins,
+                permission_ids,
+                grace_secs,
+                pq.start_after,
+            )
+            .fetch_all(&mut *self.inner)
+            .await
+        })
+        .await
+    }
+
     pub async fn insert_mfa_op_access_log(
         &mut self,
         user_id: i64,
@@ -138,6 +287,66 @@ where
         .await
     }
 
+    /// Failed attempts since `since` that were not followed by a success or a lockout
+    pub async fn count_recent_auth_failures(
+        &mut self,
+        user_id: i64,
+        auth_method: MFAAuthMethod,
+        since: OffsetDateTime,
+    ) -> DataBaseResult<i64> {
+        let since = since.unix_timestamp();
+        query_scalar!(
+            // language=sql
+            r#"
+                SELECT COUNT(1)
+                FROM mfa_op_logs
+                WHERE user_id = ?1
+                AND auth_method = ?2
+                AND op_type = ?3
+                AND is_success = 0
+                AND time >= ?5
+                AND id > COALESCE((
+                    SELECT MAX(id)
+                    FROM mfa_op_logs
+                    WHERE user_id = ?1
+                    AND auth_method = ?2
+                    AND (op_type = ?4 OR (op_type = ?3 AND is_success = 1))
+                ), 0)
+            "#,
+            user_id,
+            auth_method,
+            MFAOpType::Auth,
+            MFAOpType::Lockout,
+            since,
+        )
+        .fetch_one(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn get_last_lockout(
+        &mut self,
+        user_id: i64,
+        auth_method: MFAAuthMethod,
+    ) -> DataBaseResult<Option<OffsetDateTime>> {
+        query_scalar!(
+            // language=sql
+            r#"
+                SELECT time AS "time: OffsetDateTime"
+                FROM mfa_op_logs
+                WHERE user_id = ? AND auth_method = ? AND op_type = ?
+                ORDER BY id DESC
+                LIMIT 1
+            "#,
+            user_id,
+            auth_method,
+            MFAOpType::Lockout,
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
     pub async fn insert_totp_credential(
         &mut self,
         credential: NewTotpCredential,
@@ -145,9 +354,14 @@ where
         user_agent: Option<String>,
     ) -> DataBaseResult<i64> {
         let credential_id = query!(
-            "INSERT INTO totp_credentials (user_id, totp_credential_data) VALUES (?, ?)",
+            r#"
+                INSERT INTO totp_credentials (user_id, label, totp_credential_data, last_time_step)
+                VALUES (?, ?, ?, ?)
+            "#,
             credential.user_id,
-            credential.totp_credential_data
+            credential.label,
+            credential.totp_credential_data,
+            credential.last_time_step
         )
         .execute(&mut *self.inner)
         .await
@@ -171,71 +385,129 @@ where
 
     pub async fn delete_totp_credential(
         &mut self,
-        user_id: i64,
+        credential_id: i64,
         ip_address: Option<String>,
         user_agent: Option<String>,
     ) -> DataBaseResult<()> {
-        let credential_id = query_scalar!(
+        let user_id = query_scalar!(
             // language=sql
-            "SELECT id FROM totp_credentials WHERE user_id = ?",
-            user_id
+            "SELECT user_id FROM totp_credentials WHERE id = ?",
+            credential_id
         )
         .fetch_optional(&mut *self.inner)
         .await
         .resolve()?;
-        query!("DELETE FROM totp_credentials WHERE user_id = ?", user_id)
-            .execute(&mut *self.inner)
-            .await
-            .resolve_affected()?;
-        self.insert_mfa_op_log_with_ctx(NewMfaAuthLog {
-            user_id,
-            op_type: MFAOpType::Delete,
-            info: NewMfaAuthLogInfo {
-                auth_method: MFAAuthMethod::Totp,
-                is_success: true,
-                ip_address,
-                user_agent,
-                credential_id,
-                error_message: None,
-            },
-        })
-        .await?;
+        if let Some(user_id) = user_id {
+            query!("DELETE FROM totp_credentials WHERE id = ?", credential_id)
+                .execute(&mut *self.inner)
+                .await
+                .resolve_affected()?;
+            self.insert_mfa_op_log_with_ctx(NewMfaAuthLog {
+                user_id,
+                op_type: MFAOpType::Delete,
+                info: NewMfaAuthLogInfo {
+                    auth_method: MFAAuthMethod::Totp,
+                    is_success: true,
+                    ip_address,
+                    user_agent,
+                    credential_id: Some(credential_id),
+                    error_message: None,
+                },
+            })
+            .await?;
+        }
         Ok(())
     }
 
-    pub async fn list_user_totp_credential(
+    pub async fn list_user_totp_credentials(
         &mut self,
         user_id: i64,
-    ) -> DataBaseResult<Option<TotpCredential>> {
+    ) -> DataBaseResult<Vec<TotpCredential>> {
         query_as!(
             TotpCredential,
             r#"
                 SELECT
                     id,
                     user_id,
+                    label,
                     totp_credential_data,
                     created_at AS "created_at: OffsetDateTime",
                     updated_at AS "updated_at: OffsetDateTime",
-                    last_used_at AS "last_used_at?: OffsetDateTime"
+                    last_used_at AS "last_used_at?: OffsetDateTime",
+                    last_time_step
                 FROM totp_credentials
                 WHERE user_id = ?
+                ORDER BY created_at DESC
             "#,
             user_id
         )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn get_totp_credential_by_id(
+        &mut self,
+        credential_id: i64,
+    ) -> DataBaseResult<Option<TotpCredential>> {
+        query_as!(
+            TotpCredential,
+            r#"
+                SELECT
+                    id,
+                    user_id,
+                    label,
+                    totp_credential_data,
+                    created_at AS "created_at: OffsetDateTime",
+                    updated_at AS "updated_at: OffsetDateTime",
+                    last_used_at AS "last_used_at?: OffsetDateTime",
+                    last_time_step
+                FROM totp_credentials
+                WHERE id = ?
+            "#,
+            credential_id
+        )
         .fetch_optional(&mut *self.inner)
         .await
         .resolve()
     }
 
-    pub async fn update_totp_last_used(&mut self, user_id: i64) -> DataBaseResult<()> {
+    pub async fn rename_totp_credential(
+        &mut self,
+        credential_id: i64,
+        label: &str,
+    ) -> DataBaseResult<()> {
         query!(
             r#"
                 UPDATE totp_credentials
-                SET last_used_at = strftime('%s', 'now'),
+                SET label = ?,
                     updated_at = strftime('%s', 'now')
-                WHERE user_id = ?
+                WHERE id = ?
             "#,
-            user_id
+            label,
+            credential_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve_affected()?;
+        Ok(())
+    }
+
+    pub async fn update_totp_last_used(
+        &mut self,
+        credential_id: i64,
+        time_step: i64,
+    ) -> DataBaseResult<()> {
+        query!(
+            r#"
+                UPDATE totp_credentials
+                SET last_used_at = strftime('%s', 'now'),
+                    updated_at = strftime('%s', 'now'),
+                    last_time_step = ?
+                WHERE id = ?
+            "#,
+            time_step,
+            credential_id
         )
         .execute(&mut *self.inner)
         .await
@@ -243,6 +515,97 @@ where
         Ok(())
     }
 
+    /// Invalidates all previous codes of the user
+    pub async fn replace_recovery_codes(
+        &mut self,
+        user_id: i64,
+        code_hashes: &[String],
+        ip_address: Option<String>,
+        user_agent: Option<String>,
+    ) -> DataBaseResult<()> {
+        query!("DELETE FROM mfa_recovery_codes WHERE user_id = ?", user_id)
+            .execute(&mut *self.inner)
+            .await
+            .resolve()?;
+        for code_hash in code_hashes {
+            query!(
+                "INSERT INTO mfa_recovery_codes (user_id, code_hash) VALUES (?, ?)",
+                user_id,
+                code_hash
+            )
+            .execute(&mut *self.inner)
+            .await
+            .resolve()?;
+        }
+        self.insert_mfa_op_log_with_ctx(NewMfaAuthLog {
+            user_id,
+            op_type: MFAOpType::Add,
+            info: NewMfaAuthLogInfo {
+                auth_method: MFAAuthMethod::RecoveryCode,
+                is_success: true,
+                ip_address,
+                user_agent,
+                credential_id: None,
+                error_message: None,
+            },
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn list_unused_recovery_codes(
+        &mut self,
+        user_id: i64,
+    ) -> DataBaseResult<Vec<MfaRecoveryCode>> {
+        query_as!(
+            MfaRecoveryCode,
+            // language=sql
+            r#"
+                SELECT id AS "id!", code_hash
+                FROM mfa_recovery_codes
+                WHERE user_id = ? AND used_at IS NULL
+                ORDER BY id
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Marks the code as used, returns its id if it was unused
+    pub async fn use_recovery_code(
+        &mut self,
+        user_id: i64,
+        code_id: i64,
+    ) -> DataBaseResult<Option<i64>> {
+        query_scalar!(
+            // language=sql
+            r#"
+                UPDATE mfa_recovery_codes
+                SET used_at = strftime('%s', 'now')
+                WHERE user_id = ? AND id = ? AND used_at IS NULL
+                RETURNING id AS "id!"
+            "#,
+            user_id,
+            code_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn count_recovery_codes_left(&mut self, user_id: i64) -> DataBaseResult<i64> {
+        query_scalar!(
+            // language=sql
+            "SELECT COUNT(1) FROM mfa_recovery_codes WHERE user_id = ? AND used_at IS NULL",
+            user_id
+        )
+        .fetch_one(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
     pub async fn insert_webauthn_credential(
         &mut self,
         credential: NewWebauthnCredential,
@@ -252,14 +615,15 @@ where
         let credential_id = query!(
             r#"
                 INSERT INTO webauthn_credentials
-                (user_id, user_unique_uuid, user_name, user_display_name, credential_data)
-                VALUES (?, ?, ?, ?, ?)
+                (user_id, user_unique_uuid, user_name, user_display_name, credential_data, discoverable)
+                VALUES (?, ?, ?, ?, ?, ?)
             "#,
             credential.user_id,
             credential.user_unique_uuid,
             credential.user_name,
             credential.user_display_name,
-            credential.credential_data
+            credential.credential_data,
+            credential.discoverable
         )
         .execute(&mut *self.inner)
         .await
@@ -334,6 +698,7 @@ where
                     user_name,
                     user_display_name,
                     credential_data,
+                    discoverable AS "discoverable?: bool",
                     created_at AS "created_at: OffsetDateTime",
                     updated_at AS "updated_at: OffsetDateTime",
                     last_used_at AS "last_used_at?: OffsetDateTime"
@@ -348,6 +713,21 @@ where
         .resolve()
     }
 
+    /// Resolves the user handle a discoverable credential was registered with
+    pub async fn get_user_id_by_webauthn_handle(
+        &mut self,
+        user_unique_uuid: Uuid,
+    ) -> DataBaseResult<Option<i64>> {
+        query_scalar!(
+            // language=sql
+            "SELECT user_id FROM webauthn_credentials WHERE user_unique_uuid = ? LIMIT 1",
+            user_unique_uuid
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
     pub async fn get_webauthn_credential_by_id(
         &mut self,
         credential_id: i64,
@@ -362,6 +742,7 @@ where
                     user_name,
                     user_display_name,
                     credential_data,
+                    discoverable AS "discoverable?: bool",
                     created_at AS "created_at: OffsetDateTime",
                     updated_at AS "updated_at: OffsetDateTime",
                     last_used_at AS "last_used_at?: OffsetDateTime"
@@ -375,6 +756,20 @@ where
         .resolve()
     }
 
+    pub async fn has_discoverable_webauthn_credential(
+        &mut self,
+        user_id: i64,
+    ) -> DataBaseResult<bool> {
+        query_scalar!(
+            // language=sql
+            r#"SELECT EXISTS(SELECT 1 FROM webauthn_credentials WHERE user_id = ? AND discoverable = 1) AS "exists: bool""#,
+            user_id
+        )
+        .fetch_one(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
     pub async fn update_webauthn_last_used(&mut self, credential_id: i64) -> DataBaseResult<()> {
         query!(
             r#"
@@ -426,18 +821,23 @@ where
         for &user_id in user_ids {
             let settings = self.get_mfa_info(user_id).await?;
             let available_methods = self.list_user_available_methods(user_id).await?;
+            let recovery_codes_left = self.count_recovery_codes_left(user_id).await?;
             let mfa_info = match settings {
                 Some(s) => MfaInfo {
                     user_id: s.user_id,
                     mfa_enabled: s.mfa_enabled,
+                    passkey_login: s.passkey_login,
                     updated_at: s.updated_at,
                     available_methods,
+                    recovery_codes_left,
                 },
                 None => MfaInfo {
                     user_id,
                     mfa_enabled: false,
+                    passkey_login: false,
                     updated_at: OffsetDateTime::now_utc(),
                     available_methods,
+                    recovery_codes_left,
                 },
             };
             res.push(mfa_info);
@@ -0,0 +1,110 @@
+use crate::models::notification::{NotificationKind, NotificationRow};
+use crate::services::states::db::{
+    DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt,
+};
+use sqlx::{Executor, Sqlite, query, query_as, query_scalar};
+use time::OffsetDateTime;
+
+pub struct NotificationRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> NotificationRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    /// Notify each of `user_ids` about the same event
+    pub async fn add_notifications(
+        &mut self,
+        user_ids: &[i64],
+        kind: NotificationKind,
+        actor_id: i64,
+        echo_id: i64,
+    ) -> DataBaseResult<()> {
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+        let ids_json = serde_json::to_string(user_ids)?;
+        query!(
+            r#"
+                INSERT INTO notifications (user_id, kind, actor_id, echo_id)
+                SELECT CAST(value AS INTEGER), ?, ?, ? FROM json_each(?)
+            "#,
+            kind,
+            actor_id,
+            echo_id,
+            ids_json
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    pub async fn list_notifications_page(
+        &mut self,
+        user_id: i64,
+        unread_only: bool,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<NotificationRow>> {
+        page.query_page_ctx(|pq| async move {
+            query_as!(
+                NotificationRow,
+                r#"
+                    SELECT
+                      n.id AS "id!",
+                      n.kind AS "kind: NotificationKind",
+                      n.actor_id,
+                      n.echo_id,
+                      n.is_read AS "is_read: bool",
+                      n.created_at AS "created_at: OffsetDateTime"
+                    FROM notifications AS n
+                    WHERE n.user_id = ?1 AND (NOT ?2 OR n.is_read = 0) AND n.id > ?3
+                    ORDER BY n.id
+                    LIMIT ?4
+                "#,
+                user_id,
+                unread_only,
+                pq.start_after,
+                pq.limit,
+            )
+            .fetch_all(&mut *self.inner)
+            .await
+        })
+        .await
+    }
+
+    pub async fn count_unread(&mut self, user_id: i64) -> DataBaseResult<i64> {
+        query_scalar!(
+            r#"SELECT COUNT(*) AS "count: i64" FROM notifications WHERE user_id = ? AND is_read = 0"#,
+            user_id
+        )
+        .fetch_one(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Mark the given notifications of the user as read, or all of them if `ids` is `None`.
+    /// Returns how many notifications were unread before.
+    pub async fn mark_read(&mut self, user_id: i64, ids: Option<&[i64]>) -> DataBaseResult<u64> {
+        let ids_json = ids.map(serde_json::to_string).transpose()?;
+        query!(
+            r#"
+                UPDATE notifications
+                SET is_read = 1
+                WHERE user_id = ?1
+                  AND is_read = 0
+                  AND (?2 IS NULL OR id IN (SELECT CAST(value AS INTEGER) FROM json_each(?2)))
+            "#,
+            user_id,
+            ids_json
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|res| res.rows_affected())
+    }
+}
@@ -0,0 +1,54 @@
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
+use sqlx::{Executor, Sqlite, query, query_scalar};
+
+pub struct OidcRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> OidcRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    /// The user linked to `subject` of `provider`, if any
+    pub async fn get_linked_user_id(
+        &mut self,
+        provider: &str,
+        subject: &str,
+    ) -> DataBaseResult<Option<i64>> {
+        query_scalar!(
+            r#"
+                SELECT user_id FROM oidc_identities
+                WHERE provider = ? AND subject = ?
+            "#,
+            provider,
+            subject
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn link_identity(
+        &mut self,
+        provider: &str,
+        subject: &str,
+        user_id: i64,
+    ) -> DataBaseResult<()> {
+        query!(
+            r#"
+                INSERT INTO oidc_identities (provider, subject, user_id)
+                VALUES (?, ?, ?)
+            "#,
+            provider,
+            subject,
+            user_id
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+}
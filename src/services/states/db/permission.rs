@@ -4,7 +4,7 @@ use crate::services::states::db::{
     DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt, SqliteQueryResultExt,
 };
 use ahash::HashSet;
-use sqlx::{Executor, Sqlite, query, query_as};
+use sqlx::{Executor, Sqlite, query, query_as, query_scalar};
 use time::OffsetDateTime;
 
 pub struct PermissionRepo<'a, E>
@@ -48,6 +48,23 @@ where
         Ok(())
     }
 
+    pub async fn check_permission_exists(&mut self, pm_ids: &[i64]) -> DataBaseResult<Option<i64>> {
+        for &id in pm_ids {
+            let exists = query_scalar!(
+                // language=sql
+                "SELECT EXISTS(SELECT 1 FROM permissions WHERE id = ?) AS 'exists: bool'",
+                id
+            )
+            .fetch_one(&mut *self.inner)
+            .await
+            .resolve()?;
+            if !exists {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+
     pub async fn delete_permission(&mut self, pm_id: i64) -> DataBaseResult<()> {
         query!("DELETE FROM permissions WHERE id = ?", pm_id)
             .execute(&mut *self.inner)
@@ -159,22 +176,7 @@ where
                 .await
             })
             .await?;
-        let items = items
-            .into_iter()
-            .map(|r| UserAssignedPermission {
-                permission: Permission {
-                    id: r.permission_id,
-                    description: r.description,
-                    color: r.color,
-                },
-                id: r.record_id,
-                exp_time: r.exp_time,
-                assigner_id: r.assigner_id,
-                assigned_at: r.assigned_at,
-                revoked_at: r.revoked_at,
-                active: r.active,
-            })
-            .collect();
+        let items = items.into_iter().map(Into::into).collect();
         Ok(PageQueryResult {
             items,
             has_more,
@@ -182,6 +184,37 @@ where
         })
     }
 
+    /// Whole grant history of a user, revoked and expired grants included
+    pub async fn get_user_permission_records(
+        &mut self,
+        user_id: i64,
+    ) -> DataBaseResult<Vec<UserAssignedPermission>> {
+        let rows = query_as!(
+            RawUserPermissionRow,
+            r#"
+                SELECT
+                    p.id AS "permission_id: _",
+                    p.description,
+                    p.color,
+                    up.id AS "record_id: _",
+                    up.assigner_id,
+                    up.assigned_at AS "assigned_at: _",
+                    up.exp_time AS "exp_time: _",
+                    up.revoked_at AS "revoked_at: _",
+                    up.active AS "active: _"
+                FROM permissions AS p
+                JOIN user_permissions AS up ON p.id = up.permission_id
+                WHERE up.user_id = ?
+                ORDER BY up.id
+            "#,
+            user_id
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(rows.into_iter().map(Into::into).collect())
+    }
+
     pub(in crate::services) async fn revoke_permissions(
         &mut self,
         user_id: i64,
@@ -1,6 +1,6 @@
 use crate::models::resource::ResourceTarget;
 use crate::models::resource::{
-    ResourceItemRawInfo, ResourceItemWithRefRaw, ResourceReferenceInner,
+    ResourceItemRaw, ResourceItemRawInfo, ResourceItemWithRefRaw, ResourceReferenceInner,
 };
 use crate::models::{Change, DiffRef};
 use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
@@ -36,6 +36,42 @@ where
         Ok(res_id)
     }
 
+    pub async fn get_resources_by_uploader(
+        &mut self,
+        uploader_id: i64,
+    ) -> DataBaseResult<Vec<ResourceItemRaw>> {
+        let rows = query!(
+            r#"
+                SELECT
+                    id,
+                    uploader_id,
+                    res_name,
+                    res_uuid AS "res_uuid: Uuid",
+                    res_ext
+                FROM resources
+                WHERE uploader_id = ?
+                ORDER BY id
+            "#,
+            uploader_id
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()?;
+        let out = rows
+            .into_iter()
+            .map(|row| ResourceItemRaw {
+                id: row.id,
+                info: ResourceItemRawInfo {
+                    uploader_id: row.uploader_id,
+                    res_name: row.res_name,
+                    res_uuid: row.res_uuid,
+                    res_ext: row.res_ext,
+                },
+            })
+            .collect();
+        Ok(out)
+    }
+
     pub(in crate::services) async fn update_resource(
         &mut self,
         ref_diff: DiffRef<'_, ResourceReferenceInner>,
@@ -0,0 +1,208 @@
+use crate::models::takeout::{TakeoutRow, TakeoutStatus};
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt, SqliteQueryResultExt};
+use sqlx::{Executor, Sqlite, query, query_as, query_scalar};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub struct TakeoutRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> TakeoutRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub async fn has_pending_takeout(&mut self, user_id: i64) -> DataBaseResult<bool> {
+        query_scalar!(
+            r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM user_takeouts WHERE user_id = ? AND status = ?
+                ) AS "exists: bool"
+            "#,
+            user_id,
+            TakeoutStatus::Pending
+        )
+        .fetch_one(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn add_takeout(&mut self, user_id: i64, file_uuid: Uuid) -> DataBaseResult<i64> {
+        let result = query!(
+            "INSERT INTO user_takeouts (user_id, status, file_uuid) VALUES (?, ?, ?)",
+            user_id,
+            TakeoutStatus::Pending,
+            file_uuid
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(result.last_insert_rowid())
+    }
+
+    pub async fn finish_takeout(
+        &mut self,
+        takeout_id: i64,
+        file_size: i64,
+        expires_at: OffsetDateTime,
+    ) -> DataBaseResult<()> {
+        let expires_at = expires_at.unix_timestamp();
+        query!(
+            r#"
+                UPDATE user_takeouts
+                SET status = ?, file_size = ?, expires_at = ?, finished_at = strftime('%s', 'now')
+                WHERE id = ? AND status = ?
+            "#,
+            TakeoutStatus::Ready,
+            file_size,
+            expires_at,
+            takeout_id,
+            TakeoutStatus::Pending
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve_affected()?;
+        Ok(())
+    }
+
+    pub async fn fail_takeout(
+        &mut self,
+        takeout_id: i64,
+        error_message: &str,
+    ) -> DataBaseResult<()> {
+        query!(
+            r#"
+                UPDATE user_takeouts
+                SET status = ?, error_message = ?, finished_at = strftime('%s', 'now')
+                WHERE id = ? AND status = ?
+            "#,
+            TakeoutStatus::Failed,
+            error_message,
+            takeout_id,
+            TakeoutStatus::Pending
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    /// Jobs live in memory only, so whatever was pending when the server went down is lost
+    pub async fn fail_interrupted_takeouts(&mut self) -> DataBaseResult<Vec<TakeoutRow>> {
+        query_as!(
+            TakeoutRow,
+            r#"
+                UPDATE user_takeouts
+                SET status = ?, error_message = 'Interrupted by a server restart',
+                    finished_at = strftime('%s', 'now')
+                WHERE status = ?
+                RETURNING
+                    id,
+                    user_id,
+                    status AS "status: TakeoutStatus",
+                    file_uuid AS "file_uuid: Uuid",
+                    file_size,
+                    error_message,
+                    created_at AS "created_at: OffsetDateTime",
+                    finished_at AS "finished_at: OffsetDateTime",
+                    expires_at AS "expires_at: OffsetDateTime"
+            "#,
+            TakeoutStatus::Failed,
+            TakeoutStatus::Pending
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    pub async fn get_takeout(&mut self, takeout_id: i64) -> DataBaseResult<Option<TakeoutRow>> {
+        query_as!(
+            TakeoutRow,
+            r#"
+                SELECT
+                    id,
+                    user_id,
+                    status AS "status: TakeoutStatus",
+                    file_uuid AS "file_uuid: Uuid",
+                    file_size,
+                    error_message,
+                    created_at AS "created_at: OffsetDateTime",
+                    finished_at AS "finished_at: OffsetDateTime",
+                    expires_at AS "expires_at: OffsetDateTime"
+                FROM user_takeouts
+                WHERE id = ?
+            "#,
+            takeout_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Most recent takeouts of a user, newest first
+    pub async fn list_user_takeouts(
+        &mut self,
+        user_id: i64,
+        limit: u32,
+    ) -> DataBaseResult<Vec<TakeoutRow>> {
+        query_as!(
+            TakeoutRow,
+            r#"
+                SELECT
+                    id,
+                    user_id,
+                    status AS "status: TakeoutStatus",
+                    file_uuid AS "file_uuid: Uuid",
+                    file_size,
+                    error_message,
+                    created_at AS "created_at: OffsetDateTime",
+                    finished_at AS "finished_at: OffsetDateTime",
+                    expires_at AS "expires_at: OffsetDateTime"
+                FROM user_takeouts
+                WHERE user_id = ?
+                ORDER BY id DESC
+                LIMIT ?
+            "#,
+            user_id,
+            limit
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Mark the ready archives past `now` as expired and hand them out for the file removal
+    pub async fn expire_takeouts(
+        &mut self,
+        now: OffsetDateTime,
+    ) -> DataBaseResult<Vec<TakeoutRow>> {
+        let now = now.unix_timestamp();
+        query_as!(
+            TakeoutRow,
+            r#"
+                UPDATE user_takeouts
+                SET status = ?
+                WHERE status = ? AND expires_at <= ?
+                RETURNING
+                    id,
+                    user_id,
+                    status AS "status: TakeoutStatus",
+                    file_uuid AS "file_uuid: Uuid",
+                    file_size,
+                    error_message,
+                    created_at AS "created_at: OffsetDateTime",
+                    finished_at AS "finished_at: OffsetDateTime",
+                    expires_at AS "expires_at: OffsetDateTime"
+            "#,
+            TakeoutStatus::Expired,
+            TakeoutStatus::Ready,
+            now
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+}
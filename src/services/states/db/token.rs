@@ -1,6 +1,7 @@
-use crate::models::token::AuthTokenRaw;
-use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt, SqliteQueryResultExt};
-use sqlx::{Executor, Sqlite, query, query_as};
+use crate::models::token::{AuthTokenRaw, TokenScope};
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
+use sqlx::types::Json;
+use sqlx::{Executor, Sqlite, query_as, query_scalar};
 use time::OffsetDateTime;
 
 pub struct TokenRepo<'a, E>
@@ -17,22 +18,30 @@ where
     pub async fn insert_user_token(
         &mut self,
         user_id: i64,
-        token: String,
+        name: &str,
+        token_hash: &str,
+        scopes: &[TokenScope],
         exp_time: OffsetDateTime,
     ) -> DataBaseResult<AuthTokenRaw> {
+        let scopes = serde_json::to_string(scopes)?;
+        // compared against unix timestamps in `use_token`
+        let exp_time = exp_time.unix_timestamp();
         query_as!(
             AuthTokenRaw,
             r#"
-                INSERT INTO auth_tokens (user_id, token, exp_time)
-                VALUES (?, ?, ?)
+                INSERT INTO auth_tokens (user_id, name, token_hash, scopes, exp_time)
+                VALUES (?, ?, ?, ?, ?)
                 RETURNING
-                    id, user_id, token,
+                    id, user_id, name, token_hash,
+                    scopes AS "scopes: Json<Vec<TokenScope>>",
                     created_at AS "created_at: OffsetDateTime",
                     exp_time AS "exp_time: OffsetDateTime",
                     last_used_at AS "last_used_at: _"
             "#,
             user_id,
-            token,
+            name,
+            token_hash,
+            scopes,
             exp_time
         )
         .fetch_one(&mut *self.inner)
@@ -47,12 +56,15 @@ where
                 SELECT
                     id,
                     user_id,
-                    token,
+                    name,
+                    token_hash,
+                    scopes AS "scopes: Json<Vec<TokenScope>>",
                     created_at AS "created_at: OffsetDateTime",
                     exp_time AS "exp_time: OffsetDateTime",
                     last_used_at AS "last_used_at: _"
                 FROM auth_tokens
                 WHERE user_id = ?
+                ORDER BY id DESC
             "#,
             user_id
         )
@@ -61,23 +73,50 @@ where
         .resolve()
     }
 
-    pub async fn invalidate_user_token(
+    /// Look up an unexpired token and mark it as used
+    pub async fn use_token(
         &mut self,
-        user_id: i64,
-        token: String,
-    ) -> DataBaseResult<()> {
-        query!(
+        token_hash: &str,
+        now: OffsetDateTime,
+    ) -> DataBaseResult<Option<AuthTokenRaw>> {
+        let now = now.unix_timestamp();
+        query_as!(
+            AuthTokenRaw,
             r#"
                 UPDATE auth_tokens
-                SET last_used_at = strftime('%s','now')
-                WHERE user_id = ? AND token = ?
+                SET last_used_at = ?1
+                WHERE token_hash = ?2 AND exp_time > ?1
+                RETURNING
+                    id AS "id!",
+                    user_id,
+                    name,
+                    token_hash,
+                    scopes AS "scopes: Json<Vec<TokenScope>>",
+                    created_at AS "created_at: OffsetDateTime",
+                    exp_time AS "exp_time: OffsetDateTime",
+                    last_used_at AS "last_used_at: _"
             "#,
-            user_id,
-            token
+            now,
+            token_hash
         )
-        .execute(&mut *self.inner)
+        .fetch_optional(&mut *self.inner)
         .await
-        .resolve_affected()?;
-        Ok(())
+        .resolve()
+    }
+
+    /// Delete a token of `user_id`, returns its hash or `None` if there is no such token
+    pub async fn invalidate_user_token(
+        &mut self,
+        user_id: i64,
+        token_id: i64,
+    ) -> DataBaseResult<Option<String>> {
+        query_scalar!(
+            "DELETE FROM auth_tokens WHERE id = ? AND user_id = ? RETURNING token_hash",
+            token_id,
+            user_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
     }
 }
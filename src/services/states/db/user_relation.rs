@@ -0,0 +1,115 @@
+use crate::models::relation::{UserRelationItem, UserRelationKind, UserRelationRow};
+use crate::services::states::db::{
+    DataBaseResult, PageQueryBinder, PageQueryResult, SqliteBaseResultExt,
+};
+use sqlx::{Executor, Sqlite, query, query_as};
+use time::OffsetDateTime;
+
+pub struct UserRelationRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> UserRelationRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    /// Returns `false` if the relation exists already
+    pub async fn add_relation(
+        &mut self,
+        user_id: i64,
+        target_id: i64,
+        kind: UserRelationKind,
+    ) -> DataBaseResult<bool> {
+        query!(
+            r#"
+                INSERT INTO user_relations (user_id, target_id, kind)
+                VALUES (?, ?, ?)
+                ON CONFLICT (user_id, kind, target_id) DO NOTHING
+            "#,
+            user_id,
+            target_id,
+            kind
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|res| res.rows_affected() > 0)
+    }
+
+    /// Returns `false` if there was no such relation
+    pub async fn remove_relation(
+        &mut self,
+        user_id: i64,
+        target_id: i64,
+        kind: UserRelationKind,
+    ) -> DataBaseResult<bool> {
+        query!(
+            r#"
+                DELETE FROM user_relations
+                WHERE user_id = ? AND target_id = ? AND kind = ?
+            "#,
+            user_id,
+            target_id,
+            kind
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|res| res.rows_affected() > 0)
+    }
+
+    /// Relations of `user_id` along with the blocks targeting them
+    pub async fn get_relations(&mut self, user_id: i64) -> DataBaseResult<Vec<UserRelationRow>> {
+        let block = UserRelationKind::Block;
+        query_as!(
+            UserRelationRow,
+            r#"
+                SELECT user_id, target_id, kind AS "kind: UserRelationKind"
+                FROM user_relations
+                WHERE user_id = ?1 OR (target_id = ?1 AND kind = ?2)
+            "#,
+            user_id,
+            block
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Users blocked or muted by `user_id`, in the order they were added
+    pub async fn list_relations(
+        &mut self,
+        user_id: i64,
+        kind: UserRelationKind,
+        page: PageQueryBinder,
+    ) -> DataBaseResult<PageQueryResult<UserRelationItem>> {
+        page.query_page_ctx(|pq| async move {
+            query_as!(
+                UserRelationItem,
+                r#"
+                    SELECT
+                      r.id AS "id!",
+                      u.id AS "user_id!",
+                      u.username,
+                      u.avatar_res_id,
+                      r.created_at AS "created_at: OffsetDateTime"
+                    FROM user_relations AS r
+                    JOIN users AS u ON u.id = r.target_id
+                    WHERE r.user_id = ?1 AND r.kind = ?2 AND r.id > ?3
+                    ORDER BY r.id
+                    LIMIT ?4
+                "#,
+                user_id,
+                kind,
+                pq.start_after,
+                pq.limit,
+            )
+            .fetch_all(&mut *self.inner)
+            .await
+        })
+        .await
+    }
+}
@@ -0,0 +1,157 @@
+use crate::models::session::{NewUserSession, UserSessionRow};
+use crate::services::states::db::{DataBaseResult, SqliteBaseResultExt};
+use sqlx::{Executor, Sqlite, query, query_as, query_scalar};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+pub struct UserSessionRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub inner: &'a mut E,
+}
+
+impl<'a, E> UserSessionRepo<'a, E>
+where
+    for<'c> &'c mut E: Executor<'c, Database = Sqlite>,
+{
+    pub async fn add_session(&mut self, session: NewUserSession) -> DataBaseResult<i64> {
+        query!(
+            r#"
+                INSERT INTO user_sessions (session_uuid, user_id, device_name, ip_address, user_agent)
+                VALUES (?, ?, ?, ?, ?)
+            "#,
+            session.session_uuid,
+            session.user_id,
+            session.device_name,
+            session.ip_address,
+            session.user_agent,
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()
+        .map(|result| result.last_insert_rowid())
+    }
+
+    /// Drop the sessions of `user_id` which are revoked or created before `expired_before`
+    pub async fn prune_sessions(
+        &mut self,
+        user_id: i64,
+        expired_before: OffsetDateTime,
+    ) -> DataBaseResult<()> {
+        let expired_before = expired_before.unix_timestamp();
+        query!(
+            r#"
+                DELETE FROM user_sessions
+                WHERE user_id = ? AND (revoked_at IS NOT NULL OR created_at <= ?)
+            "#,
+            user_id,
+            expired_before
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(())
+    }
+
+    /// Record activity on a live session and return its owner, `None` if it is gone or revoked
+    pub async fn touch_session(
+        &mut self,
+        session_uuid: Uuid,
+        now: OffsetDateTime,
+    ) -> DataBaseResult<Option<i64>> {
+        let now = now.unix_timestamp();
+        query_scalar!(
+            r#"
+                UPDATE user_sessions
+                SET last_seen_at = ?
+                WHERE session_uuid = ? AND revoked_at IS NULL
+                RETURNING user_id
+            "#,
+            now,
+            session_uuid
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Live sessions of `user_id`, newest first
+    pub async fn list_sessions(
+        &mut self,
+        user_id: i64,
+        expired_before: OffsetDateTime,
+    ) -> DataBaseResult<Vec<UserSessionRow>> {
+        let expired_before = expired_before.unix_timestamp();
+        query_as!(
+            UserSessionRow,
+            r#"
+                SELECT
+                  id AS "id!",
+                  session_uuid AS "session_uuid: Uuid",
+                  user_id,
+                  device_name,
+                  ip_address,
+                  user_agent,
+                  created_at AS "created_at: OffsetDateTime",
+                  last_seen_at AS "last_seen_at: OffsetDateTime"
+                FROM user_sessions
+                WHERE user_id = ? AND revoked_at IS NULL AND created_at > ?
+                ORDER BY id DESC
+            "#,
+            user_id,
+            expired_before
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Revoke one session of `user_id`, returns its uuid or `None` if there is no such live session
+    pub async fn revoke_session(
+        &mut self,
+        user_id: i64,
+        id: i64,
+        now: OffsetDateTime,
+    ) -> DataBaseResult<Option<Uuid>> {
+        let now = now.unix_timestamp();
+        query_scalar!(
+            r#"
+                UPDATE user_sessions
+                SET revoked_at = ?
+                WHERE id = ? AND user_id = ? AND revoked_at IS NULL
+                RETURNING session_uuid AS "session_uuid: Uuid"
+            "#,
+            now,
+            id,
+            user_id
+        )
+        .fetch_optional(&mut *self.inner)
+        .await
+        .resolve()
+    }
+
+    /// Revoke every session of `user_id` except `keep`, returns the uuids of the revoked ones
+    pub async fn revoke_other_sessions(
+        &mut self,
+        user_id: i64,
+        keep: Option<Uuid>,
+        now: OffsetDateTime,
+    ) -> DataBaseResult<Vec<Uuid>> {
+        let now = now.unix_timestamp();
+        query_scalar!(
+            r#"
+                UPDATE user_sessions
+                SET revoked_at = ?
+                WHERE user_id = ? AND revoked_at IS NULL AND session_uuid IS NOT ?
+                RETURNING session_uuid AS "session_uuid: Uuid"
+            "#,
+            now,
+            user_id,
+            keep
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()
+    }
+}
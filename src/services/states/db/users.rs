@@ -88,6 +88,25 @@ where
         Ok(())
     }
 
+    /// Replace the password hash only if it is still `old_hash`, so a concurrent change wins
+    pub async fn swap_password_hash(
+        &mut self,
+        user_id: i64,
+        old_hash: &str,
+        new_hash: &str,
+    ) -> DataBaseResult<()> {
+        query!(
+            "UPDATE users SET password_hash = ? WHERE id = ? AND password_hash = ?",
+            new_hash,
+            user_id,
+            old_hash,
+        )
+        .execute(&mut *self.inner)
+        .await
+        .resolve_affected()?;
+        Ok(())
+    }
+
     async fn link_user_avatar_res(
         &mut self,
         res_id: Option<i64>,
@@ -158,6 +177,29 @@ where
         .resolve()
     }
 
+    /// `(id, username)` of the users which exist among `usernames`
+    pub async fn query_user_ids_by_usernames(
+        &mut self,
+        usernames: &[&str],
+    ) -> DataBaseResult<Vec<(i64, String)>> {
+        if usernames.is_empty() {
+            return Ok(Vec::new());
+        }
+        let names_json = serde_json::to_string(usernames)?;
+        let rows = query!(
+            r#"
+                SELECT u.id, u.username
+                FROM users AS u
+                WHERE u.username IN (SELECT value FROM json_each(?))
+            "#,
+            names_json
+        )
+        .fetch_all(&mut *self.inner)
+        .await
+        .resolve()?;
+        Ok(rows.into_iter().map(|r| (r.id, r.username)).collect())
+    }
+
     pub(in crate::services) async fn query_user_by_id(
         &mut self,
         user_id: i64,
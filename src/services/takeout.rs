@@ -0,0 +1,537 @@
+use crate::models::echo::Echo;
+use crate::models::takeout::{
+    TakeoutData, TakeoutEcho, TakeoutManifest, TakeoutResource, TakeoutRow, TakeoutStatus,
+};
+use crate::services::echo_baker::{EchoBaker, EchoBakerError};
+use crate::services::hybrid_cache::{HybridCacheError, HybridCacheService};
+use crate::services::res_manager::{
+    ExchangedTakeoutItem, ResManagerService, ResManagerServiceError,
+};
+use crate::services::states::EchoState;
+use crate::services::states::db::{
+    DataBaseError, EchoDatabaseExecutor, PageQueryBinder, PageQueryCursor, PageQueryResult,
+};
+use ahash::HashMap;
+use echo_macros::EchoBusinessError;
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use time::{Duration, OffsetDateTime};
+use uuid::Uuid;
+use zip::CompressionMethod;
+use zip::write::{SimpleFileOptions, ZipWriter};
+
+/// Base url of the signed download links
+pub const TAKEOUT_DOWNLOAD_URL: &str = "/api/v1/takeout/download";
+/// Archives are removed once they have been around for this long
+const TAKEOUT_ARCHIVE_TTL: Duration = Duration::days(3);
+/// A download link stays valid for this long (or until the archive expires), listing the
+/// takeouts hands out a fresh one
+const TAKEOUT_LINK_TTL: Duration = Duration::hours(1);
+const TAKEOUT_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+const TAKEOUT_LIST_LIMIT: u32 = 10;
+const TAKEOUT_PAGE_SIZE: u32 = 100;
+
+#[derive(Debug, thiserror::Error, EchoBusinessError)]
+pub enum TakeoutError {
+    #[error(transparent)]
+    Database(#[from] DataBaseError),
+    #[error(transparent)]
+    HybridCache(#[from] HybridCacheError),
+    #[error(transparent)]
+    EchoBaker(#[from] EchoBakerError),
+    #[error(transparent)]
+    ResManager(#[from] ResManagerServiceError),
+    #[error(transparent)]
+    SerdeJson(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Takeout task panicked or was cancelled")]
+    TaskJoin(#[from] tokio::task::JoinError),
+    #[error("Another takeout of this user is still being built")]
+    AlreadyPending,
+    #[error("Takeout not found")]
+    NotFound,
+    #[error("Takeout archive is not available for download")]
+    NotAvailable,
+}
+
+pub type TakeoutResult<T> = Result<T, TakeoutError>;
+
+#[derive(Debug, Serialize)]
+pub struct TakeoutItem {
+    #[serde(flatten)]
+    pub takeout: TakeoutRow,
+    /// Signed link, only present while the archive can be downloaded
+    pub download_url: Option<String>,
+}
+
+/// Fetch every page of a cursor paged query
+async fn collect_pages<T, F, Fut>(mut query_page: F) -> TakeoutResult<Vec<T>>
+where
+    T: PageQueryCursor,
+    F: FnMut(PageQueryBinder) -> Fut,
+    Fut: Future<Output = Result<PageQueryResult<T>, DataBaseError>>,
+{
+    let mut items = Vec::new();
+    let mut start_after = 0;
+    loop {
+        let page = query_page(PageQueryBinder {
+            start_after,
+            page_size: TAKEOUT_PAGE_SIZE,
+        })
+        .await?;
+        items.extend(page.items);
+        match page.next_cursor {
+            Some(next_cursor) if page.has_more => start_after = next_cursor,
+            _ => break,
+        }
+    }
+    Ok(items)
+}
+
+fn write_json<W, T>(zip: &mut ZipWriter<W>, name: &str, value: &T) -> TakeoutResult<()>
+where
+    W: Write + std::io::Seek,
+    T: Serialize + ?Sized,
+{
+    zip.start_file(name, SimpleFileOptions::default())?;
+    serde_json::to_writer_pretty(&mut *zip, value)?;
+    Ok(())
+}
+
+/// Write the archive to `path`, resource files are copied from `storage_path` as they are
+fn write_archive(
+    path: &Path,
+    storage_path: &Path,
+    takeout_id: i64,
+    data: &TakeoutData,
+) -> TakeoutResult<u64> {
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+    let mut missing_resource_ids = Vec::new();
+    let mut resources = Vec::with_capacity(data.resources.len());
+    for res in &data.resources {
+        let file_name = res.info.file_name();
+        let archive_path = format!("resources/{file_name}");
+        let mut file = match File::open(storage_path.join(&file_name)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                missing_resource_ids.push(res.id);
+                resources.push(TakeoutResource {
+                    resource: res,
+                    path: None,
+                });
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // uploads are mostly compressed media already
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .large_file(true);
+        zip.start_file(&archive_path, options)?;
+        std::io::copy(&mut file, &mut zip)?;
+        resources.push(TakeoutResource {
+            resource: res,
+            path: Some(archive_path),
+        });
+    }
+    let manifest = TakeoutManifest {
+        takeout_id,
+        user_id: data.profile.id,
+        created_at: OffsetDateTime::now_utc(),
+        echo_count: data.echos.len(),
+        resource_count: data.resources.len(),
+        missing_resource_ids,
+    };
+    write_json(&mut zip, "manifest.json", &manifest)?;
+    write_json(&mut zip, "profile.json", data.profile.as_ref())?;
+    write_json(&mut zip, "echos.json", &data.echos)?;
+    write_json(&mut zip, "resources.json", &resources)?;
+    write_json(
+        &mut zip,
+        "permission_records.json",
+        &data.permission_records,
+    )?;
+    write_json(&mut zip, "mfa_op_logs.json", &data.mfa_op_logs)?;
+    let mut writer = zip.finish()?;
+    writer.flush()?;
+    Ok(writer.get_ref().metadata()?.len())
+}
+
+pub struct TakeoutService {
+    state: Arc<EchoState>,
+    cache: Arc<HybridCacheService>,
+    baker: Arc<EchoBaker<'static>>,
+    res_manager: Arc<ResManagerService>,
+}
+
+impl TakeoutService {
+    pub fn new(
+        state: Arc<EchoState>,
+        cache: Arc<HybridCacheService>,
+        baker: Arc<EchoBaker<'static>>,
+        res_manager: Arc<ResManagerService>,
+    ) -> Self {
+        Self {
+            state,
+            cache,
+            baker,
+            res_manager,
+        }
+    }
+
+    /// Archives live next to the uploads, but never among them
+    fn takeout_dir(&self) -> PathBuf {
+        self.state
+            .config
+            .resource
+            .local_storage_path
+            .join("takeouts")
+    }
+
+    fn partial_path(&self, file_uuid: &Uuid) -> PathBuf {
+        self.takeout_dir().join(format!("{file_uuid}.zip.part"))
+    }
+
+    /// Queue a new takeout of `user_id`, the archive is built in the background
+    pub async fn request_takeout(self: &Arc<Self>, user_id: i64) -> TakeoutResult<i64> {
+        let file_uuid = Uuid::new_v4();
+        let takeout_id = self
+            .state
+            .db
+            .transaction(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let mut repo = exec.takeout();
+                if repo.has_pending_takeout(user_id).await? {
+                    return Err(TakeoutError::AlreadyPending);
+                }
+                Ok(repo.add_takeout(user_id, file_uuid).await?)
+            })
+            .await?;
+        let this = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = this.build_takeout(takeout_id, user_id, file_uuid).await {
+                tracing::error!("Failed to build takeout {}: {}", takeout_id, e);
+                let _ = tokio::fs::remove_file(this.partial_path(&file_uuid)).await;
+                let message = e.to_string();
+                let res = this
+                    .state
+                    .db
+                    .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                        exec.takeout().fail_takeout(takeout_id, &message).await
+                    })
+                    .await;
+                if let Err(e) = res {
+                    tracing::error!("Failed to mark takeout {} as failed: {}", takeout_id, e);
+                }
+            }
+        });
+        Ok(takeout_id)
+    }
+
+    async fn collect_data(&self, user_id: i64) -> TakeoutResult<TakeoutData> {
+        let profile = self.cache.users.get_user_by_user_id(user_id).await?;
+        let echos = collect_pages(|page| {
+            self.state
+                .db
+                .single(async move |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.echo().query_user_echo(Some(user_id), &[], page).await
+                })
+        })
+        .await?;
+        let mfa_op_logs = collect_pages(|page| {
+            self.state
+                .db
+                .single(async move |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.mfa().get_mfa_op_logs_page(user_id, page).await
+                })
+        })
+        .await?;
+        let echo_ids = echos.iter().map(|it: &Echo| it.id).collect::<Vec<_>>();
+        let (resources, permission_records, reposts) = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                let resources = exec.resources().get_resources_by_uploader(user_id).await?;
+                let permission_records = exec
+                    .permission()
+                    .get_user_permission_records(user_id)
+                    .await?;
+                let reposts = exec
+                    .echo_repost()
+                    .get_reposts_by_echo_ids(&echo_ids)
+                    .await?;
+                Ok::<_, DataBaseError>((resources, permission_records, reposts))
+            })
+            .await?;
+        let reposts = reposts
+            .into_iter()
+            .map(|it| (it.echo_id, it.original_echo_id))
+            .collect::<HashMap<_, _>>();
+        let echos = echos
+            .into_iter()
+            // the echo cache is not keyed by viewer, so render each echo afresh
+            .map(|echo: Echo| {
+                let rendered = match echo.has_permission(&profile) {
+                    true => self.baker.post_inner_echo(
+                        Arc::downgrade(&self.state),
+                        &echo,
+                        user_id,
+                        &profile.permission_ids,
+                        EchoBaker::all_ext_ids(),
+                        true,
+                    )?,
+                    false => None,
+                };
+                Ok(TakeoutEcho {
+                    id: echo.id,
+                    repost_of: reposts.get(&echo.id).copied().flatten(),
+                    content: echo.content.unwrap_or_default(),
+                    rendered,
+                    permission: echo.permission,
+                    fav_count: echo.fav_count,
+                    created_at: echo.created_at,
+                    last_modified_at: echo.last_modified_at,
+                })
+            })
+            .collect::<TakeoutResult<Vec<_>>>()?;
+        Ok(TakeoutData {
+            profile,
+            echos,
+            resources,
+            permission_records,
+            mfa_op_logs,
+        })
+    }
+
+    async fn build_takeout(
+        &self,
+        takeout_id: i64,
+        user_id: i64,
+        file_uuid: Uuid,
+    ) -> TakeoutResult<()> {
+        let data = self.collect_data(user_id).await?;
+        tokio::fs::create_dir_all(self.takeout_dir()).await?;
+        let partial_path = self.partial_path(&file_uuid);
+        let storage_path = self.state.config.resource.local_storage_path.clone();
+        let file_size = {
+            let partial_path = partial_path.clone();
+            tokio::task::spawn_blocking(move || {
+                write_archive(&partial_path, &storage_path, takeout_id, &data)
+            })
+            .await??
+        };
+        let final_path = self.takeout_dir().join(format!("{file_uuid}.zip"));
+        tokio::fs::rename(&partial_path, &final_path).await?;
+        let expires_at = OffsetDateTime::now_utc() + TAKEOUT_ARCHIVE_TTL;
+        self.state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.takeout()
+                    .finish_takeout(takeout_id, file_size as i64, expires_at)
+                    .await
+            })
+            .await?;
+        tracing::info!(
+            "Takeout {} of user {} is ready ({} bytes)",
+            takeout_id,
+            user_id,
+            file_size
+        );
+        Ok(())
+    }
+
+    /// Recent takeouts of `user_id`, the ready ones come with a freshly signed download link
+    pub async fn list_takeouts(&self, user_id: i64) -> TakeoutResult<Vec<TakeoutItem>> {
+        let takeouts: Vec<TakeoutRow> = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.takeout()
+                    .list_user_takeouts(user_id, TAKEOUT_LIST_LIMIT)
+                    .await
+            })
+            .await?;
+        let now = OffsetDateTime::now_utc();
+        takeouts
+            .into_iter()
+            .map(|takeout| {
+                let download_url = match (takeout.status, takeout.expires_at) {
+                    (TakeoutStatus::Ready, Some(expires_at)) if expires_at > now => {
+                        let exp_time = (expires_at - now).min(TAKEOUT_LINK_TTL);
+                        Some(
+                            self.res_manager
+                                .sign_takeout(user_id, exp_time, takeout.id)?
+                                .to_url(Some(TAKEOUT_DOWNLOAD_URL))?,
+                        )
+                    }
+                    _ => None,
+                };
+                Ok(TakeoutItem {
+                    takeout,
+                    download_url,
+                })
+            })
+            .collect()
+    }
+
+    /// Verify a signed download link and resolve the archive file it points to
+    pub async fn resolve_download(
+        &self,
+        item: &ExchangedTakeoutItem,
+    ) -> TakeoutResult<(TakeoutRow, PathBuf)> {
+        let tag = self
+            .res_manager
+            .verify_takeout(item.takeout_id, &item.cred)?;
+        let takeout = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.takeout().get_takeout(tag.takeout_id).await
+            })
+            .await?
+            .ok_or(TakeoutError::NotFound)?;
+        let available = takeout.user_id == tag.sign_user_id
+            && takeout.status == TakeoutStatus::Ready
+            && takeout
+                .expires_at
+                .is_some_and(|it| it > OffsetDateTime::now_utc());
+        if !available {
+            return Err(TakeoutError::NotAvailable);
+        }
+        let path = self.takeout_dir().join(takeout.file_name());
+        Ok((takeout, path))
+    }
+
+    async fn remove_archives(&self, takeouts: &[TakeoutRow]) {
+        for takeout in takeouts {
+            let path = match takeout.status {
+                TakeoutStatus::Failed => self.partial_path(&takeout.file_uuid),
+                _ => self.takeout_dir().join(takeout.file_name()),
+            };
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => tracing::info!("Takeout {} archive removed", takeout.id),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => tracing::warn!("Failed to remove takeout archive {:?}: {}", path, e),
+            }
+        }
+    }
+
+    async fn sweep_expired(&self) -> TakeoutResult<()> {
+        let now = OffsetDateTime::now_utc();
+        let expired: Vec<TakeoutRow> = self
+            .state
+            .db
+            .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                exec.takeout().expire_takeouts(now).await
+            })
+            .await?;
+        self.remove_archives(&expired).await;
+        Ok(())
+    }
+
+    /// Fail the takeouts interrupted by the last shutdown, then remove expired archives periodically
+    pub fn spawn_cleanup_worker(self: &Arc<Self>) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            let shutdown = this.state.shutdown.clone();
+            let interrupted: Result<Vec<TakeoutRow>, DataBaseError> = this
+                .state
+                .db
+                .single(async |mut exec: EchoDatabaseExecutor<'_>| {
+                    exec.takeout().fail_interrupted_takeouts().await
+                })
+                .await;
+            match interrupted {
+                Ok(interrupted) => this.remove_archives(&interrupted).await,
+                Err(e) => tracing::error!("Failed to fail interrupted takeouts: {}", e),
+            }
+            loop {
+                if let Err(e) = this.sweep_expired().await {
+                    tracing::error!("Takeout cleanup worker error: {}", e);
+                }
+                tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    _ = tokio::time::sleep(TAKEOUT_SWEEP_INTERVAL) => {},
+                }
+            }
+            tracing::info!("Takeout cleanup worker stopped.");
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::models::echo::EchoPermission;
+    use crate::models::resource::{ResourceItemRaw, ResourceItemRawInfo};
+    use crate::models::users::{Role, User};
+    use std::collections::BTreeSet;
+    use std::io::Read;
+    use zip::ZipArchive;
+
+    #[test]
+    fn test_write_archive() {
+        let storage = tempfile::tempdir().unwrap();
+        let resource = |id: i64| ResourceItemRaw {
+            id,
+            info: ResourceItemRawInfo {
+                uploader_id: 1,
+                res_name: format!("{id}.png"),
+                res_uuid: Uuid::new_v4(),
+                res_ext: "png".to_string(),
+            },
+        };
+        let (kept, missing) = (resource(1), resource(2));
+        std::fs::write(storage.path().join(kept.info.file_name()), b"png").unwrap();
+        let data = TakeoutData {
+            profile: Arc::new(User {
+                id: 1,
+                username: "alice".to_string(),
+                role: Role::User,
+                created_at: OffsetDateTime::UNIX_EPOCH,
+                permission_ids: BTreeSet::new(),
+                avatar_res_id: None,
+            }),
+            echos: vec![TakeoutEcho {
+                id: 7,
+                repost_of: None,
+                content: "<p>hi</p>".to_string(),
+                rendered: Some("<p>hi</p>".to_string()),
+                permission: EchoPermission::Private,
+                fav_count: 0,
+                created_at: OffsetDateTime::UNIX_EPOCH,
+                last_modified_at: OffsetDateTime::UNIX_EPOCH,
+            }],
+            resources: vec![kept, missing],
+            permission_records: Vec::new(),
+            mfa_op_logs: Vec::new(),
+        };
+        let path = storage.path().join("takeout.zip");
+        let size = write_archive(&path, storage.path(), 3, &data).unwrap();
+        assert_eq!(size, std::fs::metadata(&path).unwrap().len());
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut manifest = String::new();
+        archive
+            .by_name("manifest.json")
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+        let manifest = serde_json::from_str::<serde_json::Value>(&manifest).unwrap();
+        assert_eq!(manifest["takeout_id"], 3);
+        assert_eq!(manifest["echo_count"], 1);
+        assert_eq!(manifest["missing_resource_ids"], serde_json::json!([2]));
+        let mut res = Vec::new();
+        archive
+            .by_name(&format!("resources/{}", data.resources[0].info.file_name()))
+            .unwrap()
+            .read_to_end(&mut res)
+            .unwrap();
+        assert_eq!(res, b"png");
+        assert!(archive.by_name("echos.json").is_ok());
+    }
+}